@@ -0,0 +1,1130 @@
+use crate::ai::prompt::{transcript, ChatMessage};
+use crate::ai::{Completion, GenerationParams};
+use crate::viewer::gguf::format_size;
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::future::Future;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::task::AbortHandle;
+
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+pub const DEFAULT_LLAMA_CPP_URL: &str = "http://localhost:8080";
+pub const DEFAULT_OPENAI_URL: &str = "https://api.openai.com/v1";
+pub const ANTHROPIC_URL: &str = "https://api.anthropic.com/v1";
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Serialize)]
+pub struct OllamaRequest {
+    pub model: String,
+    pub prompt: String,
+    pub stream: bool,
+    pub options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+pub struct OllamaChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub stream: bool,
+    pub options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+pub struct OllamaOptions {
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    pub num_predict: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct OllamaResponse {
+    #[serde(default)]
+    pub response: String,
+    /// `/api/chat` sends the text here instead of in `response`.
+    #[serde(default)]
+    pub message: Option<ChatMessage>,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub prompt_eval_count: u64,
+    #[serde(default)]
+    pub eval_count: u64,
+    /// Nanoseconds spent generating the `eval_count` answer tokens.
+    #[serde(default)]
+    pub eval_duration: u64,
+    /// Nanoseconds spent on the whole request, as reported by Ollama.
+    #[serde(default)]
+    pub total_duration: u64,
+    /// Set instead of the other fields when generation fails mid-stream.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LlamaCppRequest {
+    pub prompt: String,
+    pub n_predict: i32,
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LlamaCppResponse {
+    pub content: String,
+    /// Set on the last chunk of a streamed answer, which carries the counts.
+    #[serde(default)]
+    pub stop: bool,
+    #[serde(default)]
+    pub tokens_evaluated: u64,
+    #[serde(default)]
+    pub tokens_predicted: u64,
+    #[serde(default)]
+    pub timings: Option<LlamaCppTimings>,
+}
+
+#[derive(Deserialize)]
+pub struct LlamaCppTimings {
+    #[serde(default)]
+    pub prompt_ms: f64,
+    #[serde(default)]
+    pub predicted_ms: f64,
+}
+
+#[derive(Deserialize)]
+pub struct LlamaCppSlot {
+    #[serde(default)]
+    pub id: i64,
+    #[serde(default)]
+    pub is_processing: bool,
+    /// Older servers report `state` (0 = idle) instead of `is_processing`.
+    #[serde(default)]
+    pub state: i64,
+}
+
+#[derive(Serialize)]
+pub struct OpenAiRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub max_tokens: i32,
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    pub stream: bool,
+    /// Asks for a final chunk carrying token usage.
+    pub stream_options: OpenAiStreamOptions,
+}
+
+#[derive(Serialize)]
+pub struct OpenAiStreamOptions {
+    pub include_usage: bool,
+}
+
+/// One server-sent event of a streamed chat completion.
+#[derive(Deserialize)]
+pub struct OpenAiChunk {
+    #[serde(default)]
+    pub choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    pub usage: Option<OpenAiUsage>,
+    #[serde(default)]
+    pub error: Option<OpenAiErrorBody>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiChoice {
+    #[serde(default)]
+    pub delta: OpenAiDelta,
+}
+
+#[derive(Deserialize, Default)]
+pub struct OpenAiDelta {
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiError {
+    pub error: OpenAiErrorBody,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiErrorBody {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiModels {
+    #[serde(default)]
+    pub data: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct AnthropicRequest {
+    pub model: String,
+    pub max_tokens: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    pub stream: bool,
+}
+
+/// The events of a streamed Messages API response that matter here.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicEvent {
+    MessageStart {
+        message: AnthropicMessageStart,
+    },
+    ContentBlockDelta {
+        delta: AnthropicDelta,
+    },
+    MessageDelta {
+        usage: AnthropicUsage,
+    },
+    MessageStop,
+    Error {
+        error: OpenAiErrorBody,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicMessageStart {
+    pub usage: AnthropicUsage,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicDelta {
+    /// Only text deltas carry text; other block types leave it empty.
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+pub fn http_client(timeout: Duration) -> Result<reqwest::blocking::Client, String> {
+    reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// The tokio runtime completions run on. The app owns one for its lifetime;
+/// the command-line modes create one per run.
+pub struct Network {
+    runtime: Option<Runtime>,
+}
+
+impl Network {
+    pub fn new() -> Result<Self, String> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("tty_doc-network")
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Failed to start the network runtime: {}", e))?;
+        Ok(Self {
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Run `task` in the background; the handle cancels it. Returns `None`
+    /// once the runtime has been shut down.
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) -> Option<AbortHandle> {
+        let runtime = self.runtime.as_ref()?;
+        Some(runtime.spawn(task).abort_handle())
+    }
+
+    pub fn block_on<T>(&self, task: impl Future<Output = T>) -> Option<T> {
+        Some(self.runtime.as_ref()?.block_on(task))
+    }
+
+    /// Cancel whatever is still running, waiting at most `timeout` for
+    /// blocking work (such as embedding a document) to wind down.
+    pub fn shutdown(&mut self, timeout: Duration) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_timeout(timeout);
+        }
+    }
+}
+
+/// How long a completion may take, and how often a server that cannot be
+/// reached is tried again before giving up.
+#[derive(Clone, Copy)]
+pub struct RequestPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl RequestPolicy {
+    fn client(&self) -> Result<reqwest::Client, String> {
+        reqwest::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))
+    }
+
+    /// Send `request`, retrying with exponential backoff while the connection
+    /// fails. Nothing has reached the server then, so the retry is safe.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            let Some(retry) = request.try_clone().filter(|_| attempt < self.retries) else {
+                return request.send().await;
+            };
+            match retry.send().await {
+                Err(e) if e.is_connect() => {
+                    tokio::time::sleep(retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Wait before retry number `attempt` (from 0): 0.5 s, 1 s, 2 s… capped at 8 s.
+pub fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500 << attempt.min(4))
+}
+
+/// Reads a streamed response body line by line as its chunks arrive.
+pub struct LineReader {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+}
+
+impl LineReader {
+    pub fn new(response: reqwest::Response) -> Self {
+        Self {
+            response,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// The next line without its line ending, or `None` at the end of the body.
+    pub async fn next_line(&mut self) -> Result<Option<String>, String> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                return Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()));
+            }
+            let chunk = self
+                .response
+                .chunk()
+                .await
+                .map_err(|e| format!("Stream interrupted: {}", e))?;
+            match chunk {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None if self.buffer.is_empty() => return Ok(None),
+                None => {
+                    let line = std::mem::take(&mut self.buffer);
+                    return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+                }
+            }
+        }
+    }
+}
+
+/// `path` on the Ollama server at `base_url`, tolerating a trailing slash.
+pub fn ollama_endpoint(base_url: &str, path: &str) -> String {
+    format!("{}{}", base_url.trim_end_matches('/'), path)
+}
+
+/// Explain a failed request to Ollama, with a hint for the usual causes.
+pub fn ollama_request_error(base_url: &str, e: reqwest::Error) -> String {
+    if e.is_connect() {
+        format!(
+            "Cannot connect to Ollama at {}. Check that `ollama serve` is running there; \
+             a server on another machine must listen on a public address (OLLAMA_HOST=0.0.0.0).",
+            base_url
+        )
+    } else if e.is_timeout() {
+        format!("Ollama at {} did not answer in time", base_url)
+    } else {
+        format!("Failed to reach Ollama at {}: {}", base_url, e)
+    }
+}
+
+/// Run a streaming chat request, passing each chunk of text to `on_token`
+/// as Ollama produces it.
+pub async fn query_ollama(
+    base_url: &str,
+    request: &OllamaChatRequest,
+    policy: RequestPolicy,
+    on_token: &mut TokenSink<'_>,
+) -> Result<Completion, String> {
+    let client = policy.client()?;
+
+    let response = policy
+        .send(
+            client
+                .post(ollama_endpoint(base_url, "/api/chat"))
+                .json(request),
+        )
+        .await
+        .map_err(|e| ollama_request_error(base_url, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Ollama returned {}", response.status()));
+    }
+
+    let mut text = String::new();
+    let mut lines = LineReader::new(response);
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let chunk: OllamaResponse = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid response from Ollama: {}", e))?;
+        if let Some(error) = chunk.error {
+            return Err(format!("Ollama error: {}", error));
+        }
+
+        if let Some(message) = chunk.message.filter(|m| !m.content.is_empty()) {
+            on_token(&message.content);
+            text.push_str(&message.content);
+        }
+
+        if chunk.done {
+            return Ok(Completion {
+                text,
+                prompt_tokens: chunk.prompt_eval_count,
+                completion_tokens: chunk.eval_count,
+                duration: (chunk.total_duration > 0)
+                    .then(|| Duration::from_nanos(chunk.total_duration)),
+                generation: (chunk.eval_duration > 0)
+                    .then(|| Duration::from_nanos(chunk.eval_duration)),
+            });
+        }
+    }
+
+    Err("Ollama closed the stream before the response was complete".to_string())
+}
+
+/// The chunk of a streamed llama.cpp answer in a `data:` line of the
+/// event stream; `None` for the other lines.
+fn llama_cpp_chunk(line: &str) -> Option<Result<LlamaCppResponse, String>> {
+    let data = line.strip_prefix("data:")?.trim();
+    Some(
+        serde_json::from_str(data)
+            .map_err(|e| format!("Invalid response from llama.cpp server: {}", e)),
+    )
+}
+
+/// Run a streaming `/completion` request on a llama.cpp server, passing each
+/// chunk of text to `on_token` as it is produced.
+pub async fn query_llama_cpp(
+    base_url: &str,
+    request: &LlamaCppRequest,
+    policy: RequestPolicy,
+    on_token: &mut TokenSink<'_>,
+) -> Result<Completion, String> {
+    let client = policy.client()?;
+
+    let response = policy
+        .send(
+            client
+                .post(format!("{}/completion", base_url.trim_end_matches('/')))
+                .json(request),
+        )
+        .await
+        .map_err(|e| format!("Failed to reach llama.cpp server at {}: {}", base_url, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("llama.cpp server returned {}", response.status()));
+    }
+
+    let mut text = String::new();
+    let mut lines = LineReader::new(response);
+    while let Some(line) = lines.next_line().await? {
+        let Some(chunk) = llama_cpp_chunk(&line) else {
+            continue;
+        };
+        let chunk = chunk?;
+        if !chunk.content.is_empty() {
+            on_token(&chunk.content);
+            text.push_str(&chunk.content);
+        }
+
+        if chunk.stop {
+            return Ok(Completion {
+                text,
+                prompt_tokens: chunk.tokens_evaluated,
+                completion_tokens: chunk.tokens_predicted,
+                duration: chunk
+                    .timings
+                    .as_ref()
+                    .map(|t| Duration::from_secs_f64((t.prompt_ms + t.predicted_ms) / 1000.0)),
+                generation: chunk
+                    .timings
+                    .map(|t| Duration::from_secs_f64(t.predicted_ms / 1000.0)),
+            });
+        }
+    }
+
+    Err("The llama.cpp server closed the stream before the response was complete".to_string())
+}
+
+/// Read an `{"error": {"message": ...}}` body, as sent by both OpenAI and
+/// Anthropic, falling back to the status.
+pub fn api_error(status: StatusCode, body: &str) -> String {
+    match serde_json::from_str::<OpenAiError>(body) {
+        Ok(body) => format!("Server returned {}: {}", status, body.error.message),
+        Err(_) => format!("Server returned {}", status),
+    }
+}
+
+fn blocking_api_error(response: reqwest::blocking::Response) -> String {
+    let status = response.status();
+    api_error(status, &response.text().unwrap_or_default())
+}
+
+async fn async_api_error(response: reqwest::Response) -> String {
+    let status = response.status();
+    api_error(status, &response.text().await.unwrap_or_default())
+}
+
+/// Stream a chat completion from an OpenAI-compatible `/chat/completions` endpoint.
+pub async fn query_openai(
+    base_url: &str,
+    api_key: Option<&str>,
+    request: &OpenAiRequest,
+    policy: RequestPolicy,
+    on_token: &mut TokenSink<'_>,
+) -> Result<Completion, String> {
+    let client = policy.client()?;
+
+    let mut builder = client
+        .post(format!(
+            "{}/chat/completions",
+            base_url.trim_end_matches('/')
+        ))
+        .json(request);
+    if let Some(key) = api_key {
+        builder = builder.bearer_auth(key);
+    }
+    let response = policy
+        .send(builder)
+        .await
+        .map_err(|e| format!("Failed to reach {}: {}", base_url, e))?;
+
+    if !response.status().is_success() {
+        return Err(async_api_error(response).await);
+    }
+
+    let mut completion = Completion {
+        text: String::new(),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        duration: None,
+        generation: None,
+    };
+    let mut lines = LineReader::new(response);
+    while let Some(line) = lines.next_line().await? {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            return Ok(completion);
+        }
+
+        let chunk: OpenAiChunk =
+            serde_json::from_str(data).map_err(|e| format!("Invalid response: {}", e))?;
+        if let Some(error) = chunk.error {
+            return Err(format!("Server error: {}", error.message));
+        }
+        for choice in chunk.choices {
+            if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                on_token(&content);
+                completion.text.push_str(&content);
+            }
+        }
+        if let Some(usage) = chunk.usage {
+            completion.prompt_tokens = usage.prompt_tokens;
+            completion.completion_tokens = usage.completion_tokens;
+        }
+    }
+
+    Err("The server closed the stream before the response was complete".to_string())
+}
+
+/// Query `/models` on an OpenAI-compatible server and describe the result.
+pub fn check_openai_server(base_url: &str, api_key: Option<&str>) -> String {
+    let client = match http_client(Duration::from_secs(10)) {
+        Ok(client) => client,
+        Err(e) => return e,
+    };
+
+    let mut builder = client.get(format!("{}/models", base_url.trim_end_matches('/')));
+    if let Some(key) = api_key {
+        builder = builder.bearer_auth(key);
+    }
+    match builder.send() {
+        Ok(response) if response.status().is_success() => match response.json::<OpenAiModels>() {
+            Ok(models) => format!("✔ Connected, {} models available", models.data.len()),
+            Err(_) => "✔ Connected".to_string(),
+        },
+        Ok(response) => format!("⚠ {}", blocking_api_error(response)),
+        Err(e) => format!("✖ Failed to reach {}: {}", base_url, e),
+    }
+}
+
+/// The API key held in environment variable `var`, if it is set.
+pub fn api_key_from_env(var: &str) -> Option<String> {
+    env::var(var.trim()).ok().filter(|key| !key.is_empty())
+}
+
+/// Headers every Anthropic API request carries.
+pub fn anthropic_headers(api_key: &str) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    let key = HeaderValue::from_str(api_key)
+        .map_err(|_| "The Anthropic API key contains invalid characters".to_string())?;
+    headers.insert("x-api-key", key);
+    headers.insert(
+        "anthropic-version",
+        HeaderValue::from_static(ANTHROPIC_VERSION),
+    );
+    Ok(headers)
+}
+
+/// Stream a reply from Anthropic's Messages API.
+pub async fn query_anthropic(
+    api_key: &str,
+    request: &AnthropicRequest,
+    policy: RequestPolicy,
+    on_token: &mut TokenSink<'_>,
+) -> Result<Completion, String> {
+    let client = policy.client()?;
+
+    let builder = client
+        .post(format!("{}/messages", ANTHROPIC_URL))
+        .headers(anthropic_headers(api_key)?)
+        .json(request);
+    let response = policy
+        .send(builder)
+        .await
+        .map_err(|e| format!("Failed to reach Anthropic: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(async_api_error(response).await);
+    }
+
+    let mut completion = Completion {
+        text: String::new(),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        duration: None,
+        generation: None,
+    };
+    let mut lines = LineReader::new(response);
+    while let Some(line) = lines.next_line().await? {
+        // `event:` lines repeat the type that is also inside each `data:` payload.
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+
+        let event: AnthropicEvent =
+            serde_json::from_str(data).map_err(|e| format!("Invalid response: {}", e))?;
+        match event {
+            AnthropicEvent::MessageStart { message } => {
+                completion.prompt_tokens = message.usage.input_tokens;
+            }
+            AnthropicEvent::ContentBlockDelta { delta } if !delta.text.is_empty() => {
+                on_token(&delta.text);
+                completion.text.push_str(&delta.text);
+            }
+            AnthropicEvent::MessageDelta { usage } => {
+                completion.completion_tokens = usage.output_tokens;
+            }
+            AnthropicEvent::MessageStop => return Ok(completion),
+            AnthropicEvent::Error { error } => {
+                return Err(format!("Anthropic error: {}", error.message))
+            }
+            _ => {}
+        }
+    }
+
+    Err("Anthropic closed the stream before the response was complete".to_string())
+}
+
+/// Check the API key against Anthropic's `/models` endpoint.
+pub fn check_anthropic_key(api_key: &str) -> String {
+    let client = match http_client(Duration::from_secs(10)) {
+        Ok(client) => client,
+        Err(e) => return e,
+    };
+
+    let headers = match anthropic_headers(api_key) {
+        Ok(headers) => headers,
+        Err(e) => return format!("✖ {}", e),
+    };
+    let request = client
+        .get(format!("{}/models", ANTHROPIC_URL))
+        .headers(headers);
+    match request.send() {
+        Ok(response) if response.status().is_success() => "✔ API key accepted".to_string(),
+        Ok(response) => format!("⚠ {}", blocking_api_error(response)),
+        Err(e) => format!("✖ Failed to reach Anthropic: {}", e),
+    }
+}
+
+/// Receives the text of a streamed answer as it arrives.
+pub type TokenSink<'a> = dyn FnMut(&str) + Send + 'a;
+
+/// A model server the AI features can send prompts to.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Continue the conversation `messages`, with `system` as the system prompt
+    /// when there is one. Backends that stream report text through `on_token`
+    /// as it arrives; the returned completion always holds the full text.
+    async fn complete(
+        &self,
+        system: Option<&str>,
+        messages: Vec<ChatMessage>,
+        params: &GenerationParams,
+        policy: RequestPolicy,
+        on_token: &mut TokenSink<'_>,
+    ) -> Result<Completion, String>;
+}
+
+pub struct OllamaBackend {
+    pub base_url: String,
+    pub model: String,
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    async fn complete(
+        &self,
+        system: Option<&str>,
+        mut messages: Vec<ChatMessage>,
+        params: &GenerationParams,
+        policy: RequestPolicy,
+        on_token: &mut TokenSink<'_>,
+    ) -> Result<Completion, String> {
+        if let Some(system) = system {
+            messages.insert(0, ChatMessage::system(system.to_string()));
+        }
+        let request = OllamaChatRequest {
+            model: self.model.clone(),
+            messages,
+            stream: true,
+            options: OllamaOptions {
+                temperature: params.temperature,
+                top_p: params.top_p(),
+                top_k: params.top_k(),
+                num_predict: params.max_tokens,
+                num_ctx: Some(params.context_window),
+            },
+        };
+        query_ollama(&self.base_url, &request, policy, on_token).await
+    }
+}
+
+pub struct LlamaCppBackend {
+    pub base_url: String,
+    pub grammar: Option<String>,
+}
+
+#[async_trait]
+impl LlmBackend for LlamaCppBackend {
+    async fn complete(
+        &self,
+        system: Option<&str>,
+        messages: Vec<ChatMessage>,
+        params: &GenerationParams,
+        policy: RequestPolicy,
+        on_token: &mut TokenSink<'_>,
+    ) -> Result<Completion, String> {
+        // `/completion` takes raw text rather than turns.
+        let request = LlamaCppRequest {
+            prompt: transcript(system, &messages),
+            n_predict: params.max_tokens,
+            temperature: params.temperature,
+            top_p: params.top_p(),
+            top_k: params.top_k(),
+            stream: true,
+            grammar: self.grammar.clone(),
+        };
+        query_llama_cpp(&self.base_url, &request, policy, on_token).await
+    }
+}
+
+/// OpenAI's chat completions API, or any server that mimics it (LM Studio,
+/// vLLM, llama.cpp's `/v1` endpoints).
+pub struct OpenAiBackend {
+    pub base_url: String,
+    /// Local servers usually need no key.
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    async fn complete(
+        &self,
+        system: Option<&str>,
+        mut messages: Vec<ChatMessage>,
+        params: &GenerationParams,
+        policy: RequestPolicy,
+        on_token: &mut TokenSink<'_>,
+    ) -> Result<Completion, String> {
+        if let Some(system) = system {
+            messages.insert(0, ChatMessage::system(system.to_string()));
+        }
+        let request = OpenAiRequest {
+            model: self.model.clone(),
+            messages,
+            max_tokens: params.max_tokens,
+            temperature: params.temperature,
+            top_p: params.top_p(),
+            stream: true,
+            stream_options: OpenAiStreamOptions {
+                include_usage: true,
+            },
+        };
+        query_openai(
+            &self.base_url,
+            self.api_key.as_deref(),
+            &request,
+            policy,
+            on_token,
+        )
+        .await
+    }
+}
+
+pub struct AnthropicBackend {
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[async_trait]
+impl LlmBackend for AnthropicBackend {
+    async fn complete(
+        &self,
+        system: Option<&str>,
+        messages: Vec<ChatMessage>,
+        params: &GenerationParams,
+        policy: RequestPolicy,
+        on_token: &mut TokenSink<'_>,
+    ) -> Result<Completion, String> {
+        let Some(api_key) = &self.api_key else {
+            return Err("No Anthropic API key; set it in the provider settings (⚙)".to_string());
+        };
+        let request = AnthropicRequest {
+            model: self.model.clone(),
+            max_tokens: params.max_tokens,
+            system: system.map(str::to_string),
+            messages,
+            temperature: params.temperature,
+            top_p: params.top_p(),
+            top_k: params.top_k(),
+            stream: true,
+        };
+        query_anthropic(api_key, &request, policy, on_token).await
+    }
+}
+
+/// Everything a network task needs to run a completion against the selected backend.
+#[derive(Clone)]
+pub struct BackendConfig {
+    pub backend: Arc<dyn LlmBackend>,
+    /// Model name, also used as the key for usage stats.
+    pub model: String,
+    pub policy: RequestPolicy,
+}
+
+impl BackendConfig {
+    pub async fn complete(
+        &self,
+        system: Option<&str>,
+        messages: Vec<ChatMessage>,
+        params: &GenerationParams,
+        mut on_token: impl FnMut(&str) + Send,
+    ) -> Result<Completion, String> {
+        self.backend
+            .complete(system, messages, params, self.policy, &mut on_token)
+            .await
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OllamaTagsResponse {
+    #[serde(default)]
+    pub models: Vec<OllamaModel>,
+}
+
+/// A model installed on the Ollama server.
+#[derive(Deserialize, Clone)]
+pub struct OllamaModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub modified_at: String,
+}
+
+impl OllamaModel {
+    pub fn description(&self) -> String {
+        // Timestamps look like 2024-01-15T10:20:30.123456-08:00; minutes are enough.
+        let modified = self.modified_at.get(..16).unwrap_or(&self.modified_at);
+        format!(
+            "Size: {}\nModified: {}",
+            format_size(self.size),
+            modified.replace('T', " ")
+        )
+    }
+}
+
+/// Progress of the background `/api/tags` request.
+#[derive(Default)]
+pub struct ModelDiscovery {
+    pub loading: bool,
+    /// Result not yet picked up by the UI.
+    pub fetched: Option<Result<Vec<OllamaModel>, String>>,
+}
+
+pub fn list_ollama_models(base_url: &str) -> Result<Vec<OllamaModel>, String> {
+    let client = http_client(Duration::from_secs(5))?;
+
+    let response = client
+        .get(ollama_endpoint(base_url, "/api/tags"))
+        .send()
+        .map_err(|e| ollama_request_error(base_url, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Ollama returned {}", response.status()));
+    }
+
+    let mut models = response
+        .json::<OllamaTagsResponse>()
+        .map_err(|e| format!("Invalid response from Ollama: {}", e))?
+        .models;
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(models)
+}
+
+#[derive(Serialize)]
+pub struct OllamaPullRequest {
+    pub model: String,
+    pub stream: bool,
+}
+
+/// A progress line of `/api/pull`. `total` and `completed` are bytes of
+/// the layer being downloaded.
+#[derive(Deserialize)]
+pub struct OllamaPullProgress {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub total: u64,
+    #[serde(default)]
+    pub completed: u64,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Progress of a "Pull model" download, shown in the AI panel.
+#[derive(Default)]
+pub struct ModelPull {
+    pub model: String,
+    pub running: bool,
+    /// Set once the model is installed, until the UI switches to it.
+    pub finished: bool,
+    pub status: String,
+    pub completed: u64,
+    pub total: u64,
+    pub error: Option<String>,
+}
+
+/// Download `model` with `/api/pull`, passing each progress line to
+/// `progress`. Blocks until the download is done.
+pub fn pull_ollama_model(
+    base_url: &str,
+    model: &str,
+    mut progress: impl FnMut(&OllamaPullProgress),
+) -> Result<(), String> {
+    // Models are gigabytes; the download takes as long as it takes.
+    let client = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let request = OllamaPullRequest {
+        model: model.to_string(),
+        stream: true,
+    };
+    let response = client
+        .post(ollama_endpoint(base_url, "/api/pull"))
+        .json(&request)
+        .send()
+        .map_err(|e| ollama_request_error(base_url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("Ollama returned {}", response.status()));
+    }
+
+    for line in BufReader::new(response).lines() {
+        let line = line.map_err(|e| format!("Download interrupted: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let chunk: OllamaPullProgress = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid response from Ollama: {}", e))?;
+        if let Some(error) = chunk.error {
+            return Err(format!("Ollama error: {}", error));
+        }
+        progress(&chunk);
+        if chunk.status == "success" {
+            return Ok(());
+        }
+    }
+    Err("Download ended before the model was installed".to_string())
+}
+
+#[derive(Deserialize)]
+pub struct OllamaVersion {
+    pub version: String,
+}
+
+/// Query `/api/version` on the Ollama server and describe the result.
+pub fn check_ollama_server(base_url: &str) -> String {
+    let client = match http_client(Duration::from_secs(5)) {
+        Ok(client) => client,
+        Err(e) => return e,
+    };
+
+    match client.get(ollama_endpoint(base_url, "/api/version")).send() {
+        Ok(response) if response.status().is_success() => match response.json::<OllamaVersion>() {
+            Ok(info) => format!("✔ Connected to Ollama {}", info.version),
+            Err(_) => "⚠ Server answered, but it does not look like Ollama".to_string(),
+        },
+        Ok(response) => format!("⚠ Server returned {}", response.status()),
+        Err(e) => format!("✖ {}", ollama_request_error(base_url, e)),
+    }
+}
+
+/// Query `/health` and `/slots` on the llama.cpp server and describe the result.
+pub fn check_llama_cpp_server(base_url: &str) -> String {
+    let client = match http_client(Duration::from_secs(5)) {
+        Ok(client) => client,
+        Err(e) => return e,
+    };
+
+    let base_url = base_url.trim_end_matches('/');
+    let health = match client.get(format!("{}/health", base_url)).send() {
+        Ok(response) if response.status().is_success() => "✔ Server healthy".to_string(),
+        Ok(response) if response.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+            "⚠ Server loading model".to_string()
+        }
+        Ok(response) => format!("⚠ Server unhealthy ({})", response.status()),
+        Err(e) => return format!("✖ Failed to reach llama.cpp server at {}: {}", base_url, e),
+    };
+
+    // The slots endpoint can be disabled on the server, so treat it as optional.
+    let slots = client
+        .get(format!("{}/slots", base_url))
+        .send()
+        .ok()
+        .filter(|response| response.status().is_success())
+        .and_then(|response| response.json::<Vec<LlamaCppSlot>>().ok());
+
+    match slots {
+        Some(slots) => {
+            let busy: Vec<String> = slots
+                .iter()
+                .filter(|slot| slot.is_processing || slot.state != 0)
+                .map(|slot| slot.id.to_string())
+                .collect();
+            format!(
+                "{} | {} slots, {} busy{}",
+                health,
+                slots.len(),
+                busy.len(),
+                if busy.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (#{})", busy.join(", #"))
+                }
+            )
+        }
+        None => format!("{} | slot info unavailable", health),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_up_to_a_limit() {
+        let delays: Vec<u64> = (0..7).map(|n| retry_delay(n).as_millis() as u64).collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 8000, 8000]);
+    }
+
+    #[test]
+    fn llama_cpp_streams_are_read_chunk_by_chunk() {
+        let first = llama_cpp_chunk(r#"data: {"content": "Hel", "stop": false}"#);
+        assert_eq!(first.unwrap().unwrap().content, "Hel");
+        let last = llama_cpp_chunk(
+            r#"data: {"content": "", "stop": true, "tokens_predicted": 2, "timings": {"predicted_ms": 40.0}}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(last.stop);
+        assert_eq!(last.tokens_predicted, 2);
+        assert!(llama_cpp_chunk("").is_none());
+        assert!(llama_cpp_chunk("data: {oops").unwrap().is_err());
+    }
+
+    #[test]
+    fn api_errors_show_the_server_message() {
+        let body = r#"{"error": {"message": "model not found"}}"#;
+        assert_eq!(
+            api_error(StatusCode::NOT_FOUND, body),
+            "Server returned 404 Not Found: model not found"
+        );
+        assert_eq!(
+            api_error(StatusCode::BAD_GATEWAY, "<html>"),
+            "Server returned 502 Bad Gateway"
+        );
+    }
+}
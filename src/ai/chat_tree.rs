@@ -0,0 +1,190 @@
+use crate::ai::prompt::ChatMessage;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ChatNode {
+    message: ChatMessage,
+    /// The message this one follows; `None` for the first of a branch.
+    parent: Option<usize>,
+}
+
+/// A conversation whose messages branch: asking a question again, edited or
+/// to regenerate its answer, starts a sibling branch instead of replacing the
+/// old one. Messages are numbered in the order they were added, so a parent
+/// always comes before its children.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ChatTree {
+    nodes: Vec<ChatNode>,
+    /// Last message of the branch on show.
+    current: Option<usize>,
+}
+
+impl ChatTree {
+    /// A conversation without branches.
+    pub fn from_messages(messages: Vec<ChatMessage>) -> Self {
+        let mut tree = Self::default();
+        for message in messages {
+            tree.push(message);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Whether every message follows one added before it and the branch on
+    /// show exists, as a tree read from a file may not.
+    pub fn is_valid(&self) -> bool {
+        let parents_come_first = self
+            .nodes
+            .iter()
+            .enumerate()
+            .all(|(id, node)| node.parent.is_none_or(|parent| parent < id));
+        let current_exists = self
+            .current
+            .is_none_or(|current| current < self.nodes.len());
+        parents_come_first && current_exists
+    }
+
+    pub fn message(&self, id: usize) -> &ChatMessage {
+        &self.nodes[id].message
+    }
+
+    pub fn parent(&self, id: usize) -> Option<usize> {
+        self.nodes[id].parent
+    }
+
+    /// The messages of the branch on show, first to last.
+    pub fn branch(&self) -> Vec<usize> {
+        let mut branch: Vec<usize> =
+            std::iter::successors(self.current, |&id| self.nodes[id].parent).collect();
+        branch.reverse();
+        branch
+    }
+
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        self.branch()
+            .into_iter()
+            .map(|id| self.nodes[id].message.clone())
+            .collect()
+    }
+
+    /// Add `message` after the last one of the branch on show.
+    pub fn push(&mut self, message: ChatMessage) -> usize {
+        self.nodes.push(ChatNode {
+            message,
+            parent: self.current,
+        });
+        self.current = Some(self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// Make the next message follow `parent`, or start the conversation
+    /// anew, branching off whatever came after it.
+    pub fn continue_after(&mut self, parent: Option<usize>) {
+        self.current = parent;
+    }
+
+    /// Messages following the same one as `id`, `id` included, oldest first.
+    pub fn siblings(&self, id: usize) -> Vec<usize> {
+        let parent = self.nodes[id].parent;
+        (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].parent == parent)
+            .collect()
+    }
+
+    /// Show the branch through `id`, down to its most recent message.
+    pub fn switch_to(&mut self, id: usize) {
+        let mut current = id;
+        while let Some(child) = (current + 1..self.nodes.len())
+            .rev()
+            .find(|&i| self.nodes[i].parent == Some(current))
+        {
+            current = child;
+        }
+        self.current = Some(current);
+    }
+
+    pub fn has_replies(&self, id: usize) -> bool {
+        self.nodes[id + 1..]
+            .iter()
+            .any(|node| node.parent == Some(id))
+    }
+
+    /// Delete every message after `id`, in all branches, leaving `id` the
+    /// last one on show.
+    pub fn remove_below(&mut self, id: usize) {
+        let mut removed = vec![false; self.nodes.len()];
+        for i in id + 1..self.nodes.len() {
+            removed[i] = self.nodes[i]
+                .parent
+                .is_some_and(|parent| parent == id || removed[parent]);
+        }
+        // New numbers of the messages kept, which move up past removed ones.
+        let mut renumbered = Vec::with_capacity(self.nodes.len());
+        let mut kept = 0;
+        for &gone in &removed {
+            renumbered.push(kept);
+            if !gone {
+                kept += 1;
+            }
+        }
+        let nodes = std::mem::take(&mut self.nodes);
+        self.nodes = nodes
+            .into_iter()
+            .zip(&removed)
+            .filter(|(_, &gone)| !gone)
+            .map(|(mut node, _)| {
+                node.parent = node.parent.map(|parent| renumbered[parent]);
+                node
+            })
+            .collect();
+        self.current = Some(renumbered[id]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(tree: &ChatTree) -> Vec<String> {
+        tree.messages().into_iter().map(|m| m.content).collect()
+    }
+
+    #[test]
+    fn questions_asked_again_branch_the_conversation() {
+        let mut tree = ChatTree::from_messages(vec![
+            ChatMessage::assistant("Summary".to_string()),
+            ChatMessage::user("Why?".to_string()),
+            ChatMessage::assistant("Because".to_string()),
+        ]);
+        tree.continue_after(Some(0));
+        let edited = tree.push(ChatMessage::user("How?".to_string()));
+        tree.push(ChatMessage::assistant("Like so".to_string()));
+        assert_eq!(contents(&tree), ["Summary", "How?", "Like so"]);
+        assert_eq!(tree.siblings(edited), [1, edited]);
+
+        tree.switch_to(1);
+        assert_eq!(contents(&tree), ["Summary", "Why?", "Because"]);
+
+        tree.remove_below(0);
+        assert_eq!(contents(&tree), ["Summary"]);
+        assert!(!tree.has_replies(0));
+        tree.push(ChatMessage::user("And?".to_string()));
+        assert_eq!(tree.branch(), [0, 1]);
+    }
+
+    #[test]
+    fn trees_from_damaged_files_are_rejected() {
+        let tree = ChatTree::from_messages(vec![ChatMessage::user("Hi".to_string())]);
+        assert!(tree.is_valid());
+        let json = serde_json::to_string(&tree).unwrap();
+        let bad_parent = json.replace(r#""parent":null"#, r#""parent":7"#);
+        let bad_current = json.replace(r#""current":0"#, r#""current":3"#);
+        for json in [bad_parent, bad_current] {
+            let tree: ChatTree = serde_json::from_str(&json).unwrap();
+            assert!(!tree.is_valid());
+        }
+    }
+}
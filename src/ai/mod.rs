@@ -0,0 +1,626 @@
+pub mod backend;
+pub mod chat_tree;
+pub mod prompt;
+pub mod question_history;
+pub mod redact;
+pub mod retrieval;
+pub mod stats;
+
+use crate::ai::chat_tree::ChatTree;
+use crate::ai::prompt::ChatMessage;
+use crate::ai::stats::ResponseMetrics;
+use crate::config::{data_dir, stable_hash};
+use crate::viewer::text::Paragraph;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Sampling settings sent with every chat request.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationParams {
+    pub temperature: f64,
+    /// 1.0 leaves nucleus sampling off.
+    pub top_p: f64,
+    /// 0 leaves top-k sampling off.
+    pub top_k: u32,
+    pub max_tokens: i32,
+    /// Tokens the model reads at once: prompt, history and reply. Documents
+    /// are cut to fit; Ollama is asked for a context this size.
+    pub context_window: u32,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self {
+            temperature: 0.7,
+            top_p: 0.9,
+            top_k: 40,
+            max_tokens: 500,
+            context_window: 4096,
+        }
+    }
+}
+
+impl GenerationParams {
+    pub fn top_p(&self) -> Option<f64> {
+        (self.top_p < 1.0).then_some(self.top_p)
+    }
+
+    pub fn top_k(&self) -> Option<u32> {
+        (self.top_k > 0).then_some(self.top_k)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Ollama,
+    LlamaCpp,
+    OpenAi,
+    Anthropic,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 4] = [
+        Provider::Ollama,
+        Provider::LlamaCpp,
+        Provider::OpenAi,
+        Provider::Anthropic,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Provider::Ollama => "Ollama",
+            Provider::LlamaCpp => "llama.cpp server",
+            Provider::OpenAi => "OpenAI-compatible",
+            Provider::Anthropic => "Anthropic",
+        }
+    }
+}
+
+/// Whether the Ollama model `name` accepts images, judged by the families
+/// known to: llava, moondream, Qwen-VL and the like.
+pub fn is_vision_model(name: &str) -> bool {
+    let name = name.to_lowercase();
+    [
+        "llava",
+        "bakllava",
+        "moondream",
+        "vision",
+        "minicpm-v",
+        "-vl",
+        "vl:",
+        "gemma3",
+        "llama4",
+        "mistral-small3",
+    ]
+    .iter()
+    .any(|family| name.contains(family))
+}
+
+/// Backend-independent result of a single completion.
+pub struct Completion {
+    pub text: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Server-side processing time, when the backend reports it.
+    pub duration: Option<Duration>,
+    /// Time spent generating the answer's tokens, when the backend reports it.
+    pub generation: Option<Duration>,
+}
+
+impl Completion {
+    /// Answer tokens generated per second, as timed by the server.
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        self.generation
+            .filter(|time| !time.is_zero() && self.completion_tokens > 0)
+            .map(|time| self.completion_tokens as f64 / time.as_secs_f64())
+    }
+}
+
+/// Progress of a chat request, sent by its worker thread and applied on the
+/// UI thread. Events carry the id of their request so they reach the right
+/// document, and are dropped once that request has been cancelled.
+pub enum AiEvent {
+    /// The prompt is assembled (retrieval included) and has been sent with
+    /// `context` from the document.
+    Started {
+        request: u64,
+        context: String,
+    },
+    Token {
+        request: u64,
+        text: String,
+    },
+    /// The answer is complete: `model` gave it, `elapsed` after the request
+    /// was sent.
+    Completed {
+        request: u64,
+        completion: Completion,
+        model: String,
+        elapsed: Duration,
+    },
+    Error {
+        request: u64,
+        message: String,
+    },
+}
+
+impl AiEvent {
+    pub fn request(&self) -> u64 {
+        match self {
+            AiEvent::Started { request, .. }
+            | AiEvent::Token { request, .. }
+            | AiEvent::Completed { request, .. }
+            | AiEvent::Error { request, .. } => *request,
+        }
+    }
+}
+
+/// The chat of one document. Only the UI thread touches it; workers report
+/// through `AiEvent`s.
+#[derive(Default)]
+pub struct AiState {
+    /// The request being answered, if any.
+    pub request: Option<u64>,
+    /// Whether the model has been asked yet, as opposed to still gathering context.
+    pub started: bool,
+    pub current_response: String,
+    /// The document context the current request was sent with, kept with its answer.
+    pub current_context: Option<String>,
+    pub chat: ChatTree,
+    pub error: Option<String>,
+    /// What had to be left out of the last request to fit the context window.
+    pub context_warnings: Vec<String>,
+    /// Where the conversation is saved when an answer completes.
+    pub conversation_key: Option<String>,
+}
+
+impl AiState {
+    pub fn is_loading(&self) -> bool {
+        self.request.is_some()
+    }
+
+    /// Start waiting for the answer to `request`, replacing any earlier one.
+    pub fn begin(&mut self, request: u64, conversation_key: Option<String>) {
+        self.request = Some(request);
+        self.started = false;
+        self.current_response.clear();
+        self.error = None;
+        self.context_warnings.clear();
+        self.conversation_key = conversation_key;
+    }
+
+    /// Stop waiting for the current answer; whatever it still sends is ignored.
+    pub fn cancel(&mut self) {
+        self.request = None;
+        self.started = false;
+        self.current_response.clear();
+        self.current_context = None;
+    }
+
+    /// Apply an event of the current request. Events of other requests are ignored.
+    pub fn apply(&mut self, event: AiEvent) {
+        if self.request != Some(event.request()) {
+            return;
+        }
+        match event {
+            AiEvent::Started { context, .. } => {
+                self.started = true;
+                self.current_context = Some(context);
+            }
+            AiEvent::Token { text, .. } => self.current_response.push_str(&text),
+            AiEvent::Completed {
+                completion,
+                model,
+                elapsed,
+                ..
+            } => {
+                let metrics = ResponseMetrics::new(&model, &completion, elapsed);
+                let mut answer = ChatMessage::assistant(completion.text);
+                answer.context = self.current_context.take();
+                answer.metrics = Some(metrics);
+                self.chat.push(answer);
+                if let Some(key) = &self.conversation_key {
+                    save_conversation(key, &self.chat);
+                }
+                self.cancel();
+            }
+            AiEvent::Error { message, .. } => {
+                self.error = Some(message);
+                self.cancel();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ContextMode {
+    DocumentStart,
+    Viewport,
+    /// The chunks most similar to the question, found through embeddings.
+    Relevant,
+}
+
+#[derive(Default)]
+pub struct TranslationState {
+    pub running: bool,
+    pub language: String,
+    /// Paragraphs that were sent, aligned with `translated`.
+    pub paragraphs: Vec<Paragraph>,
+    pub translated: Vec<Option<String>>,
+    pub error: Option<String>,
+}
+
+/// An "Annotate file" request. Its worker leaves the model's comments in
+/// `notes` for the UI thread to add to the document's annotations.
+#[derive(Default)]
+pub struct ReviewState {
+    pub running: bool,
+    pub notes: Vec<Annotation>,
+    /// Set when the file had to be cut to fit the context window.
+    pub warning: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A "Suggest tests" request for `source`, a file or some of its lines.
+/// The worker leaves the test code from the model's reply in `tests`.
+#[derive(Default)]
+pub struct TestSuggestions {
+    pub running: bool,
+    pub source: String,
+    pub tests: Option<String>,
+    /// Set when the file had to be cut to fit the context window.
+    pub warning: Option<String>,
+    pub error: Option<String>,
+    /// Where "Save as" wrote the tests, or why it could not.
+    pub status: Option<String>,
+}
+
+/// An "AI edit" of the lines in `lines`, while its window is open. The
+/// worker leaves the model's rewrite of `original` in `revised` for the
+/// diff preview.
+#[derive(Default)]
+pub struct EditState {
+    pub lines: Option<(usize, usize)>,
+    pub running: bool,
+    pub original: String,
+    pub revised: Option<String>,
+    pub error: Option<String>,
+}
+
+/// The rewritten lines in the model's reply to an "AI edit" request: its
+/// first code block, or the whole reply when it has none. Like `original`,
+/// they end with a line break or not.
+pub fn parse_edit(reply: &str, original: &str) -> String {
+    let body = match reply.split_once("```") {
+        Some((_, fenced)) => {
+            // Past the language tag.
+            let fenced = fenced.split_once('\n').map_or("", |(_, rest)| rest);
+            fenced.split("```").next().unwrap_or_default()
+        }
+        None => reply.trim_start_matches('\n'),
+    };
+    let body = body.trim_end_matches(['\n', ' ']);
+    match original.ends_with('\n') {
+        true => format!("{}\n", body),
+        false => body.to_string(),
+    }
+}
+
+/// An image picked with "Attach image" for the next question.
+pub struct AttachedImage {
+    pub name: String,
+    /// PNG or JPEG bytes, base64-encoded.
+    pub data: String,
+}
+
+/// A request held back until the user confirms sending a document containing PII.
+pub struct PendingRequest {
+    pub prompt: String,
+    pub kind: RequestKind,
+}
+
+pub enum RequestKind {
+    /// A chat turn; `question` is added to the history once the request is sent.
+    /// `warning` tells the user the prompt had to be cut. `lines` are the
+    /// selected ones the prompt shows, if any. `images` go with the prompt,
+    /// base64-encoded, to vision models.
+    Chat {
+        question: Option<String>,
+        warning: Option<String>,
+        lines: Option<(usize, usize)>,
+        images: Vec<String>,
+    },
+    /// A question asked again after the message `parent`, or at the start,
+    /// as a new branch of the conversation: edited, or as it was to
+    /// regenerate its answer. It keeps the lines and images it was sent with.
+    Resend {
+        parent: Option<usize>,
+        question: ChatMessage,
+        warning: Option<String>,
+    },
+    Translation,
+    Review,
+    Edit,
+    Tests,
+    /// A question for both models of the comparison window.
+    Comparison,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AnnotationKind {
+    Highlight,
+    Note,
+    Bookmark,
+    /// A comment from the model, made by "Annotate file".
+    Review,
+}
+
+/// Something the reader attached to a range of lines during a session.
+#[derive(Clone)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    /// Inclusive, 0-based line range.
+    pub first_line: usize,
+    pub last_line: usize,
+    pub note: String,
+}
+
+impl Annotation {
+    pub fn covers(&self, line: usize) -> bool {
+        (self.first_line..=self.last_line).contains(&line)
+    }
+
+    pub fn line_label(&self) -> String {
+        if self.first_line == self.last_line {
+            format!("L{}", self.first_line + 1)
+        } else {
+            format!("L{}–{}", self.first_line + 1, self.last_line + 1)
+        }
+    }
+}
+
+/// One comment in the model's reply to an "Annotate file" request.
+#[derive(Deserialize)]
+struct ReviewNote {
+    line_start: usize,
+    line_end: usize,
+    note: String,
+}
+
+/// Read the JSON array of `{line_start, line_end, note}` comments the model
+/// was asked for, with 1-based lines, as annotations of a file of
+/// `line_count` lines. Text around the array, such as a code fence, is
+/// ignored, and comments on lines past the end are dropped.
+pub fn parse_review(reply: &str, line_count: usize) -> Result<Vec<Annotation>, String> {
+    let json = match (reply.find('['), reply.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => return Err("The model did not reply with a list of comments".to_string()),
+    };
+    let notes: Vec<ReviewNote> = serde_json::from_str(json)
+        .map_err(|e| format!("Could not read the model's comments: {}", e))?;
+    Ok(notes
+        .into_iter()
+        .filter(|note| (1..=line_count).contains(&note.line_start) && !note.note.trim().is_empty())
+        .map(|note| Annotation {
+            kind: AnnotationKind::Review,
+            first_line: note.line_start - 1,
+            last_line: note.line_end.clamp(note.line_start, line_count) - 1,
+            note: note.note.trim().to_string(),
+        })
+        .collect())
+}
+
+/// Build a Markdown review report of `annotations`, quoting the annotated passages.
+pub fn annotations_to_markdown(
+    file_name: &str,
+    content: &str,
+    annotations: &[Annotation],
+) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let quote = |annotation: &Annotation| -> String {
+        lines
+            .iter()
+            .take(annotation.last_line + 1)
+            .skip(annotation.first_line)
+            .map(|line| format!("> {}\n", line))
+            .collect()
+    };
+
+    let mut sorted: Vec<&Annotation> = annotations.iter().collect();
+    sorted.sort_by_key(|a| (a.first_line, a.last_line));
+
+    let mut report = format!("# Review notes: {}\n", file_name);
+    let sections = [
+        (AnnotationKind::Bookmark, "Bookmarks"),
+        (AnnotationKind::Highlight, "Highlights"),
+        (AnnotationKind::Note, "Notes"),
+        (AnnotationKind::Review, "AI review"),
+    ];
+    for (kind, title) in sections {
+        let items: Vec<&&Annotation> = sorted.iter().filter(|a| a.kind == kind).collect();
+        if items.is_empty() {
+            continue;
+        }
+        report.push_str(&format!("\n## {}\n", title));
+        for annotation in items {
+            report.push_str(&format!("\n### {}\n\n", annotation.line_label()));
+            report.push_str(&quote(annotation));
+            if !annotation.note.trim().is_empty() {
+                report.push_str(&format!("\n{}\n", annotation.note.trim()));
+            }
+        }
+    }
+    report
+}
+
+/// A document's AI conversation, saved so it survives reopening the file.
+#[derive(Serialize, Deserialize)]
+pub struct SavedConversation {
+    /// Canonical path of the document, to tell hash collisions apart.
+    pub path: String,
+    /// The branch on show, which is all that older versions saved and read.
+    pub messages: Vec<ChatMessage>,
+    /// The conversation as a tree; earlier versions of edited or
+    /// regenerated messages are kept as sibling branches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<ChatTree>,
+}
+
+/// `conversations/<hash>.json` in the data directory.
+pub fn conversation_path(key: &str) -> Option<PathBuf> {
+    data_dir().map(|dir| {
+        dir.join("conversations")
+            .join(format!("{:016x}.json", stable_hash(key)))
+    })
+}
+
+pub fn load_conversation(key: &str) -> ChatTree {
+    conversation_path(key)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str::<SavedConversation>(&json).ok())
+        .filter(|saved| saved.path == key)
+        .map(|saved| {
+            // A damaged tree falls back to the branch that was on show.
+            saved
+                .tree
+                .filter(ChatTree::is_valid)
+                .unwrap_or_else(|| ChatTree::from_messages(saved.messages))
+        })
+        .unwrap_or_default()
+}
+
+/// Save `chat` for the document `key`; an empty conversation removes the file.
+pub fn save_conversation(key: &str, chat: &ChatTree) {
+    let Some(path) = conversation_path(key) else {
+        return;
+    };
+    if chat.is_empty() {
+        let _ = fs::remove_file(path);
+        return;
+    }
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let saved = SavedConversation {
+        path: key.to_string(),
+        messages: chat.messages(),
+        tree: Some(chat.clone()),
+    };
+    if let Ok(json) = serde_json::to_string_pretty(&saved) {
+        let _ = fs::write(path, json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completed(request: u64, text: &str) -> AiEvent {
+        AiEvent::Completed {
+            request,
+            completion: Completion {
+                text: text.to_string(),
+                prompt_tokens: 12,
+                completion_tokens: 30,
+                duration: None,
+                generation: Some(Duration::from_millis(1500)),
+            },
+            model: "llama3".to_string(),
+            elapsed: Duration::from_secs(2),
+        }
+    }
+
+    #[test]
+    fn streamed_answer_joins_the_history() {
+        let mut state = AiState::default();
+        state.begin(1, None);
+        state.apply(AiEvent::Started {
+            request: 1,
+            context: "Document content:\nHi".to_string(),
+        });
+        state.apply(AiEvent::Token {
+            request: 1,
+            text: "Hel".to_string(),
+        });
+        state.apply(AiEvent::Token {
+            request: 1,
+            text: "lo".to_string(),
+        });
+        assert_eq!(state.current_response, "Hello");
+
+        state.apply(completed(1, "Hello"));
+        assert!(!state.is_loading());
+        assert!(state.current_response.is_empty());
+        let messages = state.chat.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "Hello");
+        assert_eq!(
+            messages[0].context.as_deref(),
+            Some("Document content:\nHi")
+        );
+        assert_eq!(
+            messages[0].metrics.as_ref().unwrap().summary(),
+            "llama3 · 20.0 tokens/s · 2.0 s · 12 → 30 tokens"
+        );
+    }
+
+    #[test]
+    fn events_of_cancelled_requests_are_ignored() {
+        let mut state = AiState::default();
+        state.begin(1, None);
+        state.cancel();
+        state.begin(2, None);
+        state.apply(AiEvent::Token {
+            request: 1,
+            text: "stale".to_string(),
+        });
+        state.apply(completed(1, "stale"));
+        assert!(state.is_loading());
+        assert!(state.current_response.is_empty());
+        assert!(state.chat.is_empty());
+
+        state.apply(AiEvent::Error {
+            request: 2,
+            message: "offline".to_string(),
+        });
+        assert!(!state.is_loading());
+        assert_eq!(state.error.as_deref(), Some("offline"));
+    }
+
+    #[test]
+    fn review_comments_become_annotations() {
+        let reply = "```json\n[\
+            {\"line_start\": 2, \"line_end\": 3, \"note\": \"Off by one.\"},\
+            {\"line_start\": 4, \"line_end\": 1, \"note\": \"Unused.\"},\
+            {\"line_start\": 9, \"line_end\": 9, \"note\": \"Past the end.\"}\
+        ]\n```";
+        let notes = parse_review(reply, 5).unwrap();
+        let lines: Vec<(usize, usize)> =
+            notes.iter().map(|a| (a.first_line, a.last_line)).collect();
+        assert_eq!(lines, [(1, 2), (3, 3)]);
+        assert_eq!(notes[0].note, "Off by one.");
+        assert!(parse_review("Looks fine to me.", 5).is_err());
+    }
+
+    #[test]
+    fn edits_are_read_from_the_code_block() {
+        let reply = "Here you go:\n```rust\nfn f() -> Result<()> {\n    Ok(())\n}\n```\nDone.";
+        assert_eq!(
+            parse_edit(reply, "fn f() {}\n"),
+            "fn f() -> Result<()> {\n    Ok(())\n}\n"
+        );
+        assert_eq!(parse_edit("\nx = 1\n\n", "x = 0"), "x = 1");
+    }
+
+    #[test]
+    fn vision_models_are_recognised() {
+        assert!(is_vision_model("llava:13b"));
+        assert!(is_vision_model("qwen2.5-VL:7b"));
+        assert!(is_vision_model("llama3.2-vision"));
+        assert!(!is_vision_model("llama3.1:8b"));
+    }
+}
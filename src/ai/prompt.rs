@@ -0,0 +1,752 @@
+use crate::ai::stats::ResponseMetrics;
+use crate::ai::GenerationParams;
+use crate::config::config_dir;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    /// For answers, the document context their request was sent with. Kept
+    /// for the user to inspect; never sent back to a model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// Base64 images for Ollama vision models, sent with the question they
+    /// were attached to only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    /// For questions, the prompt sent in their place when it said more, such
+    /// as the excerpt around the selected lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// For questions about selected lines, which ones (0-based), to send
+    /// them again with an edited question.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<(usize, usize)>,
+    /// For answers, which model gave them and how fast.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<ResponseMetrics>,
+}
+
+impl ChatMessage {
+    pub fn system(content: String) -> Self {
+        Self {
+            role: "system".to_string(),
+            content,
+            context: None,
+            images: Vec::new(),
+            prompt: None,
+            lines: None,
+            metrics: None,
+        }
+    }
+
+    pub fn user(content: String) -> Self {
+        Self {
+            role: "user".to_string(),
+            content,
+            context: None,
+            images: Vec::new(),
+            prompt: None,
+            lines: None,
+            metrics: None,
+        }
+    }
+
+    pub fn assistant(content: String) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content,
+            context: None,
+            images: Vec::new(),
+            prompt: None,
+            lines: None,
+            metrics: None,
+        }
+    }
+
+    /// A question shown as `content` and asked with `prompt`, kept together
+    /// so it can be asked again the same way.
+    pub fn question(
+        content: String,
+        prompt: &str,
+        lines: Option<(usize, usize)>,
+        images: Vec<String>,
+    ) -> Self {
+        Self {
+            prompt: Some(prompt.to_string()).filter(|prompt| *prompt != content),
+            lines,
+            images,
+            ..Self::user(content)
+        }
+    }
+
+    /// This question asked again as `content`. Unchanged, it is sent as it
+    /// was; edited, its prompt has to be made anew around the same lines.
+    pub fn reworded(&self, content: String) -> Self {
+        if content == self.content {
+            return self.clone();
+        }
+        Self {
+            lines: self.lines,
+            images: self.images.clone(),
+            ..Self::user(content)
+        }
+    }
+}
+
+/// The turns to send with a new `prompt`: the earlier history plus the prompt,
+/// starting with a user turn and with consecutive turns from the same side
+/// merged, since chat APIs expect the two sides to alternate.
+pub fn conversation(history: &[ChatMessage], prompt: String) -> Vec<ChatMessage> {
+    let mut messages: Vec<ChatMessage> = Vec::new();
+    for mut turn in history.iter().cloned().chain([ChatMessage::user(prompt)]) {
+        turn.context = None;
+        turn.metrics = None;
+        turn.images.clear();
+        turn.prompt = None;
+        turn.lines = None;
+        if messages.is_empty() && turn.role != "user" {
+            // The automatic summary is answered without a visible question.
+            messages.push(ChatMessage::user("Summarize the document.".to_string()));
+        }
+        match messages.last_mut() {
+            Some(last) if last.role == turn.role => {
+                last.content.push_str("\n\n");
+                last.content.push_str(&turn.content);
+            }
+            _ => messages.push(turn),
+        }
+    }
+    messages
+}
+
+/// Flatten a conversation into plain text for completion-only endpoints.
+pub fn transcript(system: Option<&str>, messages: &[ChatMessage]) -> String {
+    let mut text = system.map(|s| format!("{}\n\n", s)).unwrap_or_default();
+    if let [only] = messages {
+        text.push_str(&only.content);
+        return text;
+    }
+    for message in messages {
+        let speaker = if message.role == "user" {
+            "User"
+        } else {
+            "Assistant"
+        };
+        text.push_str(&format!("{}: {}\n", speaker, message.content));
+    }
+    text.push_str("Assistant:");
+    text
+}
+
+/// Cut `text` to at most `max_bytes` without splitting a UTF-8 character.
+pub fn truncate_content(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Rough token count for the BPE vocabularies of most current models:
+/// about four characters per token for words and spaces, two for
+/// punctuation and symbols, which split more and make up much of source
+/// code, and one per CJK character.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (mut wide, mut quarters): (usize, usize) = (0, 0);
+    for c in text.chars() {
+        if is_cjk(c) {
+            wide += 1;
+        } else if c.is_ascii_punctuation() {
+            quarters += 2;
+        } else {
+            quarters += 1;
+        }
+    }
+    wide + quarters.div_ceil(4)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'     // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}'   // CJK extension A
+        | '\u{4e00}'..='\u{9fff}'   // CJK unified ideographs
+        | '\u{ac00}'..='\u{d7af}'   // Hangul syllables
+        | '\u{f900}'..='\u{faff}') // CJK compatibility ideographs
+}
+
+/// Document text is never squeezed below this many tokens, even when the
+/// rest of the request leaves less room.
+pub const MIN_CONTEXT_TOKENS: usize = 256;
+
+/// Tokens left for document text in a request whose other parts are `rest`,
+/// once room for the reply has been set aside.
+pub fn context_budget(params: &GenerationParams, rest: &[&str]) -> usize {
+    let reply = params.max_tokens.max(0) as usize;
+    let used: usize = rest.iter().map(|text| estimate_tokens(text)).sum();
+    (params.context_window as usize)
+        .saturating_sub(reply + used)
+        .max(MIN_CONTEXT_TOKENS)
+}
+
+/// The start of a text that fits a token budget.
+pub struct Fitted<'a> {
+    pub text: &'a str,
+    /// Whole lines included, out of `total_lines`.
+    pub lines: usize,
+    pub total_lines: usize,
+}
+
+impl Fitted<'_> {
+    /// A note for the user when `what` had to be cut.
+    pub fn cut_warning(&self, what: &str) -> Option<String> {
+        (self.lines < self.total_lines).then(|| {
+            format!(
+                "{} cut to fit the context window: {} of {} lines sent",
+                what, self.lines, self.total_lines
+            )
+        })
+    }
+}
+
+/// As many whole lines from the start of `text` as fit in `max_tokens`. A
+/// first line too long on its own is cut at a character boundary.
+pub fn fit_lines(text: &str, max_tokens: usize) -> Fitted<'_> {
+    let total_lines = text.lines().count();
+    let (mut end, mut lines, mut tokens) = (0, 0, 0);
+    for line in text.split_inclusive('\n') {
+        tokens += estimate_tokens(line);
+        if tokens > max_tokens {
+            break;
+        }
+        end += line.len();
+        lines += 1;
+    }
+    if lines == 0 && !text.is_empty() {
+        let end = text
+            .char_indices()
+            .nth(max_tokens * 4)
+            .map_or(text.len(), |(i, _)| i);
+        return Fitted {
+            text: &text[..end],
+            lines: 0,
+            total_lines,
+        };
+    }
+    Fitted {
+        text: &text[..end],
+        lines,
+        total_lines,
+    }
+}
+
+pub const SUMMARY_PROMPT: &str =
+    "Provide a brief summary of this document. Describe its purpose and main contents.";
+
+pub const DESCRIBE_IMAGE_PROMPT: &str =
+    "Describe this image: what it shows, any text in it, and anything notable.";
+
+/// Instructions opening a chat system prompt: the template's, then the user's
+/// own or the built-in ones.
+pub fn chat_preamble(
+    file_name: &str,
+    custom_prompt: &str,
+    template_prompt: Option<String>,
+) -> String {
+    let instructions = if custom_prompt.trim().is_empty() {
+        format!(
+            "You are helping a user understand the document {}.",
+            file_name
+        )
+    } else {
+        custom_prompt.trim().to_string()
+    };
+    format!(
+        "{}{}\n\n",
+        template_prompt
+            .map(|prompt| format!("{}\n\n", prompt))
+            .unwrap_or_default(),
+        instructions
+    )
+}
+
+/// The entry of `prompts`, key and prompt, for a document with `extension`,
+/// or else for its `language`. Keys are matched ignoring case and a leading dot, so `.MD`,
+/// `md` and `Markdown` all work.
+pub fn file_type_prompt<'a>(
+    prompts: &'a BTreeMap<String, String>,
+    extension: &str,
+    language: &str,
+) -> Option<(&'a str, &'a str)> {
+    let find = |name: &str| {
+        prompts
+            .iter()
+            .find(|(key, prompt)| {
+                !name.is_empty()
+                    && key
+                        .trim()
+                        .trim_start_matches('.')
+                        .eq_ignore_ascii_case(name)
+                    && !prompt.trim().is_empty()
+            })
+            .map(|(key, prompt)| (key.as_str(), prompt.as_str()))
+    };
+    find(extension).or_else(|| find(language))
+}
+
+/// The start of the document, as many lines as fit in `max_tokens`, and a
+/// warning when that is not all of it.
+pub fn document_context(content: &str, max_tokens: usize) -> (String, Option<String>) {
+    let fitted = fit_lines(content, max_tokens);
+    (
+        format!("Document content:\n{}", fitted.text),
+        fitted.cut_warning("Document"),
+    )
+}
+
+/// Another open file sent along with the document, labelled with its name
+/// and cut to `max_tokens`.
+pub fn file_context(name: &str, content: &str, max_tokens: usize) -> (String, Option<String>) {
+    let fitted = fit_lines(content, max_tokens);
+    (
+        format!("\n\nContent of {}:\n{}", name, fitted.text),
+        fitted.cut_warning(name),
+    )
+}
+
+/// Split `budget` tokens between texts of `sizes` tokens: each gets an equal
+/// share, and what the shorter ones leave unused goes to the longer ones.
+pub fn share_budget(sizes: &[usize], budget: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| sizes[i]);
+    let mut shares = vec![0; sizes.len()];
+    let mut left = budget;
+    for (done, &i) in order.iter().enumerate() {
+        let share = (left / (sizes.len() - done)).min(sizes[i]);
+        shares[i] = share;
+        left -= share;
+    }
+    shares
+}
+
+/// A multi-step workflow: a system prompt plus a sequence of quick-action questions.
+/// Built-in templates can be extended with `templates.json` in the config directory.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConversationTemplate {
+    pub name: String,
+    pub system_prompt: String,
+    /// File extensions the template applies to; empty means any document.
+    #[serde(default)]
+    pub file_types: Vec<String>,
+    pub questions: Vec<String>,
+}
+
+pub const CODE_EXTENSIONS: [&str; 16] = [
+    "rs", "py", "js", "ts", "go", "c", "h", "cpp", "hpp", "java", "kt", "cs", "php", "rb", "sh",
+    "swift",
+];
+
+impl ConversationTemplate {
+    pub fn applies_to(&self, extension: &str) -> bool {
+        self.file_types.is_empty()
+            || self
+                .file_types
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(extension))
+    }
+}
+
+pub fn builtin_templates() -> Vec<ConversationTemplate> {
+    let code_types: Vec<String> = CODE_EXTENSIONS.iter().map(|e| e.to_string()).collect();
+
+    vec![
+        ConversationTemplate {
+            name: "Security audit".to_string(),
+            system_prompt: "You are a meticulous application security reviewer. \
+                Point to specific lines, rate each finding by severity, and avoid speculation."
+                .to_string(),
+            file_types: code_types.clone(),
+            questions: vec![
+                "What untrusted inputs does this code accept, and where are they validated?"
+                    .to_string(),
+                "Are there injection, path traversal, or deserialization risks?".to_string(),
+                "How are secrets, credentials, and errors handled?".to_string(),
+                "List the findings ordered by severity with suggested fixes.".to_string(),
+            ],
+        },
+        ConversationTemplate {
+            name: "Code walkthrough".to_string(),
+            system_prompt: "You are a senior engineer onboarding a new teammate. \
+                Explain clearly and reference function names."
+                .to_string(),
+            file_types: code_types,
+            questions: vec![
+                "What is the overall purpose of this file?".to_string(),
+                "What are the main types and functions, and how do they interact?".to_string(),
+                "Walk through the main control flow step by step.".to_string(),
+                "What would be tricky to change here, and why?".to_string(),
+            ],
+        },
+        ConversationTemplate {
+            name: "Requirements extraction".to_string(),
+            system_prompt: "You are a business analyst extracting requirements. \
+                Quote the source text for each requirement you list."
+                .to_string(),
+            file_types: ["md", "txt", "rst", "adoc", "org"]
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            questions: vec![
+                "List every functional requirement stated in this document.".to_string(),
+                "List the non-functional requirements (performance, security, compliance)."
+                    .to_string(),
+                "Which requirements are ambiguous or contradictory?".to_string(),
+                "What open questions should be raised with the author?".to_string(),
+            ],
+        },
+    ]
+}
+
+pub fn load_templates() -> Vec<ConversationTemplate> {
+    let mut templates = builtin_templates();
+    let user_templates: Vec<ConversationTemplate> = config_dir()
+        .and_then(|dir| fs::read_to_string(dir.join("templates.json")).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    templates.extend(user_templates);
+    templates
+}
+
+/// How unit tests are written for one language, for "Suggest tests".
+#[derive(Clone, Copy)]
+pub struct TestTemplate {
+    /// The framework and conventions the model is asked to follow.
+    pub framework: &'static str,
+    /// Syntax token the tests are highlighted with.
+    pub language: &'static str,
+    /// Name of the test file for a source file, where `{stem}` and `{Stem}`
+    /// stand for the source file's name without its extension.
+    pub file_name: &'static str,
+}
+
+/// The test template for source files with `extension`, if it is a
+/// language of `CODE_EXTENSIONS`.
+pub fn test_template(extension: &str) -> Option<TestTemplate> {
+    let (framework, language, file_name) = match extension {
+        "rs" => (
+            "a `#[cfg(test)] mod tests` module with `use super::*;` and `#[test]` functions",
+            "rs",
+            "{stem}_tests.rs",
+        ),
+        "py" => (
+            "pytest: plain `test_` functions using `assert`, with fixtures for repeated setup",
+            "py",
+            "test_{stem}.py",
+        ),
+        "js" => (
+            "Jest: `describe` blocks of `test` cases with `expect` assertions",
+            "js",
+            "{stem}.test.js",
+        ),
+        "ts" => (
+            "Jest in TypeScript: `describe` blocks of `test` cases with `expect` assertions",
+            "ts",
+            "{stem}.test.ts",
+        ),
+        "go" => (
+            "the standard `testing` package, with table-driven `TestXxx(t *testing.T)` functions",
+            "go",
+            "{stem}_test.go",
+        ),
+        "c" | "h" | "cpp" | "hpp" => (
+            "GoogleTest `TEST` cases with `EXPECT_` assertions",
+            "cpp",
+            "{stem}_test.cpp",
+        ),
+        "java" => (
+            "JUnit 5: a test class of `@Test` methods using `Assertions`",
+            "java",
+            "{Stem}Test.java",
+        ),
+        "kt" => (
+            "JUnit 5 with `kotlin.test` assertions",
+            "kt",
+            "{Stem}Test.kt",
+        ),
+        "cs" => (
+            "xUnit: a test class of `[Fact]` and `[Theory]` methods",
+            "cs",
+            "{Stem}Tests.cs",
+        ),
+        "php" => ("PHPUnit: a `TestCase` class", "php", "{Stem}Test.php"),
+        "rb" => (
+            "RSpec: `describe` blocks of `it` examples",
+            "rb",
+            "{stem}_spec.rb",
+        ),
+        "sh" => ("Bats: `@test` cases", "sh", "{stem}.bats"),
+        "swift" => (
+            "XCTest: an `XCTestCase` subclass of `test` methods",
+            "swift",
+            "{Stem}Tests.swift",
+        ),
+        _ => return None,
+    };
+    Some(TestTemplate {
+        framework,
+        language,
+        file_name,
+    })
+}
+
+impl TestTemplate {
+    /// The test file's name for the source file `file_name`.
+    pub fn test_file_name(&self, file_name: &str) -> String {
+        let stem = file_name
+            .rsplit_once('.')
+            .map_or(file_name, |(stem, _)| stem);
+        let mut chars = stem.chars();
+        let capitalized: String = chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default();
+        self.file_name
+            .replace("{stem}", stem)
+            .replace("{Stem}", &capitalized)
+    }
+}
+
+/// Lines shown either side of a selection sent to the AI.
+pub const SELECTION_CONTEXT_LINES: usize = 5;
+
+/// Pull `[[n]]`-numbered sections out of a model reply, 1-based as in the prompt.
+pub fn parse_numbered_sections(reply: &str, count: usize) -> Vec<Option<String>> {
+    let marker = Regex::new(r"\[\[(\d+)\]\]").unwrap();
+    let mut sections = vec![None; count];
+
+    let markers: Vec<(usize, usize, usize)> = marker
+        .captures_iter(reply)
+        .filter_map(|caps| {
+            let m = caps.get(0)?;
+            let n = caps[1].parse::<usize>().ok()?;
+            Some((n, m.start(), m.end()))
+        })
+        .collect();
+
+    for (i, &(n, _, end)) in markers.iter().enumerate() {
+        let next_start = markers
+            .get(i + 1)
+            .map_or(reply.len(), |&(_, start, _)| start);
+        if (1..=count).contains(&n) {
+            sections[n - 1] = Some(reply[end..next_start].trim().to_string());
+        }
+    }
+
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turns(messages: &[ChatMessage]) -> Vec<(&str, &str)> {
+        messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn tokens_are_estimated_for_prose_code_and_cjk() {
+        assert_eq!(estimate_tokens("plain words"), 3);
+        assert_eq!(estimate_tokens("日本語 text"), 5);
+        assert_eq!(estimate_tokens("fn x()"), 2);
+        assert_eq!(estimate_tokens(""), 0);
+    }
+
+    #[test]
+    fn file_type_prompts_match_extension_then_language() {
+        let prompts = BTreeMap::from([
+            (".RS".to_string(), "Review strictly.".to_string()),
+            ("Markdown".to_string(), "Summarize legal text.".to_string()),
+            ("txt".to_string(), " ".to_string()),
+        ]);
+        assert_eq!(
+            file_type_prompt(&prompts, "rs", "Rust"),
+            Some((".RS", "Review strictly."))
+        );
+        assert_eq!(
+            file_type_prompt(&prompts, "md", "Markdown"),
+            Some(("Markdown", "Summarize legal text."))
+        );
+        assert_eq!(file_type_prompt(&prompts, "txt", "Plain Text"), None);
+        assert_eq!(file_type_prompt(&prompts, "", ""), None);
+    }
+
+    #[test]
+    fn test_files_are_named_by_convention() {
+        let python = test_template("py").unwrap();
+        assert_eq!(python.test_file_name("parser.py"), "test_parser.py");
+        let java = test_template("java").unwrap();
+        assert_eq!(java.test_file_name("orderBook.java"), "OrderBookTest.java");
+        assert!(test_template("md").is_none());
+    }
+
+    #[test]
+    fn conversation_starts_with_a_user_turn() {
+        let mut summary = ChatMessage::assistant("A summary.".to_string());
+        summary.context = Some("Document content:\n...".to_string());
+        let messages = conversation(&[summary], "And then?".to_string());
+        assert!(messages.iter().all(|message| message.context.is_none()));
+        assert_eq!(
+            turns(&messages),
+            [
+                ("user", "Summarize the document."),
+                ("assistant", "A summary."),
+                ("user", "And then?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversation_merges_consecutive_turns() {
+        let history = [
+            ChatMessage::user("First?".to_string()),
+            ChatMessage::user("Second?".to_string()),
+            ChatMessage::assistant("Both.".to_string()),
+        ];
+        let messages = conversation(&history, "Thanks".to_string());
+        assert_eq!(
+            turns(&messages),
+            [
+                ("user", "First?\n\nSecond?"),
+                ("assistant", "Both."),
+                ("user", "Thanks"),
+            ]
+        );
+    }
+
+    #[test]
+    fn selection_questions_are_resent_with_their_lines() {
+        let prompt = "Excerpt from main.rs:\n> 3 | let x = 1;\n\nWhy? Refer to line numbers.";
+        let images = vec!["aW1n".to_string()];
+        let question =
+            ChatMessage::question("Lines 3–3: Why?".to_string(), prompt, Some((2, 2)), images);
+
+        let regenerated = question.reworded(question.content.clone());
+        assert_eq!(regenerated.prompt.as_deref(), Some(prompt));
+        assert_eq!(regenerated.lines, Some((2, 2)));
+        assert_eq!(regenerated.images, ["aW1n"]);
+
+        let edited = question.reworded("Lines 3–3: Why not?".to_string());
+        assert_eq!(edited.prompt, None);
+        assert_eq!(edited.lines, Some((2, 2)));
+        assert_eq!(edited.images, ["aW1n"]);
+
+        // What was sent for earlier questions stays out of the history.
+        let messages = conversation(&[question], "And?".to_string());
+        assert_eq!(turns(&messages), [("user", "Lines 3–3: Why?\n\nAnd?")]);
+        assert!(messages[0].images.is_empty() && messages[0].prompt.is_none());
+
+        let plain = ChatMessage::question("Hi".to_string(), "Hi", None, Vec::new());
+        assert_eq!(plain.prompt, None);
+    }
+
+    #[test]
+    fn transcript_of_a_single_prompt_is_the_prompt() {
+        let messages = [ChatMessage::user("Hi".to_string())];
+        assert_eq!(transcript(Some("Be brief."), &messages), "Be brief.\n\nHi");
+        let messages = conversation(&[ChatMessage::assistant("Hello".to_string())], "Hi".into());
+        assert_eq!(
+            transcript(None, &messages),
+            "User: Summarize the document.\nAssistant: Hello\nUser: Hi\nAssistant:"
+        );
+    }
+
+    #[test]
+    fn truncation_keeps_whole_characters() {
+        assert_eq!(truncate_content("short", 100), "short");
+        assert_eq!(truncate_content("abcdef", 3), "abc");
+        // 'é' is two bytes; cutting through it drops the whole character.
+        assert_eq!(truncate_content("café", 4), "caf");
+        assert_eq!(truncate_content("café", 5), "café");
+    }
+
+    #[test]
+    fn short_files_leave_their_share_to_long_ones() {
+        assert_eq!(share_budget(&[100, 5000, 50], 3000), vec![100, 2850, 50]);
+        assert_eq!(share_budget(&[5000, 5000], 3000), vec![1500, 1500]);
+        assert_eq!(share_budget(&[], 3000), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn document_context_holds_the_start_of_long_documents() {
+        // Each line is 40 characters with its newline, about 10 tokens.
+        let content = format!("{}\n", "x".repeat(39)).repeat(100);
+        let (context, warning) = document_context(&content, 250);
+        assert!(context.starts_with("Document content:\n"));
+        assert_eq!(context.len(), "Document content:\n".len() + 25 * 40);
+        assert_eq!(
+            warning.as_deref(),
+            Some("Document cut to fit the context window: 25 of 100 lines sent")
+        );
+
+        let (_, warning) = document_context("short\ndocument", 250);
+        assert_eq!(warning, None);
+    }
+
+    #[test]
+    fn fitting_cuts_an_overlong_first_line_between_characters() {
+        let line = "é".repeat(100);
+        let fitted = fit_lines(&line, 10);
+        assert_eq!(fitted.text, "é".repeat(40));
+        assert_eq!((fitted.lines, fitted.total_lines), (0, 1));
+    }
+
+    #[test]
+    fn budget_leaves_room_for_the_reply() {
+        let params = GenerationParams {
+            context_window: 4096,
+            max_tokens: 500,
+            ..GenerationParams::default()
+        };
+        assert_eq!(context_budget(&params, &["abcd".repeat(96).as_str()]), 3500);
+        let tiny = GenerationParams {
+            context_window: 512,
+            ..params
+        };
+        assert_eq!(context_budget(&tiny, &[]), MIN_CONTEXT_TOKENS);
+    }
+
+    #[test]
+    fn preamble_uses_the_custom_and_template_prompts() {
+        assert_eq!(
+            chat_preamble("notes.md", "  ", None),
+            "You are helping a user understand the document notes.md.\n\n"
+        );
+        assert_eq!(
+            chat_preamble(
+                "notes.md",
+                "Answer in French.\n",
+                Some("Review it.".to_string())
+            ),
+            "Review it.\n\nAnswer in French.\n\n"
+        );
+    }
+
+    #[test]
+    fn numbered_sections_are_matched_by_number() {
+        let reply = "[[2]]\nZwei\n\n[[1]] Eins\n[[7]] ignored";
+        assert_eq!(
+            parse_numbered_sections(reply, 3),
+            [Some("Eins".to_string()), Some("Zwei".to_string()), None]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+/// Questions sent from the AI panel, recalled with Up and Down in the
+/// question box the way a shell recalls commands.
+#[derive(Default)]
+pub struct QuestionHistory {
+    /// Oldest first, without blank questions or the same one twice running.
+    questions: Vec<String>,
+    /// The question in the box while stepping through, if any.
+    position: Option<usize>,
+    /// What was typed before stepping back, put back past the newest.
+    draft: String,
+}
+
+impl QuestionHistory {
+    /// Questions kept; older ones are dropped.
+    const MAX_QUESTIONS: usize = 100;
+
+    /// Add a question that was sent, and stop stepping through.
+    pub fn remember(&mut self, question: &str) {
+        self.position = None;
+        if question.trim().is_empty() || self.questions.last().map(String::as_str) == Some(question)
+        {
+            return;
+        }
+        self.questions.push(question.to_string());
+        if self.questions.len() > Self::MAX_QUESTIONS {
+            self.questions.remove(0);
+        }
+    }
+
+    /// The text for the box after stepping back a question if `older`, else
+    /// forward, from `typed`; `None` when there is nowhere to go.
+    pub fn recall(&mut self, typed: &str, older: bool) -> Option<String> {
+        let newest = self.questions.len().checked_sub(1)?;
+        let position = match (self.position, older) {
+            (None, true) => {
+                self.draft = typed.to_string();
+                newest
+            }
+            (None, false) => return None,
+            (Some(i), true) => i.saturating_sub(1),
+            (Some(i), false) if i >= newest => {
+                self.position = None;
+                return Some(std::mem::take(&mut self.draft));
+            }
+            (Some(i), false) => i + 1,
+        };
+        self.position = Some(position);
+        Some(self.questions[position].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn questions_are_recalled_like_shell_commands() {
+        let mut history = QuestionHistory::default();
+        assert_eq!(history.recall("", true), None);
+        for question in ["one", "two", "two", " ", "three"] {
+            history.remember(question);
+        }
+
+        assert_eq!(history.recall("draft", true).as_deref(), Some("three"));
+        assert_eq!(history.recall("three", true).as_deref(), Some("two"));
+        assert_eq!(history.recall("two", true).as_deref(), Some("one"));
+        // The oldest stays put.
+        assert_eq!(history.recall("one", true).as_deref(), Some("one"));
+        assert_eq!(history.recall("one", false).as_deref(), Some("two"));
+        assert_eq!(history.recall("two", false).as_deref(), Some("three"));
+        // Past the newest, what was being typed comes back.
+        assert_eq!(history.recall("three", false).as_deref(), Some("draft"));
+        assert_eq!(history.recall("draft", false), None);
+
+        for i in 0..150 {
+            history.remember(&i.to_string());
+        }
+        assert_eq!(history.questions.len(), QuestionHistory::MAX_QUESTIONS);
+        assert_eq!(history.questions[0], "50");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::ai::backend::{http_client, ollama_endpoint, ollama_request_error};
+use crate::viewer::text::line_starts;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Serialize)]
+pub struct OllamaEmbeddingRequest<'a> {
+    pub model: &'a str,
+    pub prompt: &'a str,
+}
+
+#[derive(Deserialize)]
+pub struct OllamaEmbeddingResponse {
+    pub embedding: Vec<f32>,
+}
+
+pub fn embed_text(
+    client: &reqwest::blocking::Client,
+    base_url: &str,
+    model: &str,
+    text: &str,
+) -> Result<Vec<f32>, String> {
+    let response = client
+        .post(ollama_endpoint(base_url, "/api/embeddings"))
+        .json(&OllamaEmbeddingRequest {
+            model,
+            prompt: text,
+        })
+        .send()
+        .map_err(|e| ollama_request_error(base_url, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Embedding with {} failed ({}); try `ollama pull {}`",
+            model,
+            response.status(),
+            model
+        ));
+    }
+
+    let embedding = response
+        .json::<OllamaEmbeddingResponse>()
+        .map_err(|e| format!("Invalid embedding response: {}", e))?
+        .embedding;
+    if embedding.is_empty() {
+        return Err(format!("{} returned an empty embedding", model));
+    }
+    Ok(embedding)
+}
+
+pub const CHUNK_BYTES: usize = 1500;
+pub const CHUNK_OVERLAP: usize = 300;
+pub const RETRIEVED_CHUNKS: usize = 4;
+
+/// A piece of the document (0-based, inclusive lines) and its embedding.
+pub struct Chunk {
+    pub first_line: usize,
+    pub last_line: usize,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Split `text` into chunks of about `size` bytes broken at line ends, each
+/// repeating roughly the last `overlap` bytes of the one before.
+pub fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<Chunk> {
+    let starts = line_starts(text);
+    let end_of = |line: usize| starts.get(line).copied().unwrap_or(text.len());
+
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    while first < starts.len() {
+        let mut last = first;
+        while last < starts.len() && end_of(last) - starts[first] < size {
+            last += 1;
+        }
+        let chunk = text[starts[first]..end_of(last)].trim_end();
+        if !chunk.trim().is_empty() {
+            chunks.push(Chunk {
+                first_line: first,
+                last_line: first + chunk.lines().count() - 1,
+                text: chunk.to_string(),
+                embedding: Vec::new(),
+            });
+        }
+        if last >= starts.len() {
+            break;
+        }
+
+        let mut next = last;
+        while next > first + 1 && end_of(last) - starts[next - 1] <= overlap {
+            next -= 1;
+        }
+        first = next;
+    }
+    chunks
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm(a) * norm(b)).max(f32::EPSILON)
+}
+
+/// Embeddings of the open document, built on a worker thread.
+#[derive(Default)]
+pub struct DocumentIndex {
+    /// Length of the indexed text and the embedding model, to notice when
+    /// the index is out of date.
+    pub source_len: usize,
+    pub model: String,
+    pub chunks: Vec<Chunk>,
+    /// Number of chunks embedded so far.
+    pub embedded: usize,
+    pub building: bool,
+    pub error: Option<String>,
+}
+
+impl DocumentIndex {
+    pub fn ready(&self) -> bool {
+        !self.building && self.error.is_none() && !self.chunks.is_empty()
+    }
+}
+
+/// Embed every chunk of `content` into `index`. Stops early once the document
+/// holding the index has been closed.
+pub fn build_index(
+    content: String,
+    ollama_url: String,
+    model: String,
+    index: Arc<Mutex<DocumentIndex>>,
+) {
+    let chunks = chunk_text(&content, CHUNK_BYTES, CHUNK_OVERLAP);
+    let mut embeddings = Vec::with_capacity(chunks.len());
+    {
+        let mut index = index.lock().unwrap();
+        index.chunks = chunks;
+        index.embedded = 0;
+    }
+
+    let client = match http_client(Duration::from_secs(60)) {
+        Ok(client) => client,
+        Err(e) => {
+            let mut index = index.lock().unwrap();
+            index.error = Some(e);
+            index.building = false;
+            return;
+        }
+    };
+
+    let total = index.lock().unwrap().chunks.len();
+    for i in 0..total {
+        if Arc::strong_count(&index) == 1 {
+            return;
+        }
+        let text = index.lock().unwrap().chunks[i].text.clone();
+        match embed_text(&client, &ollama_url, &model, &text) {
+            Ok(embedding) => embeddings.push(embedding),
+            Err(e) => {
+                let mut index = index.lock().unwrap();
+                index.error = Some(e);
+                index.building = false;
+                return;
+            }
+        }
+        index.lock().unwrap().embedded = i + 1;
+    }
+
+    let mut index = index.lock().unwrap();
+    for (chunk, embedding) in index.chunks.iter_mut().zip(embeddings) {
+        chunk.embedding = embedding;
+    }
+    index.building = false;
+}
+
+/// What a chat worker needs to pick the document chunks relevant to a question.
+pub struct Retrieval {
+    pub index: Arc<Mutex<DocumentIndex>>,
+    pub ollama_url: String,
+    pub model: String,
+    /// Instructions that go before the retrieved excerpts.
+    pub preamble: String,
+}
+
+impl Retrieval {
+    /// A system prompt holding the chunks closest to `question`, or `None`
+    /// when the index is not ready or the question cannot be embedded.
+    pub fn context(&self, question: &str) -> Option<String> {
+        if !self.index.lock().unwrap().ready() {
+            return None;
+        }
+        let client = http_client(Duration::from_secs(60)).ok()?;
+        let query = embed_text(&client, &self.ollama_url, &self.model, question).ok()?;
+
+        let index = self.index.lock().unwrap();
+        let mut ranked: Vec<(f32, &Chunk)> = index
+            .chunks
+            .iter()
+            .map(|chunk| (cosine_similarity(&query, &chunk.embedding), chunk))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut best: Vec<&Chunk> = ranked
+            .into_iter()
+            .take(RETRIEVED_CHUNKS)
+            .map(|(_, chunk)| chunk)
+            .collect();
+        best.sort_by_key(|chunk| chunk.first_line);
+
+        let mut context = format!("{}Relevant excerpts from the document:\n", self.preamble);
+        for chunk in best {
+            context.push_str(&format!(
+                "\nLines {}–{}:\n```\n{}\n```\n",
+                chunk.first_line + 1,
+                chunk.last_line + 1,
+                chunk.text
+            ));
+        }
+        Some(context)
+    }
+}
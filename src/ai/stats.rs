@@ -0,0 +1,385 @@
+use crate::ai::backend::{
+    http_client, ollama_endpoint, ollama_request_error, OllamaOptions, OllamaRequest,
+    OllamaResponse,
+};
+use crate::ai::Completion;
+use crate::config::data_dir;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ModelStats {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_latency_ms: u64,
+    /// Answer tokens of the requests whose generation the server timed, and
+    /// that time, for the token rate.
+    #[serde(default)]
+    pub timed_tokens: u64,
+    #[serde(default)]
+    pub generation_ms: u64,
+}
+
+impl ModelStats {
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        (self.generation_ms > 0)
+            .then(|| self.timed_tokens as f64 * 1000.0 / self.generation_ms as f64)
+    }
+
+    pub fn average_latency_ms(&self) -> u64 {
+        self.total_latency_ms
+            .checked_div(self.requests)
+            .unwrap_or(0)
+    }
+}
+
+/// Per-model usage, both for this session and across all sessions.
+/// The historical half is kept in `stats.json` under the data directory.
+#[derive(Default)]
+pub struct UsageStats {
+    pub session: BTreeMap<String, ModelStats>,
+    pub historical: BTreeMap<String, ModelStats>,
+}
+
+impl UsageStats {
+    pub fn load() -> Self {
+        let historical = stats_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        Self {
+            session: BTreeMap::new(),
+            historical,
+        }
+    }
+
+    pub fn record(&mut self, model: &str, completion: &Completion, elapsed: Duration) {
+        // Prefer the server's own timing; fall back to wall-clock if it was not reported.
+        let latency_ms = completion.duration.unwrap_or(elapsed).as_millis() as u64;
+
+        for stats in [&mut self.session, &mut self.historical] {
+            let entry = stats.entry(model.to_string()).or_default();
+            entry.requests += 1;
+            entry.prompt_tokens += completion.prompt_tokens;
+            entry.completion_tokens += completion.completion_tokens;
+            entry.total_latency_ms += latency_ms;
+            if let Some(generation) = completion.generation {
+                entry.timed_tokens += completion.completion_tokens;
+                entry.generation_ms += generation.as_millis() as u64;
+            }
+        }
+
+        self.save();
+    }
+
+    pub fn save(&self) {
+        let Some(path) = stats_path() else { return };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(json) = serde_json::to_string_pretty(&self.historical) {
+            let _ = fs::write(path, json);
+        }
+    }
+}
+
+pub fn stats_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("stats.json"))
+}
+
+/// Fixed prompts used by the model benchmark, so results are comparable across runs.
+pub const BENCHMARK_PROMPTS: [&str; 3] = [
+    "Explain what a hash map is in two sentences.",
+    "Write a Python function that reverses a string.",
+    "Summarize the plot of Romeo and Juliet in one paragraph.",
+];
+
+#[derive(Clone)]
+pub struct BenchmarkResult {
+    pub model: String,
+    pub time_to_first_token: Duration,
+    pub tokens_per_second: f64,
+    pub total_time: Duration,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct BenchmarkRun {
+    pub running: bool,
+    pub status: String,
+    pub results: Vec<BenchmarkResult>,
+}
+
+/// How an answer in the chat was produced, shown under it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResponseMetrics {
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// As timed by the server, for backends that report it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f64>,
+    /// From sending the request to the last token.
+    pub latency_ms: u64,
+}
+
+impl ResponseMetrics {
+    pub fn new(model: &str, completion: &Completion, elapsed: Duration) -> Self {
+        Self {
+            model: model.to_string(),
+            prompt_tokens: completion.prompt_tokens,
+            completion_tokens: completion.completion_tokens,
+            tokens_per_second: completion.tokens_per_second(),
+            latency_ms: elapsed.as_millis() as u64,
+        }
+    }
+
+    /// For example "llama3 · 41.2 tokens/s · 3.1 s · 812 → 240 tokens".
+    pub fn summary(&self) -> String {
+        let mut parts = vec![self.model.clone()];
+        parts.extend(
+            self.tokens_per_second
+                .map(|rate| format!("{:.1} tokens/s", rate)),
+        );
+        parts.push(format!("{:.1} s", self.latency_ms as f64 / 1000.0));
+        if self.prompt_tokens + self.completion_tokens > 0 {
+            parts.push(format!(
+                "{} → {} tokens",
+                self.prompt_tokens, self.completion_tokens
+            ));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// One column of a side-by-side comparison: a model's answer as it
+/// streams in, and how fast it came.
+#[derive(Clone, Default)]
+pub struct ComparedAnswer {
+    pub model: String,
+    pub running: bool,
+    pub text: String,
+    pub first_token: Option<Duration>,
+    pub total_time: Option<Duration>,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub error: Option<String>,
+}
+
+impl ComparedAnswer {
+    /// Append a streamed token, noting when the first one arrived.
+    pub fn receive(&mut self, token: &str, elapsed: Duration) {
+        self.first_token.get_or_insert(elapsed);
+        self.text.push_str(token);
+    }
+
+    /// Record how the request ended and stop the spinner.
+    pub fn finish(&mut self, result: Result<Completion, String>, elapsed: Duration) {
+        self.total_time = Some(elapsed);
+        match result {
+            Ok(completion) => {
+                self.prompt_tokens = completion.prompt_tokens;
+                self.completion_tokens = completion.completion_tokens;
+                self.text = completion.text;
+            }
+            Err(e) => self.error = Some(e),
+        }
+        self.running = false;
+    }
+
+    /// Answer tokens per second, from the first one to the last.
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        let generating = self.total_time?.checked_sub(self.first_token?)?;
+        (self.completion_tokens > 0)
+            .then(|| self.completion_tokens as f64 / generating.as_secs_f64().max(f64::EPSILON))
+    }
+}
+
+/// Stream one completion and return (time to first token, tokens/sec, total time).
+pub fn benchmark_prompt(
+    base_url: &str,
+    model: &str,
+    prompt: &str,
+) -> Result<(Duration, f64, Duration), String> {
+    let client = http_client(Duration::from_secs(600))?;
+
+    let request = OllamaRequest {
+        model: model.to_string(),
+        prompt: prompt.to_string(),
+        stream: true,
+        options: OllamaOptions {
+            temperature: 0.0,
+            top_p: None,
+            top_k: None,
+            num_predict: 200,
+            num_ctx: None,
+        },
+    };
+
+    let started = Instant::now();
+    let response = client
+        .post(ollama_endpoint(base_url, "/api/generate"))
+        .json(&request)
+        .send()
+        .map_err(|e| ollama_request_error(base_url, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Ollama returned {}", response.status()));
+    }
+
+    let mut first_token = None;
+    let mut streamed_tokens = 0u64;
+    for line in BufReader::new(response).lines() {
+        let line = line.map_err(|e| format!("Stream interrupted: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let chunk: OllamaResponse = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid response from Ollama: {}", e))?;
+
+        if !chunk.response.is_empty() {
+            first_token.get_or_insert_with(|| started.elapsed());
+            streamed_tokens += 1;
+        }
+
+        if chunk.done {
+            let total = started.elapsed();
+            let ttft = first_token.unwrap_or(total);
+            let tokens_per_second = if chunk.eval_duration > 0 {
+                chunk.eval_count as f64 / (chunk.eval_duration as f64 / 1e9)
+            } else {
+                streamed_tokens as f64 / (total - ttft).as_secs_f64().max(f64::EPSILON)
+            };
+            return Ok((ttft, tokens_per_second, total));
+        }
+    }
+
+    Err("Stream ended before the model finished".to_string())
+}
+
+pub fn benchmark_model(base_url: &str, model: &str) -> BenchmarkResult {
+    let mut ttft = Duration::ZERO;
+    let mut tokens_per_second = 0.0;
+    let mut total_time = Duration::ZERO;
+
+    for prompt in BENCHMARK_PROMPTS {
+        match benchmark_prompt(base_url, model, prompt) {
+            Ok((first, rate, total)) => {
+                ttft += first;
+                tokens_per_second += rate;
+                total_time += total;
+            }
+            Err(e) => {
+                return BenchmarkResult {
+                    model: model.to_string(),
+                    time_to_first_token: Duration::ZERO,
+                    tokens_per_second: 0.0,
+                    total_time: Duration::ZERO,
+                    error: Some(e),
+                }
+            }
+        }
+    }
+
+    let runs = BENCHMARK_PROMPTS.len() as u32;
+    BenchmarkResult {
+        model: model.to_string(),
+        time_to_first_token: ttft / runs,
+        tokens_per_second: tokens_per_second / runs as f64,
+        total_time: total_time / runs,
+        error: None,
+    }
+}
+
+pub fn show_stats_grid(ui: &mut egui::Ui, id: &str, stats: &BTreeMap<String, ModelStats>) {
+    if stats.is_empty() {
+        ui.weak("No requests yet.");
+        return;
+    }
+
+    egui::Grid::new(id).striped(true).show(ui, |ui| {
+        ui.strong("Model");
+        ui.strong("Requests");
+        ui.strong("Tokens in");
+        ui.strong("Tokens out");
+        ui.strong("Avg latency");
+        ui.strong("Tokens/s");
+        ui.end_row();
+
+        let mut total = ModelStats::default();
+        for (model, entry) in stats {
+            ui.label(model);
+            stats_row(ui, entry);
+            total.requests += entry.requests;
+            total.prompt_tokens += entry.prompt_tokens;
+            total.completion_tokens += entry.completion_tokens;
+            total.total_latency_ms += entry.total_latency_ms;
+            total.timed_tokens += entry.timed_tokens;
+            total.generation_ms += entry.generation_ms;
+        }
+        if stats.len() > 1 {
+            ui.strong("Total");
+            stats_row(ui, &total);
+        }
+    });
+}
+
+fn stats_row(ui: &mut egui::Ui, entry: &ModelStats) {
+    ui.label(entry.requests.to_string());
+    ui.label(entry.prompt_tokens.to_string());
+    ui.label(entry.completion_tokens.to_string());
+    ui.label(format!(
+        "{:.1} s",
+        entry.average_latency_ms() as f64 / 1000.0
+    ));
+    match entry.tokens_per_second() {
+        Some(rate) => ui.label(format!("{:.1}", rate)),
+        None => ui.weak("–"),
+    };
+    ui.end_row();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compared_answers_are_timed_from_the_first_token() {
+        let mut answer = ComparedAnswer {
+            running: true,
+            ..ComparedAnswer::default()
+        };
+        assert_eq!(answer.tokens_per_second(), None);
+
+        answer.receive("Hel", Duration::from_millis(500));
+        answer.receive("lo", Duration::from_millis(900));
+        assert_eq!(answer.first_token, Some(Duration::from_millis(500)));
+        assert_eq!(answer.text, "Hello");
+        assert_eq!(answer.tokens_per_second(), None);
+
+        let completion = Completion {
+            text: "Hello".to_string(),
+            prompt_tokens: 12,
+            completion_tokens: 30,
+            duration: None,
+            generation: None,
+        };
+        answer.finish(Ok(completion), Duration::from_millis(2000));
+        assert!(!answer.running);
+        assert_eq!(answer.total_time, Some(Duration::from_millis(2000)));
+        assert_eq!(answer.prompt_tokens, 12);
+        assert_eq!(answer.tokens_per_second(), Some(20.0));
+
+        let mut failed = ComparedAnswer::default();
+        failed.finish(Err("model not found".to_string()), Duration::from_secs(1));
+        assert_eq!(failed.error.as_deref(), Some("model not found"));
+        assert_eq!(failed.tokens_per_second(), None);
+    }
+}
@@ -0,0 +1,9436 @@
+use crate::ai::backend::{
+    api_key_from_env, check_anthropic_key, check_llama_cpp_server, check_ollama_server,
+    check_openai_server, list_ollama_models, pull_ollama_model, BackendConfig, ModelDiscovery,
+    ModelPull, Network, OllamaModel, ANTHROPIC_URL, DEFAULT_LLAMA_CPP_URL, DEFAULT_OLLAMA_URL,
+    DEFAULT_OPENAI_URL,
+};
+use crate::ai::chat_tree::ChatTree;
+use crate::ai::prompt::{
+    chat_preamble, context_budget, conversation, document_context, estimate_tokens, file_context,
+    file_type_prompt, fit_lines, load_templates, parse_numbered_sections, share_budget,
+    test_template, ChatMessage, ConversationTemplate, DESCRIBE_IMAGE_PROMPT,
+    SELECTION_CONTEXT_LINES, SUMMARY_PROMPT,
+};
+use crate::ai::question_history::QuestionHistory;
+use crate::ai::redact::{
+    load_redaction_opt_outs, save_redaction_opt_outs, scan_pii, PiiFinding, Redaction, Redactor,
+};
+use crate::ai::retrieval::{build_index, DocumentIndex, Retrieval};
+use crate::ai::stats::{
+    benchmark_model, show_stats_grid, BenchmarkRun, ComparedAnswer, UsageStats, BENCHMARK_PROMPTS,
+};
+use crate::ai::{
+    annotations_to_markdown, is_vision_model, load_conversation, parse_edit, parse_review,
+    save_conversation, AiEvent, AiState, Annotation, AnnotationKind, AttachedImage, ContextMode,
+    EditState, GenerationParams, PendingRequest, Provider, RequestKind, ReviewState,
+    TestSuggestions, TranslationState,
+};
+use crate::cli::Cli;
+use crate::commands::{fuzzy_score, keymap, Command, CommandPalette, PaletteItem};
+use crate::config::{
+    config_path, load_config, save_config, Config, DEFAULT_FONT_SIZE, MAX_FONT_SIZE, MIN_FONT_SIZE,
+};
+use crate::export::{css_color, escape_html, highlighted_html, highlighted_pdf};
+use crate::files::{
+    decode_file, display_name, encode_text, is_fifo, newest_file, read_text_file,
+    spawn_fifo_reader, spawn_stream_reader, FileFollower, FileWatcher, FollowUpdate, StreamBuffer,
+    ENCODINGS,
+};
+#[cfg(feature = "tree-sitter")]
+use crate::highlight::tree_sitter::{self, TreeSitterHighlighter};
+use crate::highlight::{
+    detect_syntax, is_dark_theme, load_syntax_overrides, load_theme_set, parse_terminal_line,
+    rainbow_brackets, run_highlighter, save_syntax_overrides, HighlightBackend, HighlightJob,
+    HighlightTask, Highlighter, SyntectHighlighter, DEFAULT_LIGHT_THEME, DEFAULT_THEME,
+};
+use crate::session::{
+    export_bookmarks, import_bookmarks, load_bookmarks, load_recent_files, load_recoveries,
+    load_session, remove_recovery, save_bookmarks, save_recent_files, save_recovery, save_session,
+    stale_recovery, unix_seconds, Bookmark, Recovery, Session, SessionTab, MAX_RECENT_FILES,
+};
+use crate::viewer::archive::{Archive, ArchiveKind};
+use crate::viewer::diff::{
+    change_starts, diff_fill, diff_rows, unified_rows, DiffKind, DiffSide, DiffView,
+};
+use crate::viewer::file_tree::{show_tree_file, FileTree};
+use crate::viewer::fonts::{setup_fonts, FontConfig, Typography};
+use crate::viewer::gguf::{format_parameter_count, format_size, parse_gguf, GgufInfo};
+use crate::viewer::git::{format_date, GitCommit, GitFile, LineChange};
+use crate::viewer::image_preview::{is_image_path, ImagePreview, IMAGE_EXTENSIONS};
+use crate::viewer::json::JsonTree;
+use crate::viewer::links::{find_links, Link, LinkTarget};
+use crate::viewer::log::{LogFilter, LogLevel, LogLines};
+use crate::viewer::markdown::{is_web_link, parse_markdown, show_inline, MarkdownBlock};
+use crate::viewer::outline::{outline, Symbol};
+use crate::viewer::text::{
+    bracket_depths, bracket_pair_at, column_offset, convert, enclosing_brackets, expand_selection,
+    find_matches, fold_regions, line_ending_counts, line_starts, paragraph_text, parse_goto,
+    prose_blocks, replace_matches, search_pattern, split_paragraphs, word_range, Conversion,
+    FoldRegion, ProseBlock, ReplaceUndo,
+};
+use crate::viewer::text_stats::{format_minutes, text_stats, TextStats, BACKGROUND_STATS_BYTES};
+use crate::viewer::{
+    annotation_menu, hex_dump_preview, hex_row, CopyFormat, DocumentView, Minimap, ViewFrame,
+};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use eframe::egui;
+use encoding_rs::Encoding;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{FontStyle, Style, Theme, ThemeSet};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+use tokio::task::AbortHandle;
+
+/// `saved` while `current` is still the value given on the command line.
+fn unless_from_cli<T: PartialEq + Copy>(current: T, cli: Option<T>, saved: T) -> T {
+    if cli == Some(current) {
+        saved
+    } else {
+        current
+    }
+}
+
+fn visuals(dark_mode: bool) -> egui::Visuals {
+    if dark_mode {
+        egui::Visuals::dark()
+    } else {
+        egui::Visuals::light()
+    }
+}
+
+/// Generation settings for requests answered in one go, such as a review,
+/// whose reply needs more room than a chat turn.
+fn review_params(params: &GenerationParams) -> GenerationParams {
+    GenerationParams {
+        max_tokens: params.max_tokens.max(2048),
+        ..*params
+    }
+}
+
+/// Generation settings for an "AI edit" of `original`, whose reply repeats
+/// the lines with the change made.
+fn edit_params(params: &GenerationParams, original: &str) -> GenerationParams {
+    let reply = 2 * estimate_tokens(original) as i32 + 256;
+    GenerationParams {
+        max_tokens: params.max_tokens.max(reply),
+        ..review_params(params)
+    }
+}
+
+/// A collapsed view of the document context the answer at `index` of the
+/// chat history was given, as sent.
+fn show_message_context(ui: &mut egui::Ui, index: usize, context: &str) {
+    egui::CollapsingHeader::new(format!("📄 Context (~{} tokens)", estimate_tokens(context)))
+        .id_source(("message_context", index))
+        .show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .id_source(("message_context_scroll", index))
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::Label::new(egui::RichText::new(context).monospace().small())
+                            .wrap(true),
+                    );
+                });
+        });
+}
+
+/// Remove the autosaved edits of a document being closed: dropping them on
+/// purpose is no crash to recover from.
+fn forget_recovery(doc: &OpenDocument) {
+    if let Some(key) = stale_recovery(doc.file_path.as_deref(), doc.dirty, doc.streamed) {
+        remove_recovery(&key);
+    }
+}
+
+/// The pages of the settings window.
+#[derive(Clone, Copy, PartialEq, Default)]
+enum SettingsTab {
+    #[default]
+    Appearance,
+    Ai,
+    Behavior,
+}
+
+/// The second pane of a split view.
+#[derive(Default)]
+struct SplitPane {
+    /// Tab shown in the pane; `None` shows the active document a second time.
+    document: Option<usize>,
+    /// Scroll both panes together.
+    sync: bool,
+    /// The pane's scroll offset should be applied, after it switched documents.
+    restore_scroll: bool,
+    /// View state of the pane while it shows the active document.
+    scroll_offset: f32,
+    visible_lines: (usize, usize),
+    focus_line: usize,
+    minimap: Option<Minimap>,
+}
+
+impl SplitPane {
+    fn exchange_view(&mut self, app: &mut MyApp) {
+        use std::mem::swap;
+        swap(&mut self.scroll_offset, &mut app.scroll_offset);
+        swap(&mut self.visible_lines, &mut app.visible_lines);
+        swap(&mut self.focus_line, &mut app.focus_line);
+    }
+}
+
+/// Statistics for the statistics window, and the path and length of the
+/// text they were counted from, which tell when they are stale.
+#[derive(Default)]
+struct StatsJob {
+    source: Option<(Option<String>, usize)>,
+    /// `None` while a worker is counting.
+    stats: Option<TextStats>,
+}
+
+/// What the buttons of a chat message asked for.
+enum ChatAction {
+    /// Show the branch through this message instead.
+    Switch(usize),
+    Edit(usize),
+    CancelEdit,
+    /// Ask the question again with new text.
+    Resend(usize, String),
+    /// Ask the question this answer replied to again.
+    Regenerate(usize),
+    DeleteBelow(usize),
+}
+
+/// A chat request's system prompt, in parts so that retrieval can replace
+/// the document while keeping the other files.
+struct ChatContext {
+    preamble: String,
+    document: String,
+    others: String,
+    /// What had to be cut to fit the context window.
+    warnings: Vec<String>,
+}
+
+impl ChatContext {
+    fn system_prompt(&self) -> String {
+        format!("{}{}{}", self.preamble, self.document, self.others)
+    }
+}
+
+/// A file open in a tab. The active document's state lives directly on
+/// `MyApp`; the others are parked here and swapped in when selected.
+#[derive(Default)]
+struct OpenDocument {
+    file_path: Option<String>,
+    file_content: String,
+    highlighted_content: Vec<Vec<(Style, String)>>,
+    highlight_task: Option<HighlightTask>,
+    gguf_info: Option<GgufInfo>,
+    hex_data: Option<Vec<u8>>,
+    image_preview: Option<ImagePreview>,
+    archive: Option<Archive>,
+    json_tree: Option<JsonTree>,
+    git: Option<GitFile>,
+    revision: Option<String>,
+    history: Option<Result<Vec<GitCommit>, String>>,
+    session_syntax: Option<String>,
+    encoding: Option<&'static Encoding>,
+    encoding_override: Option<&'static Encoding>,
+    load_error: Option<String>,
+    ai_state: AiState,
+    active_template: Option<usize>,
+    template_progress: Vec<bool>,
+    redacted_content: String,
+    redactions: Vec<Redaction>,
+    pii_findings: Vec<PiiFinding>,
+    pii_confirmed: bool,
+    pending_request: Option<PendingRequest>,
+    visible_lines: (usize, usize),
+    scroll_offset: f32,
+    focus_line: usize,
+    annotations: Vec<Annotation>,
+    translation: Arc<Mutex<TranslationState>>,
+    review: Arc<Mutex<ReviewState>>,
+    ai_edit: Arc<Mutex<EditState>>,
+    test_suggestions: Arc<Mutex<TestSuggestions>>,
+    document_index: Arc<Mutex<DocumentIndex>>,
+    show_translation: bool,
+    prose_blocks: Vec<ProseBlock>,
+    fold_regions: Vec<FoldRegion>,
+    folded: BTreeSet<usize>,
+    outline: Vec<Symbol>,
+    bracket_depths: Vec<usize>,
+    links: BTreeMap<usize, Vec<Link>>,
+    stream: Option<Arc<Mutex<StreamBuffer>>>,
+    streamed: bool,
+    follow: bool,
+    follower: Option<FileFollower>,
+    log_dir: Option<PathBuf>,
+    log_lines: LogLines,
+    /// Colour lines by level and filter them; see `show_log_bar`.
+    log_mode: bool,
+    log_filter: LogFilter,
+    selection: Option<Range<usize>>,
+    editor_cursor: Option<(usize, usize)>,
+    selection_history: Vec<Range<usize>>,
+    replace_undo: ReplaceUndo,
+    scroll_to_line: Option<usize>,
+    ask_about: Option<(usize, usize)>,
+    attached_images: Vec<AttachedImage>,
+    edited_question: Option<(usize, String)>,
+    editing: bool,
+    dirty: bool,
+    edited_at: Option<Instant>,
+    watcher: Option<FileWatcher>,
+    disk_changed: bool,
+    in_ai_context: bool,
+}
+
+pub struct MyApp {
+    file_path: Option<String>,
+    file_content: String,
+    highlighted_content: Vec<Vec<(Style, String)>>,
+    highlight_task: Option<HighlightTask>,
+    gguf_info: Option<GgufInfo>,
+    hex_data: Option<Vec<u8>>,
+    image_preview: Option<ImagePreview>,
+    /// The archive listed in the sidebar, for an archive and the entries
+    /// opened from it. Entries are streamed documents named by
+    /// `Archive::entry_path`.
+    archive: Option<Archive>,
+    /// Parsed when the JSON tree is first shown; cleared when the text changes.
+    json_tree: Option<JsonTree>,
+    /// Set when the file is inside a git repository.
+    git: Option<GitFile>,
+    /// The commit the content was read from, for a file opened at an
+    /// earlier revision. Such documents are marked as streamed, being
+    /// read-only and not backed by the file on disk.
+    revision: Option<String>,
+    /// Commits that changed the file, listed by the history menu.
+    history: Option<Result<Vec<GitCommit>, String>>,
+    /// Language for this session only: given with `--language`, or picked for
+    /// a stream, which has no path to remember the choice by. Takes precedence
+    /// over `syntax_overrides`.
+    session_syntax: Option<String>,
+    /// The encoding the document was decoded from; `None` unless it is a text file.
+    encoding: Option<&'static Encoding>,
+    encoding_override: Option<&'static Encoding>,
+    load_error: Option<String>,
+    /// Edit mode: the document is shown in a text editor instead of the viewer.
+    editing: bool,
+    /// Byte offsets of the editor's selection anchor and cursor.
+    editor_cursor: Option<(usize, usize)>,
+    /// The buffer has changes that are not on disk yet.
+    dirty: bool,
+    /// Time of the last edit not yet re-highlighted; see `poll_edits`.
+    edited_at: Option<Instant>,
+    watcher: Option<FileWatcher>,
+    /// The file changed on disk and the user has to choose whether to reload.
+    disk_changed: bool,
+    /// Send this file along with questions asked in other tabs.
+    in_ai_context: bool,
+    save_error: Option<String>,
+    diff: Option<DiffView>,
+    diff_error: Option<String>,
+    window_title: String,
+    syntax_set: Arc<SyntaxSet>,
+    theme_set: ThemeSet,
+    theme_name: String,
+    dark_mode: bool,
+    /// Highlighted code blocks from AI answers, keyed by a hash of their content.
+    snippet_cache: HashMap<u64, egui::text::LayoutJob>,
+    /// Images shown in Markdown previews; `None` for files that failed to load.
+    image_cache: HashMap<PathBuf, Option<egui::TextureHandle>>,
+    document_view: DocumentView,
+    /// Source line the Markdown preview should scroll to.
+    preview_target: Option<usize>,
+    syntax_overrides: BTreeMap<String, String>,
+    font_size: f32,
+    show_ai_panel: bool,
+    ai_panel_width: f32,
+    show_settings: bool,
+    settings_tab: SettingsTab,
+    show_stats: bool,
+    show_text_stats: bool,
+    text_stats: Arc<Mutex<StatsJob>>,
+    show_benchmark: bool,
+    benchmark: Arc<Mutex<BenchmarkRun>>,
+    show_comparison: bool,
+    /// The Ollama models the comparison window asks, and the question.
+    comparison_models: [String; 2],
+    comparison_question: String,
+    comparison: Arc<Mutex<[ComparedAnswer; 2]>>,
+    ai_state: AiState,
+    /// Chat workers report here; the events are applied in `update()`.
+    ai_sender: Sender<AiEvent>,
+    ai_events: Receiver<AiEvent>,
+    next_ai_request: u64,
+    /// Chat requests still running, so they can be cancelled.
+    ai_tasks: HashMap<u64, AbortHandle>,
+    network: Network,
+    usage_stats: Arc<Mutex<UsageStats>>,
+    user_question: String,
+    /// Lines (0-based, inclusive) the next question is about, from "Ask about selection".
+    ask_about: Option<(usize, usize)>,
+    /// Images sent with the next question to a vision model.
+    attached_images: Vec<AttachedImage>,
+    /// The chat message being edited to ask again, and its new text.
+    edited_question: Option<(usize, String)>,
+    focus_question: bool,
+    question_history: QuestionHistory,
+    selected_model: String,
+    ollama_url: String,
+    llama_cpp_url: String,
+    ollama_status: Arc<Mutex<Option<String>>>,
+    openai_url: String,
+    openai_model: String,
+    /// Name of the environment variable holding the API key.
+    openai_key_env: String,
+    openai_status: Arc<Mutex<Option<String>>>,
+    anthropic_model: String,
+    anthropic_key_env: String,
+    anthropic_status: Arc<Mutex<Option<String>>>,
+    available_models: Vec<String>,
+    /// Details of the models Ollama reported, for tooltips.
+    installed_models: Vec<OllamaModel>,
+    model_discovery: Arc<Mutex<ModelDiscovery>>,
+    model_discovery_error: Option<String>,
+    /// The last model listing failed to reach the Ollama server.
+    ollama_down: bool,
+    /// A model asked for, in the config or before a switch, that Ollama does
+    /// not have; the AI panel offers to pull it.
+    missing_model: Option<String>,
+    model_pull: Arc<Mutex<ModelPull>>,
+    provider: Provider,
+    grammar: String,
+    server_status: Arc<Mutex<Option<String>>>,
+    templates: Vec<ConversationTemplate>,
+    active_template: Option<usize>,
+    template_progress: Vec<bool>,
+    redactor: Redactor,
+    redacted_content: String,
+    redactions: Vec<Redaction>,
+    redaction_opt_outs: HashSet<String>,
+    show_redactions: bool,
+    pii_findings: Vec<PiiFinding>,
+    pii_confirmed: bool,
+    pending_request: Option<PendingRequest>,
+    context_mode: ContextMode,
+    /// Ollama model used to embed document chunks for `ContextMode::Relevant`.
+    embedding_model: String,
+    request_timeout_secs: u64,
+    request_retries: u32,
+    generation: GenerationParams,
+    custom_system_prompt: String,
+    file_type_prompts: BTreeMap<String, String>,
+    /// Extension or language typed in settings to add a file-type prompt for.
+    new_file_type: String,
+    viewport_margin: usize,
+    /// First and last (0-based) line currently on screen.
+    visible_lines: (usize, usize),
+    auto_scroll: bool,
+    auto_scroll_speed: f32,
+    scroll_offset: f32,
+    focus_mode: bool,
+    /// Line at the vertical center of the view; its block stays lit in focus mode.
+    focus_line: usize,
+    annotations: Vec<Annotation>,
+    show_annotations: bool,
+    annotation_export_status: Option<String>,
+    translation: Arc<Mutex<TranslationState>>,
+    review: Arc<Mutex<ReviewState>>,
+    ai_edit: Arc<Mutex<EditState>>,
+    /// Change asked for in the AI edit window.
+    ai_edit_instruction: String,
+    test_suggestions: Arc<Mutex<TestSuggestions>>,
+    show_test_suggestions: bool,
+    document_index: Arc<Mutex<DocumentIndex>>,
+    translation_language: String,
+    show_translation: bool,
+    reflow_prose: bool,
+    reading_width: usize,
+    hex_bytes_per_row: usize,
+    show_minimap: bool,
+    show_outline: bool,
+    outline_filter: String,
+    rainbow_brackets: bool,
+    minimap: Option<Minimap>,
+    /// Show who last changed each line, for files in a git repository.
+    show_blame: bool,
+    auto_reload: bool,
+    autosave: bool,
+    autosave_secs: u64,
+    last_autosave: Instant,
+    /// Edits autosaved before a crash, offered back at startup.
+    recoveries: Vec<Recovery>,
+    summarize_on_open: bool,
+    summarize_on_reload: bool,
+    prose_blocks: Vec<ProseBlock>,
+    fold_regions: Vec<FoldRegion>,
+    /// First lines of the folded regions.
+    folded: BTreeSet<usize>,
+    /// Headings or definitions listed by the outline panel.
+    outline: Vec<Symbol>,
+    /// Bracket nesting depth at the start of each line, for rainbow brackets.
+    bracket_depths: Vec<usize>,
+    /// URLs and file references of each line, opened with Ctrl+click.
+    links: BTreeMap<usize, Vec<Link>>,
+    typography: Typography,
+    serif_available: bool,
+    font_config: FontConfig,
+    /// Font files from the config that could not be read.
+    font_errors: Vec<String>,
+    /// Quit on `q`, like a terminal pager.
+    pager: bool,
+    /// Incoming lines when reading from stdin or a named pipe.
+    stream: Option<Arc<Mutex<StreamBuffer>>>,
+    /// The document came from a stream rather than a file that can be re-read.
+    streamed: bool,
+    /// Tail the open file, appending lines as they are written.
+    follow: bool,
+    follower: Option<FileFollower>,
+    last_follow_poll: Instant,
+    /// Directory whose newest file is shown, switching when logs rotate.
+    log_dir: Option<PathBuf>,
+    log_lines: LogLines,
+    /// Colour lines by level and filter them; see `show_log_bar`.
+    log_mode: bool,
+    log_filter: LogFilter,
+    show_line_numbers: bool,
+    /// Height of a code row plus spacing in the last frame, to rescale the
+    /// scroll offset when the font size changes.
+    row_pitch: f32,
+    vim_keys: bool,
+    /// A count and/or `g` typed towards a vim command.
+    vim_pending: String,
+    /// Columns and rows to scroll the code view by in the next frame.
+    scroll_by: egui::Vec2,
+    horizontal_offset: f32,
+    show_search: bool,
+    search_query: String,
+    /// Whether `search_query` is a regular expression.
+    search_regex: bool,
+    /// `search_query` compiled; `None` when empty or invalid.
+    search_pattern: Option<Regex>,
+    /// Matches of `search_query` in the active document.
+    search_matches: Vec<Range<usize>>,
+    current_match: usize,
+    /// Query, mode, document and length the matches were built for.
+    search_key: (String, bool, usize, usize),
+    replace_query: String,
+    /// How many matches the last replacement changed.
+    replace_status: Option<String>,
+    focus_search: bool,
+    /// Text of the Ctrl+G popup, while it is open.
+    goto_input: Option<String>,
+    /// Link Ctrl+clicked in the view, opened once the frame is drawn.
+    clicked_link: Option<LinkTarget>,
+    /// The conversion chosen in the open Convert window.
+    conversion: Option<Conversion>,
+    tab_width: usize,
+    command_palette: Option<CommandPalette>,
+    /// Outcome of the last export or formatted copy, and when it finished.
+    export_status: Option<(Result<String, String>, Instant)>,
+    /// Kept open so rich text stays on the clipboard on platforms where
+    /// it belongs to the process that put it there.
+    clipboard: Option<arboard::Clipboard>,
+    /// When the font size last changed, for the zoom overlay.
+    zoom_changed_at: Option<Instant>,
+    /// Shortcut overrides from the config file, and the bindings they produce.
+    keybindings: BTreeMap<String, String>,
+    highlighters: BTreeMap<String, HighlightBackend>,
+    keymap: Vec<(Command, egui::KeyboardShortcut)>,
+    /// Line jumped to, highlighted until the flash fades.
+    flash_line: Option<(usize, Instant)>,
+    /// Byte range of `file_content`, grown with Alt+Up.
+    selection: Option<Range<usize>>,
+    /// Earlier selections, restored by Alt+Down.
+    selection_history: Vec<Range<usize>>,
+    /// Undo for replacements made from the find bar, cleared by any other
+    /// change to the text.
+    replace_undo: ReplaceUndo,
+    /// Byte offset where the mouse button went down while drag-selecting.
+    drag_anchor: Option<usize>,
+    bookmarks: Vec<Bookmark>,
+    show_bookmarks: bool,
+    /// Row being dragged in the bookmark panel.
+    dragged_bookmark: Option<usize>,
+    bookmark_file: String,
+    bookmark_status: Option<String>,
+    show_jump_list: bool,
+    /// Line to bring into view on the next frame.
+    scroll_to_line: Option<usize>,
+    /// Every open tab; the slot at `active_document` is an empty placeholder
+    /// while its contents are swapped into the fields above.
+    documents: Vec<OpenDocument>,
+    active_document: usize,
+    /// Tab whose unsaved edits the user is asked about before it closes.
+    closing_tab: Option<usize>,
+    /// Apply `scroll_offset` to the view, after switching tabs.
+    restore_scroll: bool,
+    split_pane: Option<SplitPane>,
+    file_tree: Option<FileTree>,
+    recent_files: Vec<String>,
+    /// The session from the last run, offered on the start screen.
+    last_session: Option<Session>,
+    saved_session: Session,
+    last_session_save: Instant,
+    /// The preferences as last written to `config.toml`.
+    saved_config: Config,
+    /// Command-line settings, which are not written to `config.toml`.
+    cli: Cli,
+}
+
+impl MyApp {
+    pub fn new(cc: &eframe::CreationContext<'_>, mut cli: Cli) -> Self {
+        let theme_set = load_theme_set();
+        let syntax_set = Arc::new(SyntaxSet::load_defaults_newlines());
+        let config = load_config();
+        if let Some(theme) = cli
+            .theme
+            .take_if(|name| !theme_set.themes.contains_key(name))
+        {
+            eprintln!("tty_doc: unknown theme '{}'", theme);
+        }
+        let language = cli.language.as_deref().and_then(|language| {
+            let syntax = syntax_set.find_syntax_by_token(language);
+            if syntax.is_none() {
+                eprintln!("tty_doc: unknown language '{}'", language);
+            }
+            syntax.map(|syntax| syntax.name.clone())
+        });
+        if let Some(size) = &mut cli.font_size {
+            *size = size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        }
+        let theme_name = cli
+            .theme
+            .clone()
+            .or(Some(config.theme.clone()))
+            .filter(|name| theme_set.themes.contains_key(name))
+            .unwrap_or_else(|| DEFAULT_THEME.to_string());
+        cc.egui_ctx.set_visuals(visuals(config.dark_mode));
+        // The zoom keys resize the document text instead of the whole UI.
+        cc.egui_ctx.options_mut(|o| o.zoom_with_keyboard = false);
+        let (serif_available, font_errors) = setup_fonts(&cc.egui_ctx, &config.fonts);
+        let (ai_sender, ai_events) = mpsc::channel();
+
+        let mut app = Self {
+            file_path: None,
+            file_content: String::new(),
+            highlighted_content: Vec::new(),
+            highlight_task: None,
+            gguf_info: None,
+            hex_data: None,
+            image_preview: None,
+            archive: None,
+            json_tree: None,
+            git: None,
+            revision: None,
+            history: None,
+            session_syntax: None,
+            encoding: None,
+            encoding_override: None,
+            load_error: None,
+            editing: false,
+            editor_cursor: None,
+            dirty: false,
+            edited_at: None,
+            watcher: None,
+            disk_changed: false,
+            in_ai_context: false,
+            save_error: None,
+            diff: None,
+            diff_error: None,
+            window_title: String::new(),
+            syntax_set,
+            theme_set,
+            theme_name,
+            dark_mode: config.dark_mode,
+            snippet_cache: HashMap::new(),
+            image_cache: HashMap::new(),
+            document_view: DocumentView::Source,
+            preview_target: None,
+            syntax_overrides: load_syntax_overrides(),
+            font_size: cli.font_size.unwrap_or(config.font_size),
+            show_ai_panel: config.show_ai_panel && !cli.no_ai,
+            ai_panel_width: config.ai_panel_width,
+            show_settings: false,
+            settings_tab: SettingsTab::default(),
+            show_stats: false,
+            show_text_stats: false,
+            text_stats: Arc::new(Mutex::new(StatsJob::default())),
+            show_benchmark: false,
+            show_comparison: false,
+            comparison_models: Default::default(),
+            comparison_question: String::new(),
+            comparison: Default::default(),
+            benchmark: Arc::new(Mutex::new(BenchmarkRun::default())),
+            ai_state: AiState::default(),
+            ai_sender,
+            ai_events,
+            next_ai_request: 0,
+            ai_tasks: HashMap::new(),
+            network: Network::new().expect("network runtime"),
+            usage_stats: Arc::new(Mutex::new(UsageStats::load())),
+            user_question: String::new(),
+            ask_about: None,
+            attached_images: Vec::new(),
+            edited_question: None,
+            focus_question: false,
+            question_history: QuestionHistory::default(),
+            selected_model: cli.model.clone().unwrap_or_else(|| config.model.clone()),
+            ollama_url: cli
+                .ollama_url
+                .clone()
+                .unwrap_or_else(|| config.ollama_url.clone()),
+            ollama_status: Arc::new(Mutex::new(None)),
+            llama_cpp_url: config.llama_cpp_url.clone(),
+            openai_url: config.openai_url.clone(),
+            openai_model: config.openai_model.clone(),
+            openai_key_env: config.openai_key_env.clone(),
+            openai_status: Arc::new(Mutex::new(None)),
+            anthropic_model: config.anthropic_model.clone(),
+            anthropic_key_env: config.anthropic_key_env.clone(),
+            anthropic_status: Arc::new(Mutex::new(None)),
+            installed_models: Vec::new(),
+            model_discovery: Arc::new(Mutex::new(ModelDiscovery::default())),
+            model_discovery_error: None,
+            ollama_down: false,
+            missing_model: None,
+            model_pull: Arc::new(Mutex::new(ModelPull::default())),
+            available_models: vec![
+                "llama2".to_string(),
+                "mistral".to_string(),
+                "phi".to_string(),
+                "codellama".to_string(),
+            ],
+            provider: config.provider,
+            grammar: String::new(),
+            server_status: Arc::new(Mutex::new(None)),
+            templates: load_templates(),
+            active_template: None,
+            template_progress: Vec::new(),
+            redactor: Redactor::new(),
+            redacted_content: String::new(),
+            redactions: Vec::new(),
+            redaction_opt_outs: load_redaction_opt_outs(),
+            show_redactions: false,
+            pii_findings: Vec::new(),
+            pii_confirmed: false,
+            pending_request: None,
+            context_mode: ContextMode::DocumentStart,
+            viewport_margin: 20,
+            visible_lines: (0, 0),
+            auto_scroll: false,
+            auto_scroll_speed: 40.0,
+            scroll_offset: 0.0,
+            focus_mode: false,
+            focus_line: 0,
+            annotations: Vec::new(),
+            show_annotations: false,
+            annotation_export_status: None,
+            translation: Arc::new(Mutex::new(TranslationState::default())),
+            review: Arc::new(Mutex::new(ReviewState::default())),
+            ai_edit: Arc::new(Mutex::new(EditState::default())),
+            ai_edit_instruction: String::new(),
+            test_suggestions: Arc::new(Mutex::new(TestSuggestions::default())),
+            show_test_suggestions: false,
+            document_index: Arc::new(Mutex::new(DocumentIndex::default())),
+            embedding_model: config.embedding_model.clone(),
+            request_timeout_secs: config.request_timeout_secs,
+            request_retries: config.request_retries,
+            generation: config.generation,
+            custom_system_prompt: config.system_prompt.clone(),
+            file_type_prompts: config.file_type_prompts.clone(),
+            new_file_type: String::new(),
+            translation_language: "English".to_string(),
+            show_translation: false,
+            reflow_prose: config.word_wrap,
+            reading_width: config.wrap_width,
+            hex_bytes_per_row: config.hex_bytes_per_row,
+            show_minimap: config.show_minimap,
+            show_outline: config.show_outline,
+            rainbow_brackets: config.rainbow_brackets,
+            outline_filter: String::new(),
+            vim_keys: config.vim_keys,
+            vim_pending: String::new(),
+            scroll_by: egui::Vec2::ZERO,
+            horizontal_offset: 0.0,
+            minimap: None,
+            show_blame: false,
+            auto_reload: config.auto_reload,
+            autosave: config.autosave,
+            autosave_secs: config.autosave_secs,
+            last_autosave: Instant::now(),
+            recoveries: Vec::new(),
+            summarize_on_open: config.summarize_on_open,
+            summarize_on_reload: config.summarize_on_reload,
+            prose_blocks: Vec::new(),
+            fold_regions: Vec::new(),
+            folded: BTreeSet::new(),
+            outline: Vec::new(),
+            bracket_depths: Vec::new(),
+            links: BTreeMap::new(),
+            typography: Typography::default(),
+            serif_available,
+            font_config: config.fonts.clone(),
+            font_errors,
+            pager: cli.pager,
+            stream: None,
+            streamed: false,
+            follow: false,
+            follower: None,
+            last_follow_poll: Instant::now(),
+            log_dir: None,
+            log_lines: LogLines::default(),
+            log_mode: false,
+            log_filter: LogFilter::default(),
+            show_line_numbers: true,
+            row_pitch: 0.0,
+            show_search: false,
+            search_query: String::new(),
+            search_matches: Vec::new(),
+            current_match: 0,
+            search_regex: false,
+            search_pattern: None,
+            search_key: (String::new(), false, 0, 0),
+            replace_query: String::new(),
+            replace_status: None,
+            focus_search: false,
+            goto_input: None,
+            clicked_link: None,
+            conversion: None,
+            tab_width: config.tab_width,
+            command_palette: None,
+            zoom_changed_at: None,
+            export_status: None,
+            clipboard: None,
+            keybindings: config.keybindings.clone(),
+            highlighters: config.highlighters.clone(),
+            keymap: keymap(&config.keybindings),
+            flash_line: None,
+            selection: None,
+            selection_history: Vec::new(),
+            replace_undo: ReplaceUndo::default(),
+            drag_anchor: None,
+            bookmarks: load_bookmarks(),
+            show_bookmarks: false,
+            dragged_bookmark: None,
+            bookmark_file: String::new(),
+            bookmark_status: None,
+            show_jump_list: false,
+            scroll_to_line: None,
+            documents: vec![OpenDocument::default()],
+            active_document: 0,
+            closing_tab: None,
+            restore_scroll: false,
+            split_pane: None,
+            file_tree: None,
+            recent_files: load_recent_files(),
+            last_session: None,
+            saved_session: Session::default(),
+            last_session_save: Instant::now(),
+            saved_config: config,
+            cli: Cli::default(),
+        };
+
+        let mut paths = std::mem::take(&mut cli.files).into_iter();
+        match paths.next() {
+            Some(path) => {
+                app.open_path(path, !cli.no_ai, &cc.egui_ctx);
+                app.apply_language(&language);
+            }
+            None => {
+                app.load_error = Some("No file specified. Usage: tty_doc <file>...".to_string());
+                app.last_session = load_session();
+            }
+        }
+        // Further files open in background tabs without an automatic summary.
+        for path in paths {
+            app.new_tab();
+            app.open_path(path, false, &cc.egui_ctx);
+            app.apply_language(&language);
+        }
+        app.switch_document(0);
+
+        if let Some(line) = cli.line {
+            let lines = app.highlighted_content.len().max(1);
+            app.go_to(line.saturating_sub(1).min(lines - 1), None);
+        }
+
+        if let Some([left, right]) = cli.diff.take().as_deref() {
+            let left = left.clone();
+            let compared =
+                read_text_file(&left).and_then(|text| app.compare(left, text, right.clone()));
+            if let Err(e) = compared {
+                app.diff_error = Some(e);
+            }
+        }
+
+        if !cli.no_ai {
+            app.refresh_models(&cc.egui_ctx);
+        }
+        if !app.pager {
+            app.recoveries = load_recoveries();
+        }
+        app.cli = cli;
+        app
+    }
+
+    /// Highlight the current document as `language`, given with --language.
+    fn apply_language(&mut self, language: &Option<String>) {
+        if language.is_some() {
+            self.session_syntax = language.clone();
+            self.rehighlight();
+        }
+    }
+
+    /// Open `path` into the current document: a file, `-` for stdin, a
+    /// named pipe or a log directory. `summarize` allows a summary when
+    /// `summarize_on_open` is set.
+    fn open_path(&mut self, path: String, summarize: bool, ctx: &egui::Context) {
+        match path {
+            path if path == "-" => {
+                // Pager use should open instantly: no AI panel or summary until asked.
+                self.show_ai_panel = false;
+                self.file_path = Some(path);
+                self.streamed = true;
+                self.stream = Some(spawn_stream_reader(io::stdin(), ctx.clone()));
+            }
+            path if is_fifo(&path) => {
+                self.show_ai_panel = false;
+                self.streamed = true;
+                self.stream = Some(spawn_fifo_reader(PathBuf::from(&path), ctx.clone()));
+                self.file_path = Some(path);
+            }
+            path if Path::new(&path).is_dir() => self.open_folder(PathBuf::from(path)),
+            path => {
+                self.load_file(&path);
+                if self.load_error.is_some() {
+                    return;
+                }
+                self.remember_recent_file();
+                // A conversation from an earlier session replaces the summary.
+                if self.restore_conversation() {
+                    return;
+                }
+                // A summary of an image would only repeat its size.
+                if summarize && self.summarize_on_open && self.image_preview.is_none() {
+                    self.generate_initial_summary();
+                }
+            }
+        }
+    }
+
+    /// Replace the current document with `path`, starting with fresh AI state.
+    fn replace_document(&mut self, path: String, ctx: &egui::Context) {
+        let mut previous = OpenDocument::default();
+        self.exchange_document(&mut previous);
+        self.open_path(path, true, ctx);
+    }
+
+    fn show_open_dialog(&mut self, ctx: &egui::Context) {
+        let mut dialog = rfd::FileDialog::new();
+        if let Some(dir) = self
+            .file_path
+            .as_deref()
+            .filter(|_| !self.streamed)
+            .and_then(|path| Path::new(path).parent())
+            .filter(|dir| dir.is_dir())
+        {
+            dialog = dialog.set_directory(dir);
+        }
+        if let Some(path) = dialog.pick_file() {
+            self.replace_document(path.to_string_lossy().into_owned(), ctx);
+        }
+    }
+
+    fn remember_recent_file(&mut self) {
+        let Some(key) = self.document_key() else {
+            return;
+        };
+        self.recent_files.retain(|path| *path != key);
+        self.recent_files.insert(0, key);
+        self.recent_files.truncate(MAX_RECENT_FILES);
+        save_recent_files(&self.recent_files);
+    }
+
+    /// Open `path`, switching to its tab if it is already open.
+    fn open_document(&mut self, path: String, new_tab: bool, ctx: &egui::Context) {
+        if let Some(index) = self.find_document(&path) {
+            self.switch_document(index);
+        } else if new_tab && self.file_path.is_some() {
+            self.new_tab();
+            self.open_path(path, true, ctx);
+        } else {
+            self.replace_document(path, ctx);
+        }
+    }
+
+    fn show_recent_menu(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let mut open = None;
+        if self.recent_files.is_empty() {
+            ui.weak("No recent files");
+        }
+        for path in &self.recent_files {
+            if ui
+                .button(display_name(Some(path)))
+                .on_hover_text(path)
+                .clicked()
+            {
+                open = Some(path.clone());
+                ui.close_menu();
+            }
+        }
+        if !self.recent_files.is_empty() {
+            ui.separator();
+            if ui.button("Clear list").clicked() {
+                self.recent_files.clear();
+                save_recent_files(&self.recent_files);
+                ui.close_menu();
+            }
+        }
+        if let Some(path) = open {
+            self.open_document(path, false, ctx);
+        }
+    }
+
+    /// Tabs and panels as they are now; streams cannot be reopened and are left out.
+    fn session(&self) -> Session {
+        let mut tabs = Vec::new();
+        let mut active = 0;
+        for i in 0..self.documents.len() {
+            let (path, streamed, scroll_offset) = if i == self.active_document {
+                (self.file_path.as_deref(), self.streamed, self.scroll_offset)
+            } else {
+                let doc = &self.documents[i];
+                (doc.file_path.as_deref(), doc.streamed, doc.scroll_offset)
+            };
+            let Some(path) = path.filter(|_| !streamed) else {
+                continue;
+            };
+            if i == self.active_document {
+                active = tabs.len();
+            }
+            tabs.push(SessionTab {
+                path: path.to_string(),
+                scroll_offset,
+            });
+        }
+        Session {
+            tabs,
+            active,
+            folder: self.file_tree.as_ref().map(|tree| tree.root.clone()),
+            show_bookmarks: self.show_bookmarks,
+        }
+    }
+
+    /// Write the session when it changed, at most once a second since
+    /// scrolling changes it every frame.
+    fn save_session_if_changed(&mut self) {
+        if self.pager || self.last_session_save.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        let session = self.session();
+        // Starting without files must not overwrite the session on offer.
+        if session.tabs.is_empty() || session == self.saved_session {
+            return;
+        }
+        save_session(&session);
+        self.saved_session = session;
+        self.last_session_save = Instant::now();
+        self.last_session = None;
+    }
+
+    fn restore_session(&mut self, session: Session, ctx: &egui::Context) {
+        for (i, tab) in session.tabs.iter().enumerate() {
+            if i > 0 {
+                self.new_tab();
+            }
+            self.open_path(tab.path.clone(), false, ctx);
+            self.scroll_offset = tab.scroll_offset;
+            self.restore_scroll = true;
+        }
+        self.switch_document(session.active);
+        if let Some(folder) = session.folder {
+            self.open_folder(folder);
+        }
+        self.show_bookmarks = session.show_bookmarks;
+    }
+
+    /// Show `dir` in the file tree sidebar.
+    fn open_folder(&mut self, dir: PathBuf) {
+        let dir = fs::canonicalize(&dir).unwrap_or(dir);
+        if self.file_path.is_none() {
+            self.load_error = Some(format!("Pick a file from {}", dir.display()));
+        }
+        self.file_tree = Some(FileTree::new(dir));
+    }
+
+    fn show_open_folder_dialog(&mut self) {
+        if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+            self.open_folder(dir);
+        }
+    }
+
+    fn show_file_tree(&mut self, ctx: &egui::Context) {
+        let Some(tree) = &mut self.file_tree else {
+            return;
+        };
+        let current = self
+            .file_path
+            .as_deref()
+            .filter(|_| !self.streamed)
+            .and_then(|path| fs::canonicalize(path).ok());
+        let mut clicked = None;
+        let mut close = false;
+        let mut follow = false;
+
+        egui::SidePanel::left("file_tree")
+            .resizable(true)
+            .default_width(220.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let name = tree
+                        .root
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| tree.root.display().to_string());
+                    ui.strong(format!("📁 {}", name))
+                        .on_hover_text(tree.root.display().to_string());
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        close = ui.small_button("✖").on_hover_text("Close folder").clicked();
+                        if ui.small_button("⟳").on_hover_text("Refresh").clicked() {
+                            tree.refresh();
+                        }
+                        follow = ui
+                            .small_button("📡")
+                            .on_hover_text("Follow the newest file, e.g. in a log directory")
+                            .clicked();
+                    });
+                });
+                ui.add(
+                    egui::TextEdit::singleline(&mut tree.filter)
+                        .hint_text("Filter files…")
+                        .desired_width(f32::INFINITY),
+                );
+                if ui
+                    .checkbox(&mut tree.show_ignored, "Show ignored files")
+                    .changed()
+                {
+                    tree.refresh();
+                }
+                ui.separator();
+
+                egui::ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        if tree.filter.trim().is_empty() {
+                            let root = tree.root.clone();
+                            tree.show_dir(ui, &root, current.as_deref(), &mut clicked);
+                        } else {
+                            for path in tree.matching_files() {
+                                let label = path
+                                    .strip_prefix(&tree.root)
+                                    .unwrap_or(&path)
+                                    .display()
+                                    .to_string();
+                                show_tree_file(ui, &path, &label, current.as_deref(), &mut clicked);
+                            }
+                        }
+                    });
+            });
+
+        if follow {
+            let root = tree.root.clone();
+            if self.file_path.is_some() {
+                self.new_tab();
+            }
+            self.watch_directory(root);
+        }
+        if close {
+            self.file_tree = None;
+        }
+        if let Some((path, new_tab)) = clicked {
+            self.open_document(path.to_string_lossy().into_owned(), new_tab, ctx);
+        }
+    }
+
+    /// Open files dropped onto the window: the first replaces the current
+    /// document, any others open in new tabs.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+
+        for (i, path) in dropped.into_iter().enumerate() {
+            let path = path.to_string_lossy().into_owned();
+            if i == 0 {
+                self.replace_document(path, ctx);
+            } else {
+                self.new_tab();
+                self.open_path(path, false, ctx);
+            }
+        }
+
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let screen = ctx.screen_rect();
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("drop_overlay"),
+            ));
+            painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop to open",
+                egui::FontId::proportional(28.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+
+    /// Swap the active document's state with `doc`.
+    fn exchange_document(&mut self, doc: &mut OpenDocument) {
+        use std::mem::swap;
+        swap(&mut self.file_path, &mut doc.file_path);
+        swap(&mut self.file_content, &mut doc.file_content);
+        swap(&mut self.highlighted_content, &mut doc.highlighted_content);
+        swap(&mut self.highlight_task, &mut doc.highlight_task);
+        swap(&mut self.gguf_info, &mut doc.gguf_info);
+        swap(&mut self.hex_data, &mut doc.hex_data);
+        swap(&mut self.image_preview, &mut doc.image_preview);
+        swap(&mut self.archive, &mut doc.archive);
+        swap(&mut self.json_tree, &mut doc.json_tree);
+        swap(&mut self.git, &mut doc.git);
+        swap(&mut self.revision, &mut doc.revision);
+        swap(&mut self.session_syntax, &mut doc.session_syntax);
+        swap(&mut self.history, &mut doc.history);
+        swap(&mut self.encoding, &mut doc.encoding);
+        swap(&mut self.encoding_override, &mut doc.encoding_override);
+        swap(&mut self.load_error, &mut doc.load_error);
+        swap(&mut self.ai_state, &mut doc.ai_state);
+        swap(&mut self.active_template, &mut doc.active_template);
+        swap(&mut self.template_progress, &mut doc.template_progress);
+        swap(&mut self.redacted_content, &mut doc.redacted_content);
+        swap(&mut self.redactions, &mut doc.redactions);
+        swap(&mut self.pii_findings, &mut doc.pii_findings);
+        swap(&mut self.pii_confirmed, &mut doc.pii_confirmed);
+        swap(&mut self.pending_request, &mut doc.pending_request);
+        swap(&mut self.visible_lines, &mut doc.visible_lines);
+        swap(&mut self.scroll_offset, &mut doc.scroll_offset);
+        swap(&mut self.focus_line, &mut doc.focus_line);
+        swap(&mut self.annotations, &mut doc.annotations);
+        swap(&mut self.translation, &mut doc.translation);
+        swap(&mut self.review, &mut doc.review);
+        swap(&mut self.ai_edit, &mut doc.ai_edit);
+        swap(&mut self.test_suggestions, &mut doc.test_suggestions);
+        swap(&mut self.document_index, &mut doc.document_index);
+        swap(&mut self.show_translation, &mut doc.show_translation);
+        swap(&mut self.prose_blocks, &mut doc.prose_blocks);
+        swap(&mut self.fold_regions, &mut doc.fold_regions);
+        swap(&mut self.folded, &mut doc.folded);
+        swap(&mut self.outline, &mut doc.outline);
+        swap(&mut self.bracket_depths, &mut doc.bracket_depths);
+        swap(&mut self.links, &mut doc.links);
+        swap(&mut self.stream, &mut doc.stream);
+        swap(&mut self.streamed, &mut doc.streamed);
+        swap(&mut self.follow, &mut doc.follow);
+        swap(&mut self.follower, &mut doc.follower);
+        swap(&mut self.log_dir, &mut doc.log_dir);
+        swap(&mut self.log_lines, &mut doc.log_lines);
+        swap(&mut self.log_mode, &mut doc.log_mode);
+        swap(&mut self.log_filter, &mut doc.log_filter);
+        swap(&mut self.selection, &mut doc.selection);
+        swap(&mut self.selection_history, &mut doc.selection_history);
+        swap(&mut self.replace_undo, &mut doc.replace_undo);
+        swap(&mut self.scroll_to_line, &mut doc.scroll_to_line);
+        swap(&mut self.ask_about, &mut doc.ask_about);
+        swap(&mut self.attached_images, &mut doc.attached_images);
+        swap(&mut self.edited_question, &mut doc.edited_question);
+        swap(&mut self.editing, &mut doc.editing);
+        swap(&mut self.editor_cursor, &mut doc.editor_cursor);
+        swap(&mut self.dirty, &mut doc.dirty);
+        swap(&mut self.edited_at, &mut doc.edited_at);
+        swap(&mut self.watcher, &mut doc.watcher);
+        swap(&mut self.disk_changed, &mut doc.disk_changed);
+        swap(&mut self.in_ai_context, &mut doc.in_ai_context);
+    }
+
+    /// Move the active document into its slot, leaving blank state behind.
+    fn park_active_document(&mut self) {
+        let mut parked = OpenDocument::default();
+        self.exchange_document(&mut parked);
+        self.documents[self.active_document] = parked;
+    }
+
+    fn switch_document(&mut self, index: usize) {
+        if index == self.active_document || index >= self.documents.len() {
+            return;
+        }
+        self.park_active_document();
+        let mut incoming = std::mem::take(&mut self.documents[index]);
+        self.exchange_document(&mut incoming);
+        self.active_document = index;
+        self.restore_scroll = true;
+    }
+
+    /// Add an empty tab and make it active.
+    fn new_tab(&mut self) {
+        self.park_active_document();
+        self.documents.push(OpenDocument::default());
+        self.active_document = self.documents.len() - 1;
+    }
+
+    /// Close the tab `index`, throwing away its unsaved edits; see
+    /// `show_close_prompt` for asking first.
+    fn close_document(&mut self, index: usize) {
+        self.closing_tab = None;
+        if self.documents.len() == 1 {
+            let mut closed = OpenDocument::default();
+            self.exchange_document(&mut closed);
+            self.load_error = Some("No file open".to_string());
+            forget_recovery(&closed);
+            return;
+        }
+
+        if index == self.active_document {
+            let next = if index + 1 < self.documents.len() {
+                index + 1
+            } else {
+                index - 1
+            };
+            self.switch_document(next);
+        }
+        let closed = self.documents.remove(index);
+        forget_recovery(&closed);
+        if index < self.active_document {
+            self.active_document -= 1;
+        }
+        if let Some(pane) = &mut self.split_pane {
+            pane.document = match pane.document {
+                Some(shown) if shown == index => None,
+                Some(shown) if shown > index => Some(shown - 1),
+                shown => shown,
+            };
+        }
+    }
+
+    /// Index of the tab showing the file with canonical path `key`.
+    fn find_document(&self, key: &str) -> Option<usize> {
+        if self.document_key().as_deref() == Some(key) {
+            return Some(self.active_document);
+        }
+        self.documents.iter().position(|doc| {
+            doc.file_path
+                .as_deref()
+                .filter(|_| !doc.streamed)
+                .and_then(|path| fs::canonicalize(path).ok())
+                .is_some_and(|path| path.to_string_lossy() == key)
+        })
+    }
+
+    fn show_tab_bar(&mut self, ctx: &egui::Context) {
+        let mut switch = None;
+        let mut close = None;
+
+        egui::TopBottomPanel::top("tab_bar").show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for i in 0..self.documents.len() {
+                    let (path, dirty, revision) = if i == self.active_document {
+                        (
+                            self.file_path.as_deref(),
+                            self.dirty,
+                            self.revision.as_deref(),
+                        )
+                    } else {
+                        let doc = &self.documents[i];
+                        (doc.file_path.as_deref(), doc.dirty, doc.revision.as_deref())
+                    };
+                    let mut title = display_name(path);
+                    if let Some(revision) = revision {
+                        title = format!("{} @ {}", title, revision);
+                    }
+                    if dirty {
+                        title.push_str(" ●");
+                    }
+                    let tab = ui
+                        .selectable_label(i == self.active_document, title)
+                        .on_hover_text(path.unwrap_or(""));
+                    if tab.clicked() {
+                        switch = Some(i);
+                    }
+                    if tab.middle_clicked() || ui.small_button("×").clicked() {
+                        close = Some(i);
+                    }
+                    ui.separator();
+                }
+            });
+        });
+
+        if let Some(i) = close {
+            let dirty = if i == self.active_document {
+                self.dirty
+            } else {
+                self.documents[i].dirty
+            };
+            if dirty {
+                self.closing_tab = Some(i);
+            } else {
+                self.close_document(i);
+            }
+        } else if let Some(i) = switch {
+            self.switch_document(i);
+        }
+    }
+
+    fn handle_command_keys(&mut self, ctx: &egui::Context) {
+        let typing = ctx.memory(|m| m.focus().is_some()) || self.editing;
+        // Stopping needs an answer being written. Escape also closes popups
+        // and the find bar, which come first.
+        let loading = self.ai_state.is_loading();
+        let escape_taken = self.escape_closes_something();
+        let pressed: Vec<Command> = ctx.input_mut(|i| {
+            self.keymap
+                .iter()
+                .filter(|(command, _)| !(typing && command.acts_on_view()))
+                .filter(|(command, shortcut)| {
+                    *command != Command::StopAnswer
+                        || (loading && !(escape_taken && shortcut.key == egui::Key::Escape))
+                })
+                .filter(|(_, shortcut)| i.consume_shortcut(shortcut))
+                .map(|&(command, _)| command)
+                .collect()
+        });
+        for command in pressed {
+            self.run_command(command, ctx);
+        }
+    }
+
+    /// Whether Escape has something else to close or cancel first: the find
+    /// bar, the bookmark jump list, the go-to-line and command popups, or a
+    /// half-typed vim command.
+    fn escape_closes_something(&self) -> bool {
+        self.show_search
+            || self.show_jump_list
+            || self.goto_input.is_some()
+            || self.command_palette.is_some()
+            || (self.vim_keys && !self.vim_pending.is_empty())
+    }
+
+    /// Whether `command` applies to the current document, for the palette.
+    fn command_available(&self, command: Command) -> bool {
+        match command {
+            Command::Save => self.dirty,
+            Command::CompareWith => self.can_compare(),
+            Command::ExportHtml | Command::ExportPdf | Command::ExportThemedPdf => {
+                self.can_export()
+            }
+            Command::CopyAnsi | Command::CopyHtml => {
+                self.can_export() && self.selection.as_ref().is_some_and(|r| !r.is_empty())
+            }
+            Command::GoToLine => self.can_go_to(),
+            Command::JumpToBracket => self.selection.is_some(),
+            Command::ConvertLineEndings | Command::ConvertIndentation => self.editing,
+            Command::NextTab | Command::PreviousTab => self.documents.len() > 1,
+            Command::NextBookmark | Command::PreviousBookmark => {
+                !self.bookmarked_lines().is_empty()
+            }
+            Command::FoldAll | Command::UnfoldAll => !self.fold_regions.is_empty(),
+            Command::ToggleBlame => self.git.is_some(),
+            Command::Summarize | Command::AskAi => !self.ai_state.is_loading(),
+            Command::StopAnswer => self.ai_state.is_loading(),
+            Command::CopyLastAnswer => self.last_answer().is_some(),
+            Command::AnnotateFile => self.can_annotate(),
+            Command::AiEdit => self.can_edit() && !self.ai_edit.lock().unwrap().running,
+            Command::DocumentSelection => self.can_document_selection(),
+            Command::SuggestTests => self.can_suggest_tests(),
+            _ => true,
+        }
+    }
+
+    fn run_command(&mut self, command: Command, ctx: &egui::Context) {
+        match command {
+            Command::ShowPalette => {
+                self.command_palette = match self.command_palette {
+                    Some(_) => None,
+                    None => Some(CommandPalette::default()),
+                };
+            }
+            Command::OpenFile => self.show_open_dialog(ctx),
+            Command::OpenFolder => self.show_open_folder_dialog(),
+            Command::Save => {
+                if self.dirty {
+                    self.save_document();
+                }
+            }
+            Command::CompareWith => {
+                if self.can_compare() {
+                    self.show_compare_dialog();
+                }
+            }
+            Command::Find => {
+                self.show_search = true;
+                self.focus_search = true;
+            }
+            Command::GoToLine => {
+                if self.can_go_to() {
+                    self.goto_input = Some(String::new());
+                }
+            }
+            Command::JumpToBracket => self.jump_to_bracket(),
+            Command::ConvertLineEndings | Command::ConvertIndentation => {
+                if self.editing {
+                    // Offer the conversion away from what the document uses now.
+                    let (_, crlf) = line_ending_counts(&self.file_content);
+                    let tabs = self.file_content.lines().any(|line| line.starts_with('\t'));
+                    self.conversion = Some(match command {
+                        Command::ConvertLineEndings if crlf > 0 => Conversion::Lf,
+                        Command::ConvertLineEndings => Conversion::Crlf,
+                        _ if tabs => Conversion::TabsToSpaces,
+                        _ => Conversion::SpacesToTabs,
+                    });
+                }
+            }
+            Command::NextTab | Command::PreviousTab => {
+                let count = self.documents.len();
+                if count > 1 {
+                    let step = if command == Command::NextTab {
+                        1
+                    } else {
+                        count - 1
+                    };
+                    self.switch_document((self.active_document + step) % count);
+                }
+            }
+            Command::ToggleBookmark => self.toggle_bookmark(self.current_line()),
+            Command::BookmarkList => self.show_jump_list = !self.show_jump_list,
+            Command::NextBookmark | Command::PreviousBookmark => {
+                let lines = self.bookmarked_lines();
+                let current = self.current_line();
+                // Wrap around at either end.
+                let target = if command == Command::NextBookmark {
+                    lines.iter().find(|&&l| l > current).or(lines.first())
+                } else {
+                    lines.iter().rev().find(|&&l| l < current).or(lines.last())
+                };
+                if let Some(&line) = target {
+                    self.go_to(line, None);
+                }
+            }
+            Command::FoldAll => self.fold_all(true),
+            Command::UnfoldAll => self.fold_all(false),
+            Command::ToggleAiPanel => self.show_ai_panel = !self.show_ai_panel,
+            Command::AskAi => {
+                self.show_ai_panel = true;
+                self.focus_question = true;
+            }
+            Command::Summarize => {
+                if !self.ai_state.is_loading() {
+                    self.show_ai_panel = true;
+                    self.generate_initial_summary();
+                }
+            }
+            Command::AnnotateFile => {
+                if self.can_annotate() {
+                    self.annotate_file();
+                }
+            }
+            Command::AiEdit => {
+                if self.can_edit() {
+                    self.start_ai_edit();
+                }
+            }
+            Command::DocumentSelection => {
+                if self.can_document_selection() {
+                    self.document_selection();
+                }
+            }
+            Command::SuggestTests => {
+                if self.can_suggest_tests() {
+                    self.suggest_tests();
+                }
+            }
+            Command::CompareModels => self.show_comparison = !self.show_comparison,
+            Command::StopAnswer => self.cancel_ai_request(),
+            Command::CopyLastAnswer => {
+                if let Some(answer) = self.last_answer() {
+                    ctx.output_mut(|o| o.copied_text = answer);
+                }
+            }
+            Command::ToggleLineNumbers => self.show_line_numbers = !self.show_line_numbers,
+            Command::ToggleMinimap => self.show_minimap = !self.show_minimap,
+            Command::ToggleOutline => self.show_outline = !self.show_outline,
+            Command::ToggleLogMode => self.log_mode = !self.log_mode,
+            Command::ShowStatistics => self.show_text_stats = !self.show_text_stats,
+            Command::ToggleBlame => self.show_blame = self.git.is_some() && !self.show_blame,
+            Command::ToggleSplit => {
+                self.split_pane = match self.split_pane {
+                    Some(_) => None,
+                    None => Some(SplitPane::default()),
+                };
+            }
+            Command::ToggleFocusMode => self.focus_mode = !self.focus_mode,
+            Command::ToggleDarkMode => self.set_dark_mode(!self.dark_mode, ctx),
+            Command::ToggleVimKeys => {
+                self.vim_keys = !self.vim_keys;
+                self.vim_pending.clear();
+            }
+            Command::OpenSettings => self.show_settings = true,
+            Command::ZoomIn => self.set_font_size(self.font_size + 1.0),
+            Command::ZoomOut => self.set_font_size(self.font_size - 1.0),
+            Command::ResetZoom => self.set_font_size(DEFAULT_FONT_SIZE),
+            Command::ExportHtml => {
+                if self.can_export() {
+                    self.export_html();
+                }
+            }
+            Command::ExportPdf | Command::ExportThemedPdf => {
+                if self.can_export() {
+                    self.export_pdf(command == Command::ExportPdf);
+                }
+            }
+            Command::CopyAnsi => self.copy_selection(ctx, CopyFormat::Ansi),
+            Command::CopyHtml => self.copy_selection(ctx, CopyFormat::Html),
+        }
+    }
+
+    /// Ctrl+scroll or a pinch scales the content font, like the zoom keys.
+    fn handle_zoom_gesture(&mut self, ctx: &egui::Context) {
+        let zoom = ctx.input(|i| i.zoom_delta());
+        if zoom != 1.0 {
+            self.set_font_size(self.font_size * zoom);
+        }
+    }
+
+    /// Resize the document text, leaving the rest of the UI alone, and
+    /// briefly show the new zoom level.
+    fn set_font_size(&mut self, size: f32) {
+        self.font_size = size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        self.zoom_changed_at = Some(Instant::now());
+    }
+
+    fn show_zoom_overlay(&mut self, ctx: &egui::Context) {
+        const SHOWN: Duration = Duration::from_millis(900);
+        let Some(at) = self.zoom_changed_at else {
+            return;
+        };
+        if at.elapsed() >= SHOWN {
+            self.zoom_changed_at = None;
+            return;
+        }
+        egui::Area::new("zoom_overlay")
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.heading(format!(
+                        "🔍 {:.0}%",
+                        self.font_size / DEFAULT_FONT_SIZE * 100.0
+                    ));
+                });
+            });
+        ctx.request_repaint_after(SHOWN - at.elapsed());
+    }
+
+    fn can_go_to(&self) -> bool {
+        !self.editing && self.gguf_info.is_none() && self.hex_data.is_none()
+    }
+
+    /// Commands, then themes, matching the palette's query, best first.
+    fn palette_items(&self, query: &str) -> Vec<(String, PaletteItem)> {
+        let commands = Command::ALL
+            .into_iter()
+            .filter(|&command| command != Command::ShowPalette)
+            .filter(|&command| self.command_available(command))
+            .map(|command| (command.title().to_string(), PaletteItem::Run(command)));
+        let themes = self
+            .theme_set
+            .themes
+            .keys()
+            .filter(|name| **name != self.theme_name)
+            .map(|name| (format!("Theme: {}", name), PaletteItem::Theme(name.clone())));
+        let mut items: Vec<(i32, String, PaletteItem)> = commands
+            .chain(themes)
+            .filter_map(|(title, item)| Some((fuzzy_score(query, &title)?, title, item)))
+            .collect();
+        // Stable, so an empty query keeps the commands in their usual order.
+        items.sort_by_key(|(score, ..)| -score);
+        items
+            .into_iter()
+            .map(|(_, title, item)| (title, item))
+            .collect()
+    }
+
+    fn show_command_palette(&mut self, ctx: &egui::Context) {
+        let Some(query) = self.command_palette.as_ref().map(|p| p.query.clone()) else {
+            return;
+        };
+        let mut items = self.palette_items(&query);
+        let (up, down) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+            )
+        });
+        let palette = self.command_palette.as_mut().unwrap();
+        if down {
+            palette.selected += 1;
+        }
+        if up {
+            palette.selected = palette.selected.saturating_sub(1);
+        }
+        palette.selected = palette.selected.min(items.len().saturating_sub(1));
+
+        let mut close = false;
+        let mut chosen = None;
+        egui::Window::new("Command palette")
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut palette.query)
+                        .hint_text("Type a command")
+                        .desired_width(360.0),
+                );
+                response.request_focus();
+                if response.changed() {
+                    palette.selected = 0;
+                }
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    close = true;
+                } else if ui.input(|i| i.key_pressed(egui::Key::Enter)) && !items.is_empty() {
+                    chosen = Some(palette.selected);
+                }
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(320.0)
+                    .show(ui, |ui| {
+                        if items.is_empty() {
+                            ui.weak("No matching commands");
+                        }
+                        for (i, (title, item)) in items.iter().enumerate() {
+                            let shortcut = match item {
+                                PaletteItem::Run(command) => self
+                                    .keymap
+                                    .iter()
+                                    .find(|(c, _)| c == command)
+                                    .map(|(_, shortcut)| ctx.format_shortcut(shortcut)),
+                                PaletteItem::Theme(_) => None,
+                            };
+                            let selected = i == palette.selected;
+                            let response = ui
+                                .horizontal(|ui| {
+                                    let row = ui.selectable_label(selected, title);
+                                    if let Some(shortcut) = shortcut {
+                                        ui.with_layout(
+                                            egui::Layout::right_to_left(egui::Align::Center),
+                                            |ui| ui.weak(shortcut),
+                                        );
+                                    }
+                                    row
+                                })
+                                .inner;
+                            if selected && (up || down) {
+                                response.scroll_to_me(None);
+                            }
+                            if response.clicked() {
+                                chosen = Some(i);
+                            }
+                        }
+                    });
+            });
+
+        if let Some(i) = chosen {
+            close = true;
+            match items.swap_remove(i).1 {
+                PaletteItem::Run(command) => {
+                    self.command_palette = None;
+                    self.run_command(command, ctx);
+                }
+                PaletteItem::Theme(name) => self.set_theme(name),
+            }
+        }
+        if close {
+            self.command_palette = None;
+        }
+    }
+
+    fn show_goto_window(&mut self, ctx: &egui::Context) {
+        let Some(input) = &mut self.goto_input else {
+            return;
+        };
+        let lines = self.highlighted_content.len().max(1);
+        let target = parse_goto(input);
+        let mut close = false;
+        let mut go = false;
+        egui::Window::new("Go to line")
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(input)
+                        .hint_text(format!("Line or line:column (1–{})", lines))
+                        .desired_width(220.0),
+                );
+                response.request_focus();
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    close = true;
+                } else if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    go = target.is_some();
+                }
+                if !input.trim().is_empty() && target.is_none() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, "Enter a line number");
+                }
+            });
+
+        if let Some((line, column)) = target.filter(|_| go) {
+            self.go_to(line.min(lines - 1), column);
+            close = true;
+        }
+        if close {
+            self.goto_input = None;
+        }
+    }
+
+    /// Scroll to a 0-based line, select the word at `column` if given, and
+    /// flash the line.
+    fn go_to(&mut self, line: usize, column: Option<usize>) {
+        match column {
+            Some(column) => self.select_at(line, column),
+            None => self.selection = None,
+        }
+        self.scroll_to_line = Some(line);
+        self.preview_target = Some(line);
+        self.flash_line = Some((line, Instant::now()));
+    }
+
+    /// Select the bracket matching the one next to the selection, or else
+    /// the opening bracket around it.
+    fn jump_to_bracket(&mut self) {
+        let Some(range) = self.selection.clone() else {
+            return;
+        };
+        let target = match bracket_pair_at(&self.file_content, &range) {
+            Some((_, other)) => other,
+            None => match enclosing_brackets(&self.file_content, &range) {
+                Some((open, _)) => open,
+                None => return,
+            },
+        };
+        self.selection = Some(target..target + 1);
+        self.scroll_to_line = Some(self.file_content[..target].matches('\n').count());
+    }
+
+    /// The fading highlight of a line just jumped to, if it is in `first..=last`.
+    fn flash_fill(&self, first: usize, last: usize) -> Option<egui::Color32> {
+        const FLASH: Duration = Duration::from_millis(1200);
+        let (line, at) = self.flash_line?;
+        let elapsed = at.elapsed();
+        if !(first..=last).contains(&line) || elapsed >= FLASH {
+            return None;
+        }
+        let fade = 1.0 - elapsed.as_secs_f32() / FLASH.as_secs_f32();
+        Some(egui::Color32::from_rgba_unmultiplied(
+            255,
+            170,
+            0,
+            (110.0 * fade) as u8,
+        ))
+    }
+
+    /// Ask Ollama which models are installed, in the background.
+    fn refresh_models(&self, ctx: &egui::Context) {
+        let discovery = self.model_discovery.clone();
+        let ollama_url = self.ollama_url.clone();
+        let ctx = ctx.clone();
+        discovery.lock().unwrap().loading = true;
+
+        thread::spawn(move || {
+            let result = list_ollama_models(&ollama_url);
+            let mut discovery = discovery.lock().unwrap();
+            discovery.loading = false;
+            discovery.fetched = Some(result);
+            ctx.request_repaint();
+        });
+    }
+
+    fn poll_model_discovery(&mut self) {
+        let Some(result) = self.model_discovery.lock().unwrap().fetched.take() else {
+            return;
+        };
+
+        self.ollama_down = result.is_err();
+        match result {
+            Ok(models) if models.is_empty() => {
+                self.model_discovery_error =
+                    Some("No models installed; run `ollama pull <model>`".to_string());
+                self.missing_model = Some(self.selected_model.clone()).filter(|m| !m.is_empty());
+            }
+            Ok(models) => {
+                self.available_models = models.iter().map(|m| m.name.clone()).collect();
+                if !self.available_models.contains(&self.selected_model) {
+                    self.missing_model = Some(self.selected_model.clone());
+                    self.selected_model = self.available_models[0].clone();
+                }
+                self.installed_models = models;
+                self.model_discovery_error = None;
+            }
+            // Keep the previous list so a stopped server does not empty the picker.
+            Err(e) => self.model_discovery_error = Some(e),
+        }
+    }
+
+    /// Download `missing_model` in the background; the AI panel shows the
+    /// progress.
+    fn pull_missing_model(&self, ctx: &egui::Context) {
+        let Some(model) = self.missing_model.clone() else {
+            return;
+        };
+        let pull = self.model_pull.clone();
+        let ollama_url = self.ollama_url.clone();
+        let ctx = ctx.clone();
+        *pull.lock().unwrap() = ModelPull {
+            model: model.clone(),
+            running: true,
+            status: "Starting download...".to_string(),
+            ..ModelPull::default()
+        };
+
+        thread::spawn(move || {
+            let result = pull_ollama_model(&ollama_url, &model, |progress| {
+                let mut pull = pull.lock().unwrap();
+                pull.status = progress.status.clone();
+                pull.completed = progress.completed;
+                pull.total = progress.total;
+                ctx.request_repaint();
+            });
+            let mut pull = pull.lock().unwrap();
+            pull.running = false;
+            match result {
+                Ok(()) => pull.finished = true,
+                Err(e) => pull.error = Some(e),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Banners at the top of the AI panel: the Ollama server cannot be
+    /// reached, or the chosen model is not installed and can be pulled.
+    fn show_ollama_banner(&mut self, ui: &mut egui::Ui) {
+        let finished = {
+            let mut pull = self.model_pull.lock().unwrap();
+            std::mem::take(&mut pull.finished).then(|| pull.model.clone())
+        };
+        if let Some(model) = finished {
+            self.missing_model = None;
+            self.selected_model = model;
+            self.refresh_models(ui.ctx());
+        }
+
+        let loading = self.model_discovery.lock().unwrap().loading;
+        let warning_fill = egui::Color32::from_rgba_unmultiplied(230, 170, 40, 40);
+        if self.ollama_down {
+            egui::Frame::none()
+                .fill(warning_fill)
+                .rounding(4.0)
+                .inner_margin(6.0)
+                .show(ui, |ui| {
+                    ui.strong(format!("⚠ Ollama is not reachable at {}", self.ollama_url));
+                    ui.label("Start it with `ollama serve`, then retry.");
+                    if let Some(error) = &self.model_discovery_error {
+                        ui.weak(error);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!loading, egui::Button::new("🔄 Retry"))
+                            .clicked()
+                        {
+                            self.refresh_models(ui.ctx());
+                        }
+                        if loading {
+                            ui.spinner();
+                        }
+                    });
+                });
+            return;
+        }
+
+        let Some(model) = self.missing_model.clone() else {
+            return;
+        };
+        let mut dismiss = false;
+        egui::Frame::none()
+            .fill(warning_fill)
+            .rounding(4.0)
+            .inner_margin(6.0)
+            .show(ui, |ui| {
+                let pull = self.model_pull.lock().unwrap();
+                let pulling = pull.running && pull.model == model;
+                ui.horizontal(|ui| {
+                    ui.strong(format!("{} is not installed", model));
+                    if !pulling && ui.small_button("✖").clicked() {
+                        dismiss = true;
+                    }
+                });
+                if pulling {
+                    let fraction = match pull.total {
+                        0 => 0.0,
+                        total => pull.completed as f32 / total as f32,
+                    };
+                    let text = match pull.total {
+                        0 => pull.status.clone(),
+                        total => format!(
+                            "{} ({} of {})",
+                            pull.status,
+                            format_size(pull.completed),
+                            format_size(total)
+                        ),
+                    };
+                    ui.add(egui::ProgressBar::new(fraction).text(text));
+                    return;
+                }
+                if let Some(error) = pull.error.as_ref().filter(|_| pull.model == model) {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                }
+                drop(pull);
+                if ui.button(format!("⬇ Pull {}", model)).clicked() {
+                    self.pull_missing_model(ui.ctx());
+                }
+            });
+        if dismiss {
+            self.missing_model = None;
+        }
+    }
+
+    fn load_file(&mut self, path: &str) {
+        self.replace_undo.clear();
+        if path.to_lowercase().ends_with(".gguf") {
+            match fs::File::open(path).and_then(|file| parse_gguf(io::BufReader::new(file))) {
+                Ok(info) => {
+                    self.file_content = info.to_text();
+                    self.file_path = Some(path.to_string());
+                    self.gguf_info = Some(info);
+                    self.hex_data = None;
+                    self.image_preview = None;
+                    self.git = None;
+                    self.encoding = None;
+                    self.load_error = None;
+                    self.highlighted_content.clear();
+                    self.highlight_task = None;
+                    self.log_lines.clear();
+                    self.follower = None;
+                    self.scan_sensitive_content();
+                }
+                Err(e) => self.load_error = Some(format!("Could not read {}: {}", path, e)),
+            }
+            return;
+        }
+
+        if ArchiveKind::of(path).is_some() {
+            return self.load_archive(path);
+        }
+
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.load_error = Some(format!("Could not read {}: {}", path, e));
+                return;
+            }
+        };
+        self.archive = None;
+        if is_image_path(path) {
+            return self.load_image(path, bytes);
+        }
+        let content = match decode_file(bytes, self.encoding_override) {
+            Ok((content, encoding)) => {
+                self.encoding = Some(encoding);
+                content
+            }
+            Err(bytes) => return self.load_binary(path, bytes),
+        };
+
+        if self.file_path.as_deref() != Some(path) {
+            self.folded.clear();
+            self.log_mode = self.log_dir.is_some()
+                || Path::new(path)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("log"));
+        }
+        self.file_content = content;
+        self.file_path = Some(path.to_string());
+        self.gguf_info = None;
+        self.hex_data = None;
+        self.image_preview = None;
+        self.json_tree = None;
+        self.git = GitFile::open(path, &self.file_content);
+        self.history = None;
+        self.load_error = None;
+        self.prose_blocks = prose_blocks(&self.file_content);
+        self.update_fold_regions();
+        self.log_lines = LogLines::new(&self.file_content);
+        self.follower = None;
+        self.selection = None;
+        self.selection_history.clear();
+        self.highlight_content();
+        self.scan_sensitive_content();
+    }
+
+    /// List an archive's entries in the sidebar. The document is the
+    /// listing, so the AI can be asked about the archive as a whole.
+    fn load_archive(&mut self, path: &str) {
+        match Archive::open(path) {
+            Ok(archive) => {
+                self.file_content = archive.to_text();
+                self.file_path = Some(path.to_string());
+                self.archive = Some(archive);
+                self.gguf_info = None;
+                self.hex_data = None;
+                self.image_preview = None;
+                self.git = None;
+                self.encoding = None;
+                self.load_error = None;
+                self.prose_blocks.clear();
+                self.update_fold_regions();
+                self.log_lines.clear();
+                self.follower = None;
+                self.selection = None;
+                self.selection_history.clear();
+                self.highlight_content();
+                self.scan_sensitive_content();
+            }
+            Err(e) => self.load_error = Some(format!("Could not read {}: {}", path, e)),
+        }
+    }
+
+    /// Open the archive entry `name` read-only: in the current tab when it
+    /// shows another entry, else in a new one.
+    fn open_archive_entry(&mut self, name: &str, new_tab: bool) {
+        let Some(archive) = self.archive.clone() else {
+            return;
+        };
+        let path = archive.entry_path(name);
+        if self.file_path.as_deref() == Some(&path) {
+            return;
+        }
+        if let Some(index) = self
+            .documents
+            .iter()
+            .position(|doc| doc.streamed && doc.file_path.as_deref() == Some(&path))
+        {
+            return self.switch_document(index);
+        }
+
+        if new_tab || !self.streamed {
+            self.new_tab();
+        } else {
+            let mut previous = OpenDocument::default();
+            self.exchange_document(&mut previous);
+        }
+        self.streamed = true;
+        self.archive = Some(archive);
+        let bytes = match self.archive.as_ref().map(|archive| archive.read(name)) {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(e)) => {
+                self.file_path = Some(path);
+                self.load_error = Some(format!("Could not read {}: {}", name, e));
+                return;
+            }
+            None => return,
+        };
+        if is_image_path(&path) {
+            return self.load_image(&path, bytes);
+        }
+        match decode_file(bytes, None) {
+            Ok((content, encoding)) => {
+                self.file_path = Some(path);
+                self.file_content = content;
+                self.encoding = Some(encoding);
+                self.prose_blocks = prose_blocks(&self.file_content);
+                self.update_fold_regions();
+                self.log_lines = LogLines::new(&self.file_content);
+                self.highlight_content();
+                self.scan_sensitive_content();
+            }
+            Err(bytes) => self.load_binary(&path, bytes),
+        }
+    }
+
+    /// The entries of the current archive, opened on click; Ctrl+click or
+    /// middle-click opens a new tab, as in the file tree.
+    fn show_archive_panel(&mut self, ctx: &egui::Context) {
+        let Some(archive) = &mut self.archive else {
+            return;
+        };
+        let current = self.file_path.as_deref();
+        // The entry, whether in a new tab, and whether to summarize it.
+        let mut clicked: Option<(String, bool, bool)> = None;
+
+        egui::SidePanel::left("archive")
+            .resizable(true)
+            .default_width(240.0)
+            .show(ctx, |ui| {
+                ui.strong(format!("🗜 {}", display_name(Some(&archive.path))))
+                    .on_hover_text(&archive.path);
+                ui.weak(format!("{} files", archive.entries.len()));
+                ui.add(
+                    egui::TextEdit::singleline(&mut archive.filter)
+                        .hint_text("Filter entries…")
+                        .desired_width(f32::INFINITY),
+                );
+                ui.separator();
+
+                let entries = archive.matching_entries();
+                let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                egui::ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .show_rows(ui, row_height, entries.len(), |ui, rows| {
+                        for entry in &entries[rows] {
+                            let path = archive.entry_path(&entry.name);
+                            let response = ui
+                                .selectable_label(current == Some(&path), &entry.name)
+                                .on_hover_text(format!(
+                                    "{} · Ctrl+click or middle-click to open in a new tab",
+                                    format_size(entry.size)
+                                ));
+                            let new_tab = response.middle_clicked()
+                                || (response.clicked() && ui.input(|i| i.modifiers.command));
+                            if new_tab || response.clicked() {
+                                clicked = Some((entry.name.clone(), new_tab, false));
+                            }
+                            response.context_menu(|ui| {
+                                if ui.button("Open in new tab").clicked() {
+                                    clicked = Some((entry.name.clone(), true, false));
+                                    ui.close_menu();
+                                }
+                                if ui.button("✨ Summarize").clicked() {
+                                    clicked = Some((entry.name.clone(), false, true));
+                                    ui.close_menu();
+                                }
+                            });
+                        }
+                    });
+            });
+
+        if let Some((name, new_tab, summarize)) = clicked {
+            self.open_archive_entry(&name, new_tab);
+            if summarize && self.load_error.is_none() && !self.ai_state.is_loading() {
+                self.show_ai_panel = true;
+                self.generate_initial_summary();
+            }
+        }
+    }
+
+    /// Show an image file, or its hex dump when it can't be decoded. The
+    /// text the AI features see describes the image.
+    fn load_image(&mut self, path: &str, bytes: Vec<u8>) {
+        let preview = ImagePreview::decode(path, &bytes);
+        self.load_binary(path, bytes);
+        // Images that don't decode stay in the hex view.
+        if let Ok(preview) = preview {
+            self.file_content = preview.summary(&self.file_name());
+            self.image_preview = Some(preview);
+            self.scan_sensitive_content();
+        }
+    }
+
+    /// Show `bytes` in the hex viewer. The text the AI features see is a
+    /// dump of the first few KiB, as with GGUF files.
+    fn load_binary(&mut self, path: &str, bytes: Vec<u8>) {
+        self.file_content = hex_dump_preview(&bytes);
+        self.file_path = Some(path.to_string());
+        self.gguf_info = None;
+        self.hex_data = Some(bytes);
+        self.image_preview = None;
+        self.git = None;
+        self.encoding = None;
+        self.load_error = None;
+        self.highlighted_content.clear();
+        self.highlight_task = None;
+        self.prose_blocks.clear();
+        self.fold_regions.clear();
+        self.folded.clear();
+        self.log_lines.clear();
+        self.follower = None;
+        self.selection = None;
+        self.selection_history.clear();
+        self.scan_sensitive_content();
+    }
+
+    fn show_hex_view(&mut self, ui: &mut egui::Ui) {
+        let len = self.hex_data.as_ref().map_or(0, Vec::len);
+        ui.horizontal(|ui| {
+            ui.strong(format!("Binary file, {} bytes", len));
+            ui.separator();
+            ui.label("Bytes per row:");
+            egui::ComboBox::from_id_source("hex_row_width")
+                .selected_text(self.hex_bytes_per_row.to_string())
+                .show_ui(ui, |ui| {
+                    for width in [8, 16, 24, 32] {
+                        ui.selectable_value(&mut self.hex_bytes_per_row, width, width.to_string());
+                    }
+                });
+        });
+        ui.separator();
+
+        let Some(bytes) = &self.hex_data else {
+            return;
+        };
+        let width = self.hex_bytes_per_row.max(1);
+        let font = egui::FontId::monospace(self.font_size);
+        let row_height = ui.fonts(|f| f.row_height(&font));
+        let offset_color = ui.visuals().weak_text_color();
+        let text_color = ui.visuals().text_color();
+
+        // Only the rows on screen are laid out, so file size does not matter.
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .show_rows(ui, row_height, bytes.len().div_ceil(width), |ui, rows| {
+                for row in rows {
+                    let start = row * width;
+                    let chunk = &bytes[start..(start + width).min(bytes.len())];
+                    let (hex, ascii) = hex_row(chunk, width);
+                    let mut job = egui::text::LayoutJob::default();
+                    let format = |color| egui::TextFormat::simple(font.clone(), color);
+                    job.append(&format!("{:08x}  ", start), 0.0, format(offset_color));
+                    job.append(&hex, 0.0, format(text_color));
+                    job.append(&format!(" |{}|", ascii), 0.0, format(offset_color));
+                    ui.label(job);
+                }
+            });
+    }
+
+    fn scan_sensitive_content(&mut self) {
+        let (redacted, redactions) = self.redactor.redact(&self.file_content);
+        self.redacted_content = redacted;
+        self.redactions = redactions;
+        self.pii_findings = scan_pii(self.prompt_content());
+        self.pii_confirmed = false;
+    }
+
+    fn redaction_opted_out(&self) -> bool {
+        self.file_path
+            .as_ref()
+            .is_some_and(|path| self.redaction_opt_outs.contains(path))
+    }
+
+    /// The document text as it may be sent to a model: redacted unless the user opted out.
+    fn prompt_content(&self) -> &str {
+        if self.redaction_opted_out() {
+            &self.file_content
+        } else {
+            &self.redacted_content
+        }
+    }
+
+    fn detect_syntax(&self) -> &SyntaxReference {
+        self.syntax_override().unwrap_or_else(|| {
+            detect_syntax(
+                &self.syntax_set,
+                self.file_path.as_deref(),
+                &self.file_content,
+            )
+        })
+    }
+
+    /// The syntax the user picked for this file, if any.
+    fn syntax_override(&self) -> Option<&SyntaxReference> {
+        let name = match &self.session_syntax {
+            Some(name) => name,
+            None => self.syntax_overrides.get(&self.document_key()?)?,
+        };
+        self.syntax_set.find_syntax_by_name(name)
+    }
+
+    /// Force the document's language (`None` goes back to detection) and re-highlight.
+    fn set_syntax_override(&mut self, name: Option<String>) {
+        let Some(key) = self.document_key() else {
+            if self.streamed {
+                self.session_syntax = name;
+                self.highlight_content();
+            }
+            return;
+        };
+        // A language picked here replaces one given with --language.
+        self.session_syntax = None;
+        match name {
+            Some(name) => self.syntax_overrides.insert(key, name),
+            None => self.syntax_overrides.remove(&key),
+        };
+        save_syntax_overrides(&self.syntax_overrides);
+        self.rehighlight();
+    }
+
+    /// Show the document as plain text right away and highlight it on a
+    /// worker thread; `poll_highlighting` swaps lines in as they are ready.
+    fn highlight_content(&mut self) {
+        let (lines, task) = self.start_highlighting(&self.file_content, self.detect_syntax());
+        self.highlighted_content = lines;
+        self.highlight_task = Some(task);
+    }
+
+    /// Plain lines to show until the highlighter catches up, and the
+    /// background job highlighting `text` as `syntax`.
+    fn start_highlighting(
+        &self,
+        text: &str,
+        syntax: &SyntaxReference,
+    ) -> (Vec<Vec<(Style, String)>>, HighlightTask) {
+        let default = self.default_text_style();
+        let lines = LinesWithEndings::from(text)
+            .map(|line| vec![(default, line.trim_end_matches(['\n', '\r']).to_string())])
+            .collect();
+
+        let job = Arc::new(Mutex::new(HighlightJob::default()));
+        let worker = job.clone();
+        let content = text.to_string();
+        let highlighter = self.highlighter(syntax);
+        thread::spawn(move || run_highlighter(content, highlighter, worker));
+
+        (lines, HighlightTask { job, next: 0 })
+    }
+
+    /// The engine chosen for `syntax` in `highlighters`, falling back to
+    /// syntect when tree-sitter is not built in or has no grammar for it.
+    fn highlighter(&self, syntax: &SyntaxReference) -> Box<dyn Highlighter> {
+        #[cfg(feature = "tree-sitter")]
+        if self.highlighters.get(&syntax.name) == Some(&HighlightBackend::TreeSitter) {
+            if let Some(highlighter) = TreeSitterHighlighter::new(&syntax.name, self.theme()) {
+                return Box::new(highlighter);
+            }
+        }
+        Box::new(SyntectHighlighter {
+            syntax_set: self.syntax_set.clone(),
+            syntax_name: syntax.name.clone(),
+            theme: self.theme().clone(),
+        })
+    }
+
+    /// Switch the highlighting engine of the current language and re-highlight.
+    #[cfg(feature = "tree-sitter")]
+    fn show_highlighter_toggle(&mut self, ui: &mut egui::Ui) {
+        let name = self.detect_syntax().name.clone();
+        if !tree_sitter::supports(&name) {
+            return;
+        }
+        let mut parsed = self.highlighters.get(&name) == Some(&HighlightBackend::TreeSitter);
+        if ui
+            .toggle_value(&mut parsed, "🌳")
+            .on_hover_text(format!("Highlight {} with tree-sitter", name))
+            .changed()
+        {
+            let backend = if parsed {
+                HighlightBackend::TreeSitter
+            } else {
+                HighlightBackend::Syntect
+            };
+            self.highlighters.insert(name, backend);
+            self.rehighlight();
+        }
+    }
+
+    /// Plain files can be edited; GGUF summaries, archive listings, binary
+    /// files, streams and watched directories cannot.
+    fn can_edit(&self) -> bool {
+        self.file_path.is_some()
+            && self.load_error.is_none()
+            && self.gguf_info.is_none()
+            && self.archive.is_none()
+            && self.hex_data.is_none()
+            && !self.streamed
+            && self.log_dir.is_none()
+    }
+
+    /// The document as an editable text field, coloured with the last
+    /// highlighting result for lines that have not changed since.
+    fn show_editor(&mut self, ui: &mut egui::Ui) {
+        let font = egui::FontId::monospace(self.font_size);
+        let text_color = ui.visuals().text_color();
+        let highlighted = &self.highlighted_content;
+        let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+            let format = |color| egui::TextFormat::simple(font.clone(), color);
+            let mut job = egui::text::LayoutJob::default();
+            for (i, line) in LinesWithEndings::from(text).enumerate() {
+                let body = line.trim_end_matches(['\n', '\r']);
+                let segments = highlighted.get(i).filter(|segments| {
+                    segments.iter().map(|(_, piece)| piece.len()).sum::<usize>() == body.len()
+                        && segments
+                            .iter()
+                            .map(|(_, piece)| piece.as_str())
+                            .collect::<String>()
+                            == body
+                });
+                match segments {
+                    Some(segments) => {
+                        for (style, piece) in segments {
+                            let color = egui::Color32::from_rgb(
+                                style.foreground.r,
+                                style.foreground.g,
+                                style.foreground.b,
+                            );
+                            job.append(piece, 0.0, format(color));
+                        }
+                        job.append(&line[body.len()..], 0.0, format(text_color));
+                    }
+                    None => job.append(line, 0.0, format(text_color)),
+                }
+            }
+            job.wrap.max_width = wrap_width;
+            ui.fonts(|f| f.layout_job(job))
+        };
+
+        let output = egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                egui::TextEdit::multiline(&mut self.file_content)
+                    .code_editor()
+                    .font(egui::FontId::monospace(self.font_size))
+                    .desired_width(f32::INFINITY)
+                    .lock_focus(true)
+                    .layouter(&mut layouter)
+                    .show(ui)
+            })
+            .inner;
+        let changed = output.response.changed();
+        if let Some(range) = output.cursor_range {
+            // The editor counts characters; the rest of the app works in bytes.
+            let byte = |index: usize| {
+                self.file_content
+                    .char_indices()
+                    .nth(index)
+                    .map_or(self.file_content.len(), |(i, _)| i)
+            };
+            self.editor_cursor = Some((
+                byte(range.secondary.ccursor.index),
+                byte(range.primary.ccursor.index),
+            ));
+        }
+
+        if changed {
+            self.dirty = true;
+            self.edited_at = Some(Instant::now());
+            // Byte ranges into the old text are meaningless now.
+            self.selection = None;
+            self.selection_history.clear();
+            self.replace_undo.clear();
+        }
+    }
+
+    /// Re-derive everything computed from the text once typing pauses.
+    fn poll_edits(&mut self, ctx: &egui::Context) {
+        const DEBOUNCE: Duration = Duration::from_millis(400);
+        let Some(edited_at) = self.edited_at else {
+            return;
+        };
+        let remaining = DEBOUNCE.saturating_sub(edited_at.elapsed());
+        if !remaining.is_zero() {
+            ctx.request_repaint_after(remaining);
+            return;
+        }
+        self.edited_at = None;
+
+        self.prose_blocks = prose_blocks(&self.file_content);
+        self.update_fold_regions();
+        if let Some(git) = &mut self.git {
+            git.update(&self.file_content);
+        }
+        if let Some(tree) = &mut self.json_tree {
+            tree.reparse(&self.file_content);
+        }
+        self.log_lines = LogLines::new(&self.file_content);
+
+        let previous = std::mem::take(&mut self.highlighted_content);
+        self.highlight_content();
+        // Keep the old colours of unchanged lines until the worker reaches them.
+        for (line, old) in self.highlighted_content.iter_mut().zip(previous) {
+            if old
+                .iter()
+                .map(|(_, piece)| piece.as_str())
+                .collect::<String>()
+                == line[0].1
+            {
+                *line = old;
+            }
+        }
+
+        // The user already agreed to send this document; edits do not change that.
+        let confirmed = self.pii_confirmed;
+        self.scan_sensitive_content();
+        self.pii_confirmed = confirmed;
+    }
+
+    fn save_document(&mut self) {
+        let Some(path) = self.file_path.as_deref() else {
+            return;
+        };
+        let encoding = self.encoding.unwrap_or(encoding_rs::UTF_8);
+        let written = encode_text(&self.file_content, encoding)
+            .and_then(|bytes| fs::write(path, bytes).map_err(|e| e.to_string()));
+        match written {
+            Ok(()) => {
+                self.dirty = false;
+                self.save_error = None;
+                if let Some(key) = self.document_key() {
+                    remove_recovery(&key);
+                }
+            }
+            Err(e) => self.save_error = Some(format!("Could not save {}: {}", path, e)),
+        }
+    }
+
+    /// Write the unsaved edits of every tab to the recovery directory, once
+    /// every `autosave_secs`.
+    fn autosave_if_due(&mut self) {
+        let interval = Duration::from_secs(self.autosave_secs.max(5));
+        if !self.autosave || self.pager || self.last_autosave.elapsed() < interval {
+            return;
+        }
+        self.last_autosave = Instant::now();
+        if self.dirty {
+            if let Some(key) = self.document_key() {
+                save_recovery(&key, &self.file_content);
+            }
+        }
+        for (i, doc) in self.documents.iter().enumerate() {
+            if i == self.active_document || !doc.dirty || doc.streamed {
+                continue;
+            }
+            if let Some(path) = &doc.file_path {
+                let key = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+                save_recovery(&key.to_string_lossy(), &doc.file_content);
+            }
+        }
+    }
+
+    /// Offer the edits autosaved before a crash: each restores into a tab
+    /// of its file, as unsaved changes, or is thrown away.
+    fn show_recovery_window(&mut self, ctx: &egui::Context) {
+        if self.recoveries.is_empty() {
+            return;
+        }
+        let now = unix_seconds(SystemTime::now());
+        let age = |saved_at: i64| match now - saved_at {
+            seconds if seconds < 60 => "just now".to_string(),
+            seconds if seconds < 3600 => format!("{} min ago", seconds / 60),
+            seconds if seconds < 86_400 => format!("{} h ago", seconds / 3600),
+            _ => format_date(saved_at),
+        };
+        let mut restore = None;
+        let mut discard = Vec::new();
+        egui::Window::new("♻ Recover unsaved edits")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("These files had unsaved edits when tty_doc last quit unexpectedly.");
+                ui.separator();
+                egui::Grid::new("recoveries").num_columns(3).show(ui, |ui| {
+                    for (i, recovery) in self.recoveries.iter().enumerate() {
+                        ui.label(display_name(Some(&recovery.path)))
+                            .on_hover_text(&recovery.path);
+                        ui.weak(age(recovery.saved_at));
+                        ui.horizontal(|ui| {
+                            if ui.button("Restore").clicked() {
+                                restore = Some(i);
+                            }
+                            if ui.button("Discard").clicked() {
+                                discard.push(i);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+                if ui.button("Discard all").clicked() {
+                    discard = (0..self.recoveries.len()).collect();
+                }
+            });
+
+        if let Some(i) = restore {
+            let recovery = self.recoveries.remove(i);
+            self.open_document(recovery.path.clone(), true, ctx);
+            if self.can_edit() {
+                self.file_content = recovery.content;
+                self.editing = true;
+                self.dirty = true;
+                self.edited_at = Some(Instant::now());
+                self.editor_cursor = None;
+                self.selection = None;
+                self.selection_history.clear();
+                self.replace_undo.clear();
+            }
+        }
+        for i in discard.into_iter().rev() {
+            let recovery = self.recoveries.remove(i);
+            remove_recovery(&recovery.path);
+        }
+    }
+
+    fn show_convert_window(&mut self, ctx: &egui::Context) {
+        let Some(mut conversion) = self.conversion.filter(|_| self.editing) else {
+            self.conversion = None;
+            return;
+        };
+        let (lf, crlf) = line_ending_counts(&self.file_content);
+        let (converted, changed) = convert(&self.file_content, conversion, self.tab_width);
+        let mut open = true;
+        let mut apply = false;
+        egui::Window::new("⇄ Convert")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.weak(format!("Line endings now: {} LF, {} CRLF", lf, crlf));
+                for option in Conversion::ALL {
+                    ui.radio_value(&mut conversion, option, option.label());
+                }
+                if conversion.is_indentation() {
+                    ui.horizontal(|ui| {
+                        ui.label("Tab width");
+                        ui.add(egui::DragValue::new(&mut self.tab_width).clamp_range(1..=16));
+                    });
+                }
+                ui.separator();
+                ui.label(match changed {
+                    0 => "No lines would change.".to_string(),
+                    1 => "1 line would change.".to_string(),
+                    n => format!("{} lines would change.", n),
+                });
+                apply = ui
+                    .add_enabled(changed > 0, egui::Button::new("Apply"))
+                    .clicked();
+            });
+
+        self.conversion = open.then_some(conversion);
+        if apply {
+            self.file_content = converted;
+            self.dirty = true;
+            self.edited_at = Some(Instant::now());
+            // Byte offsets into the old text are meaningless now.
+            self.editor_cursor = None;
+            self.selection = None;
+            self.selection_history.clear();
+            self.replace_undo.clear();
+            self.conversion = None;
+        }
+    }
+
+    /// Reload the document when the file changes on disk. Unsaved edits are
+    /// never thrown away without asking.
+    fn poll_file_changes(&mut self, ctx: &egui::Context) {
+        // Following reads appended lines itself.
+        if !self.can_edit() || self.follow {
+            self.watcher = None;
+            return;
+        }
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        if self.watcher.as_ref().is_none_or(|w| w.file != path) {
+            self.watcher = FileWatcher::new(&path, ctx.clone()).ok();
+        }
+        if !self.watcher.as_ref().is_some_and(FileWatcher::take_change) {
+            return;
+        }
+
+        let on_disk = fs::read(&path)
+            .map(|bytes| decode_file(bytes, self.encoding_override).map(|(text, _)| text));
+        match on_disk {
+            // Our own save, or a touch.
+            Ok(Ok(content)) if content == self.file_content => {}
+            Ok(_) if self.dirty || !self.auto_reload => self.disk_changed = true,
+            Ok(_) => self.reload_from_disk(),
+            // Removed or half-written; the next event will tell.
+            Err(_) => {}
+        }
+    }
+
+    fn reload_from_disk(&mut self) {
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        self.load_file(&path);
+        if let Some(key) = stale_recovery(Some(&path), self.dirty, self.streamed) {
+            remove_recovery(&key);
+        }
+        self.dirty = false;
+        self.edited_at = None;
+        self.disk_changed = false;
+        self.restore_scroll = true;
+        if self.summarize_on_reload && !self.ai_state.is_loading() {
+            self.generate_initial_summary();
+        }
+    }
+
+    /// Ask what to do with the unsaved edits of the tab being closed.
+    fn show_close_prompt(&mut self, ctx: &egui::Context) {
+        let Some(index) = self.closing_tab.filter(|&i| i < self.documents.len()) else {
+            self.closing_tab = None;
+            return;
+        };
+        let path = if index == self.active_document {
+            self.file_path.as_deref()
+        } else {
+            self.documents[index].file_path.as_deref()
+        };
+        let name = display_name(path);
+        let mut decision = None;
+        egui::Window::new("Unsaved changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!("{} has unsaved edits.", name));
+                ui.horizontal(|ui| {
+                    if ui.button("Save and close").clicked() {
+                        decision = Some(true);
+                    }
+                    if ui.button("Close without saving").clicked() {
+                        decision = Some(false);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.closing_tab = None;
+                    }
+                });
+            });
+
+        match decision {
+            Some(true) => {
+                self.switch_document(index);
+                self.save_document();
+                // A failed save keeps the tab open, showing why.
+                if !self.dirty {
+                    self.close_document(index);
+                } else {
+                    self.closing_tab = None;
+                }
+            }
+            Some(false) => self.close_document(index),
+            None => {}
+        }
+    }
+
+    fn show_reload_prompt(&mut self, ctx: &egui::Context) {
+        if !self.disk_changed {
+            return;
+        }
+        let mut decision = None;
+        egui::Window::new("File changed on disk")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} was changed by another program.",
+                    self.file_name()
+                ));
+                if self.dirty {
+                    ui.label("Reloading discards your unsaved edits.");
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Reload").clicked() {
+                        decision = Some(true);
+                    }
+                    if ui.button("Keep my version").clicked() {
+                        decision = Some(false);
+                    }
+                });
+            });
+
+        match decision {
+            Some(true) => self.reload_from_disk(),
+            Some(false) => self.disk_changed = false,
+            None => {}
+        }
+    }
+
+    /// Show the file name in the window title, marked while there are unsaved changes.
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let title = match (&self.file_path, self.dirty) {
+            (Some(_), true) => format!("● {} — tty_doc", self.file_name()),
+            (Some(_), false) => format!("{} — tty_doc", self.file_name()),
+            (None, _) => "tty_doc".to_string(),
+        };
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    fn theme(&self) -> &Theme {
+        self.theme_set
+            .themes
+            .get(&self.theme_name)
+            .unwrap_or_else(|| &self.theme_set.themes[DEFAULT_THEME])
+    }
+
+    /// Switch to theme `name` and re-highlight every open document.
+    fn set_theme(&mut self, name: String) {
+        if name == self.theme_name {
+            return;
+        }
+        self.theme_name = name;
+        self.snippet_cache.clear();
+        if let Some(mut diff) = self.diff.take() {
+            for side in [&mut diff.left, &mut diff.right] {
+                let text = std::mem::take(&mut side.text);
+                let revision = side.revision.take();
+                *side = self.diff_side(std::mem::take(&mut side.path), text);
+                side.revision = revision;
+            }
+            self.diff = Some(diff);
+        }
+
+        self.rehighlight();
+        for i in 0..self.documents.len() {
+            if i == self.active_document {
+                continue;
+            }
+            let mut doc = std::mem::take(&mut self.documents[i]);
+            self.exchange_document(&mut doc);
+            self.rehighlight();
+            self.exchange_document(&mut doc);
+            self.documents[i] = doc;
+        }
+    }
+
+    /// Switch the whole app between light and dark, moving the syntax theme
+    /// to its counterpart for the new mode.
+    fn set_dark_mode(&mut self, dark_mode: bool, ctx: &egui::Context) {
+        self.dark_mode = dark_mode;
+        ctx.set_visuals(visuals(dark_mode));
+        self.set_theme(self.theme_for_mode(dark_mode));
+    }
+
+    /// The current theme if it already suits the mode, otherwise its light or
+    /// dark sibling (`base16-ocean.dark` ↔ `base16-ocean.light`), otherwise a default.
+    fn theme_for_mode(&self, dark_mode: bool) -> String {
+        if is_dark_theme(self.theme()) == dark_mode {
+            return self.theme_name.clone();
+        }
+        let (from, to) = if dark_mode {
+            ("light", "dark")
+        } else {
+            ("dark", "light")
+        };
+        let sibling = self.theme_name.replace(from, to);
+        if self.theme_set.themes.contains_key(&sibling) {
+            sibling
+        } else if dark_mode {
+            DEFAULT_THEME.to_string()
+        } else {
+            DEFAULT_LIGHT_THEME.to_string()
+        }
+    }
+
+    /// The preferences in effect, including those given on the command line.
+    fn settings(&self) -> Config {
+        Config {
+            font_size: self.font_size,
+            theme: self.theme_name.clone(),
+            dark_mode: self.dark_mode,
+            provider: self.provider,
+            model: self.selected_model.clone(),
+            ollama_url: self.ollama_url.clone(),
+            llama_cpp_url: self.llama_cpp_url.clone(),
+            openai_url: self.openai_url.clone(),
+            openai_model: self.openai_model.clone(),
+            openai_key_env: self.openai_key_env.clone(),
+            anthropic_model: self.anthropic_model.clone(),
+            anthropic_key_env: self.anthropic_key_env.clone(),
+            embedding_model: self.embedding_model.clone(),
+            request_timeout_secs: self.request_timeout_secs,
+            request_retries: self.request_retries,
+            show_ai_panel: self.show_ai_panel,
+            ai_panel_width: self.ai_panel_width,
+            word_wrap: self.reflow_prose,
+            wrap_width: self.reading_width,
+            hex_bytes_per_row: self.hex_bytes_per_row,
+            show_minimap: self.show_minimap,
+            show_outline: self.show_outline,
+            rainbow_brackets: self.rainbow_brackets,
+            tab_width: self.tab_width,
+            vim_keys: self.vim_keys,
+            auto_reload: self.auto_reload,
+            autosave: self.autosave,
+            autosave_secs: self.autosave_secs,
+            summarize_on_open: self.summarize_on_open,
+            summarize_on_reload: self.summarize_on_reload,
+            system_prompt: self.custom_system_prompt.clone(),
+            keybindings: self.keybindings.clone(),
+            highlighters: self.highlighters.clone(),
+            file_type_prompts: self.file_type_prompts.clone(),
+            fonts: self.font_config.clone(),
+            generation: self.generation,
+        }
+    }
+
+    /// The preferences to save. A setting given on the command line is saved
+    /// only once changed in the app.
+    fn config(&self) -> Config {
+        let settings = self.settings();
+        let saved = &self.saved_config;
+        let cli = &self.cli;
+        Config {
+            font_size: unless_from_cli(settings.font_size, cli.font_size, saved.font_size),
+            theme: unless_from_cli(&settings.theme, cli.theme.as_ref(), &saved.theme).clone(),
+            model: unless_from_cli(&settings.model, cli.model.as_ref(), &saved.model).clone(),
+            ollama_url: unless_from_cli(
+                &settings.ollama_url,
+                cli.ollama_url.as_ref(),
+                &saved.ollama_url,
+            )
+            .clone(),
+            show_ai_panel: unless_from_cli(
+                settings.show_ai_panel,
+                cli.no_ai.then_some(false),
+                saved.show_ai_panel,
+            ),
+            ..settings
+        }
+    }
+
+    /// Write `config.toml` whenever a preference differs from what was last saved.
+    fn save_config_if_changed(&mut self) {
+        let config = self.config();
+        if config != self.saved_config {
+            save_config(&config);
+            self.saved_config = config;
+        }
+    }
+
+    fn show_generation_settings(&mut self, ui: &mut egui::Ui) {
+        let params = &mut self.generation;
+        egui::Grid::new("generation_params")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Temperature");
+                ui.add(egui::Slider::new(&mut params.temperature, 0.0..=2.0));
+                ui.end_row();
+
+                ui.label("Top P");
+                ui.add(egui::Slider::new(&mut params.top_p, 0.0..=1.0))
+                    .on_hover_text("1.0 turns nucleus sampling off");
+                ui.end_row();
+
+                ui.label("Top K");
+                ui.add(egui::DragValue::new(&mut params.top_k).clamp_range(0..=500))
+                    .on_hover_text("0 turns top-k sampling off; OpenAI ignores it");
+                ui.end_row();
+
+                ui.label("Max tokens");
+                ui.add(
+                    egui::DragValue::new(&mut params.max_tokens)
+                        .clamp_range(16..=32768)
+                        .speed(8),
+                );
+                ui.end_row();
+
+                ui.label("Context window");
+                ui.add(
+                    egui::DragValue::new(&mut params.context_window)
+                        .clamp_range(512..=200_000)
+                        .speed(64)
+                        .suffix(" tokens"),
+                )
+                .on_hover_text("How much the model reads at once; longer documents are cut to fit");
+                ui.end_row();
+
+                ui.label("Timeout");
+                ui.add(
+                    egui::DragValue::new(&mut self.request_timeout_secs)
+                        .clamp_range(10..=3600)
+                        .suffix(" s"),
+                )
+                .on_hover_text("Longest a whole answer may take");
+                ui.end_row();
+
+                ui.label("Retries");
+                ui.add(egui::DragValue::new(&mut self.request_retries).clamp_range(0..=10))
+                    .on_hover_text("Times to try again when the server cannot be reached");
+                ui.end_row();
+            });
+
+        ui.label("System prompt");
+        ui.add(
+            egui::TextEdit::multiline(&mut self.custom_system_prompt)
+                .desired_rows(3)
+                .desired_width(f32::INFINITY)
+                .hint_text("You are helping a user understand the document …"),
+        );
+        if ui.button("Reset to defaults").clicked() {
+            self.generation = GenerationParams::default();
+            self.custom_system_prompt.clear();
+        }
+        self.show_file_type_prompts(ui);
+    }
+
+    /// Editors for `file_type_prompts`, which override the system prompt
+    /// for documents of one extension or language.
+    fn show_file_type_prompts(&mut self, ui: &mut egui::Ui) {
+        ui.add_space(6.0);
+        ui.label("System prompts by file type");
+        ui.weak("Used instead of the prompt above for matching documents.");
+        let applied = self.file_type_prompt().map(|(key, _)| key.to_string());
+        let mut removed = None;
+        for (key, prompt) in self.file_type_prompts.iter_mut() {
+            ui.horizontal(|ui| {
+                ui.strong(key.as_str());
+                if applied.as_ref() == Some(key) {
+                    ui.weak("(this document)");
+                }
+                if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                    removed = Some(key.clone());
+                }
+            });
+            ui.add(
+                egui::TextEdit::multiline(prompt)
+                    .desired_rows(2)
+                    .desired_width(f32::INFINITY),
+            );
+        }
+        if let Some(key) = removed {
+            self.file_type_prompts.remove(&key);
+        }
+
+        ui.horizontal(|ui| {
+            let extension = self.file_extension();
+            let hint = if extension.is_empty() {
+                "rs, md, Python…".to_string()
+            } else {
+                extension
+            };
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_file_type)
+                    .desired_width(100.0)
+                    .hint_text(hint.as_str()),
+            );
+            let key = match self.new_file_type.trim() {
+                "" => hint,
+                typed => typed.trim_start_matches('.').to_string(),
+            };
+            let can_add =
+                !self.file_extension().is_empty() || !self.new_file_type.trim().is_empty();
+            if ui
+                .add_enabled(can_add, egui::Button::new("➕ Add prompt"))
+                .clicked()
+            {
+                self.file_type_prompts.entry(key).or_default();
+                self.new_file_type.clear();
+            }
+        });
+    }
+
+    /// Checkboxes for the other open tabs whose files go along with questions.
+    fn show_context_files(&mut self, ui: &mut egui::Ui) {
+        let active = self.active_document;
+        let others: Vec<(usize, String)> = self
+            .documents
+            .iter()
+            .enumerate()
+            .filter(|(i, doc)| *i != active && doc.file_path.is_some())
+            .map(|(i, doc)| (i, display_name(doc.file_path.as_deref())))
+            .collect();
+        if others.is_empty() {
+            return;
+        }
+        let included = others
+            .iter()
+            .filter(|(i, _)| self.documents[*i].in_ai_context)
+            .count();
+        egui::CollapsingHeader::new(format!("📚 Other open files ({} included)", included))
+            .id_source("context_files")
+            .show(ui, |ui| {
+                for (i, name) in others {
+                    ui.checkbox(&mut self.documents[i].in_ai_context, name);
+                }
+            });
+    }
+
+    fn show_index_status(&mut self, ui: &mut egui::Ui) {
+        let mut rebuild = false;
+        ui.horizontal(|ui| {
+            ui.label("Embeddings:");
+            let response =
+                ui.add(egui::TextEdit::singleline(&mut self.embedding_model).desired_width(140.0));
+            let index = self.document_index.lock().unwrap();
+            rebuild = response.lost_focus() && index.model != self.embedding_model;
+            if index.building {
+                ui.ctx().request_repaint_after(Duration::from_millis(200));
+                ui.spinner();
+                ui.label(format!(
+                    "Indexing {}/{}",
+                    index.embedded,
+                    index.chunks.len()
+                ));
+            } else if let Some(error) = &index.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, "⚠")
+                    .on_hover_text(error);
+                rebuild |= ui.button("Retry").clicked();
+            } else if index.ready() {
+                ui.weak(format!("{} chunks indexed", index.chunks.len()));
+            }
+        });
+        // Selecting the mode indexes the document once; a new model name
+        // applies when the field loses focus.
+        self.ensure_document_index(rebuild, ui.ctx());
+    }
+
+    /// Render a model answer as Markdown. `copy_button` adds a button copying
+    /// all its code.
+    fn show_markdown(&mut self, ui: &mut egui::Ui, text: &str, copy_button: bool) {
+        let size = egui::TextStyle::Body.resolve(ui.style()).size;
+        let mut code_blocks = Vec::new();
+        let mut link = None;
+        for (_, block) in parse_markdown(text) {
+            link = self.show_markdown_block(ui, &block, size).or(link);
+            if let MarkdownBlock::Code { code, .. } = block {
+                code_blocks.push(code);
+            }
+        }
+        if let Some(target) = link {
+            self.open_markdown_link(&target, ui.ctx());
+        }
+
+        if copy_button && !code_blocks.is_empty() && ui.small_button("📋 Copy code").clicked() {
+            ui.output_mut(|o| o.copied_text = code_blocks.join("\n"));
+        }
+    }
+
+    /// One Markdown block, with code highlighted in the current theme. Returns
+    /// a clicked link that is not a web address.
+    fn show_markdown_block(
+        &mut self,
+        ui: &mut egui::Ui,
+        block: &MarkdownBlock,
+        size: f32,
+    ) -> Option<String> {
+        match block {
+            MarkdownBlock::Heading { level, text } => {
+                let scale = match level {
+                    1 => 1.4,
+                    2 => 1.25,
+                    _ => 1.1,
+                };
+                let color = ui.visuals().strong_text_color();
+                show_inline(ui, text, size * scale, Some(color))
+            }
+            MarkdownBlock::Paragraph(text) => show_inline(ui, text, size, None),
+            MarkdownBlock::ListItem {
+                marker,
+                indent,
+                text,
+            } => {
+                ui.horizontal_top(|ui| {
+                    ui.add_space(*indent as f32 * 12.0);
+                    ui.label(egui::RichText::new(format!("{} ", marker)).size(size));
+                    ui.vertical(|ui| show_inline(ui, text, size, None)).inner
+                })
+                .inner
+            }
+            MarkdownBlock::Quote(text) => {
+                ui.horizontal_top(|ui| {
+                    ui.weak("▏");
+                    let color = ui.visuals().weak_text_color();
+                    ui.vertical(|ui| show_inline(ui, text, size, Some(color)))
+                        .inner
+                })
+                .inner
+            }
+            MarkdownBlock::Code { language, code } => {
+                let job = self.highlight_snippet(ui, language, code, size);
+                egui::Frame::none()
+                    .fill(self.theme_background())
+                    .rounding(4.0)
+                    .inner_margin(6.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        ui.label(job);
+                    });
+                None
+            }
+            MarkdownBlock::Table { header, rows } => {
+                let mut link = None;
+                egui::Frame::group(ui.style()).show(ui, |ui| {
+                    egui::Grid::new(ui.next_auto_id())
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for cell in header {
+                                let color = ui.visuals().strong_text_color();
+                                link = show_inline(ui, cell, size, Some(color)).or(link.take());
+                            }
+                            ui.end_row();
+                            for row in rows {
+                                for cell in row {
+                                    link = show_inline(ui, cell, size, None).or(link.take());
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+                link
+            }
+            MarkdownBlock::Image { alt, src } => self.show_markdown_image(ui, alt, src, size),
+            MarkdownBlock::Rule => {
+                ui.separator();
+                None
+            }
+        }
+    }
+
+    /// A local image, scaled down to fit; anything that cannot be shown
+    /// becomes a link.
+    fn show_markdown_image(
+        &mut self,
+        ui: &mut egui::Ui,
+        alt: &str,
+        src: &str,
+        size: f32,
+    ) -> Option<String> {
+        let texture = self
+            .resolve_document_link(src)
+            .filter(|_| !is_web_link(src))
+            .and_then(|path| self.image_texture(ui.ctx(), &path));
+        match texture {
+            Some(texture) => {
+                let image_size = texture.size_vec2();
+                let scale = (ui.available_width() / image_size.x).min(1.0);
+                ui.image((texture.id(), image_size * scale))
+                    .on_hover_text(alt);
+                None
+            }
+            None => show_inline(ui, &format!("![{}]({})", alt, src), size, None),
+        }
+    }
+
+    /// Decode an image file into a texture, once per path.
+    fn image_texture(&mut self, ctx: &egui::Context, path: &Path) -> Option<egui::TextureHandle> {
+        self.image_cache
+            .entry(path.to_path_buf())
+            .or_insert_with(|| {
+                let image = image::open(path).ok()?.to_rgba8();
+                let size = [image.width() as usize, image.height() as usize];
+                let pixels = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+                Some(ctx.load_texture(
+                    path.to_string_lossy(),
+                    pixels,
+                    egui::TextureOptions::default(),
+                ))
+            })
+            .clone()
+    }
+
+    /// A link target relative to the open document, without any `#anchor`.
+    fn resolve_document_link(&self, target: &str) -> Option<PathBuf> {
+        let target = target.split('#').next().filter(|t| !t.is_empty())?;
+        let base = self
+            .file_path
+            .as_deref()
+            .filter(|_| !self.streamed)
+            .and_then(|path| Path::new(path).parent())
+            .unwrap_or(Path::new("."));
+        Some(base.join(target))
+    }
+
+    /// Open a relative link from Markdown, such as another document in the repo, in a new tab.
+    fn open_markdown_link(&mut self, target: &str, ctx: &egui::Context) {
+        let Some(path) = self
+            .resolve_document_link(target)
+            .filter(|path| path.is_file())
+        else {
+            return;
+        };
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        self.open_document(path.to_string_lossy().into_owned(), true, ctx);
+    }
+
+    /// Open a URL in the browser, or a referenced file in a new tab.
+    fn open_link(&mut self, target: LinkTarget, ctx: &egui::Context) {
+        match target {
+            LinkTarget::Url(url) => ctx.open_url(egui::OpenUrl::new_tab(url)),
+            LinkTarget::File { path, line } => {
+                self.open_document(path.to_string_lossy().into_owned(), true, ctx);
+                if let Some(line) = line.filter(|_| self.load_error.is_none()) {
+                    self.go_to(line, None);
+                }
+            }
+        }
+    }
+
+    /// The link under character `column` of `line`.
+    fn link_at(&self, line: usize, column: usize) -> Option<&Link> {
+        self.links
+            .get(&line)?
+            .iter()
+            .find(|link| link.columns.contains(&column))
+    }
+
+    fn is_markdown(&self) -> bool {
+        matches!(self.file_extension().as_str(), "md" | "markdown")
+    }
+
+    fn is_json(&self) -> bool {
+        self.file_extension() == "json" && self.hex_data.is_none()
+    }
+
+    /// Whether the document can also be shown rendered; see `DocumentView`.
+    fn has_rendered_view(&self) -> bool {
+        self.is_markdown() || self.is_json()
+    }
+
+    fn show_rendered_view(&mut self, ui: &mut egui::Ui) {
+        if self.is_json() {
+            self.show_json_tree(ui);
+        } else {
+            self.show_markdown_preview(ui);
+        }
+    }
+
+    fn show_json_tree(&mut self, ui: &mut egui::Ui) {
+        let tree = self
+            .json_tree
+            .get_or_insert_with(|| JsonTree::parse(&self.file_content));
+        if tree.source_len != self.file_content.len() {
+            tree.reparse(&self.file_content);
+        }
+        tree.show(ui, self.font_size);
+    }
+
+    /// The rendered document. Clicking a block scrolls the source to it, and
+    /// `preview_target` scrolls the preview to the block holding that line.
+    fn show_markdown_preview(&mut self, ui: &mut egui::Ui) {
+        let blocks = parse_markdown(&self.file_content);
+        let target = self.preview_target.take().map(|line| {
+            blocks
+                .partition_point(|(first, _)| *first <= line)
+                .saturating_sub(1)
+        });
+        let size = self.font_size;
+        let mut clicked_line = None;
+        let mut link = None;
+
+        egui::ScrollArea::vertical()
+            .id_source("markdown_preview")
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                for (i, (line, block)) in blocks.iter().enumerate() {
+                    let shown = ui.scope(|ui| self.show_markdown_block(ui, block, size));
+                    link = shown.inner.or(link.take());
+                    let response = shown.response.interact(egui::Sense::click());
+                    if response.clicked() {
+                        clicked_line = Some(*line);
+                    }
+                    if target == Some(i) {
+                        response.scroll_to_me(Some(egui::Align::TOP));
+                    }
+                    ui.add_space(size * 0.4);
+                }
+            });
+
+        if let Some(line) = clicked_line {
+            self.scroll_to_line = Some(line);
+        }
+        if let Some(target) = link {
+            self.open_markdown_link(&target, ui.ctx());
+        }
+    }
+
+    /// Highlight a Markdown code block. Markdown is redrawn every frame, so the
+    /// result is cached by content, theme and size.
+    fn highlight_snippet(
+        &mut self,
+        ui: &egui::Ui,
+        language: &str,
+        code: &str,
+        size: f32,
+    ) -> egui::text::LayoutJob {
+        use std::hash::{Hash, Hasher};
+
+        let font = egui::FontId::monospace(size);
+        let wrap_width = ui.available_width() - 12.0;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (language, code, &self.theme_name, font.size.to_bits()).hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some(job) = self.snippet_cache.get(&key) {
+            let mut job = job.clone();
+            job.wrap.max_width = wrap_width;
+            return job;
+        }
+
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_token(language)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, self.theme());
+        let mut job = egui::text::LayoutJob::default();
+        for line in LinesWithEndings::from(code.trim_end()) {
+            let ranges = highlighter
+                .highlight_line(line, &self.syntax_set)
+                .unwrap_or_else(|_| vec![(self.default_text_style(), line)]);
+            for (style, piece) in ranges {
+                job.append(
+                    piece,
+                    0.0,
+                    egui::TextFormat {
+                        font_id: font.clone(),
+                        color: egui::Color32::from_rgb(
+                            style.foreground.r,
+                            style.foreground.g,
+                            style.foreground.b,
+                        ),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        self.snippet_cache.insert(key, job.clone());
+        job.wrap.max_width = wrap_width;
+        job
+    }
+
+    fn theme_background(&self) -> egui::Color32 {
+        match self.theme().settings.background {
+            Some(c) => egui::Color32::from_rgb(c.r, c.g, c.b),
+            None => visuals(self.dark_mode).extreme_bg_color,
+        }
+    }
+
+    fn chat_bubble_fill(&self, from_user: bool) -> egui::Color32 {
+        match (self.dark_mode, from_user) {
+            (true, true) => egui::Color32::from_rgb(40, 60, 90),
+            (true, false) => egui::Color32::from_rgb(45, 45, 45),
+            (false, true) => egui::Color32::from_rgb(210, 225, 245),
+            (false, false) => egui::Color32::from_rgb(232, 232, 232),
+        }
+    }
+
+    /// Highlight the active document again, unless its colours come from
+    /// elsewhere (a GGUF summary, a hex dump, or terminal escapes on a stream).
+    fn rehighlight(&mut self) {
+        if self.file_path.is_some()
+            && self.gguf_info.is_none()
+            && self.hex_data.is_none()
+            && (!self.streamed || self.revision.is_some() || self.session_syntax.is_some())
+        {
+            self.highlight_content();
+        }
+    }
+
+    fn poll_highlighting(&mut self) {
+        let Some(task) = &mut self.highlight_task else {
+            return;
+        };
+        if task.poll(&mut self.highlighted_content) {
+            self.highlight_task = None;
+        }
+    }
+
+    fn file_name(&self) -> String {
+        match &self.revision {
+            Some(revision) => format!("{} @ {}", display_name(self.file_path.as_deref()), revision),
+            None => display_name(self.file_path.as_deref()),
+        }
+    }
+
+    fn get_file_info(&self) -> String {
+        if let Some(bytes) = &self.hex_data {
+            return format!("{} bytes | binary", bytes.len());
+        }
+        let mut info = format!(
+            "{} lines | {} bytes | {}",
+            self.file_content.lines().count(),
+            self.file_content.len(),
+            self.detect_syntax().name
+        );
+        if let Some(cursor) = self.cursor_info() {
+            info.push_str(" | ");
+            info.push_str(&cursor);
+        }
+        info
+    }
+
+    /// Line and column of the cursor, plus the size of the selection, once
+    /// there is one.
+    fn cursor_info(&self) -> Option<String> {
+        let (anchor, cursor) = if self.editing {
+            self.editor_cursor?
+        } else {
+            let selection = self.selection.as_ref()?;
+            (selection.start, selection.end)
+        };
+        let text = &self.file_content;
+        let cursor = cursor.min(text.len());
+        let line_start = text[..cursor].rfind('\n').map_or(0, |i| i + 1);
+        let mut info = format!(
+            "Ln {}, Col {}",
+            text[..cursor].matches('\n').count() + 1,
+            text[line_start..cursor].chars().count() + 1
+        );
+        let selected = &text[anchor.min(cursor)..anchor.max(cursor).min(text.len())];
+        if !selected.is_empty() {
+            let lines = selected.trim_end_matches('\n').matches('\n').count() + 1;
+            info.push_str(&format!(
+                " ({} chars, {} {} selected)",
+                selected.chars().count(),
+                lines,
+                if lines == 1 { "line" } else { "lines" }
+            ));
+        }
+        Some(info)
+    }
+
+    /// Load the saved conversation for the current document, if it has one.
+    fn restore_conversation(&mut self) -> bool {
+        let Some(key) = self.document_key() else {
+            return false;
+        };
+        let chat = load_conversation(&key);
+        if chat.is_empty() {
+            return false;
+        }
+        self.ai_state.chat = chat;
+        true
+    }
+
+    fn save_chat(&self) {
+        if let Some(key) = self.document_key() {
+            save_conversation(&key, &self.ai_state.chat);
+        }
+    }
+
+    /// Forget the conversation, here and on disk.
+    fn clear_conversation(&mut self) {
+        self.cancel_ai_request();
+        self.ai_state.chat = ChatTree::default();
+        self.ai_state.error = None;
+        self.edited_question = None;
+        self.save_chat();
+    }
+
+    /// A message of the chat's branch on show, with buttons to switch
+    /// branches, ask again or delete what follows.
+    fn show_chat_message(
+        &mut self,
+        ui: &mut egui::Ui,
+        chat: &ChatTree,
+        id: usize,
+        is_loading: bool,
+    ) -> Option<ChatAction> {
+        let message = chat.message(id);
+        let from_user = message.role == "user";
+        let mut action = None;
+        egui::Frame::none()
+            .fill(self.chat_bubble_fill(from_user))
+            .rounding(6.0)
+            .inner_margin(8.0)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(if from_user { "You" } else { "AI" });
+                    let siblings = chat.siblings(id);
+                    if siblings.len() > 1 {
+                        let index = siblings.iter().position(|&s| s == id).unwrap_or(0);
+                        let earlier = index > 0 && !is_loading;
+                        let later = index + 1 < siblings.len() && !is_loading;
+                        if ui
+                            .add_enabled(earlier, egui::Button::new("◀").small())
+                            .clicked()
+                        {
+                            action = Some(ChatAction::Switch(siblings[index - 1]));
+                        }
+                        ui.weak(format!("{}/{}", index + 1, siblings.len()));
+                        if ui
+                            .add_enabled(later, egui::Button::new("▶").small())
+                            .clicked()
+                        {
+                            action = Some(ChatAction::Switch(siblings[index + 1]));
+                        }
+                    }
+                });
+                match &mut self.edited_question {
+                    Some((edited, text)) if *edited == id => {
+                        ui.add(
+                            egui::TextEdit::multiline(text)
+                                .desired_rows(2)
+                                .desired_width(f32::INFINITY),
+                        );
+                        ui.horizontal(|ui| {
+                            let ready = !is_loading && !text.trim().is_empty();
+                            if ui.add_enabled(ready, egui::Button::new("Send")).clicked() {
+                                action = Some(ChatAction::Resend(id, text.clone()));
+                            }
+                            if ui.button("Cancel").clicked() {
+                                action = Some(ChatAction::CancelEdit);
+                            }
+                        });
+                        return;
+                    }
+                    _ if from_user => {
+                        ui.label(&message.content);
+                    }
+                    _ => self.show_markdown(ui, &message.content, true),
+                }
+                if let Some(metrics) = &message.metrics {
+                    ui.label(egui::RichText::new(metrics.summary()).small().weak());
+                }
+                if let Some(context) = &message.context {
+                    show_message_context(ui, id, context);
+                }
+                if is_loading {
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    let asked = chat
+                        .parent(id)
+                        .is_some_and(|parent| chat.message(parent).role == "user");
+                    if from_user {
+                        if ui.small_button("✏ Edit & resend").clicked() {
+                            action = Some(ChatAction::Edit(id));
+                        }
+                    } else if asked
+                        && ui
+                            .small_button("↻ Regenerate")
+                            .on_hover_text("Ask again, with the model selected now")
+                            .clicked()
+                    {
+                        action = Some(ChatAction::Regenerate(id));
+                    }
+                    if chat.has_replies(id) && ui.small_button("🗑 Delete below").clicked() {
+                        action = Some(ChatAction::DeleteBelow(id));
+                    }
+                });
+            });
+        action
+    }
+
+    fn apply_chat_action(&mut self, action: ChatAction) {
+        match action {
+            ChatAction::Switch(id) => {
+                self.ai_state.chat.switch_to(id);
+                self.save_chat();
+            }
+            ChatAction::Edit(id) => {
+                let text = self.ai_state.chat.message(id).content.clone();
+                self.edited_question = Some((id, text));
+            }
+            ChatAction::CancelEdit => self.edited_question = None,
+            ChatAction::Resend(id, question) => {
+                self.edited_question = None;
+                self.ask_again(id, question);
+            }
+            ChatAction::Regenerate(answer) => {
+                if let Some(id) = self.ai_state.chat.parent(answer) {
+                    let question = self.ai_state.chat.message(id).content.clone();
+                    self.ask_again(id, question);
+                }
+            }
+            ChatAction::DeleteBelow(id) => {
+                self.ai_state.chat.remove_below(id);
+                // Messages were renumbered.
+                self.edited_question = None;
+                self.save_chat();
+            }
+        }
+    }
+
+    /// Ask `question` in place of the chat message `id`, on a new branch
+    /// beside the one it started, with the lines and images it was about.
+    fn ask_again(&mut self, id: usize, question: String) {
+        if self.ai_state.is_loading() {
+            return;
+        }
+        let parent = self.ai_state.chat.parent(id);
+        let mut question = self.ai_state.chat.message(id).reworded(question);
+        let mut warning = None;
+        if let (None, Some((first, last))) = (&question.prompt, question.lines) {
+            let (prompt, cut) = self.selection_prompt(first, last, &question.content);
+            question.prompt = Some(prompt);
+            warning = cut;
+        }
+        let prompt = question
+            .prompt
+            .clone()
+            .unwrap_or_else(|| question.content.clone());
+        self.dispatch_ai_request(
+            prompt,
+            RequestKind::Resend {
+                parent,
+                question,
+                warning,
+            },
+        );
+    }
+
+    fn show_describe_image(&mut self, ui: &mut egui::Ui) {
+        ui.weak("Ask about the image below, or start with a description.");
+        if ui
+            .add_enabled(self.sees_images(), egui::Button::new("🖼 Describe image"))
+            .on_disabled_hover_text(
+                "Pick an Ollama vision model, such as llava, to describe images",
+            )
+            .clicked()
+        {
+            self.describe_image();
+        }
+    }
+
+    /// Whether the selected model takes images along with the prompt.
+    fn sees_images(&self) -> bool {
+        self.provider == Provider::Ollama && is_vision_model(&self.selected_model)
+    }
+
+    /// Pick an image to send with the next question, in a form vision
+    /// models take.
+    fn attach_image(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_title("Attach image")
+            .add_filter("Images", &IMAGE_EXTENSIONS)
+            .pick_file()
+        else {
+            return;
+        };
+        let path = path.to_string_lossy().into_owned();
+        let name = display_name(Some(&path));
+        let decoded = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| ImagePreview::decode(&path, &bytes));
+        match decoded {
+            Ok(image) => self.attached_images.push(AttachedImage {
+                name,
+                data: BASE64_STANDARD.encode(&image.encoded),
+            }),
+            Err(e) => self.ai_state.error = Some(format!("Could not attach {}: {}", name, e)),
+        }
+    }
+
+    fn describe_image(&mut self) {
+        let Some(image) = &self.image_preview else {
+            return;
+        };
+        let images = vec![BASE64_STANDARD.encode(&image.encoded)];
+        self.dispatch_ai_request(
+            DESCRIBE_IMAGE_PROMPT.to_string(),
+            RequestKind::Chat {
+                question: Some("Describe this image".to_string()),
+                warning: None,
+                lines: None,
+                images,
+            },
+        );
+    }
+
+    fn generate_initial_summary(&mut self) {
+        self.dispatch_ai_request(
+            SUMMARY_PROMPT.to_string(),
+            RequestKind::Chat {
+                question: None,
+                warning: None,
+                lines: None,
+                images: Vec::new(),
+            },
+        );
+    }
+
+    /// The most recent answer of the conversation on show.
+    fn last_answer(&self) -> Option<String> {
+        self.ai_state
+            .chat
+            .messages()
+            .into_iter()
+            .rev()
+            .find(|message| message.role == "assistant")
+            .map(|message| message.content)
+    }
+
+    fn send_to_ai(&mut self, question: String) {
+        if question.trim().is_empty() || self.ai_state.is_loading() {
+            return;
+        }
+
+        let lines = self.ask_about.take();
+        let ((prompt, warning), question) = match lines {
+            Some((first, last)) => (
+                self.selection_prompt(first, last, &question),
+                format!("Lines {}–{}: {}", first + 1, last + 1, question),
+            ),
+            None => ((question.clone(), None), question),
+        };
+        // An open image goes with every question; attachments with the next one.
+        let mut images = Vec::new();
+        if self.sees_images() {
+            images.extend(
+                self.image_preview
+                    .as_ref()
+                    .map(|image| BASE64_STANDARD.encode(&image.encoded)),
+            );
+            images.extend(self.attached_images.drain(..).map(|image| image.data));
+        }
+        self.dispatch_ai_request(
+            prompt,
+            RequestKind::Chat {
+                question: Some(question),
+                warning,
+                lines,
+                images,
+            },
+        );
+    }
+
+    /// The parts of a chat request's system prompt: the active template's
+    /// instructions, the document in the form chosen by `context_mode`, and
+    /// the other files ticked as context. The document and files share what
+    /// the context window leaves after `rest` of the request.
+    /// Relevant chunks are picked on the worker thread (see `Retrieval`); until
+    /// the index is ready this falls back to the document start.
+    fn chat_context(&self, rest: &[&str]) -> ChatContext {
+        let preamble = self.chat_preamble();
+        let budget = context_budget(&self.generation, &[rest, &[preamble.as_str()]].concat());
+        let files = self.context_files();
+        let sizes: Vec<usize> = std::iter::once(self.prompt_content())
+            .chain(files.iter().map(|(_, content)| *content))
+            .map(estimate_tokens)
+            .collect();
+        let shares = share_budget(&sizes, budget);
+
+        let (mut document, warning) = match self.context_mode {
+            ContextMode::DocumentStart | ContextMode::Relevant => {
+                document_context(self.prompt_content(), shares[0])
+            }
+            ContextMode::Viewport => self.viewport_context(shares[0]),
+        };
+        if !files.is_empty() {
+            document = format!("Current file: {}\n{}", self.file_name(), document);
+        }
+        let mut warnings: Vec<String> = warning.into_iter().collect();
+        let mut others = String::new();
+        for ((name, content), share) in files.iter().zip(&shares[1..]) {
+            let (context, warning) = file_context(name, content, *share);
+            others.push_str(&context);
+            warnings.extend(warning);
+        }
+        ChatContext {
+            preamble,
+            document,
+            others,
+            warnings,
+        }
+    }
+
+    /// Names and text of the other tabs ticked as context, redacted unless
+    /// the user opted out for that file.
+    fn context_files(&self) -> Vec<(String, &str)> {
+        self.documents
+            .iter()
+            .enumerate()
+            .filter(|(i, doc)| *i != self.active_document && doc.in_ai_context)
+            .filter_map(|(_, doc)| {
+                let path = doc.file_path.as_ref()?;
+                let content = if self.redaction_opt_outs.contains(path) {
+                    &doc.file_content
+                } else {
+                    &doc.redacted_content
+                };
+                Some((display_name(Some(path)), content.as_str()))
+            })
+            .collect()
+    }
+
+    fn chat_preamble(&self) -> String {
+        chat_preamble(
+            &self.file_name(),
+            self.file_type_prompt()
+                .map_or(self.custom_system_prompt.as_str(), |(_, prompt)| prompt),
+            self.template_system_prompt(),
+        )
+    }
+
+    /// The `file_type_prompts` entry that replaces the system prompt for
+    /// this document, if any.
+    fn file_type_prompt(&self) -> Option<(&str, &str)> {
+        file_type_prompt(
+            &self.file_type_prompts,
+            &self.file_extension(),
+            &self.detect_syntax().name,
+        )
+    }
+
+    /// Start embedding the document if it has not been indexed yet or has
+    /// changed since. `force` rebuilds anyway, to retry or switch models.
+    fn ensure_document_index(&self, force: bool, ctx: &egui::Context) {
+        if self.embedding_model.trim().is_empty() {
+            return;
+        }
+        let content = self.prompt_content();
+        {
+            let mut index = self.document_index.lock().unwrap();
+            let stale = index.model.is_empty() || index.source_len != content.len();
+            if index.building || !(stale || force) {
+                return;
+            }
+            *index = DocumentIndex {
+                source_len: content.len(),
+                model: self.embedding_model.clone(),
+                building: true,
+                ..DocumentIndex::default()
+            };
+        }
+        let content = content.to_string();
+
+        let index = self.document_index.clone();
+        let ollama_url = self.ollama_url.clone();
+        let model = self.embedding_model.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            build_index(content, ollama_url, model, index);
+            ctx.request_repaint();
+        });
+    }
+
+    /// The lines on screen plus `viewport_margin` lines either side, numbered,
+    /// as many as fit in `max_tokens`.
+    fn viewport_context(&self, max_tokens: usize) -> (String, Option<String>) {
+        let (first, last) = self.visible_lines;
+        let start = first.saturating_sub(self.viewport_margin);
+        let end = last + self.viewport_margin;
+
+        let mut excerpt = String::new();
+        for (i, line) in self
+            .prompt_content()
+            .lines()
+            .enumerate()
+            .skip(start)
+            .take(end - start + 1)
+        {
+            excerpt.push_str(&format!("{:>5} | {}\n", i + 1, line));
+        }
+
+        let fitted = fit_lines(&excerpt, max_tokens);
+        let context = format!(
+            "The user is looking at lines {}-{}. Excerpt with line numbers:\n{}",
+            first + 1,
+            last + 1,
+            fitted.text
+        );
+        (context, fitted.cut_warning("Visible lines"))
+    }
+
+    /// Send a prompt, unless this is the first request for a document containing
+    /// likely PII, in which case it is held until the user confirms.
+    fn dispatch_ai_request(&mut self, prompt: String, kind: RequestKind) {
+        if !self.pii_findings.is_empty() && !self.pii_confirmed {
+            self.pending_request = Some(PendingRequest { prompt, kind });
+            return;
+        }
+
+        match kind {
+            RequestKind::Chat {
+                question,
+                warning,
+                lines,
+                images,
+            } => {
+                let history = self.ai_state.chat.messages();
+                if let Some(question) = question {
+                    let question = ChatMessage::question(question, &prompt, lines, images.clone());
+                    self.ai_state.chat.push(question);
+                }
+                self.spawn_ai_request(prompt, history, images);
+                self.ai_state.context_warnings.extend(warning);
+            }
+            RequestKind::Resend {
+                parent,
+                question,
+                warning,
+            } => {
+                self.ai_state.chat.continue_after(parent);
+                let history = self.ai_state.chat.messages();
+                let images = question.images.clone();
+                self.ai_state.chat.push(question);
+                self.spawn_ai_request(prompt, history, images);
+                self.ai_state.context_warnings.extend(warning);
+            }
+            RequestKind::Translation => self.spawn_translation(prompt),
+            RequestKind::Review => self.spawn_review(prompt),
+            RequestKind::Edit => self.spawn_edit(prompt),
+            RequestKind::Tests => self.spawn_tests(prompt),
+            RequestKind::Comparison => self.spawn_comparison(prompt),
+        }
+    }
+
+    fn backend_config(&self) -> BackendConfig {
+        let grammar = Some(self.grammar.trim().to_string()).filter(|g| !g.is_empty());
+        self.settings().backend(grammar)
+    }
+
+    fn template_system_prompt(&self) -> Option<String> {
+        self.active_template
+            .and_then(|i| self.templates.get(i))
+            .map(|t| t.system_prompt.clone())
+    }
+
+    /// Translate the document paragraph by paragraph into `translation_language`.
+    fn translate_document(&mut self) {
+        let language = self.translation_language.trim().to_string();
+        if language.is_empty() || self.translation.lock().unwrap().running {
+            return;
+        }
+
+        // Send whole paragraphs until the budget is used up; the rest stay untranslated.
+        let content = self.prompt_content();
+        let mut sections = String::new();
+        let mut paragraphs = Vec::new();
+        for paragraph in split_paragraphs(content) {
+            let text = paragraph_text(content, &paragraph);
+            if !sections.is_empty() && sections.len() + text.len() > 6000 {
+                break;
+            }
+            sections.push_str(&format!("[[{}]]\n{}\n\n", paragraphs.len() + 1, text));
+            paragraphs.push(paragraph);
+        }
+
+        let prompt = format!(
+            "Translate each numbered paragraph below into {}. \
+             Reply with the translations only, each preceded by the same [[n]] marker \
+             on its own line. Keep the numbering, do not merge or split paragraphs, \
+             and do not add commentary.\n\n{}",
+            language, sections
+        );
+
+        {
+            let mut translation = self.translation.lock().unwrap();
+            translation.language = language;
+            translation.translated = vec![None; paragraphs.len()];
+            translation.paragraphs = paragraphs;
+            translation.error = None;
+        }
+        self.show_translation = true;
+        self.dispatch_ai_request(prompt, RequestKind::Translation);
+    }
+
+    fn spawn_translation(&mut self, prompt: String) {
+        let translation = self.translation.clone();
+        let usage_stats = self.usage_stats.clone();
+        let backend = self.backend_config();
+        // The whole translation comes back in one answer.
+        let params = GenerationParams {
+            max_tokens: 4096,
+            ..self.generation
+        };
+        translation.lock().unwrap().running = true;
+
+        self.network.spawn(async move {
+            let started = Instant::now();
+            let result = backend
+                .complete(None, vec![ChatMessage::user(prompt)], &params, |_| {})
+                .await;
+
+            let mut translation = translation.lock().unwrap();
+            match result {
+                Ok(completion) => {
+                    usage_stats.lock().unwrap().record(
+                        &backend.model,
+                        &completion,
+                        started.elapsed(),
+                    );
+                    translation.translated =
+                        parse_numbered_sections(&completion.text, translation.paragraphs.len());
+                }
+                Err(e) => translation.error = Some(e),
+            }
+            translation.running = false;
+        });
+    }
+
+    fn can_annotate(&self) -> bool {
+        self.file_path.is_some()
+            && self.gguf_info.is_none()
+            && self.hex_data.is_none()
+            && !self.review.lock().unwrap().running
+    }
+
+    /// Ask the model to review the file, answering with line comments that
+    /// become annotations.
+    fn annotate_file(&mut self) {
+        let numbered: String = self
+            .prompt_content()
+            .lines()
+            .enumerate()
+            .map(|(i, line)| format!("{:>5} | {}\n", i + 1, line))
+            .collect();
+        let instructions = format!(
+            "Review {} below, whose lines are numbered. Comment on bugs, risky or \
+             unclear parts and anything worth a second look; leave out lines with nothing \
+             to say. Reply with only a JSON array of comments, each an object \
+             {{\"line_start\": n, \"line_end\": n, \"note\": \"...\"}} naming the \
+             numbered lines it is about.\n\n",
+            self.file_name()
+        );
+        let fitted = fit_lines(
+            &numbered,
+            context_budget(&review_params(&self.generation), &[&instructions]),
+        );
+        let prompt = format!("{}```\n{}```", instructions, fitted.text);
+
+        {
+            let mut review = self.review.lock().unwrap();
+            review.warning = fitted.cut_warning("File");
+            review.error = None;
+        }
+        self.show_annotations = true;
+        self.dispatch_ai_request(prompt, RequestKind::Review);
+    }
+
+    fn spawn_review(&mut self, prompt: String) {
+        let review = self.review.clone();
+        let usage_stats = self.usage_stats.clone();
+        let backend = self.backend_config();
+        let params = review_params(&self.generation);
+        let line_count = self.file_content.lines().count();
+        review.lock().unwrap().running = true;
+
+        self.network.spawn(async move {
+            let started = Instant::now();
+            let result = backend
+                .complete(None, vec![ChatMessage::user(prompt)], &params, |_| {})
+                .await;
+
+            let mut review = review.lock().unwrap();
+            match result {
+                Ok(completion) => {
+                    usage_stats.lock().unwrap().record(
+                        &backend.model,
+                        &completion,
+                        started.elapsed(),
+                    );
+                    match parse_review(&completion.text, line_count) {
+                        Ok(notes) => review.notes = notes,
+                        Err(e) => review.error = Some(e),
+                    }
+                }
+                Err(e) => review.error = Some(e),
+            }
+            review.running = false;
+        });
+    }
+
+    /// Move finished review comments into the document's annotations,
+    /// replacing those of an earlier review.
+    fn collect_review_notes(&mut self) {
+        let notes = std::mem::take(&mut self.review.lock().unwrap().notes);
+        if !notes.is_empty() {
+            self.annotations
+                .retain(|annotation| annotation.kind != AnnotationKind::Review);
+            self.annotations.extend(notes);
+        }
+    }
+
+    /// Open the AI edit window for the selected lines, or the whole
+    /// document when nothing is selected.
+    fn start_ai_edit(&mut self) {
+        let lines = match self.selection.clone().filter(|r| !r.is_empty()) {
+            Some(range) => self.selection_lines(&range),
+            None => (0, self.file_content.lines().count().saturating_sub(1)),
+        };
+        let mut edit = self.ai_edit.lock().unwrap();
+        if !edit.running {
+            *edit = EditState {
+                lines: Some(lines),
+                ..EditState::default()
+            };
+        }
+    }
+
+    fn can_document_selection(&self) -> bool {
+        let busy = match self.can_edit() {
+            true => self.ai_edit.lock().unwrap().running,
+            false => self.ai_state.is_loading(),
+        };
+        self.selection.as_ref().is_some_and(|r| !r.is_empty()) && !busy
+    }
+
+    /// Ask the model for doc comments on the selected code: as an AI edit
+    /// adding them, to review and apply, when the document can be edited,
+    /// else as an answer in the AI panel.
+    fn document_selection(&mut self) {
+        let Some(range) = self.selection.clone().filter(|r| !r.is_empty()) else {
+            return;
+        };
+        let language = self.detect_syntax().name.clone();
+        if self.can_edit() {
+            self.start_ai_edit();
+            self.ai_edit_instruction = format!(
+                "Add idiomatic {} doc comments to the definitions, in the form its \
+                 documentation tools read (docstrings in Python), without changing the code",
+                language
+            );
+            self.request_ai_edit();
+            return;
+        }
+        if self.ai_state.is_loading() {
+            return;
+        }
+
+        let (first, last) = self.selection_lines(&range);
+        let (prompt, warning) = self.selection_prompt(
+            first,
+            last,
+            &format!(
+                "Write idiomatic {} doc comments for the definitions in the selected lines, \
+                 in the form its documentation tools read. Reply with the commented \
+                 definitions in a code block, leaving out their bodies.",
+                language
+            ),
+        );
+        self.show_ai_panel = true;
+        self.dispatch_ai_request(
+            prompt,
+            RequestKind::Chat {
+                question: Some(format!("Document lines {}–{}", first + 1, last + 1)),
+                warning,
+                lines: Some((first, last)),
+                images: Vec::new(),
+            },
+        );
+    }
+
+    /// Byte range of lines `first..=last` (0-based), with the last line's
+    /// line break.
+    fn line_range(&self, (first, last): (usize, usize)) -> Range<usize> {
+        let starts = line_starts(&self.file_content);
+        let start = starts
+            .get(first)
+            .copied()
+            .unwrap_or(self.file_content.len());
+        let end = starts
+            .get(last + 1)
+            .copied()
+            .unwrap_or(self.file_content.len());
+        start..end
+    }
+
+    /// Ask the model to rewrite the AI edit window's lines as its
+    /// instruction says. The reply is shown as a diff to apply or reject.
+    fn request_ai_edit(&mut self) {
+        let Some(lines) = self.ai_edit.lock().unwrap().lines else {
+            return;
+        };
+        let range = self.line_range(lines);
+        let original = self.file_content[range.clone()].to_string();
+        let error = if self.prompt_content().get(range) != Some(original.as_str()) {
+            // Rewriting redacted text would write the placeholders back.
+            Some("The lines hold redacted text; opt out of redaction to edit them".to_string())
+        } else {
+            None
+        };
+
+        let instructions = format!(
+            "Rewrite lines {}–{} of {} as the request below asks. Reply with only the \
+             rewritten lines, in one code block, keeping whatever the request does not \
+             touch as it is, indentation included.\n\nRequest: {}\n\n",
+            lines.0 + 1,
+            lines.1 + 1,
+            self.file_name(),
+            self.ai_edit_instruction.trim()
+        );
+        let budget = context_budget(&edit_params(&self.generation, &original), &[&instructions]);
+        let error = error.or_else(|| {
+            (estimate_tokens(&original) > budget)
+                .then(|| "Too many lines for the model's context; select fewer".to_string())
+        });
+
+        {
+            let mut edit = self.ai_edit.lock().unwrap();
+            edit.revised = None;
+            edit.error = error;
+            if edit.error.is_some() {
+                return;
+            }
+            edit.original = original.clone();
+        }
+        let prompt = format!("{}```\n{}```", instructions, original);
+        self.dispatch_ai_request(prompt, RequestKind::Edit);
+    }
+
+    fn spawn_edit(&mut self, prompt: String) {
+        let ai_edit = self.ai_edit.clone();
+        let usage_stats = self.usage_stats.clone();
+        let backend = self.backend_config();
+        let original = ai_edit.lock().unwrap().original.clone();
+        let params = edit_params(&self.generation, &original);
+        ai_edit.lock().unwrap().running = true;
+
+        self.network.spawn(async move {
+            let started = Instant::now();
+            let result = backend
+                .complete(None, vec![ChatMessage::user(prompt)], &params, |_| {})
+                .await;
+
+            let mut edit = ai_edit.lock().unwrap();
+            match result {
+                Ok(completion) => {
+                    usage_stats.lock().unwrap().record(
+                        &backend.model,
+                        &completion,
+                        started.elapsed(),
+                    );
+                    edit.revised = Some(parse_edit(&completion.text, &original));
+                }
+                Err(e) => edit.error = Some(e),
+            }
+            edit.running = false;
+        });
+    }
+
+    /// Put the suggested lines in place of the ones they were made from,
+    /// unless those changed in the meantime.
+    fn apply_ai_edit(&mut self) {
+        let (lines, original, revised) = {
+            let edit = self.ai_edit.lock().unwrap();
+            match (edit.lines, &edit.revised) {
+                (Some(lines), Some(revised)) => (lines, edit.original.clone(), revised.clone()),
+                _ => return,
+            }
+        };
+        let range = self.line_range(lines);
+        if self.file_content.get(range.clone()) != Some(original.as_str()) {
+            self.ai_edit.lock().unwrap().error =
+                Some("The lines changed since the suggestion was made".to_string());
+            return;
+        }
+
+        self.file_content.replace_range(range, &revised);
+        self.dirty = true;
+        self.edited_at = Some(Instant::now());
+        self.editor_cursor = None;
+        self.selection = None;
+        self.selection_history.clear();
+        self.replace_undo.clear();
+        *self.ai_edit.lock().unwrap() = EditState::default();
+    }
+
+    /// The AI edit window: the instruction for the model, then its
+    /// suggestion as a diff of the lines, for the user to apply or reject.
+    fn show_ai_edit_window(&mut self, ctx: &egui::Context) {
+        let (lines, running, original, revised, error) = {
+            let edit = self.ai_edit.lock().unwrap();
+            let Some(lines) = edit.lines else {
+                return;
+            };
+            (
+                lines,
+                edit.running,
+                edit.original.clone(),
+                edit.revised.clone(),
+                edit.error.clone(),
+            )
+        };
+        let mut open = true;
+        let mut suggest = false;
+        let mut apply = false;
+        let mut reject = false;
+        let font = egui::FontId::monospace(self.font_size);
+
+        egui::Window::new("✨ AI edit")
+            .open(&mut open)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                ui.label(format!("Lines {}–{}", lines.0 + 1, lines.1 + 1));
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.ai_edit_instruction)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Describe the change, e.g. add error handling"),
+                );
+                let entered =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                let ready = !running && !self.ai_edit_instruction.trim().is_empty();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(ready, egui::Button::new("Suggest"))
+                        .clicked()
+                        || (entered && ready)
+                    {
+                        suggest = true;
+                    }
+                    if running {
+                        ui.spinner();
+                        ui.label("Rewriting...");
+                    }
+                });
+                if let Some(error) = error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                }
+                let Some(revised) = revised else {
+                    return;
+                };
+
+                ui.separator();
+                if revised == original {
+                    ui.weak("The model left these lines unchanged.");
+                } else {
+                    let dark_mode = ui.visuals().dark_mode;
+                    let old: Vec<&str> = original.lines().collect();
+                    let new: Vec<&str> = revised.lines().collect();
+                    egui::ScrollArea::both()
+                        .max_height(360.0)
+                        .auto_shrink([false, true])
+                        .show(ui, |ui| {
+                            ui.spacing_mut().item_spacing.y = 0.0;
+                            for row in unified_rows(&diff_rows(&original, &revised)) {
+                                let (marker, text) = match (row.kind, row.left, row.right) {
+                                    (DiffKind::Removed, Some(line), _) => ('-', old[line]),
+                                    (DiffKind::Added, _, Some(line)) => ('+', new[line]),
+                                    (_, _, Some(line)) => (' ', new[line]),
+                                    _ => continue,
+                                };
+                                let frame = match diff_fill(row.kind, dark_mode) {
+                                    Some(fill) => egui::Frame::none().fill(fill),
+                                    None => egui::Frame::none(),
+                                };
+                                frame.show(ui, |ui| {
+                                    ui.set_min_width(ui.available_width());
+                                    ui.label(
+                                        egui::RichText::new(format!("{} {}", marker, text))
+                                            .font(font.clone()),
+                                    );
+                                });
+                            }
+                        });
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(revised != original, egui::Button::new("✔ Apply"))
+                        .clicked()
+                    {
+                        apply = true;
+                    }
+                    if ui.button("✖ Reject").clicked() {
+                        reject = true;
+                    }
+                });
+            });
+
+        if suggest {
+            self.request_ai_edit();
+        }
+        if apply {
+            self.apply_ai_edit();
+        } else if reject || !open {
+            // A request still running answers into the state it was sent from.
+            self.ai_edit = Arc::new(Mutex::new(EditState::default()));
+        }
+    }
+
+    /// Tests can be suggested for source files of the languages with a
+    /// test template.
+    fn can_suggest_tests(&self) -> bool {
+        test_template(&self.file_extension()).is_some()
+            && self.load_error.is_none()
+            && self.hex_data.is_none()
+            && !self.test_suggestions.lock().unwrap().running
+    }
+
+    /// Ask the model for unit test skeletons for the selected lines, or the
+    /// whole file when nothing is selected, in the language's usual framework.
+    fn suggest_tests(&mut self) {
+        let Some(template) = test_template(&self.file_extension()) else {
+            return;
+        };
+        let lines = self
+            .selection
+            .clone()
+            .filter(|r| !r.is_empty())
+            .map(|range| self.selection_lines(&range));
+        let source = match lines {
+            Some((first, last)) => {
+                format!("lines {}–{} of {}", first + 1, last + 1, self.file_name())
+            }
+            None => self.file_name(),
+        };
+        let code: String = match lines {
+            Some((first, last)) => self
+                .prompt_content()
+                .lines()
+                .skip(first)
+                .take(last - first + 1)
+                .map(|line| format!("{}\n", line))
+                .collect(),
+            None => self.prompt_content().to_string(),
+        };
+
+        let instructions = format!(
+            "Write unit tests for the code below, {}, using {}. Give runnable \
+             skeletons: a test for each behaviour worth checking, including edge cases \
+             and errors, with setup and assertions filled in where the code makes them \
+             clear and TODO comments where it does not. Reply with only the test code, \
+             in one code block.\n\n",
+            source, template.framework
+        );
+        let fitted = fit_lines(
+            &code,
+            context_budget(&review_params(&self.generation), &[&instructions]),
+        );
+        let prompt = format!("{}```\n{}```", instructions, fitted.text);
+
+        *self.test_suggestions.lock().unwrap() = TestSuggestions {
+            source,
+            warning: fitted.cut_warning("Code"),
+            ..TestSuggestions::default()
+        };
+        self.show_test_suggestions = true;
+        self.dispatch_ai_request(prompt, RequestKind::Tests);
+    }
+
+    fn spawn_tests(&mut self, prompt: String) {
+        let suggestions = self.test_suggestions.clone();
+        let usage_stats = self.usage_stats.clone();
+        let backend = self.backend_config();
+        let params = review_params(&self.generation);
+        suggestions.lock().unwrap().running = true;
+
+        self.network.spawn(async move {
+            let started = Instant::now();
+            let result = backend
+                .complete(None, vec![ChatMessage::user(prompt)], &params, |_| {})
+                .await;
+
+            let mut suggestions = suggestions.lock().unwrap();
+            match result {
+                Ok(completion) => {
+                    usage_stats.lock().unwrap().record(
+                        &backend.model,
+                        &completion,
+                        started.elapsed(),
+                    );
+                    suggestions.tests = Some(parse_edit(&completion.text, "\n"));
+                }
+                Err(e) => suggestions.error = Some(e),
+            }
+            suggestions.running = false;
+        });
+    }
+
+    /// Write the suggested tests to a file, by default named as the
+    /// language's test files are, next to the document.
+    fn save_test_suggestions(&mut self) {
+        let tests = self.test_suggestions.lock().unwrap().tests.clone();
+        let (Some(tests), Some(template)) = (tests, test_template(&self.file_extension())) else {
+            return;
+        };
+        let name = display_name(self.file_path.as_deref());
+        let mut dialog = rfd::FileDialog::new()
+            .set_title("Save tests")
+            .set_file_name(template.test_file_name(&name));
+        if let Some(dir) = self
+            .file_path
+            .as_deref()
+            .and_then(|path| Path::new(path).parent())
+            .filter(|dir| dir.is_dir())
+        {
+            dialog = dialog.set_directory(dir);
+        }
+        let Some(target) = dialog.save_file() else {
+            return;
+        };
+        self.test_suggestions.lock().unwrap().status = Some(match fs::write(&target, tests) {
+            Ok(()) => format!("Saved to {}", target.display()),
+            Err(e) => format!("Could not write {}: {}", target.display(), e),
+        });
+    }
+
+    fn show_test_suggestions_window(&mut self, ctx: &egui::Context) {
+        let (running, source, tests, warning, error, status) = {
+            let suggestions = self.test_suggestions.lock().unwrap();
+            (
+                suggestions.running,
+                suggestions.source.clone(),
+                suggestions.tests.clone(),
+                suggestions.warning.clone(),
+                suggestions.error.clone(),
+                suggestions.status.clone(),
+            )
+        };
+        let language = test_template(&self.file_extension()).map_or("", |t| t.language);
+        let mut open = self.show_test_suggestions;
+        let mut save = false;
+        let mut again = false;
+
+        egui::Window::new("🧪 Suggested tests")
+            .open(&mut open)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Tests for {}", source));
+                    if running {
+                        ui.spinner();
+                        ui.label("Writing tests...");
+                    }
+                });
+                if let Some(warning) = warning.filter(|_| !running) {
+                    ui.colored_label(egui::Color32::YELLOW, format!("✂ {}", warning));
+                }
+                if let Some(error) = error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                }
+                let Some(tests) = tests else {
+                    return;
+                };
+
+                let size = egui::TextStyle::Body.resolve(ui.style()).size;
+                egui::ScrollArea::vertical()
+                    .max_height(420.0)
+                    .show(ui, |ui| {
+                        self.show_markdown_block(
+                            ui,
+                            &MarkdownBlock::Code {
+                                language: language.to_string(),
+                                code: tests.clone(),
+                            },
+                            size,
+                        );
+                    });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("📋 Copy").clicked() {
+                        ui.output_mut(|o| o.copied_text = tests.clone());
+                    }
+                    if ui.button("💾 Save as…").clicked() {
+                        save = true;
+                    }
+                    if ui
+                        .add_enabled(!running, egui::Button::new("↻ Regenerate"))
+                        .clicked()
+                    {
+                        again = true;
+                    }
+                });
+                if let Some(status) = status {
+                    ui.label(status);
+                }
+            });
+
+        self.show_test_suggestions = open;
+        if save {
+            self.save_test_suggestions();
+        }
+        if again && self.can_suggest_tests() {
+            self.suggest_tests();
+        }
+    }
+
+    fn ai_endpoint(&self) -> String {
+        match self.provider {
+            Provider::Ollama => format!("{} ({})", self.ollama_url, self.selected_model),
+            Provider::LlamaCpp => self.llama_cpp_url.clone(),
+            Provider::OpenAi => format!("{} ({})", self.openai_url, self.openai_model),
+            Provider::Anthropic => format!("{} ({})", ANTHROPIC_URL, self.anthropic_model),
+        }
+    }
+
+    fn show_pii_dialog(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.pending_request else {
+            return;
+        };
+        let mut decision = None;
+
+        egui::Window::new("⚠ Personal data detected")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "This document appears to contain personal data. Sending will share up to {} bytes of it with {}:",
+                    pending.prompt.len()
+                        + match pending.kind {
+                            RequestKind::Chat { .. }
+                            | RequestKind::Resend { .. }
+                            | RequestKind::Comparison => {
+                                self.chat_context(&[&pending.prompt]).system_prompt().len()
+                            }
+                            RequestKind::Translation
+                            | RequestKind::Review
+                            | RequestKind::Edit
+                            | RequestKind::Tests => 0,
+                        },
+                    self.ai_endpoint()
+                ));
+                ui.add_space(4.0);
+
+                egui::Grid::new("pii_findings").striped(true).show(ui, |ui| {
+                    for finding in &self.pii_findings {
+                        ui.strong(format!("{} × {}", finding.count, finding.kind));
+                        ui.monospace(finding.examples.join(", "));
+                        ui.end_row();
+                    }
+                });
+
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Send anyway").clicked() {
+                        decision = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        decision = Some(false);
+                    }
+                });
+            });
+
+        match decision {
+            Some(true) => {
+                self.pii_confirmed = true;
+                if let Some(pending) = self.pending_request.take() {
+                    self.dispatch_ai_request(pending.prompt, pending.kind);
+                }
+            }
+            Some(false) => {
+                if let Some(PendingRequest {
+                    kind: RequestKind::Translation,
+                    ..
+                }) = self.pending_request.take()
+                {
+                    self.show_translation = false;
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Ask the model `prompt`, with `images` if any, as the next turn after `history`.
+    fn spawn_ai_request(&mut self, prompt: String, history: Vec<ChatMessage>, images: Vec<String>) {
+        let events = self.ai_sender.clone();
+        let usage_stats = self.usage_stats.clone();
+        let backend = self.backend_config();
+        let params = self.generation;
+        let rest: Vec<&str> = history
+            .iter()
+            .map(|message| message.content.as_str())
+            .chain([prompt.as_str()])
+            .collect();
+        let context = self.chat_context(&rest);
+        let system_prompt = context.system_prompt();
+        let ChatContext {
+            preamble,
+            others,
+            warnings,
+            ..
+        } = context;
+        let retrieval = (self.context_mode == ContextMode::Relevant).then(|| Retrieval {
+            index: self.document_index.clone(),
+            ollama_url: self.ollama_url.clone(),
+            model: self.embedding_model.clone(),
+            preamble: preamble.clone(),
+        });
+        let question = prompt.clone();
+        let mut messages = conversation(&history, prompt);
+        if let Some(last) = messages.last_mut() {
+            last.images = images;
+        }
+        let request = self.next_ai_request;
+        self.next_ai_request += 1;
+        self.ai_state.begin(request, self.document_key());
+        self.ai_state.context_warnings.extend(warnings);
+
+        let task = self.network.spawn(async move {
+            // Embedding the question uses the blocking client.
+            let context = tokio::task::spawn_blocking(move || {
+                retrieval.and_then(|retrieval| retrieval.context(&question))
+            });
+            let system_prompt = match context.await.ok().flatten() {
+                Some(relevant) => format!("{}{}", relevant, others),
+                None => system_prompt,
+            };
+            let context = system_prompt
+                .strip_prefix(&preamble)
+                .unwrap_or(&system_prompt)
+                .to_string();
+            let _ = events.send(AiEvent::Started { request, context });
+            let started = Instant::now();
+            let result = backend
+                .complete(Some(&system_prompt), messages, &params, |token| {
+                    let _ = events.send(AiEvent::Token {
+                        request,
+                        text: token.to_string(),
+                    });
+                })
+                .await;
+            let event = match result {
+                Ok(completion) => {
+                    usage_stats.lock().unwrap().record(
+                        &backend.model,
+                        &completion,
+                        started.elapsed(),
+                    );
+                    AiEvent::Completed {
+                        request,
+                        completion,
+                        model: backend.model.clone(),
+                        elapsed: started.elapsed(),
+                    }
+                }
+                Err(message) => AiEvent::Error { request, message },
+            };
+            let _ = events.send(event);
+        });
+        if let Some(task) = task {
+            self.ai_tasks.insert(request, task);
+        }
+    }
+
+    /// Stop the answer the current document is waiting for, if any.
+    fn cancel_ai_request(&mut self) {
+        if let Some(task) = self
+            .ai_state
+            .request
+            .and_then(|id| self.ai_tasks.remove(&id))
+        {
+            task.abort();
+        }
+        self.ai_state.cancel();
+    }
+
+    /// Apply what chat workers have reported since the last frame, to
+    /// whichever tab asked.
+    fn poll_ai_events(&mut self) {
+        while let Ok(event) = self.ai_events.try_recv() {
+            let request = Some(event.request());
+            let state = std::iter::once(&mut self.ai_state)
+                .chain(self.documents.iter_mut().map(|doc| &mut doc.ai_state))
+                .find(|state| state.request == request);
+            if let Some(state) = state {
+                state.apply(event);
+            }
+        }
+        self.ai_tasks.retain(|_, task| !task.is_finished());
+    }
+
+    fn ai_requests_running(&self) -> bool {
+        self.ai_state.is_loading() || self.documents.iter().any(|doc| doc.ai_state.is_loading())
+    }
+
+    fn show_top_panel(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading(format!("📄 {}", self.file_name()));
+                ui.menu_button("File", |ui| {
+                    if ui.button("📂 Open File…").clicked() {
+                        ui.close_menu();
+                        self.show_open_dialog(ctx);
+                    }
+                    if ui.button("🗁 Open Folder…").clicked() {
+                        ui.close_menu();
+                        self.show_open_folder_dialog();
+                    }
+                    ui.menu_button("Recent", |ui| self.show_recent_menu(ui, ctx));
+                    ui.separator();
+                    if ui
+                        .add_enabled(self.can_compare(), egui::Button::new("⇆ Compare with…"))
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        self.show_compare_dialog();
+                    }
+                    ui.separator();
+                    if ui
+                        .add_enabled(self.can_export(), egui::Button::new("🌐 Export as HTML…"))
+                        .on_hover_text("Uses the current theme; line numbers follow the # toggle")
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        self.export_html();
+                    }
+                    ui.add_enabled_ui(self.can_export(), |ui| {
+                        ui.menu_button("📄 Export as PDF", |ui| {
+                            if ui.button("Print-friendly").clicked() {
+                                ui.close_menu();
+                                self.export_pdf(true);
+                            }
+                            if ui.button("With the current theme").clicked() {
+                                ui.close_menu();
+                                self.export_pdf(false);
+                            }
+                        });
+                    });
+                });
+                ui.separator();
+
+                ui.toggle_value(&mut self.show_settings, "⚙ Settings")
+                    .on_hover_text(format!("AI: {}", self.ai_endpoint()));
+                let (icon, hint) = if self.dark_mode {
+                    ("☀", "Switch to light mode")
+                } else {
+                    ("🌙", "Switch to dark mode")
+                };
+                if ui.button(icon).on_hover_text(hint).clicked() {
+                    self.set_dark_mode(!self.dark_mode, ctx);
+                }
+                ui.separator();
+
+                let label = if self.auto_scroll {
+                    "⏸ Auto-scroll"
+                } else {
+                    "▶ Auto-scroll"
+                };
+                ui.toggle_value(&mut self.auto_scroll, label)
+                    .on_hover_text("Space: play/pause, -/+: slower/faster");
+                ui.add(
+                    egui::DragValue::new(&mut self.auto_scroll_speed)
+                        .clamp_range(5.0..=600.0)
+                        .speed(1.0)
+                        .suffix(" px/s"),
+                );
+                ui.separator();
+
+                if self.file_path.is_some() && self.gguf_info.is_none() && self.hex_data.is_none() {
+                    let current = self.syntax_override().map(|syntax| syntax.name.clone());
+                    let mut choice = current.clone();
+                    let mut names: Vec<&str> = self
+                        .syntax_set
+                        .syntaxes()
+                        .iter()
+                        .map(|syntax| syntax.name.as_str())
+                        .collect();
+                    names.sort_unstable_by_key(|name| name.to_lowercase());
+                    egui::ComboBox::from_id_source("syntax_select")
+                        .selected_text(&self.detect_syntax().name)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut choice, None, "Auto-detect");
+                            for name in names {
+                                ui.selectable_value(&mut choice, Some(name.to_string()), name);
+                            }
+                        })
+                        .response
+                        .on_hover_text("Language used for highlighting");
+                    if choice != current {
+                        self.set_syntax_override(choice);
+                    }
+                    #[cfg(feature = "tree-sitter")]
+                    self.show_highlighter_toggle(ui);
+                    ui.separator();
+                }
+
+                ui.toggle_value(&mut self.show_line_numbers, "#")
+                    .on_hover_text("Line numbers");
+                ui.toggle_value(&mut self.show_minimap, "▥")
+                    .on_hover_text("Minimap");
+                ui.toggle_value(&mut self.show_outline, "🗂")
+                    .on_hover_text("Outline");
+                if self.git.is_some() {
+                    ui.toggle_value(&mut self.show_blame, "👤 Blame")
+                        .on_hover_text("Show the last commit to change each line");
+                    ui.menu_button("🕘 History", |ui| self.show_history_menu(ui))
+                        .response
+                        .on_hover_text("Open the file as of an earlier commit");
+                }
+                if ui
+                    .selectable_label(self.split_pane.is_some(), "◫ Split")
+                    .on_hover_text("Show another tab, or another part of this one, alongside")
+                    .clicked()
+                {
+                    self.split_pane = match self.split_pane {
+                        Some(_) => None,
+                        None => Some(SplitPane::default()),
+                    };
+                }
+                ui.toggle_value(&mut self.focus_mode, "🎯 Focus")
+                    .on_hover_text("Dim everything except the block in the middle of the view");
+
+                if self.can_edit()
+                    && ui
+                        .toggle_value(&mut self.editing, "✏ Edit")
+                        .on_hover_text("Edit the file; Ctrl+S saves")
+                        .changed()
+                    && self.editing
+                {
+                    // Following would append to the buffer under the cursor.
+                    self.follow = false;
+                    self.follower = None;
+                }
+                if self.editing
+                    && ui
+                        .button("⇄ Convert")
+                        .on_hover_text("Convert line endings or indentation")
+                        .clicked()
+                {
+                    self.conversion.get_or_insert(Conversion::Lf);
+                }
+                if let Some(image) = &mut self.image_preview {
+                    ui.selectable_value(&mut image.show_hex, false, "🖼 Image");
+                    ui.selectable_value(&mut image.show_hex, true, "Hex");
+                }
+                if self.has_rendered_view() && !self.editing {
+                    let rendered = if self.is_markdown() {
+                        "Preview"
+                    } else {
+                        "🌳 Tree"
+                    };
+                    ui.selectable_value(&mut self.document_view, DocumentView::Source, "Source");
+                    ui.selectable_value(&mut self.document_view, DocumentView::Rendered, rendered);
+                    let split =
+                        ui.selectable_value(&mut self.document_view, DocumentView::Split, "Split");
+                    if self.is_markdown() {
+                        split.on_hover_text("Click a block in either pane to find it in the other");
+                    }
+                }
+                if self.file_path.is_some() && !self.streamed && !self.editing {
+                    ui.toggle_value(&mut self.follow, "📡 Follow")
+                        .on_hover_text("Append new lines as the file grows");
+                }
+                if self.log_mode || self.log_lines.has_levels() {
+                    ui.toggle_value(&mut self.log_mode, "📜 Log")
+                        .on_hover_text("Colour lines by level and filter them by level and time");
+                }
+                ui.separator();
+
+                if self.is_prose() {
+                    ui.menu_button("Aa", |ui| {
+                        let typography = &mut self.typography;
+                        ui.checkbox(&mut typography.justify, "Justify");
+                        ui.add(
+                            egui::Slider::new(&mut typography.line_height, 1.0..=2.5)
+                                .text("Line height"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut typography.paragraph_spacing, 0.0..=40.0)
+                                .text("Paragraph spacing"),
+                        );
+                        ui.add_enabled(
+                            self.serif_available,
+                            egui::Checkbox::new(&mut typography.serif, "Serif font"),
+                        )
+                        .on_disabled_hover_text(
+                            "No serif font found; put one at serif.ttf in the config directory",
+                        );
+                    });
+                    ui.separator();
+                }
+
+                ui.toggle_value(&mut self.show_ai_panel, "🤖 AI Panel");
+                ui.toggle_value(&mut self.show_stats, "📊 Stats");
+                ui.toggle_value(&mut self.show_benchmark, "⏱ Benchmark");
+                ui.toggle_value(&mut self.show_comparison, "⚖ Compare");
+                ui.toggle_value(
+                    &mut self.show_annotations,
+                    format!("🖍 Annotations ({})", self.annotations.len()),
+                );
+                ui.toggle_value(
+                    &mut self.show_bookmarks,
+                    format!("🔖 Bookmarks ({})", self.bookmarks.len()),
+                );
+            });
+
+            if self.show_search {
+                self.show_search_bar(ui);
+            }
+        });
+    }
+
+    fn show_search_bar(&mut self, ui: &mut egui::Ui) {
+        let mut step = None;
+        ui.horizontal(|ui| {
+            ui.label("🔍");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.search_query)
+                    .hint_text("Find in document")
+                    .desired_width(240.0),
+            );
+            if std::mem::take(&mut self.focus_search) {
+                response.request_focus();
+            }
+            ui.toggle_value(&mut self.search_regex, ".*")
+                .on_hover_text("Regular expression");
+            if response.lost_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    step = Some(if ui.input(|i| i.modifiers.shift) {
+                        -1
+                    } else {
+                        1
+                    });
+                    response.request_focus();
+                } else if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    self.show_search = false;
+                }
+            }
+
+            if self.search_query.is_empty() {
+                // Nothing to report yet.
+            } else if self.search_pattern.is_none() {
+                ui.colored_label(egui::Color32::LIGHT_RED, "Invalid expression");
+            } else if self.search_matches.is_empty() {
+                ui.colored_label(egui::Color32::LIGHT_RED, "No matches");
+            } else {
+                ui.label(format!(
+                    "{} of {}",
+                    self.current_match + 1,
+                    self.search_matches.len()
+                ));
+            }
+            if ui
+                .small_button("⬆")
+                .on_hover_text("Previous (Shift+Enter)")
+                .clicked()
+            {
+                step = Some(-1);
+            }
+            if ui.small_button("⬇").on_hover_text("Next (Enter)").clicked() {
+                step = Some(1);
+            }
+            if ui.small_button("✖").clicked() {
+                self.show_search = false;
+            }
+        });
+        if self.editing {
+            self.show_replace_bar(ui);
+        }
+
+        if let Some(step) = step {
+            self.step_match(step);
+        }
+    }
+
+    /// Replace the current match or all of them, each replacement undoable
+    /// as a whole.
+    fn show_replace_bar(&mut self, ui: &mut egui::Ui) {
+        let (mut replace_one, mut replace_all, mut undo) = (false, false, false);
+        ui.horizontal(|ui| {
+            ui.label("⇄");
+            let hint = match self.search_regex {
+                true => "Replace with ($1 for groups)",
+                false => "Replace with",
+            };
+            ui.add(
+                egui::TextEdit::singleline(&mut self.replace_query)
+                    .hint_text(hint)
+                    .desired_width(240.0),
+            );
+            let has_matches = !self.search_matches.is_empty();
+            replace_one = ui
+                .add_enabled(has_matches, egui::Button::new("Replace"))
+                .clicked();
+            replace_all = ui
+                .add_enabled(has_matches, egui::Button::new("Replace all"))
+                .clicked();
+            undo = ui
+                .add_enabled(!self.replace_undo.is_empty(), egui::Button::new("↶ Undo"))
+                .on_hover_text("Undo the last replacement")
+                .clicked();
+            if let Some(status) = &self.replace_status {
+                ui.weak(status);
+            }
+        });
+
+        if replace_one || replace_all {
+            self.replace(replace_all);
+        } else if undo {
+            if let Some(text) = self.replace_undo.undo() {
+                self.set_replaced_text(text);
+                self.replace_status = Some("Replacement undone".to_string());
+            }
+        }
+    }
+
+    /// Replace every match, or only the current one, moving on to the next.
+    fn replace(&mut self, all: bool) {
+        let Some(pattern) = &self.search_pattern else {
+            return;
+        };
+        let starts: Vec<usize> = match all {
+            true => self.search_matches.iter().map(|m| m.start).collect(),
+            false => self
+                .search_matches
+                .get(self.current_match)
+                .map(|m| m.start)
+                .into_iter()
+                .collect(),
+        };
+        let (text, count) = replace_matches(
+            &self.file_content,
+            pattern,
+            &starts,
+            &self.replace_query,
+            self.search_regex,
+        );
+        if count == 0 {
+            return;
+        }
+        self.replace_undo
+            .record(std::mem::take(&mut self.file_content));
+        self.set_replaced_text(text);
+        self.replace_status = Some(match count {
+            1 => "Replaced 1 match".to_string(),
+            count => format!("Replaced {} matches", count),
+        });
+    }
+
+    fn set_replaced_text(&mut self, text: String) {
+        self.file_content = text;
+        self.dirty = true;
+        self.edited_at = Some(Instant::now());
+        // Byte offsets into the old text are meaningless now.
+        self.editor_cursor = None;
+        self.selection = None;
+        self.selection_history.clear();
+        // Rebuild the matches even if the length is unchanged, keeping the
+        // current index, which now holds the next match.
+        self.search_key.3 = usize::MAX;
+    }
+
+    /// Rebuild the match index when the query or document changed, moving
+    /// to the first match at or below the top of the view.
+    fn update_search(&mut self) {
+        let key = (
+            self.search_query.clone(),
+            self.search_regex,
+            self.active_document,
+            self.file_content.len(),
+        );
+        if !self.show_search || key == self.search_key {
+            return;
+        }
+        let query_changed =
+            key.0 != self.search_key.0 || key.1 != self.search_key.1 || key.2 != self.search_key.2;
+        self.search_key = key;
+        self.search_pattern = search_pattern(&self.search_query, self.search_regex);
+        self.search_matches = match &self.search_pattern {
+            Some(pattern) => find_matches(&self.file_content, pattern),
+            None => Vec::new(),
+        };
+
+        if query_changed {
+            let top = line_starts(&self.file_content)
+                .get(self.visible_lines.0)
+                .copied()
+                .unwrap_or(0);
+            self.current_match = self.search_matches.partition_point(|m| m.start < top);
+            if self.current_match >= self.search_matches.len() {
+                self.current_match = 0;
+            }
+            self.scroll_to_current_match();
+        } else {
+            self.current_match = self
+                .current_match
+                .min(self.search_matches.len().saturating_sub(1));
+        }
+    }
+
+    fn step_match(&mut self, step: isize) {
+        let count = self.search_matches.len();
+        if count == 0 {
+            return;
+        }
+        self.current_match =
+            (self.current_match as isize + step).rem_euclid(count as isize) as usize;
+        self.scroll_to_current_match();
+    }
+
+    fn scroll_to_current_match(&mut self) {
+        if let Some(hit) = self.search_matches.get(self.current_match) {
+            self.auto_scroll = false;
+            self.scroll_to_line = Some(self.file_content[..hit.start].matches('\n').count());
+        }
+    }
+
+    /// Decode the file again in `encoding`, or the detected one for `None`.
+    fn set_encoding_override(&mut self, encoding: Option<&'static Encoding>) {
+        self.encoding_override = encoding;
+        if let Some(path) = self.file_path.clone() {
+            self.load_file(&path);
+        }
+    }
+
+    fn show_encoding_picker(&mut self, ui: &mut egui::Ui) {
+        let mut choice = self.encoding_override;
+        let selected = self.encoding.map_or("Binary", Encoding::name);
+        ui.add_enabled_ui(!self.dirty, |ui| {
+            egui::ComboBox::from_id_source("encoding_select")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut choice, None, "Auto-detect");
+                    for encoding in ENCODINGS {
+                        ui.selectable_value(&mut choice, Some(encoding), encoding.name());
+                    }
+                })
+                .response
+                .on_hover_text("Text encoding")
+                .on_disabled_hover_text("Save or discard your edits first");
+        });
+        if choice != self.encoding_override {
+            self.set_encoding_override(choice);
+        }
+    }
+
+    fn show_status_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if self.file_path.is_some()
+                    && ui
+                        .add(egui::Label::new(self.get_file_info()).sense(egui::Sense::click()))
+                        .on_hover_text("Click for document statistics")
+                        .clicked()
+                {
+                    self.show_text_stats = !self.show_text_stats;
+                }
+                if let Some(image) = &self.image_preview {
+                    ui.separator();
+                    ui.label(format!(
+                        "{} × {} px · {} · {:.0}%",
+                        image.width,
+                        image.height,
+                        image.format,
+                        image.shown_zoom * 100.0
+                    ));
+                }
+                if self.file_path.is_some() && self.gguf_info.is_none() && !self.streamed {
+                    ui.separator();
+                    self.show_encoding_picker(ui);
+                }
+                if let Some(git) = &self.git {
+                    ui.separator();
+                    let changed = git.changes.iter().filter(|c| c.is_some()).count();
+                    let branch = git.branch.as_deref().unwrap_or("detached");
+                    ui.label(format!("⎇ {}", branch)).on_hover_text(format!(
+                        "{} ({} changed lines)",
+                        git.relative.display(),
+                        changed
+                    ));
+                }
+                if self.dirty {
+                    ui.separator();
+                    ui.label("● Modified");
+                }
+                if self.vim_keys {
+                    ui.separator();
+                    ui.monospace(format!("VIM {}", self.vim_pending))
+                        .on_hover_text(
+                            "j/k/h/l scroll, gg/G jump, Ctrl+D/U half a page, / search, \
+                         n/N next/previous match, : go to line",
+                        );
+                }
+                if let Some(error) = &self.save_error {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                }
+                if let Some(error) = &self.diff_error {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                }
+                match &self.export_status {
+                    Some((Err(error), _)) => {
+                        ui.separator();
+                        ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                    }
+                    Some((Ok(message), at)) if at.elapsed() < Duration::from_secs(6) => {
+                        ui.separator();
+                        ui.label(message);
+                        ctx.request_repaint_after(Duration::from_secs(1));
+                    }
+                    _ => {}
+                }
+                if !self.font_errors.is_empty() {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::LIGHT_RED, "⚠ Fonts")
+                        .on_hover_text(format!(
+                            "Could not load fonts from config.toml:\n{}",
+                            self.font_errors.join("\n")
+                        ));
+                }
+                if let Some(task) = &self.highlight_task {
+                    ui.separator();
+                    ui.spinner();
+                    ui.label(format!(
+                        "Highlighting {}%",
+                        task.next * 100 / self.highlighted_content.len().max(1)
+                    ));
+                }
+                if let Some(dir) = &self.log_dir {
+                    ui.separator();
+                    ui.label(format!("👁 Watching {}", dir.display()));
+                } else if self.follow || self.stream.is_some() {
+                    ui.separator();
+                    ui.label("📡 Following");
+                }
+                if let Some(selection) = self.selection.as_ref().filter(|r| !r.is_empty()) {
+                    ui.separator();
+                    ui.label(format!(
+                        "{} chars selected (Alt+Up/Down to grow/shrink)",
+                        self.file_content[selection.clone()].chars().count()
+                    ));
+                }
+            });
+        });
+    }
+
+    fn file_extension(&self) -> String {
+        self.file_path
+            .as_deref()
+            .and_then(|path| Path::new(path).extension())
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    fn show_workflow_picker(&mut self, ui: &mut egui::Ui, is_loading: bool) {
+        let extension = self.file_extension();
+        let selected_name = self
+            .active_template
+            .and_then(|i| self.templates.get(i))
+            .map(|t| t.name.clone())
+            .unwrap_or_else(|| "None".to_string());
+
+        let mut chosen = self.active_template;
+        ui.horizontal(|ui| {
+            ui.label("Workflow:");
+            egui::ComboBox::from_id_source("workflow_select")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut chosen, None, "None");
+                    for (i, template) in self.templates.iter().enumerate() {
+                        if template.applies_to(&extension) {
+                            ui.selectable_value(&mut chosen, Some(i), &template.name);
+                        }
+                    }
+                });
+        });
+
+        if chosen != self.active_template {
+            self.active_template = chosen;
+            self.template_progress = chosen
+                .and_then(|i| self.templates.get(i))
+                .map(|t| vec![false; t.questions.len()])
+                .unwrap_or_default();
+        }
+
+        let Some(template) = self.active_template.and_then(|i| self.templates.get(i)) else {
+            return;
+        };
+
+        let mut ask = None;
+        for (i, question) in template.questions.iter().enumerate() {
+            let done = self.template_progress.get(i).copied().unwrap_or(false);
+            let text = format!("{} {}. {}", if done { "✔" } else { "▶" }, i + 1, question);
+            let button = egui::Button::new(if done {
+                egui::RichText::new(text).weak()
+            } else {
+                egui::RichText::new(text)
+            })
+            .wrap(true);
+            if ui.add_enabled(!is_loading, button).clicked() {
+                ask = Some(i);
+            }
+        }
+
+        if let Some(i) = ask {
+            let question = template.questions[i].clone();
+            if let Some(done) = self.template_progress.get_mut(i) {
+                *done = true;
+            }
+            self.send_to_ai(question);
+        }
+    }
+
+    fn show_redaction_status(&mut self, ui: &mut egui::Ui) {
+        if !self.redactor.invalid.is_empty() {
+            ui.horizontal(|ui| {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!(
+                        "⚠ {} redaction patterns are invalid and mask nothing",
+                        self.redactor.invalid.len()
+                    ),
+                );
+                if ui.small_button("Show").clicked() {
+                    self.show_redactions = true;
+                }
+            });
+        }
+        if self.redactions.is_empty() {
+            return;
+        }
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            if self.redaction_opted_out() {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!("⚠ {} secrets will be sent unmasked", self.redactions.len()),
+                );
+            } else {
+                ui.label(format!("🔒 {} secrets masked", self.redactions.len()));
+            }
+            if ui.small_button("Preview").clicked() {
+                self.show_redactions = true;
+            }
+        });
+
+        let mut opted_out = self.redaction_opted_out();
+        if ui
+            .checkbox(&mut opted_out, "Send this file unredacted")
+            .changed()
+        {
+            if opted_out {
+                self.redaction_opt_outs.insert(path);
+            } else {
+                self.redaction_opt_outs.remove(&path);
+            }
+            save_redaction_opt_outs(&self.redaction_opt_outs);
+            self.pii_findings = scan_pii(self.prompt_content());
+        }
+    }
+
+    fn show_redactions_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("🔒 Redacted before sending")
+            .open(&mut self.show_redactions)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    egui::Grid::new("redactions").striped(true).show(ui, |ui| {
+                        ui.strong("Line");
+                        ui.strong("Kind");
+                        ui.strong("Starts with");
+                        ui.end_row();
+                        for redaction in &self.redactions {
+                            ui.label(redaction.line.to_string());
+                            ui.label(&redaction.kind);
+                            ui.monospace(&redaction.hint);
+                            ui.end_row();
+                        }
+                    });
+                });
+                if !self.redactor.invalid.is_empty() {
+                    ui.separator();
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        "These patterns in redact_patterns.txt are invalid and mask nothing:",
+                    );
+                    for invalid in &self.redactor.invalid {
+                        ui.monospace(invalid);
+                    }
+                }
+                ui.separator();
+                ui.weak("Add custom patterns, one regex per line, to redact_patterns.txt in the config directory.");
+            });
+    }
+
+    fn show_ai_panel(&mut self, ctx: &egui::Context) {
+        let panel = egui::SidePanel::right("ai_panel")
+            .resizable(true)
+            .default_width(self.ai_panel_width)
+            .show(ctx, |ui| {
+                ui.heading("🤖 AI Assistant");
+                match self.provider {
+                    Provider::Ollama => ui.label(format!("Model: {}", self.selected_model)),
+                    Provider::LlamaCpp => {
+                        ui.label(format!("Backend: {}", self.llama_cpp_url))
+                    }
+                    Provider::OpenAi => ui.label(format!(
+                        "Model: {} at {}",
+                        self.openai_model, self.openai_url
+                    )),
+                    Provider::Anthropic => {
+                        ui.label(format!("Model: {} (Anthropic)", self.anthropic_model))
+                    }
+                };
+
+                if self.provider == Provider::Ollama {
+                    self.show_ollama_banner(ui);
+                }
+                if self.provider == Provider::LlamaCpp {
+                    ui.collapsing("Grammar constraint (GBNF)", |ui| {
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.grammar)
+                                .code_editor()
+                                .desired_rows(4)
+                                .hint_text("root ::= \"yes\" | \"no\""),
+                        );
+                    });
+                }
+                ui.separator();
+
+                let is_loading = self.ai_state.is_loading();
+
+                self.show_workflow_picker(ui, is_loading);
+                ui.horizontal(|ui| {
+                    let running = self.translation.lock().unwrap().running;
+                    if ui
+                        .add_enabled(!running, egui::Button::new("🌐 Translate to"))
+                        .clicked()
+                    {
+                        self.translate_document();
+                    }
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.translation_language)
+                            .desired_width(100.0),
+                    );
+                    if !self.translation.lock().unwrap().paragraphs.is_empty() {
+                        ui.toggle_value(&mut self.show_translation, "Side by side");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Context:");
+                    ui.radio_value(
+                        &mut self.context_mode,
+                        ContextMode::DocumentStart,
+                        "Document start",
+                    );
+                    ui.radio_value(
+                        &mut self.context_mode,
+                        ContextMode::Viewport,
+                        "Visible lines",
+                    );
+                    ui.radio_value(
+                        &mut self.context_mode,
+                        ContextMode::Relevant,
+                        "Relevant chunks",
+                    )
+                    .on_hover_text(
+                        "Embed the document with Ollama and send the parts closest to each question",
+                    );
+                    if self.context_mode == ContextMode::Viewport {
+                        ui.label("±");
+                        ui.add(
+                            egui::DragValue::new(&mut self.viewport_margin).clamp_range(0..=500),
+                        );
+                    }
+                });
+                if self.context_mode == ContextMode::Relevant {
+                    self.show_index_status(ui);
+                }
+                self.show_context_files(ui);
+                self.show_redaction_status(ui);
+                ui.separator();
+
+                let mut chat_action = None;
+                egui::ScrollArea::vertical()
+                    .max_height(ui.available_height() - 100.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        // Taken out for the loop, as rendering markdown borrows `self`.
+                        let chat = std::mem::take(&mut self.ai_state.chat);
+                        for id in chat.branch() {
+                            let action = self.show_chat_message(ui, &chat, id, is_loading);
+                            chat_action = action.or(chat_action.take());
+                            ui.add_space(6.0);
+                        }
+
+                        self.ai_state.chat = chat;
+
+                        let empty = self.ai_state.chat.is_empty();
+                        if empty && !is_loading && self.image_preview.is_some() {
+                            self.show_describe_image(ui);
+                        } else if empty && !is_loading && self.file_path.is_some() {
+                            ui.weak("Ask a question below, or start with a summary.");
+                            if ui.button("📝 Summarize document").clicked() {
+                                self.generate_initial_summary();
+                            }
+                        }
+
+                        let current = self.ai_state.current_response.clone();
+                        if is_loading && !current.is_empty() {
+                            // The partial answer, with a cursor showing more is on its way.
+                            egui::Frame::none()
+                                .fill(self.chat_bubble_fill(false))
+                                .rounding(6.0)
+                                .inner_margin(8.0)
+                                .show(ui, |ui| {
+                                    ui.strong("AI");
+                                    let blink = (ui.input(|i| i.time) * 2.0) as i64 % 2 == 0;
+                                    let cursor = if blink { "_" } else { " " };
+                                    let text = format!("{}{}", current, cursor);
+                                    self.show_markdown(ui, &text, false);
+                                });
+                        } else if is_loading {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(if self.ai_state.started {
+                                    "Thinking..."
+                                } else {
+                                    "Gathering context..."
+                                });
+                            });
+                        }
+
+                        for warning in &self.ai_state.context_warnings {
+                            ui.colored_label(egui::Color32::YELLOW, format!("✂ {}", warning));
+                        }
+
+                        if let Some(error) = &self.ai_state.error {
+                            ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                        }
+                    });
+
+                if let Some(action) = chat_action {
+                    self.apply_chat_action(action);
+                }
+                ui.separator();
+
+                if let Some((first, last)) = self.ask_about {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("📎 Asking about lines {}–{}", first + 1, last + 1));
+                        if ui.small_button("✖").clicked() {
+                            self.ask_about = None;
+                        }
+                    });
+                }
+                let mut detached = None;
+                for (i, image) in self.attached_images.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let label = ui.label(format!("🖼 {}", image.name));
+                        if !self.sees_images() {
+                            label.on_hover_text("Sent only to Ollama vision models");
+                        }
+                        if ui.small_button("✖").clicked() {
+                            detached = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = detached {
+                    self.attached_images.remove(i);
+                }
+                let hint = if self.ask_about.is_some() {
+                    "Ask about the selected lines..."
+                } else if self.image_preview.is_some() && self.sees_images() {
+                    "Ask about this image..."
+                } else {
+                    "Ask about this document..."
+                };
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.user_question)
+                        .hint_text(hint)
+                        .desired_width(f32::INFINITY),
+                );
+                if std::mem::take(&mut self.focus_question) {
+                    response.request_focus();
+                }
+                if response.has_focus() {
+                    let (up, down) = ui.input_mut(|i| {
+                        (
+                            i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                            i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                        )
+                    });
+                    let recalled = (up || down)
+                        .then(|| self.question_history.recall(&self.user_question, up))
+                        .flatten();
+                    if let Some(question) = recalled {
+                        self.user_question = question;
+                        // Put the cursor after the recalled question.
+                        if let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), response.id) {
+                            let end = egui::text::CCursor::new(self.user_question.chars().count());
+                            state.set_ccursor_range(Some(egui::text::CCursorRange::one(end)));
+                            state.store(ui.ctx(), response.id);
+                        }
+                    }
+                }
+                let submitted =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+                ui.horizontal(|ui| {
+                    let ask = ui.add_enabled(!is_loading, egui::Button::new("Ask"));
+                    if (ask.clicked() || submitted) && !is_loading {
+                        let question = std::mem::take(&mut self.user_question);
+                        self.question_history.remember(&question);
+                        self.send_to_ai(question);
+                    }
+
+                    if is_loading && ui.button("⏹ Stop").on_hover_text("Esc").clicked() {
+                        self.cancel_ai_request();
+                    }
+                    if ui
+                        .add_enabled(self.sees_images(), egui::Button::new("📎 Attach image"))
+                        .on_hover_text("Send a screenshot or picture with the next question")
+                        .on_disabled_hover_text(
+                            "Pick an Ollama vision model, such as llava, to send images",
+                        )
+                        .clicked()
+                    {
+                        self.attach_image();
+                    }
+                    if ui.button("🗑 Clear Memory").clicked() {
+                        self.clear_conversation();
+                    }
+                    if ui
+                        .add_enabled(!is_loading, egui::Button::new("🆕 New conversation"))
+                        .on_hover_text("Forget this conversation and summarize the document again")
+                        .clicked()
+                    {
+                        self.clear_conversation();
+                        self.generate_initial_summary();
+                    }
+                });
+            });
+        self.ai_panel_width = panel.response.rect.width();
+    }
+
+    /// Counts for the active document, redone when it changes: right away
+    /// for small files, on a worker thread for big ones.
+    fn show_text_stats_window(&mut self, ctx: &egui::Context) {
+        let source = (self.file_path.clone(), self.file_content.len());
+        let binary = self.hex_data.is_some() || self.gguf_info.is_some();
+        let stats = {
+            let mut job = self.text_stats.lock().unwrap();
+            if !binary && job.source.as_ref() != Some(&source) {
+                job.source = Some(source.clone());
+                if self.file_content.len() < BACKGROUND_STATS_BYTES {
+                    job.stats = Some(text_stats(&self.file_content));
+                } else {
+                    job.stats = None;
+                    let text = self.file_content.clone();
+                    let text_stats_job = self.text_stats.clone();
+                    let ctx = ctx.clone();
+                    thread::spawn(move || {
+                        let stats = text_stats(&text);
+                        let mut job = text_stats_job.lock().unwrap();
+                        // Dropped if the document changed meanwhile.
+                        if job.source == Some(source) {
+                            job.stats = Some(stats);
+                        }
+                        ctx.request_repaint();
+                    });
+                }
+            }
+            job.stats.clone()
+        };
+
+        let mut go_to = None;
+        egui::Window::new("📈 Document statistics")
+            .open(&mut self.show_text_stats)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let Some(stats) = stats.filter(|_| !binary) else {
+                    if binary {
+                        ui.label("No text statistics for binary files");
+                    } else {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Counting...");
+                        });
+                    }
+                    return;
+                };
+                egui::Grid::new("text_stats")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Lines:");
+                        ui.label(format!("{} ({} blank)", stats.lines, stats.blank_lines));
+                        ui.end_row();
+                        ui.label("Words:");
+                        ui.label(format!("{} ({} unique)", stats.words, stats.unique_words));
+                        ui.end_row();
+                        ui.label("Characters:");
+                        ui.label(format!(
+                            "{} ({})",
+                            stats.chars,
+                            format_size(stats.bytes as u64)
+                        ));
+                        ui.end_row();
+                        ui.label("Longest line:");
+                        let (line, length) = stats.longest_line;
+                        if ui
+                            .link(format!("{} chars, line {}", length, line + 1))
+                            .clicked()
+                        {
+                            go_to = Some(line);
+                        }
+                        ui.end_row();
+                        ui.label("Average line:");
+                        ui.label(format!("{:.1} chars", stats.average_line_length));
+                        ui.end_row();
+                        ui.label("Reading time:");
+                        ui.label(format_minutes(stats.reading_minutes));
+                        ui.end_row();
+                        ui.label("Tokens:");
+                        ui.label(format!("≈ {}", stats.tokens)).on_hover_text(
+                            "Estimated as for fitting documents into a model's context: about \
+                             4 characters per token for words, 2 for symbols and 1 per CJK \
+                             character",
+                        );
+                        ui.end_row();
+                    });
+            });
+        if let Some(line) = go_to.filter(|_| self.can_go_to()) {
+            self.go_to(line, None);
+        }
+    }
+
+    fn show_stats_window(&mut self, ctx: &egui::Context) {
+        let stats = self.usage_stats.lock().unwrap();
+        egui::Window::new("📊 AI Usage")
+            .open(&mut self.show_stats)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.strong("This session");
+                show_stats_grid(ui, "session_stats", &stats.session);
+                ui.separator();
+                ui.strong("All time");
+                show_stats_grid(ui, "historical_stats", &stats.historical);
+            });
+    }
+
+    fn start_benchmark(&self, ctx: &egui::Context) {
+        let benchmark = self.benchmark.clone();
+        let models = self.available_models.clone();
+        let ollama_url = self.ollama_url.clone();
+        let ctx = ctx.clone();
+
+        {
+            let mut run = benchmark.lock().unwrap();
+            run.running = true;
+            run.results.clear();
+        }
+
+        thread::spawn(move || {
+            for (i, model) in models.iter().enumerate() {
+                benchmark.lock().unwrap().status =
+                    format!("Benchmarking {} ({}/{})...", model, i + 1, models.len());
+                ctx.request_repaint();
+
+                let result = benchmark_model(&ollama_url, model);
+                benchmark.lock().unwrap().results.push(result);
+                ctx.request_repaint();
+            }
+
+            let mut run = benchmark.lock().unwrap();
+            run.running = false;
+            run.status = "Done".to_string();
+            ctx.request_repaint();
+        });
+    }
+
+    /// The preferences kept in `config.toml`, grouped by topic.
+    fn show_settings_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_settings;
+        egui::Window::new("⚙ Settings")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let tab = &mut self.settings_tab;
+                    ui.selectable_value(tab, SettingsTab::Appearance, "Appearance");
+                    ui.selectable_value(tab, SettingsTab::Ai, "AI");
+                    ui.selectable_value(tab, SettingsTab::Behavior, "Behavior");
+                });
+                ui.separator();
+                match self.settings_tab {
+                    SettingsTab::Appearance => self.show_appearance_settings(ui, ctx),
+                    SettingsTab::Ai => self.show_ai_settings(ui, ctx),
+                    SettingsTab::Behavior => self.show_behavior_settings(ui),
+                }
+                if let Some(path) = config_path() {
+                    ui.separator();
+                    ui.weak(format!("Saved to {}", path.display()));
+                }
+            });
+        self.show_settings = open;
+    }
+
+    fn show_appearance_settings(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        egui::Grid::new("appearance_settings")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Theme");
+                let mut theme_name = self.theme_name.clone();
+                egui::ComboBox::from_id_source("theme_select")
+                    .selected_text(&theme_name)
+                    .show_ui(ui, |ui| {
+                        for name in self.theme_set.themes.keys() {
+                            ui.selectable_value(&mut theme_name, name.clone(), name);
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Syntax theme; add .tmTheme files to the themes config directory",
+                    );
+                self.set_theme(theme_name);
+                ui.end_row();
+
+                ui.label("Dark mode");
+                let mut dark_mode = self.dark_mode;
+                if ui.checkbox(&mut dark_mode, "").changed() {
+                    self.set_dark_mode(dark_mode, ctx);
+                }
+                ui.end_row();
+
+                ui.label("Font size");
+                ui.add(egui::Slider::new(
+                    &mut self.font_size,
+                    MIN_FONT_SIZE..=MAX_FONT_SIZE,
+                ));
+                ui.end_row();
+
+                ui.label("Reflow prose");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.reflow_prose, "");
+                    ui.add_enabled(
+                        self.reflow_prose,
+                        egui::DragValue::new(&mut self.reading_width)
+                            .clamp_range(40..=160)
+                            .suffix(" cols"),
+                    );
+                });
+                ui.end_row();
+
+                ui.label("Minimap");
+                ui.checkbox(&mut self.show_minimap, "");
+                ui.end_row();
+
+                ui.label("Outline");
+                ui.checkbox(&mut self.show_outline, "");
+                ui.end_row();
+
+                ui.label("Rainbow brackets");
+                ui.checkbox(&mut self.rainbow_brackets, "");
+                ui.end_row();
+            });
+    }
+
+    fn show_ai_settings(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        ui.horizontal(|ui| {
+            ui.label("Provider:");
+            egui::ComboBox::from_id_source("provider_select")
+                .selected_text(self.provider.label())
+                .show_ui(ui, |ui| {
+                    for provider in Provider::ALL {
+                        ui.selectable_value(&mut self.provider, provider, provider.label());
+                    }
+                });
+        });
+        match self.provider {
+            Provider::Ollama => self.show_ollama_settings(ui, ctx),
+            Provider::LlamaCpp => self.show_llama_cpp_settings(ui, ctx),
+            Provider::OpenAi => self.show_openai_settings(ui, ctx),
+            Provider::Anthropic => self.show_anthropic_settings(ui, ctx),
+        }
+        ui.separator();
+        self.show_generation_settings(ui);
+    }
+
+    fn show_behavior_settings(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(
+            &mut self.summarize_on_open,
+            "Summarize documents when they open",
+        )
+        .on_hover_text("Otherwise the AI panel waits for a question or \"Summarize document\"");
+        ui.checkbox(
+            &mut self.auto_reload,
+            "Reload files when they change on disk",
+        );
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.autosave, "Autosave unsaved edits every")
+                .on_hover_text("Kept for crash recovery; files on disk are left alone");
+            ui.add_enabled(
+                self.autosave,
+                egui::DragValue::new(&mut self.autosave_secs)
+                    .clamp_range(5..=600)
+                    .suffix(" s"),
+            );
+        });
+        ui.add_enabled(
+            self.auto_reload,
+            egui::Checkbox::new(
+                &mut self.summarize_on_reload,
+                "Summarize again after reloading",
+            ),
+        );
+        if ui
+            .checkbox(&mut self.vim_keys, "Vim-style navigation")
+            .on_hover_text("j/k/h/l, gg/G, Ctrl+D/U, /, n/N and :")
+            .changed()
+        {
+            self.vim_pending.clear();
+        }
+    }
+
+    fn show_llama_cpp_settings(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        ui.horizontal(|ui| {
+            ui.label("Server URL:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.llama_cpp_url)
+                    .hint_text(DEFAULT_LLAMA_CPP_URL)
+                    .desired_width(260.0),
+            );
+        });
+        ui.weak("llama.cpp's llama-server, or another server with its /completion API.");
+        ui.horizontal(|ui| {
+            if ui.button("🩺 Check server").clicked() {
+                let status = self.server_status.clone();
+                let ctx = ctx.clone();
+                let url = self.llama_cpp_url.clone();
+                *status.lock().unwrap() = Some("Checking...".to_string());
+                thread::spawn(move || {
+                    *status.lock().unwrap() = Some(check_llama_cpp_server(&url));
+                    ctx.request_repaint();
+                });
+            }
+            if let Some(status) = self.server_status.lock().unwrap().as_ref() {
+                ui.label(status);
+            }
+        });
+    }
+
+    fn show_openai_settings(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let mut test = false;
+        egui::Grid::new("openai_settings").show(ui, |ui| {
+            ui.label("Base URL:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.openai_url)
+                    .hint_text(DEFAULT_OPENAI_URL)
+                    .desired_width(260.0),
+            );
+            ui.end_row();
+
+            ui.label("Model:");
+            ui.add(egui::TextEdit::singleline(&mut self.openai_model).desired_width(260.0));
+            ui.end_row();
+
+            ui.label("API key variable:");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.openai_key_env).desired_width(160.0));
+                if api_key_from_env(&self.openai_key_env).is_some() {
+                    ui.label("✔ set");
+                } else {
+                    ui.weak("not set");
+                }
+            });
+            ui.end_row();
+        });
+        ui.weak("Local servers (LM Studio, vLLM, llama.cpp) usually need no key.");
+
+        ui.horizontal(|ui| {
+            test = ui.button("Test connection").clicked();
+            if let Some(status) = self.openai_status.lock().unwrap().as_ref() {
+                ui.label(status);
+            }
+        });
+
+        if test {
+            let status = self.openai_status.clone();
+            let url = self.openai_url.clone();
+            let key = api_key_from_env(&self.openai_key_env);
+            let ctx = ctx.clone();
+            *status.lock().unwrap() = Some("Checking...".to_string());
+            thread::spawn(move || {
+                *status.lock().unwrap() = Some(check_openai_server(&url, key.as_deref()));
+                ctx.request_repaint();
+            });
+        }
+    }
+
+    fn show_anthropic_settings(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let mut test = false;
+        egui::Grid::new("anthropic_settings").show(ui, |ui| {
+            ui.label("Model:");
+            ui.add(egui::TextEdit::singleline(&mut self.anthropic_model).desired_width(260.0));
+            ui.end_row();
+
+            ui.label("API key variable:");
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.anthropic_key_env).desired_width(160.0),
+                );
+                if api_key_from_env(&self.anthropic_key_env).is_some() {
+                    ui.label("✔ set");
+                } else {
+                    ui.colored_label(egui::Color32::LIGHT_RED, "not set");
+                }
+            });
+            ui.end_row();
+        });
+        ui.weak(
+            "Documents are sent to Anthropic's servers; local models keep them on this machine.",
+        );
+
+        ui.horizontal(|ui| {
+            test = ui
+                .add_enabled(
+                    api_key_from_env(&self.anthropic_key_env).is_some(),
+                    egui::Button::new("Test key"),
+                )
+                .clicked();
+            if let Some(status) = self.anthropic_status.lock().unwrap().as_ref() {
+                ui.label(status);
+            }
+        });
+
+        if let Some(key) = api_key_from_env(&self.anthropic_key_env).filter(|_| test) {
+            let status = self.anthropic_status.clone();
+            let ctx = ctx.clone();
+            *status.lock().unwrap() = Some("Checking...".to_string());
+            thread::spawn(move || {
+                *status.lock().unwrap() = Some(check_anthropic_key(&key));
+                ctx.request_repaint();
+            });
+        }
+    }
+
+    fn show_ollama_settings(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let mut url_changed = false;
+        let mut test = false;
+        ui.horizontal(|ui| {
+            ui.label("Model:");
+            egui::ComboBox::from_id_source("model_select")
+                .selected_text(&self.selected_model)
+                .show_ui(ui, |ui| {
+                    for model in &self.available_models {
+                        let response =
+                            ui.selectable_value(&mut self.selected_model, model.clone(), model);
+                        if let Some(details) =
+                            self.installed_models.iter().find(|m| &m.name == model)
+                        {
+                            response.on_hover_text(details.description());
+                        }
+                    }
+                });
+
+            let loading = self.model_discovery.lock().unwrap().loading;
+            if loading {
+                ui.spinner();
+            } else if ui.button("🔄").on_hover_text("Refresh models").clicked() {
+                self.refresh_models(ctx);
+            }
+            if let Some(error) = &self.model_discovery_error {
+                ui.colored_label(egui::Color32::LIGHT_RED, "⚠")
+                    .on_hover_text(error);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("URL:");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.ollama_url)
+                    .hint_text(DEFAULT_OLLAMA_URL)
+                    .desired_width(260.0),
+            );
+            url_changed = response.lost_focus();
+            test = ui.button("Test connection").clicked();
+        });
+        if ui.button("Reset to default").clicked() {
+            self.ollama_url = DEFAULT_OLLAMA_URL.to_string();
+            url_changed = true;
+        }
+        if let Some(status) = self.ollama_status.lock().unwrap().as_ref() {
+            ui.label(status);
+        }
+        ui.weak("Also settable with --ollama-url or ollama_url in config.toml.");
+
+        if url_changed {
+            self.refresh_models(ctx);
+        }
+        if test || url_changed {
+            let status = self.ollama_status.clone();
+            let url = self.ollama_url.clone();
+            let ctx = ctx.clone();
+            *status.lock().unwrap() = Some("Checking...".to_string());
+            thread::spawn(move || {
+                *status.lock().unwrap() = Some(check_ollama_server(&url));
+                ctx.request_repaint();
+            });
+        }
+    }
+
+    fn show_benchmark_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_benchmark;
+        let mut start = false;
+        let mut use_model = None;
+
+        egui::Window::new("⏱ Benchmark models")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let run = self.benchmark.lock().unwrap();
+                ui.label(format!(
+                    "Runs {} fixed prompts against every model in the model list.",
+                    BENCHMARK_PROMPTS.len()
+                ));
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!run.running, egui::Button::new("▶ Run benchmark"))
+                        .clicked()
+                    {
+                        start = true;
+                    }
+                    if run.running {
+                        ui.spinner();
+                    }
+                    ui.label(&run.status);
+                });
+
+                if run.results.is_empty() {
+                    return;
+                }
+
+                ui.separator();
+                let fastest = run
+                    .results
+                    .iter()
+                    .filter(|r| r.error.is_none())
+                    .max_by(|a, b| a.tokens_per_second.total_cmp(&b.tokens_per_second))
+                    .map(|r| r.model.clone());
+
+                egui::Grid::new("benchmark_results")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Model");
+                        ui.strong("First token");
+                        ui.strong("Tokens/s");
+                        ui.strong("Avg total");
+                        ui.label("");
+                        ui.end_row();
+
+                        for result in &run.results {
+                            if fastest.as_deref() == Some(result.model.as_str()) {
+                                ui.strong(format!("🏆 {}", result.model));
+                            } else {
+                                ui.label(&result.model);
+                            }
+
+                            match &result.error {
+                                Some(error) => {
+                                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                                    ui.label("");
+                                    ui.label("");
+                                    ui.label("");
+                                }
+                                None => {
+                                    ui.label(format!(
+                                        "{:.2} s",
+                                        result.time_to_first_token.as_secs_f64()
+                                    ));
+                                    ui.label(format!("{:.1}", result.tokens_per_second));
+                                    ui.label(format!("{:.1} s", result.total_time.as_secs_f64()));
+                                    if ui.button("Use as default").clicked() {
+                                        use_model = Some(result.model.clone());
+                                    }
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        self.show_benchmark = open;
+        if start {
+            self.start_benchmark(ctx);
+        }
+        if let Some(model) = use_model {
+            self.selected_model = model;
+        }
+    }
+
+    /// Ask both models of the comparison window its question about the
+    /// document, with the context a chat question would get.
+    fn compare_models(&mut self) {
+        let question = self.comparison_question.trim().to_string();
+        if question.is_empty() || self.comparison.lock().unwrap().iter().any(|a| a.running) {
+            return;
+        }
+        self.dispatch_ai_request(question, RequestKind::Comparison);
+    }
+
+    /// Stream the answers of both models at once, each into its column.
+    /// Relevant-chunk retrieval is left out; the document goes as with the
+    /// other context modes.
+    fn spawn_comparison(&mut self, prompt: String) {
+        let system_prompt = self.chat_context(&[&prompt]).system_prompt();
+        let params = self.generation;
+        for (side, model) in self.comparison_models.clone().into_iter().enumerate() {
+            let mut settings = self.settings();
+            settings.provider = Provider::Ollama;
+            settings.model = model.clone();
+            let backend = settings.backend(None);
+            self.comparison.lock().unwrap()[side] = ComparedAnswer {
+                model,
+                running: true,
+                ..ComparedAnswer::default()
+            };
+
+            let comparison = self.comparison.clone();
+            let usage_stats = self.usage_stats.clone();
+            let system_prompt = system_prompt.clone();
+            let prompt = prompt.clone();
+            self.network.spawn(async move {
+                let started = Instant::now();
+                let messages = vec![ChatMessage::user(prompt)];
+                let result = backend
+                    .complete(Some(&system_prompt), messages, &params, |token| {
+                        comparison.lock().unwrap()[side].receive(token, started.elapsed());
+                    })
+                    .await;
+
+                if let Ok(completion) = &result {
+                    usage_stats.lock().unwrap().record(
+                        &backend.model,
+                        completion,
+                        started.elapsed(),
+                    );
+                }
+                comparison.lock().unwrap()[side].finish(result, started.elapsed());
+            });
+        }
+    }
+
+    /// Two Ollama models answering the same question in columns, with how
+    /// fast each was, to pick the better one for the documents at hand.
+    fn show_comparison_window(&mut self, ctx: &egui::Context) {
+        let answers = self.comparison.lock().unwrap().clone();
+        let running = answers.iter().any(|a| a.running);
+        let mut open = self.show_comparison;
+        let mut ask = false;
+        let mut use_model = None;
+
+        egui::Window::new("⚖ Compare models")
+            .open(&mut open)
+            .default_width(760.0)
+            .show(ctx, |ui| {
+                if self.available_models.len() < 2 {
+                    ui.weak("Comparing needs two Ollama models; pull another with `ollama pull`.");
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    for (side, model) in self.comparison_models.iter_mut().enumerate() {
+                        if !self.available_models.contains(model) {
+                            *model = self.available_models[side].clone();
+                        }
+                        egui::ComboBox::from_id_source(("comparison_model", side))
+                            .selected_text(model.as_str())
+                            .show_ui(ui, |ui| {
+                                for name in &self.available_models {
+                                    ui.selectable_value(model, name.clone(), name);
+                                }
+                            });
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.comparison_question)
+                            .hint_text("Ask both models about this document...")
+                            .desired_width(ui.available_width() - 90.0),
+                    );
+                    let entered =
+                        response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    let ready = !running && !self.comparison_question.trim().is_empty();
+                    if ui
+                        .add_enabled(ready, egui::Button::new("Ask both"))
+                        .clicked()
+                        || (entered && ready)
+                    {
+                        ask = true;
+                    }
+                });
+                if answers.iter().all(|a| a.model.is_empty()) {
+                    return;
+                }
+
+                ui.separator();
+                ui.columns(2, |columns| {
+                    for (ui, (side, answer)) in columns.iter_mut().zip(answers.iter().enumerate()) {
+                        ui.horizontal(|ui| {
+                            ui.strong(&answer.model);
+                            if answer.running {
+                                ui.spinner();
+                            } else if answer.error.is_none()
+                                && ui.small_button("Use as default").clicked()
+                            {
+                                use_model = Some(answer.model.clone());
+                            }
+                        });
+                        let mut timing = Vec::new();
+                        if let Some(first) = answer.first_token {
+                            timing.push(format!("first token {:.2} s", first.as_secs_f64()));
+                        }
+                        if let Some(rate) = answer.tokens_per_second() {
+                            timing.push(format!("{:.1} tokens/s", rate));
+                        }
+                        if let Some(total) = answer.total_time {
+                            timing.push(format!("{:.1} s total", total.as_secs_f64()));
+                        }
+                        if answer.completion_tokens > 0 {
+                            timing.push(format!(
+                                "{} + {} tokens",
+                                answer.prompt_tokens, answer.completion_tokens
+                            ));
+                        }
+                        ui.weak(timing.join(" · "));
+                        if let Some(error) = &answer.error {
+                            ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+                        }
+                        egui::ScrollArea::vertical()
+                            .id_source(("comparison_answer", side))
+                            .max_height(420.0)
+                            .show(ui, |ui| self.show_markdown(ui, &answer.text, true));
+                    }
+                });
+            });
+
+        self.show_comparison = open;
+        if ask {
+            self.compare_models();
+        }
+        if let Some(model) = use_model {
+            self.selected_model = model;
+        }
+    }
+
+    fn show_gguf_info(&self, ui: &mut egui::Ui, info: &GgufInfo) {
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.heading("🧠 GGUF model");
+                ui.add_space(4.0);
+
+                egui::Grid::new("gguf_summary")
+                    .num_columns(2)
+                    .spacing([24.0, 6.0])
+                    .show(ui, |ui| {
+                        let rows = [
+                            ("Name", info.get("general.name").map(str::to_string)),
+                            ("Architecture", info.architecture().map(str::to_string)),
+                            (
+                                "Parameters",
+                                Some(format_parameter_count(info.parameter_count)),
+                            ),
+                            ("Quantization", info.quantization().map(str::to_string)),
+                            ("Context length", info.context_length().map(str::to_string)),
+                            ("Tensors", Some(info.tensor_count.to_string())),
+                            ("GGUF version", Some(info.version.to_string())),
+                        ];
+                        for (label, value) in rows {
+                            ui.strong(label);
+                            ui.label(value.unwrap_or_else(|| "—".to_string()));
+                            ui.end_row();
+                        }
+                    });
+
+                if let Some(template) = info.get("tokenizer.chat_template") {
+                    ui.add_space(8.0);
+                    ui.collapsing("Chat template", |ui| {
+                        ui.label(
+                            egui::RichText::new(template)
+                                .monospace()
+                                .size(self.font_size),
+                        );
+                    });
+                }
+
+                ui.collapsing(
+                    format!("All metadata ({} keys)", info.metadata.len()),
+                    |ui| {
+                        egui::Grid::new("gguf_metadata")
+                            .num_columns(2)
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for (key, value) in &info.metadata {
+                                    ui.monospace(key);
+                                    ui.add(egui::Label::new(value).wrap(true));
+                                    ui.end_row();
+                                }
+                            });
+                    },
+                );
+            });
+    }
+
+    fn default_text_style(&self) -> Style {
+        let theme = self.theme();
+        Style {
+            foreground: theme
+                .settings
+                .foreground
+                .unwrap_or(syntect::highlighting::Color::WHITE),
+            ..Style::default()
+        }
+    }
+
+    /// Append lines that arrived on the input stream, keeping their terminal formatting.
+    fn poll_stream(&mut self) {
+        let Some(stream) = &self.stream else { return };
+        let (lines, finished) = {
+            let mut buffer = stream.lock().unwrap();
+            (std::mem::take(&mut buffer.pending), buffer.finished)
+        };
+
+        if !lines.is_empty() {
+            self.append_lines(&lines);
+        }
+
+        if finished {
+            self.stream = None;
+            // Lines that arrived after a language was picked are still plain.
+            if self.session_syntax.is_some() {
+                self.highlight_content();
+            }
+        }
+    }
+
+    fn append_lines(&mut self, lines: &[String]) {
+        let default = self.default_text_style();
+        for line in lines {
+            let (segments, plain) = parse_terminal_line(line, default);
+            self.log_lines.push(&plain);
+            self.file_content.push_str(&plain);
+            self.file_content.push('\n');
+            self.highlighted_content.push(segments);
+        }
+        self.prose_blocks = prose_blocks(&self.file_content);
+        self.update_fold_regions();
+        self.scan_sensitive_content();
+    }
+
+    fn watch_directory(&mut self, dir: PathBuf) {
+        self.show_ai_panel = false;
+        self.follow = true;
+        match newest_file(&dir) {
+            Some(newest) => {
+                self.load_file(&newest.to_string_lossy());
+                self.log_mode = true;
+            }
+            None => {
+                self.load_error = Some(format!("Waiting for files in {}", dir.display()));
+            }
+        }
+        self.log_dir = Some(dir);
+    }
+
+    /// Switch to the newest file in the watched directory and pick up
+    /// anything appended to the open file.
+    fn poll_follow(&mut self) {
+        if self.last_follow_poll.elapsed() < Duration::from_millis(500) {
+            return;
+        }
+        self.last_follow_poll = Instant::now();
+
+        if let Some(newest) = self.log_dir.as_deref().and_then(newest_file) {
+            let newest = newest.to_string_lossy().into_owned();
+            if self.file_path.as_deref() != Some(newest.as_str()) {
+                self.load_file(&newest);
+                return;
+            }
+        }
+
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        if !self.follow || self.streamed || self.gguf_info.is_some() {
+            return;
+        }
+
+        let offset = self.file_content.len() as u64;
+        let follower = self
+            .follower
+            .get_or_insert_with(|| FileFollower::new(PathBuf::from(&path), offset));
+        match follower.poll() {
+            Ok(FollowUpdate::Appended(lines)) => self.append_lines(&lines),
+            Ok(FollowUpdate::Truncated) => self.load_file(&path),
+            Ok(FollowUpdate::Unchanged) | Err(_) => {}
+        }
+    }
+
+    /// Recompute fold regions, the outline, bracket depths and links after the text changed,
+    /// keeping folds whose region still starts on the same line.
+    fn update_fold_regions(&mut self) {
+        self.outline = outline(&self.file_content, self.file_path.as_deref());
+        self.fold_regions = fold_regions(&self.file_content);
+        self.bracket_depths = bracket_depths(&self.file_content);
+        let links = find_links(&self.file_content, |name| {
+            // Next to the document, or relative to where we were started.
+            let path = self
+                .resolve_document_link(name)
+                .filter(|path| path.is_file())
+                .or_else(|| Some(PathBuf::from(name)).filter(|path| path.is_file()))?;
+            Some(fs::canonicalize(&path).unwrap_or(path))
+        });
+        self.links = links;
+        let regions = &self.fold_regions;
+        self.folded
+            .retain(|first| regions.binary_search_by_key(first, |r| r.first).is_ok());
+    }
+
+    fn fold_region(&self, first: usize) -> Option<FoldRegion> {
+        self.fold_regions
+            .binary_search_by_key(&first, |r| r.first)
+            .ok()
+            .map(|i| self.fold_regions[i])
+    }
+
+    fn toggle_fold(&mut self, first: usize) {
+        if !self.folded.remove(&first) && self.fold_region(first).is_some() {
+            self.folded.insert(first);
+        }
+    }
+
+    /// Unfold every region hiding `line`, so that it can be shown.
+    fn unfold_line(&mut self, line: usize) {
+        let regions = &self.fold_regions;
+        self.folded.retain(|&first| {
+            regions
+                .binary_search_by_key(&first, |r| r.first)
+                .map_or(true, |i| !(first + 1..=regions[i].last).contains(&line))
+        });
+    }
+
+    /// Which lines are inside a folded region; empty when nothing is folded.
+    fn folded_lines(&self) -> Vec<bool> {
+        if self.folded.is_empty() {
+            return Vec::new();
+        }
+        let mut hidden = vec![false; self.highlighted_content.len()];
+        for region in self
+            .folded
+            .iter()
+            .filter_map(|&first| self.fold_region(first))
+        {
+            let end = (region.last + 1).min(hidden.len());
+            if let Some(lines) = hidden.get_mut(region.first + 1..end) {
+                lines.fill(true);
+            }
+        }
+        hidden
+    }
+
+    fn fold_all(&mut self, fold: bool) {
+        self.folded = if fold {
+            self.fold_regions.iter().map(|r| r.first).collect()
+        } else {
+            BTreeSet::new()
+        };
+    }
+
+    fn hidden_by_log_filter(&self, index: usize) -> bool {
+        self.log_mode && self.log_filter.hides(&self.log_lines, index)
+    }
+
+    /// Background of a line in log mode, by its level.
+    fn level_fill(&self, line: usize) -> Option<egui::Color32> {
+        let level = self.log_lines.levels.get(line).copied().flatten();
+        level.filter(|_| self.log_mode).and_then(LogLevel::fill)
+    }
+
+    /// Level checkboxes with their line counts, and the time range.
+    fn show_log_bar(&mut self, ui: &mut egui::Ui) {
+        let counts = self.log_lines.level_counts();
+        let filter = &mut self.log_filter;
+        ui.horizontal(|ui| {
+            for level in LogLevel::ALL {
+                let mut shown = !filter.hidden_levels.contains(&level);
+                let label =
+                    egui::RichText::new(format!("{} ({})", level.label(), counts[level as usize]))
+                        .color(level.color());
+                if ui.checkbox(&mut shown, label).changed() {
+                    if shown {
+                        filter.hidden_levels.remove(&level);
+                    } else {
+                        filter.hidden_levels.insert(level);
+                    }
+                }
+            }
+            ui.separator();
+            let mut changed = false;
+            for (label, bound, parsed) in [
+                ("From", &mut filter.from, filter.range.0),
+                ("to", &mut filter.to, filter.range.1),
+            ] {
+                ui.label(label);
+                changed |= ui
+                    .add(
+                        egui::TextEdit::singleline(bound)
+                            .hint_text("12:00 or 2024-05-01 12:00")
+                            .desired_width(150.0),
+                    )
+                    .changed();
+                if !bound.trim().is_empty() && parsed.is_none() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, "?")
+                        .on_hover_text("Use HH:MM[:SS], YYYY-MM-DD or both");
+                }
+            }
+            if changed {
+                filter.update_range();
+            }
+            if filter.is_active() && ui.button("Clear").clicked() {
+                *filter = LogFilter::default();
+            }
+        });
+        ui.separator();
+    }
+
+    fn is_prose(&self) -> bool {
+        matches!(
+            self.file_extension().as_str(),
+            "md" | "markdown" | "txt" | "text" | "rst" | "adoc" | "org"
+        ) || (self.file_extension().is_empty()
+            && !self.streamed
+            && self.detect_syntax().name == "Plain Text")
+    }
+
+    fn prose_font(&self) -> egui::FontId {
+        if self.typography.serif && self.serif_available {
+            egui::FontId::new(self.font_size, egui::FontFamily::Name("serif".into()))
+        } else {
+            egui::FontId::proportional(self.font_size)
+        }
+    }
+
+    /// Render reflowed prose in a centered column `reading_width` characters wide.
+    /// The blank-line-delimited block of highlighted lines around `line`.
+    fn paragraph_range(&self, line: usize) -> (usize, usize) {
+        let is_blank = |i: usize| {
+            self.highlighted_content[i]
+                .iter()
+                .all(|(_, text)| text.trim().is_empty())
+        };
+        if self.highlighted_content.is_empty() {
+            return (0, 0);
+        }
+        let line = line.min(self.highlighted_content.len() - 1);
+        if is_blank(line) {
+            return (line, line);
+        }
+
+        let mut start = line;
+        while start > 0 && !is_blank(start - 1) {
+            start -= 1;
+        }
+        let mut end = line;
+        while end + 1 < self.highlighted_content.len() && !is_blank(end + 1) {
+            end += 1;
+        }
+        (start, end)
+    }
+
+    /// The start of each review comment on `line`, after its text; hovering
+    /// shows the whole comment.
+    fn show_review_markers(&self, ui: &mut egui::Ui, line: usize) {
+        let comments = self
+            .annotations
+            .iter()
+            .filter(|a| a.kind == AnnotationKind::Review && a.first_line == line);
+        for annotation in comments {
+            let summary: String = annotation
+                .note
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .take(60)
+                .collect();
+            let ellipsis = if summary.len() < annotation.note.len() {
+                "…"
+            } else {
+                ""
+            };
+            ui.label(
+                egui::RichText::new(format!("   🤖 {}{}", summary, ellipsis))
+                    .size(self.font_size * 0.85)
+                    .color(egui::Color32::from_rgb(170, 90, 230)),
+            )
+            .on_hover_ui(|ui| {
+                ui.strong(annotation.line_label());
+                ui.label(&annotation.note);
+            });
+        }
+    }
+
+    fn highlight_fill(&self, line: usize) -> Option<egui::Color32> {
+        self.annotations
+            .iter()
+            .rev()
+            .find(|a| a.kind != AnnotationKind::Bookmark && a.covers(line))
+            .map(|a| match a.kind {
+                AnnotationKind::Note => egui::Color32::from_rgba_unmultiplied(80, 140, 255, 40),
+                AnnotationKind::Review => egui::Color32::from_rgba_unmultiplied(170, 90, 230, 40),
+                _ => egui::Color32::from_rgba_unmultiplied(255, 210, 0, 45),
+            })
+    }
+
+    fn show_prose(&self, ui: &mut egui::Ui, frame: &mut ViewFrame) {
+        let font = self.prose_font();
+        let code_font = egui::FontId::monospace(self.font_size);
+        // Average prose glyphs are roughly the width of 'n'.
+        let column = ui.fonts(|f| f.glyph_width(&font, 'n')) * self.reading_width as f32;
+        let column = column.min(ui.available_width());
+        let margin = ((ui.available_width() - column) / 2.0).max(0.0);
+        let text_color = ui.visuals().text_color();
+        let line_height = self.font_size * self.typography.line_height;
+        let center_y = ui.clip_rect().center().y;
+        let focused_block = self
+            .prose_blocks
+            .iter()
+            .rev()
+            .find(|block| block.source_line <= self.focus_line)
+            .map(|block| block.source_line);
+
+        for block in &self.prose_blocks {
+            let color = if self.focus_mode && focused_block != Some(block.source_line) {
+                text_color.gamma_multiply(0.25)
+            } else {
+                text_color
+            };
+
+            let mut job = egui::text::LayoutJob::default();
+            job.append(
+                &block.text,
+                0.0,
+                egui::TextFormat {
+                    font_id: if block.paragraph {
+                        font.clone()
+                    } else {
+                        code_font.clone()
+                    },
+                    color,
+                    line_height: Some(line_height),
+                    ..Default::default()
+                },
+            );
+            job.justify = block.paragraph && self.typography.justify;
+
+            // Reserve a background slot; its size is only known after layout.
+            let background = ui.painter().add(egui::Shape::Noop);
+
+            // Label wraps to the available width, so give it a column-wide child ui.
+            let row = ui.horizontal(|ui| {
+                ui.add_space(margin);
+                ui.vertical(|ui| {
+                    ui.set_max_width(column);
+                    ui.add(egui::Label::new(job).wrap(block.paragraph));
+                });
+            });
+            if block.paragraph {
+                ui.add_space(self.typography.paragraph_spacing);
+            }
+
+            let rect = row.response.rect;
+            if let Some(fill) = self
+                .flash_fill(block.source_line, block.last_line)
+                .or_else(|| self.highlight_fill(block.source_line))
+            {
+                ui.painter()
+                    .set(background, egui::Shape::rect_filled(rect, 2.0, fill));
+            }
+
+            if self
+                .scroll_to_line
+                .is_some_and(|line| (block.source_line..=block.last_line).contains(&line))
+            {
+                ui.scroll_to_rect(rect, Some(egui::Align::Center));
+            }
+            if ui.clip_rect().intersects(rect) {
+                let range = frame
+                    .visible
+                    .get_or_insert((block.source_line, block.source_line));
+                range.1 = block.source_line;
+
+                let id = ui.id().with(("prose_block", block.source_line));
+                ui.interact(rect, id, egui::Sense::click())
+                    .context_menu(|ui| {
+                        annotation_menu(ui, block.source_line, block.last_line, frame)
+                    });
+            }
+            if rect.y_range().contains(center_y) {
+                frame.center_line = Some(block.source_line);
+            }
+        }
+    }
+
+    fn handle_selection_keys(&mut self, ctx: &egui::Context) {
+        if ctx.memory(|m| m.focus().is_some()) {
+            return;
+        }
+
+        let (expand, shrink, clear, select_all, copy) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::ALT, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::ALT, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::A),
+                // Most backends turn Ctrl+C into a Copy event rather than a key press.
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::C)
+                    || i.events.iter().any(|e| matches!(e, egui::Event::Copy)),
+            )
+        });
+
+        if expand {
+            if let Some(current) = self.selection.clone() {
+                if let Some(grown) = expand_selection(&self.file_content, &current) {
+                    self.selection_history.push(current);
+                    self.selection = Some(grown);
+                }
+            }
+        }
+        if shrink {
+            if let Some(previous) = self.selection_history.pop() {
+                self.selection = Some(previous);
+            }
+        }
+        if clear {
+            self.selection = None;
+            self.selection_history.clear();
+        }
+        if select_all && self.hex_data.is_none() {
+            self.selection = Some(0..self.file_content.len());
+            self.selection_history.clear();
+        }
+        if copy {
+            self.copy_selection(ctx, CopyFormat::Plain);
+        }
+    }
+
+    /// Extend a mouse drag selection to the pointer. Rows that are not laid
+    /// out are clamped to the first or last visible one.
+    fn drag_select(&mut self, ui: &egui::Ui, frame: &ViewFrame, char_width: f32) {
+        let starts = line_starts(&self.file_content);
+        let offset = |line: usize, column: usize| {
+            starts
+                .get(line)
+                .map(|&start| column_offset(&self.file_content, start, column))
+        };
+        if let Some((line, column)) = frame.drag_started {
+            self.drag_anchor = offset(line, column);
+        }
+        let Some(anchor) = self.drag_anchor else {
+            return;
+        };
+        let (down, pos) = ui.input(|i| (i.pointer.primary_down(), i.pointer.interact_pos()));
+        if !down {
+            self.drag_anchor = None;
+            return;
+        }
+        let (Some(pos), Some(first), Some(last)) = (pos, frame.rows.first(), frame.rows.last())
+        else {
+            return;
+        };
+        let &(line, rect) = frame
+            .rows
+            .iter()
+            .find(|(_, rect)| rect.y_range().contains(pos.y))
+            .unwrap_or(if pos.y < first.1.top() { first } else { last });
+        let column = ((pos.x - rect.left()) / char_width).round().max(0.0) as usize;
+        let Some(caret) = offset(line, column) else {
+            return;
+        };
+        // A press without movement is a click, which selects a word instead.
+        if caret != anchor {
+            self.selection = Some(anchor.min(caret)..anchor.max(caret));
+            self.selection_history.clear();
+        }
+    }
+
+    fn copy_selection(&mut self, ctx: &egui::Context, format: CopyFormat) {
+        let Some(range) = self.selection.clone().filter(|r| !r.is_empty()) else {
+            return;
+        };
+        let text = self.file_content[range.clone()].to_string();
+        match format {
+            CopyFormat::Plain => ctx.output_mut(|o| o.copied_text = text),
+            CopyFormat::Ansi => {
+                let mut ansi = String::new();
+                for line in self.styled_range(range) {
+                    let runs: Vec<(Style, &str)> = line
+                        .iter()
+                        .map(|(style, run)| (*style, run.as_str()))
+                        .collect();
+                    ansi.push_str(&syntect::util::as_24_bit_terminal_escaped(&runs, false));
+                    ansi.push_str("\x1b[0m\n");
+                }
+                if !text.ends_with('\n') {
+                    ansi.pop();
+                }
+                ctx.output_mut(|o| o.copied_text = ansi);
+                self.export_status =
+                    Some((Ok("Copied with ANSI colours".to_string()), Instant::now()));
+            }
+            CopyFormat::Html => {
+                let theme = self.theme();
+                let background = theme
+                    .settings
+                    .background
+                    .map_or("#ffffff".to_string(), css_color);
+                let foreground = theme
+                    .settings
+                    .foreground
+                    .map_or("#000000".to_string(), css_color);
+                let mut html = format!(
+                    "<pre style=\"background-color:{};color:{};font-family:monospace;\">",
+                    background, foreground
+                );
+                for line in self.styled_range(range) {
+                    let runs: Vec<(Style, &str)> = line
+                        .iter()
+                        .map(|(style, run)| (*style, run.as_str()))
+                        .collect();
+                    match syntect::html::styled_line_to_highlighted_html(
+                        &runs,
+                        syntect::html::IncludeBackground::No,
+                    ) {
+                        Ok(line) => html.push_str(&line),
+                        Err(_) => html.push_str(&escape_html(
+                            &runs.iter().map(|(_, r)| *r).collect::<String>(),
+                        )),
+                    }
+                    html.push('\n');
+                }
+                html.push_str("</pre>");
+                let copied = self
+                    .clipboard
+                    .take()
+                    .map_or_else(arboard::Clipboard::new, Ok)
+                    .and_then(|mut clipboard| {
+                        clipboard.set_html(html.as_str(), Some(text.as_str()))?;
+                        self.clipboard = Some(clipboard);
+                        Ok(())
+                    });
+                self.export_status = Some((
+                    copied
+                        .map(|()| "Copied as rich text".to_string())
+                        .map_err(|e| format!("Could not copy as rich text: {}", e)),
+                    Instant::now(),
+                ));
+            }
+        }
+    }
+
+    /// The highlighted runs of `range`, one list per line it touches.
+    fn styled_range(&self, range: Range<usize>) -> Vec<Vec<(Style, String)>> {
+        let starts = line_starts(&self.file_content);
+        let first = starts.partition_point(|&start| start <= range.start) - 1;
+        let mut lines = Vec::new();
+        for (index, &start) in starts.iter().enumerate().skip(first) {
+            if start >= range.end && index > first {
+                break;
+            }
+            let mut offset = start;
+            let mut line = Vec::new();
+            for (style, run) in self.highlighted_content.get(index).into_iter().flatten() {
+                let (run_start, run_end) = (offset, offset + run.len());
+                offset = run_end;
+                let (from, to) = (range.start.max(run_start), range.end.min(run_end));
+                if from < to {
+                    line.push((*style, run[from - run_start..to - run_start].to_string()));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Select the word at a clicked position, ready to be grown with Alt+Up.
+    fn select_at(&mut self, line: usize, column: usize) {
+        let Some(&line_start) = line_starts(&self.file_content).get(line) else {
+            return;
+        };
+        let caret = column_offset(&self.file_content, line_start, column);
+        let caret = caret..caret;
+        let word = word_range(&self.file_content, &caret);
+        self.selection = Some(if word.is_empty() { caret } else { word });
+        self.selection_history.clear();
+    }
+
+    /// Select whole line `line`, or with `extend` the lines from the current
+    /// selection to it.
+    fn select_lines(&mut self, line: usize, extend: bool) {
+        let (first, last) = match self.selection.clone().filter(|_| extend) {
+            Some(current) => {
+                let (first, last) = self.selection_lines(&current);
+                if line < first {
+                    (line, last)
+                } else {
+                    (first, line)
+                }
+            }
+            None => (line, line),
+        };
+
+        let starts = line_starts(&self.file_content);
+        let Some(&start) = starts.get(first) else {
+            return;
+        };
+        let end = starts
+            .get(last + 1)
+            .copied()
+            .unwrap_or(self.file_content.len());
+        self.selection = Some(start..end);
+        self.selection_history.clear();
+    }
+
+    /// First and last line (0-based) touched by the selection.
+    fn selection_lines(&self, range: &Range<usize>) -> (usize, usize) {
+        let first = self.file_content[..range.start].matches('\n').count();
+        let last = first
+            + self.file_content[range.clone()]
+                .trim_end_matches('\n')
+                .matches('\n')
+                .count();
+        (first, last)
+    }
+
+    fn explain_selection(&mut self) {
+        let Some(range) = self.selection.clone().filter(|r| !r.is_empty()) else {
+            return;
+        };
+        if self.ai_state.is_loading() {
+            return;
+        }
+
+        let (first, last) = self.selection_lines(&range);
+        let (prompt, warning) = self.selection_prompt(
+            first,
+            last,
+            "Explain what the selected lines do and how they fit into the document.",
+        );
+
+        self.show_ai_panel = true;
+        self.dispatch_ai_request(
+            prompt,
+            RequestKind::Chat {
+                question: Some(format!("Explain lines {}–{}", first + 1, last + 1)),
+                warning,
+                lines: Some((first, last)),
+                images: Vec::new(),
+            },
+        );
+    }
+
+    /// A prompt about lines `first..=last` (0-based): those lines marked with
+    /// `>` among a few lines of surrounding context, all numbered, then `request`.
+    /// The excerpt may take half the context window; a warning says when it
+    /// had to be cut.
+    fn selection_prompt(
+        &self,
+        first: usize,
+        last: usize,
+        request: &str,
+    ) -> (String, Option<String>) {
+        let start = first.saturating_sub(SELECTION_CONTEXT_LINES);
+        let end = last + SELECTION_CONTEXT_LINES;
+
+        let mut excerpt = String::new();
+        for (i, line) in self
+            .prompt_content()
+            .lines()
+            .enumerate()
+            .skip(start)
+            .take(end - start + 1)
+        {
+            let marker = if (first..=last).contains(&i) {
+                '>'
+            } else {
+                ' '
+            };
+            excerpt.push_str(&format!("{} {:>5} | {}\n", marker, i + 1, line));
+        }
+
+        let fitted = fit_lines(&excerpt, context_budget(&self.generation, &[]) / 2);
+        let prompt = format!(
+            "Excerpt from {} with line numbers; the selected lines {}–{} are marked with '>':\n\
+             ```\n{}```\n\n\
+             {} Refer to specific line numbers in your answer.",
+            self.file_name(),
+            first + 1,
+            last + 1,
+            fitted.text,
+            request
+        );
+        (prompt, fitted.cut_warning("Selection"))
+    }
+
+    fn handle_vim_keys(&mut self, ctx: &egui::Context) {
+        if !self.vim_keys
+            || ctx.memory(|m| m.focus().is_some())
+            || self.editing
+            || self.hex_data.is_some()
+            || self.gguf_info.is_some()
+        {
+            return;
+        }
+        let (typed, half_down, half_up, escape) = ctx.input_mut(|i| {
+            let mut typed = String::new();
+            // Take the keys handled here, so that `/` and `:` don't end up
+            // in the text field they open.
+            i.events.retain(|event| match event {
+                egui::Event::Text(text)
+                    if text.chars().all(|c| "0123456789hjklgGnN/:%".contains(c)) =>
+                {
+                    typed.push_str(text);
+                    false
+                }
+                _ => true,
+            });
+            (
+                typed,
+                i.consume_key(egui::Modifiers::CTRL, egui::Key::D),
+                i.consume_key(egui::Modifiers::CTRL, egui::Key::U),
+                i.key_pressed(egui::Key::Escape),
+            )
+        });
+        if escape {
+            self.vim_pending.clear();
+        }
+        let half_page =
+            (self.visible_lines.1.saturating_sub(self.visible_lines.0) / 2).max(1) as f32;
+        if half_down || half_up {
+            self.auto_scroll = false;
+            self.scroll_by.y += if half_down { half_page } else { -half_page };
+        }
+
+        for c in typed.chars() {
+            // A leading 0 is not a count.
+            if c.is_ascii_digit() && (c != '0' || !self.vim_pending.is_empty()) {
+                self.vim_pending.push(c);
+                continue;
+            }
+            let pending = std::mem::take(&mut self.vim_pending);
+            let count: Option<usize> = pending.trim_end_matches('g').parse().ok();
+            let times = count.unwrap_or(1) as f32;
+            match c {
+                'j' => self.scroll_by.y += times,
+                'k' => self.scroll_by.y -= times,
+                'l' => self.scroll_by.x += times,
+                'h' => self.scroll_by.x -= times,
+                'g' if !pending.ends_with('g') => self.vim_pending = pending + "g",
+                // gg, or 5gg, like G with a count.
+                'g' | 'G' => {
+                    let last = self.highlighted_content.len().saturating_sub(1);
+                    match count {
+                        Some(line) => self.go_to(line.clamp(1, last + 1) - 1, None),
+                        None if c == 'g' => self.scroll_to_line = Some(0),
+                        None => self.scroll_to_line = Some(last),
+                    }
+                }
+                'n' | 'N' if !self.search_matches.is_empty() => {
+                    self.show_search = true;
+                    self.step_match(if c == 'n' { 1 } else { -1 });
+                }
+                '/' => {
+                    self.show_search = true;
+                    self.focus_search = true;
+                }
+                ':' => self.goto_input = Some(String::new()),
+                '%' => self.jump_to_bracket(),
+                _ => {}
+            }
+            if "jkhlgG".contains(c) {
+                self.auto_scroll = false;
+            }
+        }
+    }
+
+    fn handle_auto_scroll_keys(&mut self, ctx: &egui::Context) {
+        // Leave the keys alone while the user is typing in a text field.
+        if ctx.memory(|m| m.focus().is_some()) {
+            return;
+        }
+
+        if self.pager && ctx.input(|i| i.key_pressed(egui::Key::Q)) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+
+        ctx.input(|i| {
+            if i.key_pressed(egui::Key::Space) {
+                self.auto_scroll = !self.auto_scroll;
+            }
+            if i.key_pressed(egui::Key::Minus) {
+                self.auto_scroll_speed = (self.auto_scroll_speed / 1.25).max(5.0);
+            }
+            if i.key_pressed(egui::Key::PlusEquals) {
+                self.auto_scroll_speed = (self.auto_scroll_speed * 1.25).min(600.0);
+            }
+        });
+    }
+
+    /// Original and translated paragraphs in two aligned columns of one scroll area,
+    /// so both panes always scroll together.
+    fn show_translation_view(&self, ui: &mut egui::Ui) {
+        let translation = self.translation.lock().unwrap();
+
+        ui.horizontal(|ui| {
+            ui.heading(format!("🌐 {} translation", translation.language));
+            if translation.running {
+                ui.spinner();
+                ui.label("Translating...");
+            }
+            if let Some(error) = &translation.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+            }
+        });
+        ui.separator();
+
+        let column = ((ui.available_width() - 40.0) / 2.0).max(100.0);
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                egui::Grid::new("translation_grid")
+                    .num_columns(2)
+                    .striped(true)
+                    .min_col_width(column)
+                    .max_col_width(column)
+                    .spacing([20.0, 10.0])
+                    .show(ui, |ui| {
+                        for (paragraph, translated) in
+                            translation.paragraphs.iter().zip(&translation.translated)
+                        {
+                            ui.add(
+                                egui::Label::new(
+                                    egui::RichText::new(paragraph_text(
+                                        &self.file_content,
+                                        paragraph,
+                                    ))
+                                    .size(self.font_size),
+                                )
+                                .wrap(true),
+                            );
+                            match translated {
+                                Some(text) => ui.add(
+                                    egui::Label::new(
+                                        egui::RichText::new(text).size(self.font_size),
+                                    )
+                                    .wrap(true),
+                                ),
+                                None if translation.running => ui.weak("…"),
+                                None => ui.weak("(not translated)"),
+                            };
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    /// Canonical path of the open document, used to match bookmarks.
+    fn document_key(&self) -> Option<String> {
+        let path = self.file_path.as_deref().filter(|_| !self.streamed)?;
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    /// Annotations plus this document's bookmarks, for the review report.
+    fn review_annotations(&self) -> Vec<Annotation> {
+        let key = self.document_key();
+        let mut annotations = self.annotations.clone();
+        annotations.extend(
+            self.bookmarks
+                .iter()
+                .filter(|b| Some(&b.path) == key.as_ref())
+                .map(|b| Annotation {
+                    kind: AnnotationKind::Bookmark,
+                    first_line: b.line,
+                    last_line: b.line,
+                    note: b.label.clone(),
+                }),
+        );
+        annotations
+    }
+
+    fn add_bookmark(&mut self, line: usize) {
+        let Some(path) = self.document_key() else {
+            return;
+        };
+        let label: String = self
+            .file_content
+            .lines()
+            .nth(line)
+            .unwrap_or("")
+            .trim()
+            .chars()
+            .take(60)
+            .collect();
+        self.bookmarks.push(Bookmark { path, line, label });
+        save_bookmarks(&self.bookmarks);
+    }
+
+    /// Lines of the open document that have a bookmark, in order.
+    fn bookmarked_lines(&self) -> Vec<usize> {
+        let Some(key) = self.document_key() else {
+            return Vec::new();
+        };
+        let mut lines: Vec<usize> = self
+            .bookmarks
+            .iter()
+            .filter(|b| b.path == key)
+            .map(|b| b.line)
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Remove the bookmarks on `line`, or add one if there are none.
+    fn toggle_bookmark(&mut self, line: usize) {
+        let Some(key) = self.document_key() else {
+            return;
+        };
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|b| b.path != key || b.line != line);
+        if self.bookmarks.len() == before {
+            self.add_bookmark(line);
+        } else {
+            save_bookmarks(&self.bookmarks);
+        }
+    }
+
+    /// The line keyboard commands act on: the start of the selection, or
+    /// the line in the middle of the view.
+    fn current_line(&self) -> usize {
+        match &self.selection {
+            Some(range) => self.file_content[..range.start].matches('\n').count(),
+            None => self.focus_line,
+        }
+    }
+
+    /// A popup listing every bookmark with the text of its line.
+    fn show_jump_list(&mut self, ctx: &egui::Context) {
+        let current = self.document_key();
+        let mut jump = None;
+        let mut open = true;
+        egui::Window::new("🔖 Jump to bookmark")
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(420.0)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                if self.bookmarks.is_empty() {
+                    ui.weak("No bookmarks yet. Click left of a line number or press Ctrl+B.");
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(360.0)
+                    .show(ui, |ui| {
+                        for (i, bookmark) in self.bookmarks.iter().enumerate() {
+                            let file = display_name(Some(&bookmark.path));
+                            // The open document shows its current text; others show the label.
+                            let preview = match &current {
+                                Some(key) if *key == bookmark.path => self
+                                    .file_content
+                                    .lines()
+                                    .nth(bookmark.line)
+                                    .unwrap_or("")
+                                    .trim()
+                                    .to_string(),
+                                _ => bookmark.label.clone(),
+                            };
+                            let response = ui
+                                .horizontal(|ui| {
+                                    let location =
+                                        ui.link(format!("{}:{}", file, bookmark.line + 1));
+                                    ui.add(
+                                        egui::Label::new(
+                                            egui::RichText::new(preview).monospace().weak(),
+                                        )
+                                        .truncate(true),
+                                    );
+                                    location
+                                })
+                                .inner;
+                            if response.on_hover_text(&bookmark.path).clicked() {
+                                jump = Some(i);
+                            }
+                        }
+                    });
+                ui.weak("F2 / Shift+F2: next / previous bookmark in this file");
+            });
+        if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            open = false;
+        }
+        if let Some(i) = jump {
+            let bookmark = self.bookmarks[i].clone();
+            self.jump_to_bookmark(&bookmark);
+            open = false;
+        }
+        self.show_jump_list = open;
+    }
+
+    fn jump_to_bookmark(&mut self, bookmark: &Bookmark) {
+        match self.find_document(&bookmark.path) {
+            Some(index) => self.switch_document(index),
+            None => {
+                self.new_tab();
+                self.load_file(&bookmark.path);
+            }
+        }
+        self.auto_scroll = false;
+        self.scroll_to_line = Some(bookmark.line);
+    }
+
+    fn default_bookmark_file(&self) -> String {
+        match self.file_path.as_deref().filter(|_| !self.streamed) {
+            Some(path) => format!("{}.bookmarks.json", path),
+            None => "bookmarks.json".to_string(),
+        }
+    }
+
+    /// Headings or definitions of the document; clicking one scrolls to it.
+    fn show_outline_panel(&mut self, ctx: &egui::Context) {
+        let current = self.current_line();
+        // The symbol the view is in: the last one starting at or above it.
+        let here = self
+            .outline
+            .iter()
+            .rposition(|symbol| symbol.line <= current);
+        let mut jump = None;
+
+        egui::SidePanel::left("outline_panel")
+            .resizable(true)
+            .default_width(220.0)
+            .show(ctx, |ui| {
+                ui.heading("🗂 Outline");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.outline_filter)
+                        .hint_text("Filter symbols…")
+                        .desired_width(f32::INFINITY),
+                );
+                ui.separator();
+
+                if self.outline.is_empty() {
+                    ui.weak("No headings or definitions found in this file.");
+                    return;
+                }
+                let filter = self.outline_filter.trim().to_lowercase();
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        for (i, symbol) in self.outline.iter().enumerate() {
+                            if !filter.is_empty()
+                                && fuzzy_score(&filter, &symbol.name.to_lowercase()).is_none()
+                            {
+                                continue;
+                            }
+                            // Filtered results are listed flat.
+                            let depth = if filter.is_empty() { symbol.depth } else { 0 };
+                            ui.horizontal(|ui| {
+                                ui.add_space(depth as f32 * 12.0);
+                                let label = format!("{} {}", symbol.kind.icon(), symbol.name);
+                                let response = ui
+                                    .selectable_label(here == Some(i), label)
+                                    .on_hover_text(format!("Line {}", symbol.line + 1));
+                                if response.clicked() {
+                                    jump = Some(symbol.line);
+                                }
+                            });
+                        }
+                    });
+            });
+
+        if let Some(line) = jump {
+            self.go_to(line, None);
+        }
+    }
+
+    fn show_bookmarks_panel(&mut self, ctx: &egui::Context) {
+        let current = self.document_key();
+        let mut jump = None;
+        let mut remove = None;
+        let mut changed = false;
+        let mut row_rects = Vec::new();
+
+        egui::SidePanel::left("bookmarks_panel")
+            .resizable(true)
+            .default_width(280.0)
+            .show(ctx, |ui| {
+                ui.heading("🔖 Bookmarks");
+                ui.separator();
+
+                if self.bookmarks.is_empty() {
+                    ui.weak("Click left of a line number, press Ctrl+B, or right-click a line and choose Bookmark.");
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(ui.available_height() - 90.0)
+                    .show(ui, |ui| {
+                        for (i, bookmark) in self.bookmarks.iter_mut().enumerate() {
+                            let top = ui.cursor().top();
+                            ui.horizontal(|ui| {
+                                let handle = ui
+                                    .add(egui::Label::new("☰").sense(egui::Sense::drag()))
+                                    .on_hover_cursor(egui::CursorIcon::Grab);
+                                if handle.drag_started() {
+                                    self.dragged_bookmark = Some(i);
+                                }
+
+                                let file = Path::new(&bookmark.path)
+                                    .file_name()
+                                    .map(|name| name.to_string_lossy().into_owned())
+                                    .unwrap_or_default();
+                                let location = format!("{}:{}", file, bookmark.line + 1);
+                                let location = if current.as_ref() == Some(&bookmark.path) {
+                                    egui::RichText::new(location).strong()
+                                } else {
+                                    egui::RichText::new(location)
+                                };
+                                if ui.link(location).on_hover_text(&bookmark.path).clicked() {
+                                    jump = Some(i);
+                                }
+                                if ui.small_button("🗑").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                            changed |= ui
+                                .add(
+                                    egui::TextEdit::singleline(&mut bookmark.label)
+                                        .desired_width(f32::INFINITY)
+                                        .hint_text("Label"),
+                                )
+                                .lost_focus();
+                            ui.add_space(4.0);
+                            row_rects.push(egui::Rect::from_x_y_ranges(
+                                ui.max_rect().x_range(),
+                                top..=ui.cursor().top(),
+                            ));
+                        }
+                    });
+
+                ui.separator();
+                if self.bookmark_file.is_empty() {
+                    self.bookmark_file = self.default_bookmark_file();
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.bookmark_file)
+                        .desired_width(f32::INFINITY),
+                );
+                ui.horizontal(|ui| {
+                    if ui.button("💾 Export JSON").clicked() {
+                        let target = PathBuf::from(&self.bookmark_file);
+                        self.bookmark_status =
+                            Some(match export_bookmarks(&self.bookmarks, &target) {
+                                Ok(()) => format!("Exported {} bookmarks", self.bookmarks.len()),
+                                Err(e) => e,
+                            });
+                    }
+                    if ui.button("📂 Import JSON").clicked() {
+                        let source = PathBuf::from(&self.bookmark_file);
+                        self.bookmark_status =
+                            Some(match import_bookmarks(&source) {
+                                Ok(imported) => {
+                                    let before = self.bookmarks.len();
+                                    for bookmark in imported {
+                                        if !self.bookmarks.iter().any(|b| {
+                                            b.path == bookmark.path && b.line == bookmark.line
+                                        }) {
+                                            self.bookmarks.push(bookmark);
+                                        }
+                                    }
+                                    changed = true;
+                                    format!("Imported {} bookmarks", self.bookmarks.len() - before)
+                                }
+                                Err(e) => e,
+                            });
+                    }
+                });
+                if let Some(status) = &self.bookmark_status {
+                    ui.label(status);
+                }
+
+                // Drop the dragged row before the row under the pointer.
+                if let Some(from) = self.dragged_bookmark {
+                    let pointer = ctx.pointer_interact_pos();
+                    let target = pointer.map(|pos| {
+                        row_rects
+                            .iter()
+                            .position(|rect| pos.y < rect.center().y)
+                            .unwrap_or(row_rects.len())
+                    });
+                    if let (Some(target), Some(rect)) = (target, row_rects.first()) {
+                        let y = row_rects.get(target).map_or_else(
+                            || row_rects.last().map_or(rect.top(), |r| r.bottom()),
+                            |r| r.top(),
+                        );
+                        ui.painter()
+                            .hline(rect.x_range(), y, ui.visuals().selection.stroke);
+                    }
+                    if ctx.input(|i| i.pointer.any_released()) {
+                        self.dragged_bookmark = None;
+                        if let Some(target) = target.filter(|&t| t != from && t != from + 1) {
+                            let bookmark = self.bookmarks.remove(from);
+                            let target = if target > from { target - 1 } else { target };
+                            self.bookmarks.insert(target, bookmark);
+                            changed = true;
+                        }
+                    }
+                }
+            });
+
+        if let Some(i) = remove {
+            self.bookmarks.remove(i);
+            changed = true;
+        }
+        if changed {
+            save_bookmarks(&self.bookmarks);
+        }
+        if let Some(i) = jump {
+            let bookmark = self.bookmarks[i].clone();
+            self.jump_to_bookmark(&bookmark);
+        }
+    }
+
+    fn export_annotations(&mut self) {
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        let report = annotations_to_markdown(
+            &self.file_name(),
+            &self.file_content,
+            &self.review_annotations(),
+        );
+        let target = format!("{}.review.md", path);
+        self.annotation_export_status = Some(match fs::write(&target, report) {
+            Ok(()) => format!("Saved to {}", target),
+            Err(e) => format!("Could not write {}: {}", target, e),
+        });
+    }
+
+    /// The "Annotate file" button with the progress and outcome of the last review.
+    fn show_review_status(&mut self, ui: &mut egui::Ui) {
+        let (running, warning, error) = {
+            let review = self.review.lock().unwrap();
+            (review.running, review.warning.clone(), review.error.clone())
+        };
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.can_annotate(), egui::Button::new("🤖 Annotate file"))
+                .on_hover_text("Ask the model for review comments on the file's lines")
+                .clicked()
+            {
+                self.annotate_file();
+            }
+            if running {
+                ui.spinner();
+                ui.label("Reviewing...");
+            }
+        });
+        if let Some(warning) = warning.filter(|_| !running) {
+            ui.colored_label(egui::Color32::YELLOW, format!("✂ {}", warning));
+        }
+        if let Some(error) = error {
+            ui.colored_label(egui::Color32::LIGHT_RED, format!("⚠ {}", error));
+        }
+        ui.separator();
+    }
+
+    fn show_annotations_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_annotations;
+        let mut remove = None;
+        let mut export = false;
+        let mut copy = false;
+
+        egui::Window::new("🖍 Annotations")
+            .open(&mut open)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                self.show_review_status(ui);
+                if self.annotations.is_empty() {
+                    ui.weak("Right-click a line or paragraph to highlight or annotate it.");
+                    return;
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for (i, annotation) in self.annotations.iter_mut().enumerate() {
+                            ui.horizontal(|ui| {
+                                let icon = match annotation.kind {
+                                    AnnotationKind::Highlight => "🖍",
+                                    AnnotationKind::Note => "📝",
+                                    AnnotationKind::Bookmark => "🔖",
+                                    AnnotationKind::Review => "🤖",
+                                };
+                                ui.strong(format!("{} {}", icon, annotation.line_label()));
+                                if ui.small_button("🗑").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                            ui.add(
+                                egui::TextEdit::multiline(&mut annotation.note)
+                                    .desired_rows(1)
+                                    .desired_width(f32::INFINITY)
+                                    .hint_text("Note"),
+                            );
+                            ui.add_space(4.0);
+                        }
+                    });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("💾 Export Markdown").clicked() {
+                        export = true;
+                    }
+                    if ui.button("📋 Copy Markdown").clicked() {
+                        copy = true;
+                    }
+                });
+                if let Some(status) = &self.annotation_export_status {
+                    ui.label(status);
+                }
+            });
+
+        self.show_annotations = open;
+        if let Some(i) = remove {
+            self.annotations.remove(i);
+        }
+        if export {
+            self.export_annotations();
+        }
+        if copy {
+            let report = annotations_to_markdown(
+                &self.file_name(),
+                &self.file_content,
+                &self.review_annotations(),
+            );
+            ctx.output_mut(|o| o.copied_text = report);
+        }
+    }
+
+    fn show_history_menu(&mut self, ui: &mut egui::Ui) {
+        let Some(git) = &self.git else {
+            return;
+        };
+        let history = self.history.get_or_insert_with(|| git.history());
+        let commits = match history {
+            Ok(commits) if commits.is_empty() => {
+                ui.weak("No commits touch this file yet");
+                return;
+            }
+            Ok(commits) => commits,
+            Err(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, e.as_str());
+                return;
+            }
+        };
+        let mut open = None;
+        let mut compare = None;
+        egui::ScrollArea::vertical()
+            .max_height(400.0)
+            .show(ui, |ui| {
+                for (i, commit) in commits.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let label = format!("{} {} {}", commit.short, commit.date, commit.summary);
+                        if ui
+                            .button(egui::RichText::new(label).monospace())
+                            .on_hover_text(format!("{}\nOpen this version", commit.author))
+                            .clicked()
+                        {
+                            open = Some(i);
+                            ui.close_menu();
+                        }
+                        if ui
+                            .small_button("⇆")
+                            .on_hover_text("Compare with the working copy")
+                            .clicked()
+                        {
+                            compare = Some(i);
+                            ui.close_menu();
+                        }
+                    });
+                }
+            });
+        let result = match (open, compare) {
+            (Some(i), _) => self.open_revision(i),
+            (_, Some(i)) => self.compare_revision(i),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.diff_error = Some(e);
+        }
+    }
+
+    /// The path and content of the file at the `index`th commit of its history.
+    fn revision_text(&self, index: usize) -> Result<(String, String, String), String> {
+        let (Some(git), Some(Ok(commits)), Some(path)) =
+            (&self.git, &self.history, &self.file_path)
+        else {
+            return Err("No history for this file".to_string());
+        };
+        let commit = &commits[index];
+        let text = git.content_at(commit.id)?;
+        Ok((path.clone(), commit.short.clone(), text))
+    }
+
+    /// Open the file as of the `index`th commit of its history, in a new tab.
+    fn open_revision(&mut self, index: usize) -> Result<(), String> {
+        let (path, revision, text) = self.revision_text(index)?;
+        self.new_tab();
+        self.file_path = Some(path);
+        self.streamed = true;
+        self.revision = Some(revision);
+        self.file_content = text;
+        self.prose_blocks = prose_blocks(&self.file_content);
+        self.update_fold_regions();
+        self.log_lines = LogLines::new(&self.file_content);
+        self.highlight_content();
+        self.scan_sensitive_content();
+        Ok(())
+    }
+
+    /// Diff the `index`th commit of the file's history against the working
+    /// copy, including unsaved edits.
+    fn compare_revision(&mut self, index: usize) -> Result<(), String> {
+        let (path, revision, text) = self.revision_text(index)?;
+        let mut left = self.diff_side(path.clone(), text);
+        left.revision = Some(revision);
+        let right = self.diff_side(path, self.file_content.clone());
+        self.show_diff(left, right);
+        Ok(())
+    }
+
+    /// Compare `left` (a path and its text) with the file at `right`.
+    fn compare(&mut self, left: String, left_text: String, right: String) -> Result<(), String> {
+        let right_text = read_text_file(&right)?;
+        let (left, right) = (
+            self.diff_side(left, left_text),
+            self.diff_side(right, right_text),
+        );
+        self.show_diff(left, right);
+        Ok(())
+    }
+
+    fn show_diff(&mut self, left: DiffSide, right: DiffSide) {
+        let mut diff = DiffView {
+            rows: diff_rows(&left.text, &right.text),
+            left,
+            right,
+            unified: false,
+            current_change: None,
+            scroll_to_row: None,
+        };
+        diff.step_change(1);
+        self.diff = Some(diff);
+        self.diff_error = None;
+    }
+
+    /// Whether the document is text that can be exported, printed or copied with colours.
+    fn can_export(&self) -> bool {
+        self.file_path.is_some()
+            && self.load_error.is_none()
+            && self.hex_data.is_none()
+            && self.gguf_info.is_none()
+    }
+
+    /// Ask where to save an export of the document, suggesting its name with `extension`.
+    fn export_target(&self, title: &str, extension: &str) -> Option<PathBuf> {
+        let mut dialog = rfd::FileDialog::new()
+            .set_title(title)
+            .set_file_name(format!("{}.{}", self.file_name(), extension))
+            .add_filter(extension.to_uppercase(), &[extension]);
+        if let Some(dir) = self
+            .file_path
+            .as_deref()
+            .filter(|_| !self.streamed)
+            .and_then(|path| Path::new(path).parent())
+            .filter(|dir| dir.is_dir())
+        {
+            dialog = dialog.set_directory(dir);
+        }
+        dialog.save_file()
+    }
+
+    /// Save the document, highlighted with the current theme, as a standalone web page.
+    fn export_html(&mut self) {
+        let Some(target) = self.export_target("Export as HTML", "html") else {
+            return;
+        };
+        let html = highlighted_html(
+            &self.file_content,
+            &self.file_name(),
+            &self.syntax_set,
+            self.detect_syntax(),
+            self.theme(),
+            self.show_line_numbers,
+        );
+        let result = fs::write(&target, html)
+            .map(|()| format!("Exported to {}", target.display()))
+            .map_err(|e| format!("Could not write {}: {}", target.display(), e));
+        self.export_status = Some((result, Instant::now()));
+    }
+
+    /// Save the document as a paginated PDF, in the current theme or, for
+    /// printing, a light theme on white paper.
+    fn export_pdf(&mut self, print_friendly: bool) {
+        let Some(target) = self.export_target("Export as PDF", "pdf") else {
+            return;
+        };
+        let mut theme = self.theme().clone();
+        if print_friendly {
+            if let Some(light) = ["InspiredGitHub", DEFAULT_LIGHT_THEME]
+                .iter()
+                .find_map(|name| self.theme_set.themes.get(*name))
+            {
+                theme = light.clone();
+            }
+            theme.settings.background = None;
+        }
+        let result = highlighted_pdf(
+            &self.file_content,
+            &self.file_name(),
+            &self.syntax_set,
+            self.detect_syntax(),
+            &theme,
+            self.show_line_numbers,
+        )
+        .and_then(|pdf| fs::write(&target, pdf).map_err(|e| e.to_string()))
+        .map(|()| format!("Exported to {}", target.display()))
+        .map_err(|e| format!("Could not export {}: {}", target.display(), e));
+        self.export_status = Some((result, Instant::now()));
+    }
+
+    fn can_compare(&self) -> bool {
+        self.file_path.is_some()
+            && !self.streamed
+            && self.hex_data.is_none()
+            && self.gguf_info.is_none()
+    }
+
+    /// Compare the open document, including unsaved edits, with a file picked by the user.
+    fn show_compare_dialog(&mut self) {
+        let Some(path) = self.file_path.clone().filter(|_| !self.streamed) else {
+            return;
+        };
+        let mut dialog = rfd::FileDialog::new().set_title("Compare with…");
+        if let Some(dir) = Path::new(&path).parent().filter(|dir| dir.is_dir()) {
+            dialog = dialog.set_directory(dir);
+        }
+        if let Some(other) = dialog.pick_file() {
+            let text = self.file_content.clone();
+            if let Err(e) = self.compare(path, text, other.to_string_lossy().into_owned()) {
+                self.diff_error = Some(e);
+            }
+        }
+    }
+
+    fn diff_side(&self, path: String, text: String) -> DiffSide {
+        let syntax = self
+            .syntax_set
+            .find_syntax_for_file(&path)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let (lines, task) = self.start_highlighting(&text, syntax);
+        DiffSide {
+            path,
+            revision: None,
+            text,
+            lines,
+            highlight_task: Some(task),
+        }
+    }
+
+    fn show_diff_view(&mut self, ui: &mut egui::Ui) {
+        let font = egui::FontId::monospace(self.font_size);
+        let Some(diff) = &mut self.diff else {
+            return;
+        };
+        for side in [&mut diff.left, &mut diff.right] {
+            if let Some(task) = &mut side.highlight_task {
+                if task.poll(&mut side.lines) {
+                    side.highlight_task = None;
+                }
+            }
+        }
+        if diff.left.highlight_task.is_some() || diff.right.highlight_task.is_some() {
+            ui.ctx().request_repaint_after(Duration::from_millis(50));
+        }
+
+        let rows = diff.rows();
+        let starts = change_starts(&rows);
+        let (next, previous) = ui.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::F7),
+                i.consume_key(egui::Modifiers::SHIFT, egui::Key::F7),
+            )
+        });
+        let loading = self.ai_state.is_loading();
+        let mut close = false;
+        let mut explain = false;
+        let mut step = None;
+        ui.horizontal(|ui| {
+            let name = |side: &DiffSide| display_name(Some(&side.label()));
+            ui.strong(name(&diff.left)).on_hover_text(diff.left.label());
+            ui.label("↔");
+            ui.strong(name(&diff.right))
+                .on_hover_text(diff.right.label());
+            let (added, removed) = diff.added_and_removed();
+            ui.colored_label(egui::Color32::from_rgb(80, 200, 100), format!("+{}", added));
+            ui.colored_label(
+                egui::Color32::from_rgb(230, 90, 90),
+                format!("−{}", removed),
+            );
+            ui.separator();
+
+            let before = diff.unified;
+            ui.selectable_value(&mut diff.unified, false, "Side by side");
+            ui.selectable_value(&mut diff.unified, true, "Unified");
+            if diff.unified != before {
+                diff.current_change = None;
+            }
+            ui.separator();
+            if ui
+                .button("⬆")
+                .on_hover_text("Previous change (Shift+F7)")
+                .clicked()
+            {
+                step = Some(-1);
+            }
+            if ui.button("⬇").on_hover_text("Next change (F7)").clicked() {
+                step = Some(1);
+            }
+            match (diff.current_change, starts.len()) {
+                (_, 0) => ui.weak("The files are identical"),
+                (Some(current), count) => ui.label(format!("Change {} of {}", current + 1, count)),
+                (None, count) => ui.label(format!("{} changes", count)),
+            };
+            ui.separator();
+            explain = ui
+                .add_enabled(
+                    !loading && !starts.is_empty(),
+                    egui::Button::new("🤖 Explain changes"),
+                )
+                .on_hover_text("Summarize the changes and their risks in the AI panel")
+                .clicked();
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                close = ui.button("✖ Close diff").clicked();
+            });
+        });
+        ui.separator();
+        if next {
+            step = Some(1);
+        } else if previous {
+            step = Some(-1);
+        }
+        if let Some(step) = step {
+            diff.step_change(step);
+        }
+
+        let (char_width, font_row_height) =
+            ui.fonts(|f| (f.glyph_width(&font, 'M'), f.row_height(&font)));
+        let row_height = font_row_height;
+        let pitch = row_height + ui.spacing().item_spacing.y;
+        let digits = diff
+            .left
+            .lines
+            .len()
+            .max(diff.right.lines.len())
+            .max(1)
+            .to_string()
+            .len();
+        let gutter_width = (digits + 2) as f32 * char_width;
+        let gutter_color = ui.visuals().weak_text_color();
+        let dark_mode = ui.visuals().dark_mode;
+        let current_row = diff.current_change.and_then(|i| starts.get(i)).copied();
+
+        let mut scroll_area = egui::ScrollArea::vertical()
+            .id_source("diff_view")
+            .auto_shrink([false, false]);
+        if let Some(row) = diff.scroll_to_row.take() {
+            let offset = row as f32 * pitch - ui.available_height() / 3.0;
+            scroll_area = scroll_area.vertical_scroll_offset(offset.max(0.0));
+        }
+
+        // Paint one side of a row: background, line number and highlighted text.
+        let cell = |ui: &mut egui::Ui,
+                    width: f32,
+                    side: &DiffSide,
+                    line: Option<usize>,
+                    kind: DiffKind,
+                    marker: &str| {
+            let (rect, _) =
+                ui.allocate_exact_size(egui::vec2(width, row_height), egui::Sense::hover());
+            let painter = ui.painter_at(rect);
+            if let Some(fill) = diff_fill(kind, dark_mode).filter(|_| line.is_some()) {
+                painter.rect_filled(rect, 0.0, fill);
+            }
+            let Some(line) = line else {
+                return;
+            };
+            painter.text(
+                rect.left_center() + egui::vec2(gutter_width - char_width, 0.0),
+                egui::Align2::RIGHT_CENTER,
+                format!("{}{}", marker, line + 1),
+                font.clone(),
+                gutter_color,
+            );
+            let mut job = egui::text::LayoutJob::default();
+            for (style, text) in side.lines.get(line).into_iter().flatten() {
+                let color = egui::Color32::from_rgb(
+                    style.foreground.r,
+                    style.foreground.g,
+                    style.foreground.b,
+                );
+                job.append(text, 0.0, egui::TextFormat::simple(font.clone(), color));
+            }
+            let galley = ui.fonts(|f| f.layout_job(job));
+            let top = rect.center().y - galley.size().y / 2.0;
+            painter.galley(egui::pos2(rect.left() + gutter_width, top), galley);
+        };
+
+        scroll_area.show_rows(ui, row_height, rows.len(), |ui, row_range| {
+            for index in row_range {
+                let row = rows[index];
+                let response = ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    let width = ui.available_width();
+                    if diff.unified {
+                        let (side, line, marker) = match row.kind {
+                            DiffKind::Added => (&diff.right, row.right, "+ "),
+                            DiffKind::Removed => (&diff.left, row.left, "- "),
+                            _ => (&diff.right, row.right, "  "),
+                        };
+                        cell(ui, width, side, line, row.kind, marker);
+                    } else {
+                        let half = (width - char_width) / 2.0;
+                        cell(ui, half, &diff.left, row.left, row.kind, "");
+                        ui.add_space(char_width);
+                        cell(ui, half, &diff.right, row.right, row.kind, "");
+                    }
+                });
+                if current_row == Some(index) {
+                    ui.painter().rect_stroke(
+                        response.response.rect,
+                        0.0,
+                        ui.visuals().selection.stroke,
+                    );
+                }
+            }
+        });
+
+        if explain {
+            self.explain_diff();
+        }
+        if close {
+            self.diff = None;
+        }
+    }
+
+    /// Ask the model what the diff changes and what could go wrong, in the
+    /// chat of the open document.
+    fn explain_diff(&mut self) {
+        let Some(diff) = &self.diff else {
+            return;
+        };
+        if self.ai_state.is_loading() {
+            return;
+        }
+
+        let mut patch = diff.unified_diff();
+        if !self.redaction_opted_out() {
+            patch = self.redactor.redact(&patch).0;
+        }
+        // The diff may take half the context window, the document the rest.
+        let fitted = fit_lines(&patch, context_budget(&self.generation, &[]) / 2);
+        let warning = fitted.cut_warning("Diff");
+        let mut shown = fitted.text.to_string();
+        if warning.is_some() {
+            shown.push_str("\n[diff truncated]\n");
+        }
+        let (left, right) = (
+            display_name(Some(&diff.left.label())),
+            display_name(Some(&diff.right.label())),
+        );
+        let prompt = format!(
+            "Here is a unified diff from {} to {}:\n\n```diff\n{}```\n\n\
+             Summarize what changed and why it matters. Then list potential risks: \
+             bugs, changes in behaviour, security or compatibility problems, and \
+             anything a reviewer should look at closely.",
+            left, right, shown
+        );
+
+        self.show_ai_panel = true;
+        self.dispatch_ai_request(
+            prompt,
+            RequestKind::Chat {
+                question: Some(format!("Explain the changes from {} to {}", left, right)),
+                warning,
+                lines: None,
+                images: Vec::new(),
+            },
+        );
+    }
+
+    /// The active document in whichever view suits it.
+    fn show_document(&mut self, ui: &mut egui::Ui) {
+        if let Some(info) = &self.gguf_info {
+            self.show_gguf_info(ui, info);
+        } else if let Some(image) = self.image_preview.as_mut().filter(|image| !image.show_hex) {
+            image.show(ui);
+        } else if self.hex_data.is_some() {
+            self.show_hex_view(ui);
+        } else if self.show_translation {
+            self.show_translation_view(ui);
+        } else if self.editing {
+            self.show_editor(ui);
+        } else if self.has_rendered_view() && self.document_view == DocumentView::Rendered {
+            self.show_rendered_view(ui);
+        } else if self.has_rendered_view() && self.document_view == DocumentView::Split {
+            ui.columns(2, |columns| {
+                self.show_content(&mut columns[0]);
+                self.show_rendered_view(&mut columns[1]);
+            });
+        } else {
+            self.show_content(ui);
+        }
+    }
+
+    /// The right half of a split view. Another tab is swapped in for the
+    /// duration; the active document gets the pane's own scroll state instead.
+    /// Returns whether the pane was closed.
+    fn show_split_pane(&mut self, ui: &mut egui::Ui, pane: &mut SplitPane) -> bool {
+        let mut close = false;
+        ui.horizontal(|ui| {
+            let name = |doc: &OpenDocument| display_name(doc.file_path.as_deref());
+            let selected = match pane.document.and_then(|i| self.documents.get(i)) {
+                Some(doc) if pane.document != Some(self.active_document) => name(doc),
+                _ => "Second view of this tab".to_string(),
+            };
+            let before = pane.document;
+            egui::ComboBox::from_id_source("split_document")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut pane.document, None, "Second view of this tab");
+                    for (i, doc) in self.documents.iter().enumerate() {
+                        if i != self.active_document && doc.load_error.is_none() {
+                            ui.selectable_value(&mut pane.document, Some(i), name(doc));
+                        }
+                    }
+                });
+            if pane.document != before {
+                pane.restore_scroll = true;
+            }
+            ui.toggle_value(&mut pane.sync, "🔗 Sync scroll")
+                .on_hover_text("Scroll both panes together");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                close = ui.small_button("✖").on_hover_text("Close split").clicked();
+            });
+        });
+        ui.separator();
+
+        let primary_offset = self.scroll_offset;
+        let primary_restore = std::mem::take(&mut self.restore_scroll);
+        let other = pane
+            .document
+            .filter(|&i| i != self.active_document && i < self.documents.len());
+        let mut parked = other.map(|i| std::mem::take(&mut self.documents[i]));
+        match &mut parked {
+            Some(doc) => self.exchange_document(doc),
+            None => pane.exchange_view(self),
+        }
+        std::mem::swap(&mut self.minimap, &mut pane.minimap);
+        // A parked tab is not highlighted in the background, so do it here.
+        self.poll_highlighting();
+        if self.highlight_task.is_some() {
+            ui.ctx().request_repaint_after(Duration::from_millis(50));
+        }
+        if pane.sync {
+            self.scroll_offset = primary_offset;
+            self.restore_scroll = true;
+        } else {
+            self.restore_scroll = std::mem::take(&mut pane.restore_scroll);
+        }
+
+        ui.push_id("split_pane", |ui| self.show_document(ui));
+
+        let pane_offset = self.scroll_offset;
+        std::mem::swap(&mut self.minimap, &mut pane.minimap);
+        match (other, parked) {
+            (Some(i), Some(mut doc)) => {
+                self.exchange_document(&mut doc);
+                self.documents[i] = doc;
+            }
+            _ => pane.exchange_view(self),
+        }
+        self.restore_scroll = primary_restore;
+        // Scrolling the pane moves the main view along on the next frame.
+        if pane.sync && (pane_offset - primary_offset).abs() > 0.5 {
+            self.scroll_offset = pane_offset;
+            self.restore_scroll = true;
+        }
+        close
+    }
+
+    /// The document view, with the minimap on its right when it is enabled.
+    fn show_content(&mut self, ui: &mut egui::Ui) {
+        if self.log_mode {
+            self.show_log_bar(ui);
+        }
+        let reflow = self.reflow_prose && self.is_prose();
+        if !self.show_minimap || reflow || self.highlighted_content.is_empty() {
+            self.show_lines(ui);
+            return;
+        }
+        let full = ui.available_rect_before_wrap();
+        let map_rect = full.with_min_x(full.right() - Minimap::WIDTH);
+        let lines_rect = full.with_max_x(map_rect.left() - ui.spacing().item_spacing.x);
+        ui.allocate_ui_at_rect(lines_rect, |ui| self.show_lines(ui));
+        self.show_minimap(ui, map_rect);
+    }
+
+    /// Draw the minimap with the visible lines marked. Clicking or dragging
+    /// on it scrolls the view there.
+    fn show_minimap(&mut self, ui: &mut egui::Ui, rect: egui::Rect) {
+        let key = (
+            self.file_path.clone(),
+            self.file_content.len(),
+            self.highlighted_content.len(),
+            self.highlight_task.is_some(),
+            self.theme_name.clone(),
+        );
+        if self.minimap.as_ref().is_none_or(|m| m.key != key) {
+            self.minimap = Some(Minimap::render(ui.ctx(), key, &self.highlighted_content));
+        }
+        let Some(minimap) = &self.minimap else {
+            return;
+        };
+
+        let lines = self.highlighted_content.len() as f32;
+        // Two pixels per line, squeezed to fit when the document is long.
+        let height = (lines * 2.0).min(rect.height());
+        let map = egui::Rect::from_min_size(rect.min, egui::vec2(rect.width(), height));
+        let response = ui.interact(rect, ui.id().with("minimap"), egui::Sense::click_and_drag());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, self.theme_background());
+        painter.image(
+            minimap.texture.id(),
+            map,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE,
+        );
+
+        let (first, last) = self.visible_lines;
+        let y = |line: usize| map.top() + line as f32 / lines * height;
+        let viewport =
+            egui::Rect::from_x_y_ranges(map.x_range(), y(first)..=y(last + 1).max(y(first) + 4.0));
+        let fill = if response.hovered() || response.dragged() {
+            egui::Color32::from_white_alpha(45)
+        } else {
+            egui::Color32::from_white_alpha(25)
+        };
+        painter.rect_filled(viewport, 0.0, fill);
+        painter.rect_stroke(viewport, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+
+        if let Some(pos) = response
+            .interact_pointer_pos()
+            .filter(|_| response.clicked() || response.dragged())
+        {
+            let line = ((pos.y - map.top()) / height * lines).clamp(0.0, lines - 1.0);
+            self.scroll_to_line = Some(line as usize);
+        }
+    }
+
+    fn show_lines(&mut self, ui: &mut egui::Ui) {
+        let mut frame = ViewFrame::default();
+
+        let reflow = self.reflow_prose && self.is_prose();
+        let code_font = egui::FontId::monospace(self.font_size);
+        let (char_width, font_row_height) =
+            ui.fonts(|f| (f.glyph_width(&code_font, 'M'), f.row_height(&code_font)));
+        // Every code row is one horizontal layout, which is at least interact_size tall.
+        let row_height = font_row_height.max(ui.spacing().interact_size.y);
+        let pitch = row_height + ui.spacing().item_spacing.y;
+
+        if let Some(line) = self.scroll_to_line {
+            self.unfold_line(line);
+        }
+        // Only lines passing the level filter and outside folded regions get a row.
+        let folded = self.folded_lines();
+        let rows: Vec<usize> =
+            if (self.log_mode && self.log_filter.is_active()) || !folded.is_empty() {
+                (0..self.highlighted_content.len())
+                    .filter(|&i| {
+                        !self.hidden_by_log_filter(i) && !folded.get(i).copied().unwrap_or(false)
+                    })
+                    .collect()
+            } else {
+                (0..self.highlighted_content.len()).collect()
+            };
+
+        // Dragging over the text selects it rather than scrolling.
+        let mut scroll_area = egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .drag_to_scroll(false);
+        let mut target_offset = None;
+        if std::mem::take(&mut self.restore_scroll) {
+            scroll_area = scroll_area.vertical_scroll_offset(self.scroll_offset);
+        } else if let Some(line) = self.scroll_to_line.filter(|_| !reflow) {
+            // Rows off screen are never laid out, so scroll by arithmetic.
+            let row = rows.partition_point(|&i| i < line) as f32;
+            let offset = row * pitch - (ui.available_height() - row_height) / 2.0;
+            scroll_area = scroll_area.vertical_scroll_offset(offset.max(0.0));
+        } else if self.scroll_by != egui::Vec2::ZERO {
+            let by = std::mem::take(&mut self.scroll_by);
+            scroll_area = scroll_area
+                .vertical_scroll_offset((self.scroll_offset + by.y * pitch).max(0.0))
+                .horizontal_scroll_offset((self.horizontal_offset + by.x * char_width).max(0.0));
+        } else if !reflow && self.row_pitch > 0.0 && pitch != self.row_pitch {
+            // Keep the same line at the top when the font size changes.
+            scroll_area =
+                scroll_area.vertical_scroll_offset(self.scroll_offset / self.row_pitch * pitch);
+        } else if self.auto_scroll {
+            let dt = ui.input(|i| i.stable_dt).min(0.1);
+            let offset = self.scroll_offset + self.auto_scroll_speed * dt;
+            scroll_area = scroll_area.vertical_scroll_offset(offset);
+            target_offset = Some(offset);
+            ui.ctx().request_repaint();
+        }
+        self.row_pitch = pitch;
+
+        if self.follow || self.stream.is_some() {
+            scroll_area = scroll_area.stick_to_bottom(true);
+        }
+
+        let focus_range = self.paragraph_range(self.focus_line);
+        let searching = self.show_search && !self.search_matches.is_empty();
+        let starts = if self.selection.is_some() || searching {
+            line_starts(&self.file_content)
+        } else {
+            Vec::new()
+        };
+        let brackets = self
+            .selection
+            .as_ref()
+            .and_then(|range| bracket_pair_at(&self.file_content, range));
+        // Fixed-width gutter: enough digits for the last line plus a column of padding each side.
+        let gutter_width = if self.show_line_numbers {
+            let digits = self.highlighted_content.len().max(1).to_string().len();
+            (digits + 2) as f32 * char_width
+        } else {
+            0.0
+        };
+        let bookmarked = self.bookmarked_lines();
+        let bookmark_width = if self.show_line_numbers || !bookmarked.is_empty() {
+            2.0 * char_width
+        } else {
+            0.0
+        };
+        if let Some(git) = self.git.as_ref().filter(|_| self.show_blame) {
+            git.start_blame(&self.file_content, ui.ctx());
+        }
+        let blame_state = self
+            .git
+            .as_ref()
+            .filter(|_| self.show_blame)
+            .map(|git| git.blame.clone());
+        let blame_state = blame_state.as_ref().map(|state| state.lock().unwrap());
+        let blame = match blame_state.as_ref().and_then(|state| state.lines.as_ref()) {
+            Some(Ok(lines)) => Some(lines.as_slice()),
+            _ => None,
+        };
+        let blame_width = if blame_state.is_some() {
+            30.0 * char_width
+        } else {
+            0.0
+        };
+        let changes = self.git.as_ref().map(|git| git.changes.as_slice());
+        let fold_width = if self.fold_regions.is_empty() {
+            0.0
+        } else {
+            2.0 * char_width
+        };
+        let gutter_color = ui.visuals().weak_text_color();
+        let selected_gutter_color = ui.visuals().strong_text_color();
+
+        let output = if reflow {
+            scroll_area.show(ui, |ui| self.show_prose(ui, &mut frame))
+        } else {
+            scroll_area.show_rows(ui, row_height, rows.len(), |ui, row_range| {
+                let center_y = ui.clip_rect().center().y;
+                for &index in &rows[row_range] {
+                    let line = &self.highlighted_content[index];
+                    let dimmed =
+                        self.focus_mode && (index < focus_range.0 || index > focus_range.1);
+                    let folded_here = self.folded.contains(&index);
+                    let background = ui.painter().add(egui::Shape::Noop);
+                    let selection_background = ui.painter().add(egui::Shape::Noop);
+                    let match_background = ui.painter().add(egui::Shape::Noop);
+                    let bracket_background = ui.painter().add(egui::Shape::Noop);
+                    // Byte range of this line, excluding its newline.
+                    let span = starts.get(index).map(|&start| {
+                        let end = self.file_content[start..]
+                            .find('\n')
+                            .map_or(self.file_content.len(), |i| start + i);
+                        (start, end)
+                    });
+                    let selected = match (&self.selection, span) {
+                        (Some(selection), Some((start, end))) => {
+                            selection.start.max(start) < selection.end.min(end)
+                                || (selection.start <= start && selection.end > end)
+                        }
+                        _ => false,
+                    };
+
+                    let row = ui.horizontal(|ui| {
+                        ui.spacing_mut().item_spacing.x = 0.0;
+                        if bookmark_width > 0.0 {
+                            let (marker, response) = ui.allocate_exact_size(
+                                egui::vec2(bookmark_width, row_height),
+                                egui::Sense::click(),
+                            );
+                            let marked = bookmarked.binary_search(&index).is_ok();
+                            if marked || response.hovered() {
+                                let color = if marked {
+                                    egui::Color32::from_rgb(80, 140, 255)
+                                } else {
+                                    gutter_color
+                                };
+                                ui.painter().text(
+                                    marker.center(),
+                                    egui::Align2::CENTER_CENTER,
+                                    "🔖",
+                                    egui::FontId::proportional(self.font_size * 0.8),
+                                    color,
+                                );
+                            }
+                            if response.on_hover_text("Toggle bookmark (Ctrl+B)").clicked() {
+                                frame.bookmark_toggled = Some(index);
+                            }
+                        }
+                        if gutter_width > 0.0 {
+                            let (gutter, response) = ui.allocate_exact_size(
+                                egui::vec2(gutter_width, row_height),
+                                egui::Sense::click(),
+                            );
+                            ui.painter().text(
+                                gutter.right_center() - egui::vec2(char_width, 0.0),
+                                egui::Align2::RIGHT_CENTER,
+                                (index + 1).to_string(),
+                                code_font.clone(),
+                                if selected {
+                                    selected_gutter_color
+                                } else {
+                                    gutter_color
+                                },
+                            );
+                            if response.clicked() {
+                                frame.gutter_clicked =
+                                    Some((index, ui.input(|i| i.modifiers.shift)));
+                            }
+                        }
+                        if blame_width > 0.0 {
+                            let (cell, response) = ui.allocate_exact_size(
+                                egui::vec2(blame_width, row_height),
+                                egui::Sense::hover(),
+                            );
+                            let line = blame.and_then(|lines| lines.get(index)).cloned().flatten();
+                            let previous = index.checked_sub(1).and_then(|i| {
+                                blame.and_then(|lines| lines.get(i)).cloned().flatten()
+                            });
+                            // Only the first line of each run from the same commit is labelled.
+                            let first_of_run = match (&line, &previous) {
+                                (Some(line), Some(previous)) => !Arc::ptr_eq(line, previous),
+                                _ => true,
+                            };
+                            let label = match (&line, blame_state.as_ref()) {
+                                (Some(line), _) => {
+                                    format!("{} {} {}", line.commit, line.date, line.author)
+                                }
+                                (None, Some(state)) if state.loading => "…".to_string(),
+                                (None, Some(state)) => match &state.lines {
+                                    Some(Err(e)) => e.clone(),
+                                    _ => "Not committed".to_string(),
+                                },
+                                (None, None) => String::new(),
+                            };
+                            if first_of_run {
+                                let galley = ui.fonts(|f| {
+                                    f.layout_no_wrap(
+                                        label.clone(),
+                                        egui::FontId::monospace(self.font_size * 0.85),
+                                        gutter_color,
+                                    )
+                                });
+                                let pos = egui::pos2(
+                                    cell.left() + char_width / 2.0,
+                                    cell.center().y - galley.size().y / 2.0,
+                                );
+                                ui.painter_at(cell.shrink2(egui::vec2(char_width / 2.0, 0.0)))
+                                    .galley(pos, galley);
+                            }
+                            if let Some(line) = line {
+                                response.on_hover_ui(|ui| {
+                                    ui.strong(format!("{} — {}", line.commit, line.author));
+                                    ui.weak(&line.date);
+                                    ui.label(&line.message);
+                                });
+                            }
+                        }
+                        if fold_width > 0.0 {
+                            let (marker, response) = ui.allocate_exact_size(
+                                egui::vec2(fold_width, row_height),
+                                egui::Sense::click(),
+                            );
+                            if let Some(region) = self.fold_region(index) {
+                                let folded = self.folded.contains(&index);
+                                let color = if response.hovered() {
+                                    selected_gutter_color
+                                } else {
+                                    gutter_color
+                                };
+                                ui.painter().text(
+                                    marker.center(),
+                                    egui::Align2::CENTER_CENTER,
+                                    if folded { "▸" } else { "▾" },
+                                    code_font.clone(),
+                                    color,
+                                );
+                                let hint =
+                                    format!("Lines {}–{}", region.first + 1, region.last + 1);
+                                if response.on_hover_text(hint).clicked() {
+                                    frame.fold_toggled = Some(index);
+                                }
+                            }
+                        }
+                        let rainbow;
+                        let segments = if self.rainbow_brackets {
+                            let depth = self.bracket_depths.get(index).copied().unwrap_or(0);
+                            rainbow = rainbow_brackets(line, depth);
+                            &rainbow
+                        } else {
+                            line
+                        };
+                        for (style, text) in segments {
+                            let mut color = egui::Color32::from_rgb(
+                                style.foreground.r,
+                                style.foreground.g,
+                                style.foreground.b,
+                            );
+                            if dimmed {
+                                color = color.gamma_multiply(0.25);
+                            }
+                            let mut text = egui::RichText::new(text)
+                                .monospace()
+                                .size(self.font_size)
+                                .color(color);
+                            if style.font_style.contains(FontStyle::BOLD) {
+                                text = text.strong();
+                            }
+                            if style.font_style.contains(FontStyle::ITALIC) {
+                                text = text.italics();
+                            }
+                            if style.font_style.contains(FontStyle::UNDERLINE) {
+                                text = text.underline();
+                            }
+                            ui.label(text);
+                        }
+                        if let Some(region) = self.fold_region(index).filter(|_| folded_here) {
+                            ui.label(
+                                egui::RichText::new(format!(
+                                    "  ⋯ {} lines",
+                                    region.last - region.first
+                                ))
+                                .monospace()
+                                .size(self.font_size)
+                                .color(gutter_color),
+                            );
+                        }
+                        self.show_review_markers(ui, index);
+                    });
+
+                    let rect = row.response.rect;
+                    let text_rect = rect.with_min_x(
+                        rect.left() + bookmark_width + gutter_width + blame_width + fold_width,
+                    );
+                    if let Some(change) = changes.and_then(|c| c.get(index).copied().flatten()) {
+                        let bar = egui::Rect::from_min_size(
+                            rect.left_top(),
+                            egui::vec2(3.0, rect.height()),
+                        );
+                        let shape = match change {
+                            LineChange::Added => egui::Shape::rect_filled(
+                                bar,
+                                0.0,
+                                egui::Color32::from_rgb(80, 180, 90),
+                            ),
+                            LineChange::Modified => egui::Shape::rect_filled(
+                                bar,
+                                0.0,
+                                egui::Color32::from_rgb(90, 140, 230),
+                            ),
+                            LineChange::DeletedAbove => egui::Shape::convex_polygon(
+                                vec![
+                                    rect.left_top() - egui::vec2(0.0, 4.0),
+                                    rect.left_top() + egui::vec2(6.0, 0.0),
+                                    rect.left_top() + egui::vec2(0.0, 4.0),
+                                ],
+                                egui::Color32::from_rgb(220, 80, 80),
+                                egui::Stroke::NONE,
+                            ),
+                        };
+                        ui.painter().add(shape);
+                    }
+                    if let Some(fill) = self
+                        .flash_fill(index, index)
+                        .or_else(|| self.highlight_fill(index))
+                        .or_else(|| self.level_fill(index))
+                    {
+                        let full_row =
+                            egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), rect.y_range());
+                        ui.painter()
+                            .set(background, egui::Shape::rect_filled(full_row, 0.0, fill));
+                    }
+                    if let (Some(selection), Some((start, end))) =
+                        (self.selection.as_ref().filter(|_| selected), span)
+                    {
+                        let from = selection.start.max(start);
+                        let to = selection.end.min(end);
+                        let column =
+                            |offset: usize| self.file_content[start..offset].chars().count() as f32;
+                        let mut x_range = egui::Rangef::new(
+                            text_rect.left() + column(from) * char_width,
+                            text_rect.left() + column(to) * char_width,
+                        );
+                        // Show that the line break is selected too.
+                        if selection.end > end {
+                            x_range.max += char_width / 2.0;
+                        }
+                        ui.painter().set(
+                            selection_background,
+                            egui::Shape::rect_filled(
+                                egui::Rect::from_x_y_ranges(x_range, rect.y_range()),
+                                0.0,
+                                ui.visuals().selection.bg_fill.gamma_multiply(0.6),
+                            ),
+                        );
+                    }
+
+                    if let Some((start, end)) = span.filter(|_| searching) {
+                        let column =
+                            |offset: usize| self.file_content[start..offset].chars().count() as f32;
+                        let first = self.search_matches.partition_point(|m| m.end <= start);
+                        let hits: Vec<egui::Shape> = self.search_matches[first..]
+                            .iter()
+                            .enumerate()
+                            .take_while(|(_, m)| m.start < end)
+                            .map(|(i, m)| {
+                                let x_range = egui::Rangef::new(
+                                    text_rect.left() + column(m.start.max(start)) * char_width,
+                                    text_rect.left() + column(m.end.min(end)) * char_width,
+                                );
+                                let fill = if first + i == self.current_match {
+                                    egui::Color32::from_rgba_unmultiplied(255, 140, 0, 150)
+                                } else {
+                                    egui::Color32::from_rgba_unmultiplied(255, 210, 0, 70)
+                                };
+                                egui::Shape::rect_filled(
+                                    egui::Rect::from_x_y_ranges(x_range, rect.y_range()),
+                                    2.0,
+                                    fill,
+                                )
+                            })
+                            .collect();
+                        ui.painter().set(match_background, egui::Shape::Vec(hits));
+                    }
+
+                    if let (Some((first, second)), Some((start, end))) = (brackets, span) {
+                        let column =
+                            |offset: usize| self.file_content[start..offset].chars().count() as f32;
+                        let boxes: Vec<egui::Shape> = [first, second]
+                            .into_iter()
+                            .filter(|at| (start..end).contains(at))
+                            .map(|at| {
+                                let left = text_rect.left() + column(at) * char_width;
+                                egui::Shape::rect_stroke(
+                                    egui::Rect::from_x_y_ranges(
+                                        left..=left + char_width,
+                                        rect.y_range(),
+                                    ),
+                                    2.0,
+                                    egui::Stroke::new(1.0, ui.visuals().strong_text_color()),
+                                )
+                            })
+                            .collect();
+                        ui.painter()
+                            .set(bracket_background, egui::Shape::Vec(boxes));
+                    }
+
+                    for link in self.links.get(&index).into_iter().flatten() {
+                        let left = text_rect.left() + link.columns.start as f32 * char_width;
+                        let right = text_rect.left() + link.columns.end as f32 * char_width;
+                        ui.painter().hline(
+                            left..=right,
+                            rect.bottom() - 1.0,
+                            egui::Stroke::new(1.0, ui.visuals().hyperlink_color),
+                        );
+                    }
+
+                    if ui.clip_rect().intersects(rect) {
+                        let range = frame.visible.get_or_insert((index, index));
+                        range.1 = index;
+
+                        let id = ui.id().with(("line", index));
+                        let response = ui.interact(text_rect, id, egui::Sense::click_and_drag());
+                        let column = |pos: egui::Pos2| {
+                            ((pos.x - text_rect.left()) / char_width).round().max(0.0) as usize
+                        };
+                        let link = response.hover_pos().and_then(|pos| {
+                            let column = ((pos.x - text_rect.left()) / char_width).max(0.0);
+                            self.link_at(index, column as usize)
+                        });
+                        let command = ui.input(|i| i.modifiers.command);
+                        let response = match link {
+                            Some(link) => {
+                                if command {
+                                    ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                                }
+                                let hint = match &link.target {
+                                    LinkTarget::Url(url) => url.clone(),
+                                    LinkTarget::File { path, .. } => path.display().to_string(),
+                                };
+                                response.on_hover_text(format!("{}\nCtrl+click to open", hint))
+                            }
+                            None => response,
+                        };
+                        if let Some(pos) = response
+                            .interact_pointer_pos()
+                            .filter(|_| response.clicked())
+                        {
+                            match link.filter(|_| command) {
+                                Some(link) => frame.open_link = Some(link.target.clone()),
+                                None => frame.clicked = Some((index, column(pos))),
+                            }
+                        }
+                        if let Some(pos) = response
+                            .interact_pointer_pos()
+                            .filter(|_| response.drag_started())
+                        {
+                            frame.drag_started = Some((index, column(pos)));
+                        }
+                        frame.rows.push((index, text_rect));
+                        response.context_menu(|ui| {
+                            if self.selection.as_ref().is_some_and(|r| !r.is_empty()) {
+                                if ui.button("📋 Copy").clicked() {
+                                    frame.copy_selection = Some(CopyFormat::Plain);
+                                    ui.close_menu();
+                                }
+                                ui.menu_button("🎨 Copy with formatting", |ui| {
+                                    if ui.button("ANSI colours (terminal)").clicked() {
+                                        frame.copy_selection = Some(CopyFormat::Ansi);
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("HTML (rich text)").clicked() {
+                                        frame.copy_selection = Some(CopyFormat::Html);
+                                        ui.close_menu();
+                                    }
+                                });
+                                if ui.button("💡 Explain selection").clicked() {
+                                    frame.explain_selection = true;
+                                    ui.close_menu();
+                                }
+                                if ui.button("💬 Ask about selection…").clicked() {
+                                    frame.ask_about_selection = true;
+                                    ui.close_menu();
+                                }
+                                if self.can_edit() && ui.button("✨ AI edit…").clicked() {
+                                    frame.edit_selection = true;
+                                    ui.close_menu();
+                                }
+                                if ui.button("📝 Document this").clicked() {
+                                    frame.document_selection = true;
+                                    ui.close_menu();
+                                }
+                                if self.can_suggest_tests()
+                                    && ui.button("🧪 Suggest tests").clicked()
+                                {
+                                    frame.suggest_tests = true;
+                                    ui.close_menu();
+                                }
+                                ui.separator();
+                            }
+                            if !self.fold_regions.is_empty() {
+                                let label = match self.fold_region(index) {
+                                    Some(_) if folded_here => Some("Unfold"),
+                                    Some(_) => Some("Fold"),
+                                    None => None,
+                                };
+                                if let Some(label) = label {
+                                    if ui.button(label).clicked() {
+                                        frame.fold_toggled = Some(index);
+                                        ui.close_menu();
+                                    }
+                                }
+                                if ui.button("Fold all (Ctrl+Shift+-)").clicked() {
+                                    frame.fold_all = Some(true);
+                                    ui.close_menu();
+                                }
+                                if ui.button("Unfold all (Ctrl+Shift++)").clicked() {
+                                    frame.fold_all = Some(false);
+                                    ui.close_menu();
+                                }
+                                ui.separator();
+                            }
+                            let (start, end) = self.paragraph_range(index);
+                            ui.label(format!("Line {}", index + 1));
+                            annotation_menu(ui, index, index, &mut frame);
+                            if end > start {
+                                ui.separator();
+                                ui.label(format!("Block {}–{}", start + 1, end + 1));
+                                annotation_menu(ui, start, end, &mut frame);
+                            }
+                        });
+                    }
+                    if rect.y_range().contains(center_y) {
+                        frame.center_line = Some(index);
+                    }
+                }
+            })
+        };
+
+        if let Some(range) = frame.visible {
+            self.visible_lines = range;
+        }
+        if let Some(annotation) = frame.new_annotation.take() {
+            match annotation.kind {
+                AnnotationKind::Bookmark => {
+                    self.add_bookmark(annotation.first_line);
+                    self.show_bookmarks = true;
+                }
+                AnnotationKind::Note | AnnotationKind::Review => {
+                    self.show_annotations = true;
+                    self.annotations.push(annotation);
+                }
+                AnnotationKind::Highlight => self.annotations.push(annotation),
+            }
+        }
+        if let Some((line, column)) = frame.clicked {
+            self.select_at(line, column);
+            self.preview_target = Some(line);
+        }
+        if let Some((line, extend)) = frame.gutter_clicked {
+            self.select_lines(line, extend);
+        }
+        if let Some(line) = frame.bookmark_toggled {
+            self.toggle_bookmark(line);
+        }
+        if let Some(first) = frame.fold_toggled {
+            self.toggle_fold(first);
+        }
+        if let Some(fold) = frame.fold_all {
+            self.fold_all(fold);
+        }
+        self.drag_select(ui, &frame, char_width);
+        if let Some(format) = frame.copy_selection {
+            self.copy_selection(ui.ctx(), format);
+        }
+        if frame.explain_selection {
+            self.explain_selection();
+        }
+        if frame.edit_selection {
+            self.start_ai_edit();
+        }
+        if frame.document_selection && self.can_document_selection() {
+            self.document_selection();
+        }
+        if frame.suggest_tests {
+            self.suggest_tests();
+        }
+        if frame.ask_about_selection {
+            if let Some(range) = self.selection.clone() {
+                self.ask_about = Some(self.selection_lines(&range));
+                self.show_ai_panel = true;
+                self.focus_question = true;
+            }
+        }
+        if let Some(line) = frame.center_line {
+            if self.focus_mode && line != self.focus_line {
+                ui.ctx().request_repaint();
+            }
+            self.focus_line = line;
+        }
+
+        self.scroll_to_line = None;
+        match self.flash_line {
+            Some(_) if self.flash_fill(0, usize::MAX).is_some() => ui.ctx().request_repaint(),
+            Some(_) => self.flash_line = None,
+            None => {}
+        }
+
+        // The scroll area clamps the offset, so falling short means we hit the bottom.
+        self.scroll_offset = output.state.offset.y;
+        self.horizontal_offset = output.state.offset.x;
+        if target_offset.is_some_and(|target| self.scroll_offset + 0.5 < target) {
+            self.auto_scroll = false;
+        }
+        if frame.open_link.is_some() {
+            self.clicked_link = frame.open_link;
+        }
+    }
+}
+
+impl eframe::App for MyApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_ai_events();
+        self.collect_review_notes();
+        if self.ai_requests_running()
+            || self.translation.lock().unwrap().running
+            || self.review.lock().unwrap().running
+            || self.ai_edit.lock().unwrap().running
+            || self.test_suggestions.lock().unwrap().running
+            || self.comparison.lock().unwrap().iter().any(|a| a.running)
+        {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        self.poll_stream();
+        self.poll_edits(ctx);
+        self.poll_file_changes(ctx);
+        self.poll_highlighting();
+        if self.highlight_task.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+        self.poll_model_discovery();
+        if self.follow || self.log_dir.is_some() {
+            self.poll_follow();
+            ctx.request_repaint_after(Duration::from_millis(500));
+        }
+        self.handle_dropped_files(ctx);
+        self.handle_command_keys(ctx);
+        self.handle_zoom_gesture(ctx);
+        self.handle_vim_keys(ctx);
+        self.handle_selection_keys(ctx);
+        self.handle_auto_scroll_keys(ctx);
+        self.update_search();
+        self.show_top_panel(ctx);
+        if self.documents.len() > 1 {
+            self.show_tab_bar(ctx);
+        }
+        self.show_status_bar(ctx);
+        self.show_file_tree(ctx);
+        self.show_archive_panel(ctx);
+
+        if self.show_ai_panel {
+            self.show_ai_panel(ctx);
+        }
+
+        if self.show_jump_list {
+            self.show_jump_list(ctx);
+        }
+        if self.show_bookmarks {
+            self.show_bookmarks_panel(ctx);
+        }
+        if self.show_outline {
+            self.show_outline_panel(ctx);
+        }
+
+        if self.show_stats {
+            self.show_stats_window(ctx);
+        }
+        if self.show_text_stats {
+            self.show_text_stats_window(ctx);
+        }
+
+        if self.show_benchmark {
+            self.show_benchmark_window(ctx);
+        }
+        if self.show_comparison {
+            self.show_comparison_window(ctx);
+        }
+
+        if self.show_settings {
+            self.show_settings_window(ctx);
+        }
+
+        if self.show_redactions {
+            self.show_redactions_window(ctx);
+        }
+
+        if self.show_annotations {
+            self.show_annotations_window(ctx);
+        }
+
+        self.show_pii_dialog(ctx);
+        self.show_reload_prompt(ctx);
+        self.show_close_prompt(ctx);
+        self.show_goto_window(ctx);
+        self.show_convert_window(ctx);
+        self.show_ai_edit_window(ctx);
+        if self.show_test_suggestions {
+            self.show_test_suggestions_window(ctx);
+        }
+        self.show_recovery_window(ctx);
+        self.show_command_palette(ctx);
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(error) = &self.load_error {
+                let mut open = false;
+                let mut recent = None;
+                let mut restore = false;
+                ui.vertical_centered(|ui| {
+                    ui.add_space(ui.available_height() / 4.0);
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                    ui.add_space(8.0);
+                    open = ui.button("📂 Open File…").clicked();
+                    ui.weak("or drop a file onto this window");
+
+                    if let Some(session) = &self.last_session {
+                        ui.add_space(12.0);
+                        restore = ui
+                            .button(format!(
+                                "↺ Restore last session ({} files)",
+                                session.tabs.len()
+                            ))
+                            .clicked();
+                    }
+                    if !self.recent_files.is_empty() {
+                        ui.add_space(12.0);
+                        ui.strong("Recent files");
+                        for path in self.recent_files.iter().take(8) {
+                            if ui
+                                .link(display_name(Some(path)))
+                                .on_hover_text(path)
+                                .clicked()
+                            {
+                                recent = Some(path.clone());
+                            }
+                        }
+                    }
+                });
+                if open {
+                    self.show_open_dialog(ctx);
+                }
+                if let Some(path) = recent {
+                    self.open_document(path, false, ctx);
+                }
+                if restore {
+                    if let Some(session) = self.last_session.take() {
+                        self.restore_session(session, ctx);
+                    }
+                }
+            } else if self.diff.is_some() {
+                self.show_diff_view(ui);
+            } else if let Some(mut pane) = self.split_pane.take() {
+                let mut close = false;
+                ui.columns(2, |columns| {
+                    self.show_document(&mut columns[0]);
+                    close = self.show_split_pane(&mut columns[1], &mut pane);
+                });
+                if !close {
+                    self.split_pane = Some(pane);
+                }
+            } else {
+                self.show_document(ui);
+            }
+        });
+        // After drawing, as it may switch to another tab.
+        if let Some(target) = self.clicked_link.take() {
+            self.open_link(target, ctx);
+        }
+
+        self.show_zoom_overlay(ctx);
+        self.update_window_title(ctx);
+        self.save_config_if_changed();
+        self.save_session_if_changed();
+        self.autosave_if_due();
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Quitting with unsaved edits drops them; recovery is for crashes.
+        self.park_active_document();
+        for doc in &self.documents {
+            forget_recovery(doc);
+        }
+        self.network.shutdown(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::ai::backend::Network;
+use crate::ai::prompt::{
+    chat_preamble, context_budget, conversation, document_context, file_type_prompt, SUMMARY_PROMPT,
+};
+use crate::ai::redact::{load_redaction_opt_outs, scan_pii, Redactor};
+use crate::ai::retrieval::{build_index, DocumentIndex, Retrieval};
+use crate::ai::stats::UsageStats;
+use crate::ai::{Completion, Provider};
+use crate::config::{load_config, Config};
+use crate::files::{decode_file, display_name, read_text_file};
+use crate::highlight::{detect_syntax, load_syntax_overrides};
+use clap::Parser;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use syntect::parsing::SyntaxSet;
+
+/// Command-line options. Settings given here override `config.toml` for
+/// this session only.
+#[derive(Parser, Default)]
+#[command(
+    version,
+    about = "A document viewer with a local AI assistant",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub mode: Option<Mode>,
+    /// Files, folders or named pipes to open; `-` reads from stdin.
+    pub files: Vec<String>,
+    /// Compare two files side by side.
+    #[arg(long, num_args = 2, value_names = ["LEFT", "RIGHT"])]
+    pub diff: Option<Vec<String>>,
+    /// Open at this line of the first file.
+    #[arg(long, value_name = "NUMBER")]
+    pub line: Option<usize>,
+    /// Syntax highlighting language, by name or file extension.
+    #[arg(long)]
+    pub language: Option<String>,
+    /// Colour theme name.
+    #[arg(long)]
+    pub theme: Option<String>,
+    /// Content font size in points.
+    #[arg(long)]
+    pub font_size: Option<f32>,
+    /// Model to chat with.
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+    /// Ollama server address.
+    #[arg(long, value_name = "URL", global = true)]
+    pub ollama_url: Option<String>,
+    /// Keep the AI panel closed and send nothing to a model until asked.
+    #[arg(long)]
+    pub no_ai: bool,
+    /// Quit with q and leave the saved session alone, for use as $PAGER.
+    #[arg(long)]
+    pub pager: bool,
+}
+
+/// Modes that print to stdout instead of opening a window.
+#[derive(clap::Subcommand)]
+pub enum Mode {
+    /// Print a summary of a file, as the AI panel shows when it is opened.
+    Summarize {
+        /// The file to summarize; `-` reads from stdin.
+        file: String,
+        /// Print the summary, model and token counts as a JSON object.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Answer a question about a file, as the AI panel does.
+    Ask {
+        /// The file to ask about; `-` reads from stdin.
+        file: String,
+        question: String,
+        /// Send the passages closest to the question instead of the start of
+        /// the document, using the embedding model from the config file.
+        #[arg(long)]
+        relevant: bool,
+    },
+}
+
+/// Run a mode that prints to stdout instead of opening a window. An error,
+/// such as a backend that cannot be reached, makes `main` exit non-zero.
+pub fn run(cli: &Cli, mode: &Mode) -> Result<(), String> {
+    let config = headless_config(cli, load_config())?;
+    run_mode(&config, mode)
+}
+
+fn run_mode(config: &Config, mode: &Mode) -> Result<(), String> {
+    match mode {
+        Mode::Summarize { file, json } => summarize(config, file, *json),
+        Mode::Ask {
+            file,
+            question,
+            relevant,
+        } => headless_chat(config, file, question.clone(), *relevant, true).map(|_| ()),
+    }
+}
+
+/// `config` with the model and server given on the command line. `--model`
+/// picks the model of the configured provider; llama.cpp serves only the
+/// one it was started with.
+pub fn headless_config(cli: &Cli, mut config: Config) -> Result<Config, String> {
+    if let Some(model) = &cli.model {
+        match config.provider {
+            Provider::Ollama => config.model = model.clone(),
+            Provider::OpenAi => config.openai_model = model.clone(),
+            Provider::Anthropic => config.anthropic_model = model.clone(),
+            Provider::LlamaCpp => {
+                return Err(
+                    "--model cannot be used with llama.cpp, which serves the model it was \
+                     started with"
+                        .to_string(),
+                )
+            }
+        }
+    }
+    if let Some(url) = &cli.ollama_url {
+        config.ollama_url = url.clone();
+    }
+    Ok(config)
+}
+
+/// `file`, or stdin for `-`, as it may be sent to a model: redacted unless
+/// the user opted out for this file.
+pub fn headless_content(file: &str) -> Result<String, String> {
+    let text = if file == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("Could not read stdin: {}", e))?;
+        decode_file(bytes, None)
+            .map(|(text, _)| text)
+            .map_err(|_| "stdin is binary".to_string())?
+    } else {
+        read_text_file(file)?
+    };
+    if load_redaction_opt_outs().contains(file) {
+        return Ok(text);
+    }
+    let content = Redactor::new().redact(&text).0;
+    // There is nobody to confirm with, as the AI panel would.
+    for finding in scan_pii(&content) {
+        eprintln!(
+            "tty_doc: warning: sending {} found in the document ({})",
+            finding.kind.to_lowercase(),
+            finding.count
+        );
+    }
+    Ok(content)
+}
+
+/// The language the viewer highlights `file` as: the one picked for it in
+/// the viewer, else the one detected from its name and first line.
+fn headless_language(file: &str, content: &str) -> String {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let path = Some(file).filter(|file| *file != "-");
+    let picked = path
+        .and_then(|path| fs::canonicalize(path).ok())
+        .and_then(|path| load_syntax_overrides().remove(path.to_string_lossy().as_ref()))
+        .filter(|name| syntax_set.find_syntax_by_name(name).is_some());
+    picked.unwrap_or_else(|| detect_syntax(&syntax_set, path, content).name.clone())
+}
+
+/// Send `prompt` about `file` as the first turn of an AI panel conversation,
+/// with the chunks closest to it if `relevant`. Prints the answer as it
+/// streams in when `print` is set; returns the model used and the answer.
+pub fn headless_chat(
+    config: &Config,
+    file: &str,
+    prompt: String,
+    relevant: bool,
+    print: bool,
+) -> Result<(String, Completion), String> {
+    let content = headless_content(file)?;
+    let extension = Path::new(file)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let language = headless_language(file, &content);
+    let custom_prompt = file_type_prompt(&config.file_type_prompts, &extension, &language)
+        .map_or(config.system_prompt.as_str(), |(_, prompt)| prompt);
+    let preamble = chat_preamble(&display_name(Some(file)), custom_prompt, None);
+    let budget = context_budget(&config.generation, &[&preamble, &prompt]);
+    let (context, warning) = document_context(&content, budget);
+    if let Some(warning) = warning {
+        eprintln!("tty_doc: warning: {}", warning);
+    }
+    let mut system_prompt = format!("{}{}", preamble, context);
+    if relevant {
+        if config.embedding_model.trim().is_empty() {
+            return Err("--relevant needs embedding_model set in config.toml".to_string());
+        }
+        let index = Arc::new(Mutex::new(DocumentIndex {
+            source_len: content.len(),
+            model: config.embedding_model.clone(),
+            building: true,
+            ..DocumentIndex::default()
+        }));
+        let ollama_url = config.ollama_url.clone();
+        let model = config.embedding_model.clone();
+        build_index(content, ollama_url.clone(), model.clone(), index.clone());
+        if let Some(e) = index.lock().unwrap().error.take() {
+            return Err(format!("Could not index the document: {}", e));
+        }
+        let retrieval = Retrieval {
+            index,
+            ollama_url,
+            model,
+            preamble,
+        };
+        // Like the AI panel, fall back to the document start.
+        if let Some(context) = retrieval.context(&prompt) {
+            system_prompt = context;
+        }
+    }
+    let backend = config.backend(None);
+    let messages = conversation(&[], prompt);
+
+    let mut stdout = io::stdout();
+    let mut streamed = false;
+    let started = Instant::now();
+    let network = Network::new()?;
+    let request = backend.complete(
+        Some(&system_prompt),
+        messages,
+        &config.generation,
+        |token| {
+            if print {
+                streamed = true;
+                let _ = write!(stdout, "{}", token);
+                let _ = stdout.flush();
+            }
+        },
+    );
+    let completion = network
+        .block_on(request)
+        .ok_or("The network runtime has shut down")??;
+    UsageStats::load().record(&backend.model, &completion, started.elapsed());
+
+    if streamed {
+        println!();
+    } else if print {
+        println!("{}", completion.text);
+    }
+    Ok((backend.model, completion))
+}
+
+/// `tty_doc summarize`: the summary the AI panel starts with.
+pub fn summarize(config: &Config, file: &str, json: bool) -> Result<(), String> {
+    let (model, completion) =
+        headless_chat(config, file, SUMMARY_PROMPT.to_string(), false, !json)?;
+    if json {
+        let output = serde_json::json!({
+            "file": file,
+            "model": model,
+            "summary": completion.text,
+            "prompt_tokens": completion.prompt_tokens,
+            "completion_tokens": completion.completion_tokens,
+        });
+        println!("{}", output);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_flag_picks_the_configured_providers_model() {
+        let cli = Cli {
+            model: Some("m".to_string()),
+            ..Cli::default()
+        };
+        let with = |provider| {
+            headless_config(
+                &cli,
+                Config {
+                    provider,
+                    ..Config::default()
+                },
+            )
+        };
+        assert_eq!(with(Provider::Ollama).unwrap().model, "m");
+        let openai = with(Provider::OpenAi).unwrap();
+        assert_eq!(openai.openai_model, "m");
+        assert_eq!(openai.model, Config::default().model);
+        assert_eq!(with(Provider::Anthropic).unwrap().anthropic_model, "m");
+        assert!(with(Provider::LlamaCpp).is_err());
+        assert!(headless_config(&Cli::default(), Config::default()).is_ok());
+    }
+
+    #[test]
+    fn languages_are_detected_as_in_the_viewer() {
+        assert_eq!(headless_language("missing/notes.md", ""), "Markdown");
+        assert_eq!(headless_language("-", "#!/usr/bin/env python3\n"), "Python");
+    }
+
+    #[test]
+    fn backend_errors_fail_the_command() {
+        let file = std::env::temp_dir().join("tty_doc_cli_backend_error.txt");
+        std::fs::write(&file, "Hello\n").unwrap();
+        let config = Config {
+            ollama_url: "http://127.0.0.1:1".to_string(),
+            request_retries: 0,
+            ..Config::default()
+        };
+        let ask = Mode::Ask {
+            file: file.display().to_string(),
+            question: "Why?".to_string(),
+            relevant: false,
+        };
+        assert!(run_mode(&config, &ask).is_err());
+        let _ = std::fs::remove_file(file);
+    }
+}
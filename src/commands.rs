@@ -0,0 +1,449 @@
+use eframe::egui;
+use std::collections::BTreeMap;
+
+/// An action that can be bound to a key and run from the command palette.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    ShowPalette,
+    OpenFile,
+    OpenFolder,
+    Save,
+    CompareWith,
+    Find,
+    GoToLine,
+    JumpToBracket,
+    ConvertLineEndings,
+    ConvertIndentation,
+    NextTab,
+    PreviousTab,
+    ToggleBookmark,
+    BookmarkList,
+    NextBookmark,
+    PreviousBookmark,
+    FoldAll,
+    UnfoldAll,
+    ToggleAiPanel,
+    AskAi,
+    Summarize,
+    AnnotateFile,
+    AiEdit,
+    DocumentSelection,
+    SuggestTests,
+    CompareModels,
+    StopAnswer,
+    CopyLastAnswer,
+    ToggleLineNumbers,
+    ToggleMinimap,
+    ToggleOutline,
+    ToggleLogMode,
+    ShowStatistics,
+    ToggleBlame,
+    ToggleSplit,
+    ToggleFocusMode,
+    ToggleDarkMode,
+    ToggleVimKeys,
+    OpenSettings,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    ExportHtml,
+    ExportPdf,
+    ExportThemedPdf,
+    CopyAnsi,
+    CopyHtml,
+}
+
+impl Command {
+    pub const ALL: [Command; 47] = [
+        Command::ShowPalette,
+        Command::OpenFile,
+        Command::OpenFolder,
+        Command::Save,
+        Command::CompareWith,
+        Command::Find,
+        Command::GoToLine,
+        Command::JumpToBracket,
+        Command::ConvertLineEndings,
+        Command::ConvertIndentation,
+        Command::NextTab,
+        Command::PreviousTab,
+        Command::ToggleBookmark,
+        Command::BookmarkList,
+        Command::NextBookmark,
+        Command::PreviousBookmark,
+        Command::FoldAll,
+        Command::UnfoldAll,
+        Command::ToggleAiPanel,
+        Command::AskAi,
+        Command::Summarize,
+        Command::AnnotateFile,
+        Command::AiEdit,
+        Command::DocumentSelection,
+        Command::SuggestTests,
+        Command::CompareModels,
+        Command::StopAnswer,
+        Command::CopyLastAnswer,
+        Command::ToggleLineNumbers,
+        Command::ToggleMinimap,
+        Command::ToggleOutline,
+        Command::ToggleLogMode,
+        Command::ShowStatistics,
+        Command::ToggleBlame,
+        Command::ToggleSplit,
+        Command::ToggleFocusMode,
+        Command::ToggleDarkMode,
+        Command::ToggleVimKeys,
+        Command::OpenSettings,
+        Command::ZoomIn,
+        Command::ZoomOut,
+        Command::ResetZoom,
+        Command::ExportHtml,
+        Command::ExportPdf,
+        Command::ExportThemedPdf,
+        Command::CopyAnsi,
+        Command::CopyHtml,
+    ];
+
+    /// The name used for the command in the `keybindings` table of `config.toml`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Command::ShowPalette => "command_palette",
+            Command::OpenFile => "open_file",
+            Command::OpenFolder => "open_folder",
+            Command::Save => "save",
+            Command::CompareWith => "compare_with",
+            Command::Find => "find",
+            Command::GoToLine => "go_to_line",
+            Command::JumpToBracket => "jump_to_bracket",
+            Command::ConvertLineEndings => "convert_line_endings",
+            Command::ConvertIndentation => "convert_indentation",
+            Command::NextTab => "next_tab",
+            Command::PreviousTab => "previous_tab",
+            Command::ToggleBookmark => "toggle_bookmark",
+            Command::BookmarkList => "bookmark_list",
+            Command::NextBookmark => "next_bookmark",
+            Command::PreviousBookmark => "previous_bookmark",
+            Command::FoldAll => "fold_all",
+            Command::UnfoldAll => "unfold_all",
+            Command::ToggleAiPanel => "toggle_ai_panel",
+            Command::AskAi => "ask_ai",
+            Command::Summarize => "summarize",
+            Command::AnnotateFile => "annotate_file",
+            Command::AiEdit => "ai_edit",
+            Command::DocumentSelection => "document_selection",
+            Command::SuggestTests => "suggest_tests",
+            Command::CompareModels => "compare_models",
+            Command::StopAnswer => "stop_answer",
+            Command::CopyLastAnswer => "copy_last_answer",
+            Command::ToggleLineNumbers => "toggle_line_numbers",
+            Command::ToggleMinimap => "toggle_minimap",
+            Command::ToggleOutline => "toggle_outline",
+            Command::ToggleLogMode => "toggle_log_mode",
+            Command::ShowStatistics => "document_statistics",
+            Command::ToggleBlame => "toggle_blame",
+            Command::ToggleSplit => "toggle_split",
+            Command::ToggleFocusMode => "toggle_focus_mode",
+            Command::ToggleDarkMode => "toggle_dark_mode",
+            Command::ToggleVimKeys => "toggle_vim_keys",
+            Command::OpenSettings => "open_settings",
+            Command::ZoomIn => "zoom_in",
+            Command::ZoomOut => "zoom_out",
+            Command::ResetZoom => "reset_zoom",
+            Command::ExportHtml => "export_html",
+            Command::ExportPdf => "export_pdf",
+            Command::ExportThemedPdf => "export_themed_pdf",
+            Command::CopyAnsi => "copy_ansi",
+            Command::CopyHtml => "copy_html",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Command::ShowPalette => "Show command palette",
+            Command::OpenFile => "Open file…",
+            Command::OpenFolder => "Open folder…",
+            Command::Save => "Save",
+            Command::CompareWith => "Compare with…",
+            Command::Find => "Find in document",
+            Command::GoToLine => "Go to line…",
+            Command::JumpToBracket => "Go to matching bracket",
+            Command::ConvertLineEndings => "Convert line endings…",
+            Command::ConvertIndentation => "Convert indentation (tabs/spaces)…",
+            Command::NextTab => "Next tab",
+            Command::PreviousTab => "Previous tab",
+            Command::ToggleBookmark => "Toggle bookmark",
+            Command::BookmarkList => "Show bookmarks",
+            Command::NextBookmark => "Next bookmark",
+            Command::PreviousBookmark => "Previous bookmark",
+            Command::FoldAll => "Fold all",
+            Command::UnfoldAll => "Unfold all",
+            Command::ToggleAiPanel => "Toggle AI panel",
+            Command::AskAi => "Ask AI about this document",
+            Command::Summarize => "Summarize document",
+            Command::AnnotateFile => "Annotate file with AI review comments",
+            Command::AiEdit => "AI edit selection…",
+            Command::DocumentSelection => "Write doc comments for selection",
+            Command::SuggestTests => "Suggest unit tests",
+            Command::CompareModels => "Compare two models side by side",
+            Command::StopAnswer => "Stop the answer being written",
+            Command::CopyLastAnswer => "Copy the last answer",
+            Command::ToggleLineNumbers => "Toggle line numbers",
+            Command::ToggleMinimap => "Toggle minimap",
+            Command::ToggleOutline => "Toggle outline",
+            Command::ToggleLogMode => "Toggle log mode",
+            Command::ShowStatistics => "Show document statistics",
+            Command::ToggleBlame => "Toggle git blame",
+            Command::ToggleSplit => "Toggle split view",
+            Command::ToggleFocusMode => "Toggle focus mode",
+            Command::ToggleDarkMode => "Toggle dark mode",
+            Command::ToggleVimKeys => "Toggle vim-style navigation",
+            Command::OpenSettings => "Open settings",
+            Command::ZoomIn => "Zoom in",
+            Command::ZoomOut => "Zoom out",
+            Command::ResetZoom => "Reset zoom",
+            Command::ExportHtml => "Export as HTML…",
+            Command::ExportPdf => "Export as PDF (print-friendly)…",
+            Command::ExportThemedPdf => "Export as PDF with the current theme…",
+            Command::CopyAnsi => "Copy selection with ANSI colours",
+            Command::CopyHtml => "Copy selection as rich text (HTML)",
+        }
+    }
+
+    pub fn default_shortcut(self) -> Option<egui::KeyboardShortcut> {
+        use egui::{Key, KeyboardShortcut, Modifiers};
+        let command = Modifiers::COMMAND;
+        let command_shift = Modifiers::COMMAND | Modifiers::SHIFT;
+        let (modifiers, key) = match self {
+            Command::ShowPalette => (command_shift, Key::P),
+            Command::OpenFile => (command, Key::O),
+            Command::Save => (command, Key::S),
+            Command::Find => (command, Key::F),
+            Command::GoToLine => (command, Key::G),
+            Command::JumpToBracket => (command, Key::M),
+            Command::NextTab => (Modifiers::CTRL, Key::Tab),
+            Command::PreviousTab => (Modifiers::CTRL | Modifiers::SHIFT, Key::Tab),
+            Command::ToggleBookmark => (command, Key::B),
+            Command::BookmarkList => (command_shift, Key::B),
+            Command::ToggleOutline => (command_shift, Key::O),
+            Command::AskAi => (command, Key::L),
+            Command::StopAnswer => (Modifiers::NONE, Key::Escape),
+            Command::CopyLastAnswer => (command_shift, Key::C),
+            Command::NextBookmark => (Modifiers::NONE, Key::F2),
+            Command::PreviousBookmark => (Modifiers::SHIFT, Key::F2),
+            Command::FoldAll => (command_shift, Key::Minus),
+            Command::UnfoldAll => (command_shift, Key::PlusEquals),
+            Command::ZoomIn => (command, Key::PlusEquals),
+            Command::ZoomOut => (command, Key::Minus),
+            Command::ResetZoom => (command, Key::Num0),
+            _ => return None,
+        };
+        Some(KeyboardShortcut::new(modifiers, key))
+    }
+
+    /// Commands that act on the text view, whose keys are left alone while
+    /// typing in a text field or editing the document.
+    pub fn acts_on_view(self) -> bool {
+        matches!(
+            self,
+            Command::JumpToBracket
+                | Command::ToggleBookmark
+                | Command::BookmarkList
+                | Command::NextBookmark
+                | Command::PreviousBookmark
+        )
+    }
+}
+
+/// Keys that can be named in a binding, besides modifiers.
+pub const BINDABLE_KEYS: [egui::Key; 60] = {
+    use egui::Key::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Num0, Num1,
+        Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10,
+        F11, F12, ArrowUp, ArrowDown, ArrowLeft, ArrowRight, Escape, Tab, Enter, Space, Home, End,
+        Minus, PlusEquals,
+    ]
+};
+
+/// Parse a binding such as `Ctrl+Shift+P` or `F2`. `Ctrl` and `Cmd` both
+/// mean Cmd on macOS and Ctrl elsewhere.
+pub fn parse_shortcut(text: &str) -> Option<egui::KeyboardShortcut> {
+    let mut modifiers = egui::Modifiers::NONE;
+    let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+    // "Ctrl++" names the plus key.
+    if text.trim_end().ends_with("++") {
+        parts.truncate(parts.len() - 2);
+        parts.push("Plus");
+    }
+    let (key, modifier_names) = parts.split_last()?;
+    for name in modifier_names {
+        match name.to_lowercase().as_str() {
+            "ctrl" | "control" | "cmd" | "command" => {
+                modifiers = modifiers | egui::Modifiers::COMMAND
+            }
+            "shift" => modifiers = modifiers | egui::Modifiers::SHIFT,
+            "alt" | "option" => modifiers = modifiers | egui::Modifiers::ALT,
+            _ => return None,
+        }
+    }
+    let key = BINDABLE_KEYS.into_iter().find(|k| {
+        k.name().eq_ignore_ascii_case(key)
+                || k.symbol_or_name() == *key
+                // egui shows Minus as '−', which is not what people type.
+                || (*k == egui::Key::Minus && *key == "-")
+    })?;
+    Some(egui::KeyboardShortcut::new(modifiers, key))
+}
+
+/// Every command's shortcut: its default unless `overrides` rebinds it. An
+/// empty or unparsable override leaves the command unbound.
+pub fn keymap(overrides: &BTreeMap<String, String>) -> Vec<(Command, egui::KeyboardShortcut)> {
+    Command::ALL
+        .into_iter()
+        .filter_map(|command| {
+            let shortcut = match overrides.get(command.id()) {
+                Some(text) => parse_shortcut(text),
+                None => command.default_shortcut(),
+            }?;
+            Some((command, shortcut))
+        })
+        .collect()
+}
+
+/// How well `query` matches `text` as a case-insensitive subsequence; `None`
+/// when it does not. Consecutive letters and word starts score higher.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for wanted in query.chars().filter(|c| !c.is_whitespace()) {
+        let index = next
+            + text[next..]
+                .iter()
+                .position(|c| c.to_lowercase().eq(wanted.to_lowercase()))?;
+        score += match last {
+            Some(last) if last + 1 == index => 8,
+            _ if index == 0 || !text[index - 1].is_alphanumeric() => 6,
+            _ => 1,
+        };
+        last = Some(index);
+        next = index + 1;
+    }
+    // Prefer shorter titles among equal matches.
+    Some(score * 100 - text.len() as i32)
+}
+
+/// The Ctrl+Shift+P popup.
+#[derive(Default)]
+pub struct CommandPalette {
+    pub query: String,
+    pub selected: usize,
+}
+
+/// Something the command palette can do.
+pub enum PaletteItem {
+    Run(Command),
+    Theme(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{Key, KeyboardShortcut, Modifiers};
+
+    #[test]
+    fn shortcuts_are_parsed_from_config_names() {
+        assert_eq!(
+            parse_shortcut("Ctrl+Shift+P"),
+            Some(KeyboardShortcut::new(
+                Modifiers::COMMAND | Modifiers::SHIFT,
+                Key::P
+            ))
+        );
+        assert_eq!(
+            parse_shortcut("cmd + alt + f2"),
+            Some(KeyboardShortcut::new(
+                Modifiers::COMMAND | Modifiers::ALT,
+                Key::F2
+            ))
+        );
+        assert_eq!(
+            parse_shortcut("Escape"),
+            Some(KeyboardShortcut::new(Modifiers::NONE, Key::Escape))
+        );
+        assert_eq!(
+            parse_shortcut("Ctrl++"),
+            Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::PlusEquals))
+        );
+        assert_eq!(
+            parse_shortcut("Ctrl+-"),
+            Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::Minus))
+        );
+    }
+
+    #[test]
+    fn bad_shortcuts_are_rejected() {
+        for text in [
+            "",
+            "Ctrl+",
+            "Hyper+P",
+            "Ctrl+Shift",
+            "Ctrl+PageUp",
+            "P+Ctrl",
+        ] {
+            assert_eq!(parse_shortcut(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn config_bindings_replace_the_defaults() {
+        let overrides = BTreeMap::from([
+            ("find".to_string(), "Ctrl+K".to_string()),
+            ("save".to_string(), String::new()),
+            ("toggle_minimap".to_string(), "F9".to_string()),
+        ]);
+        let keymap = keymap(&overrides);
+        let bound = |command| {
+            keymap
+                .iter()
+                .find(|(c, _)| *c == command)
+                .map(|(_, shortcut)| *shortcut)
+        };
+        assert_eq!(
+            bound(Command::Find),
+            Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::K))
+        );
+        assert_eq!(bound(Command::Save), None);
+        assert_eq!(
+            bound(Command::ToggleMinimap),
+            Some(KeyboardShortcut::new(Modifiers::NONE, Key::F9))
+        );
+        assert_eq!(
+            bound(Command::OpenFile),
+            Command::OpenFile.default_shortcut()
+        );
+    }
+
+    #[test]
+    fn fuzzy_matches_rank_word_starts_and_runs_first() {
+        assert_eq!(fuzzy_score("xyz", "Toggle minimap"), None);
+        assert_eq!(fuzzy_score("pamin", "Toggle minimap"), None);
+        assert!(fuzzy_score("", "Save").is_some());
+
+        let mut titles = ["Toggle line numbers", "Go to line…", "Save"];
+        titles.sort_by_key(|title| std::cmp::Reverse(fuzzy_score("tl", title)));
+        // Both letters start words in "Go to line", only one in "Toggle line".
+        assert_eq!(titles, ["Go to line…", "Toggle line numbers", "Save"]);
+        // Equal matches put the shorter title first.
+        assert!(fuzzy_score("zoom", "Zoom in") > fuzzy_score("zoom", "Zoom out"));
+
+        // A run of letters beats the same letters scattered.
+        assert!(fuzzy_score("save", "Save") > fuzzy_score("save", "Show a view e"));
+        // Case does not matter.
+        assert_eq!(
+            fuzzy_score("FIND", "Find in document"),
+            fuzzy_score("find", "Find in document")
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use crate::ai::backend::{
+    api_key_from_env, AnthropicBackend, BackendConfig, LlamaCppBackend, OllamaBackend,
+    OpenAiBackend, RequestPolicy, DEFAULT_LLAMA_CPP_URL, DEFAULT_OLLAMA_URL, DEFAULT_OPENAI_URL,
+};
+use crate::ai::{GenerationParams, Provider};
+use crate::highlight::{HighlightBackend, DEFAULT_THEME};
+use crate::viewer::fonts::FontConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Content font size at 100% zoom, and the range zooming allows.
+pub const DEFAULT_FONT_SIZE: f32 = 14.0;
+pub const MIN_FONT_SIZE: f32 = 8.0;
+pub const MAX_FONT_SIZE: f32 = 32.0;
+
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("tty_doc"))
+}
+
+/// FNV-1a hash of `key`, for naming data files after a document. Unlike
+/// `DefaultHasher`, it stays the same between builds.
+pub fn stable_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tty_doc"))
+}
+
+/// Preferences kept in `config.toml`. Keys missing from the file take their defaults.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub font_size: f32,
+    pub theme: String,
+    pub dark_mode: bool,
+    pub provider: Provider,
+    pub model: String,
+    pub ollama_url: String,
+    /// A llama.cpp server, or another that speaks its `/completion` API.
+    pub llama_cpp_url: String,
+    pub openai_url: String,
+    pub openai_model: String,
+    pub openai_key_env: String,
+    pub anthropic_model: String,
+    pub anthropic_key_env: String,
+    pub embedding_model: String,
+    /// Longest a completion may take, streaming included.
+    pub request_timeout_secs: u64,
+    /// Times an unreachable server is tried again before a request fails.
+    pub request_retries: u32,
+    pub show_ai_panel: bool,
+    pub ai_panel_width: f32,
+    /// Reflow prose documents to `wrap_width` columns.
+    pub word_wrap: bool,
+    pub wrap_width: usize,
+    pub hex_bytes_per_row: usize,
+    pub show_minimap: bool,
+    pub show_outline: bool,
+    /// Colour brackets by nesting depth in code views.
+    pub rainbow_brackets: bool,
+    /// Columns per tab when converting indentation.
+    pub tab_width: usize,
+    /// Navigate with vim keys: j/k/h/l, gg/G, Ctrl+D/U, /, n/N, `:` and `%`.
+    pub vim_keys: bool,
+    /// Reload documents changed on disk, unless they have unsaved edits.
+    pub auto_reload: bool,
+    /// Write unsaved edits to the recovery directory every
+    /// `autosave_secs`, to offer them back after a crash.
+    pub autosave: bool,
+    pub autosave_secs: u64,
+    /// Ask for a summary as soon as a document opens.
+    pub summarize_on_open: bool,
+    pub summarize_on_reload: bool,
+    /// Replaces the built-in instructions in chat requests when not empty.
+    pub system_prompt: String,
+    // Tables from here on: TOML needs them after every plain value.
+    /// Shortcuts by command id, e.g. `command_palette = "Ctrl+Shift+P"`,
+    /// replacing the defaults; an empty string unbinds the command.
+    pub keybindings: BTreeMap<String, String>,
+    /// Highlighting engine by syntax name, e.g. `Rust = "tree-sitter"`;
+    /// syntect for the rest.
+    pub highlighters: BTreeMap<String, HighlightBackend>,
+    /// System prompts by file extension or language name, e.g.
+    /// `rs = "You are a strict code reviewer."`; they take the place of
+    /// `system_prompt` for matching documents.
+    pub file_type_prompts: BTreeMap<String, String>,
+    /// Font files; read at startup only.
+    pub fonts: FontConfig,
+    pub generation: GenerationParams,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            font_size: DEFAULT_FONT_SIZE,
+            theme: DEFAULT_THEME.to_string(),
+            dark_mode: true,
+            provider: Provider::Ollama,
+            model: "llama2".to_string(),
+            ollama_url: DEFAULT_OLLAMA_URL.to_string(),
+            llama_cpp_url: DEFAULT_LLAMA_CPP_URL.to_string(),
+            openai_url: DEFAULT_OPENAI_URL.to_string(),
+            openai_model: "gpt-4o-mini".to_string(),
+            openai_key_env: "OPENAI_API_KEY".to_string(),
+            anthropic_model: "claude-3-5-sonnet-latest".to_string(),
+            anthropic_key_env: "ANTHROPIC_API_KEY".to_string(),
+            embedding_model: "nomic-embed-text".to_string(),
+            request_timeout_secs: 300,
+            request_retries: 3,
+            show_ai_panel: true,
+            ai_panel_width: 380.0,
+            word_wrap: true,
+            wrap_width: 88,
+            hex_bytes_per_row: 16,
+            show_minimap: false,
+            show_outline: false,
+            rainbow_brackets: false,
+            tab_width: 4,
+            vim_keys: false,
+            auto_reload: true,
+            autosave: true,
+            autosave_secs: 30,
+            summarize_on_open: false,
+            summarize_on_reload: false,
+            system_prompt: String::new(),
+            keybindings: BTreeMap::new(),
+            highlighters: BTreeMap::new(),
+            file_type_prompts: BTreeMap::new(),
+            fonts: FontConfig::default(),
+            generation: GenerationParams::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The selected provider; `grammar` constrains llama.cpp output.
+    pub fn backend(&self, grammar: Option<String>) -> BackendConfig {
+        let policy = RequestPolicy {
+            timeout: Duration::from_secs(self.request_timeout_secs.max(1)),
+            retries: self.request_retries,
+        };
+        match self.provider {
+            Provider::Ollama => BackendConfig {
+                backend: Arc::new(OllamaBackend {
+                    base_url: self.ollama_url.clone(),
+                    model: self.model.clone(),
+                }),
+                model: self.model.clone(),
+                policy,
+            },
+            // llama.cpp serves a single model, so its stats are grouped under the backend name.
+            Provider::LlamaCpp => BackendConfig {
+                backend: Arc::new(LlamaCppBackend {
+                    base_url: self.llama_cpp_url.clone(),
+                    grammar,
+                }),
+                model: "llama.cpp".to_string(),
+                policy,
+            },
+            Provider::OpenAi => BackendConfig {
+                backend: Arc::new(OpenAiBackend {
+                    base_url: self.openai_url.clone(),
+                    api_key: api_key_from_env(&self.openai_key_env),
+                    model: self.openai_model.clone(),
+                }),
+                model: self.openai_model.clone(),
+                policy,
+            },
+            Provider::Anthropic => BackendConfig {
+                backend: Arc::new(AnthropicBackend {
+                    api_key: api_key_from_env(&self.anthropic_key_env),
+                    model: self.anthropic_model.clone(),
+                }),
+                model: self.anthropic_model.clone(),
+                policy,
+            },
+        }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+pub fn load_config() -> Config {
+    config_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| toml::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_config(config: &Config) {
+    let Some(path) = config_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(text) = toml::to_string_pretty(config) {
+        let _ = fs::write(path, text);
+    }
+}
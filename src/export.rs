@@ -0,0 +1,230 @@
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Style, Theme};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// `#rrggbb` for a theme colour.
+pub fn css_color(color: syntect::highlighting::Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// `text` highlighted with `theme` as a standalone HTML page with inline
+/// styles, optionally with line numbers.
+pub fn highlighted_html(
+    text: &str,
+    title: &str,
+    syntax_set: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    line_numbers: bool,
+) -> String {
+    let background = theme
+        .settings
+        .background
+        .map_or("#ffffff".to_string(), css_color);
+    let foreground = theme
+        .settings
+        .foreground
+        .map_or("#000000".to_string(), css_color);
+    let gutter = theme
+        .settings
+        .gutter_foreground
+        .map_or("#888888".to_string(), css_color);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>\n\
+         body {{ margin: 0; background: {bg}; color: {fg}; }}\n\
+         pre {{ margin: 0; padding: 1em; font: 13px/1.4 ui-monospace, Menlo, Consolas, monospace; }}\n\
+         .ln {{ color: {gutter}; user-select: none; }}\n\
+         </style>\n</head>\n<body>\n<pre>",
+        escape_html(title),
+        bg = background,
+        fg = foreground,
+        gutter = gutter,
+    );
+    let width = text.lines().count().max(1).to_string().len();
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    for (i, line) in LinesWithEndings::from(text).enumerate() {
+        if line_numbers {
+            html.push_str(&format!("<span class=\"ln\">{:>width$}  </span>", i + 1));
+        }
+        let ranges = highlighter
+            .highlight_line(line, syntax_set)
+            .unwrap_or_else(|_| vec![(Style::default(), line)]);
+        match syntect::html::styled_line_to_highlighted_html(
+            &ranges,
+            syntect::html::IncludeBackground::No,
+        ) {
+            Ok(line) => html.push_str(&line),
+            Err(_) => html.push_str(&escape_html(line)),
+        }
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+/// Page layout for PDF export, in millimetres and points. A4 portrait with
+/// Courier, whose glyphs are 0.6 em wide.
+pub const PDF_PAGE: (f32, f32) = (210.0, 297.0);
+pub const PDF_MARGIN: f32 = 15.0;
+pub const PDF_FONT_SIZE: f32 = 9.0;
+pub const PT_TO_MM: f32 = 25.4 / 72.0;
+
+/// One printed row: the start of a source line, which is numbered, or the
+/// continuation of a wrapped one.
+#[derive(Default)]
+pub struct PrintedRow {
+    pub number: Option<usize>,
+    pub runs: Vec<(Style, String)>,
+}
+
+/// `text` highlighted with `theme` and laid out as a PDF, with the title in
+/// each page's header and page numbers in the footer. Long lines wrap.
+/// The built-in font only covers Windows-1252; other characters print as `?`.
+pub fn highlighted_pdf(
+    text: &str,
+    title: &str,
+    syntax_set: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    line_numbers: bool,
+) -> Result<Vec<u8>, String> {
+    use printpdf::path::PaintMode;
+    use printpdf::{BuiltinFont, Color, Mm, PdfDocument, Rect, Rgb};
+
+    let (page_width, page_height) = PDF_PAGE;
+    let char_width = PDF_FONT_SIZE * 0.6 * PT_TO_MM;
+    let line_height = PDF_FONT_SIZE * 1.3 * PT_TO_MM;
+    let columns = ((page_width - 2.0 * PDF_MARGIN) / char_width) as usize;
+    // Room for a header and footer line inside the margins.
+    let rows = ((page_height - 2.0 * PDF_MARGIN) / line_height) as usize - 3;
+
+    let printable = |c: char| {
+        let mut buffer = [0; 4];
+        let (_, _, unmappable) = encoding_rs::WINDOWS_1252.encode(c.encode_utf8(&mut buffer));
+        if unmappable || c.is_control() {
+            '?'
+        } else {
+            c
+        }
+    };
+    let number_width = text.lines().count().max(1).to_string().len();
+    let gutter = if line_numbers { number_width + 2 } else { 0 };
+    let wrap_at = columns.saturating_sub(gutter).max(1);
+
+    // Break the highlighted text into printed rows of styled runs.
+    let mut printed: Vec<PrintedRow> = Vec::new();
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    for (i, line) in LinesWithEndings::from(text).enumerate() {
+        let ranges = highlighter
+            .highlight_line(line, syntax_set)
+            .unwrap_or_else(|_| vec![(Style::default(), line)]);
+        let mut row = PrintedRow {
+            number: Some(i + 1),
+            runs: Vec::new(),
+        };
+        let mut width = 0;
+        for (style, piece) in ranges {
+            let mut run = String::new();
+            for c in piece.trim_end_matches(['\n', '\r']).chars() {
+                let expanded = if c == '\t' {
+                    "    ".to_string()
+                } else {
+                    printable(c).to_string()
+                };
+                for c in expanded.chars() {
+                    if width == wrap_at {
+                        row.runs.push((style, std::mem::take(&mut run)));
+                        printed.push(std::mem::take(&mut row));
+                        width = 0;
+                    }
+                    run.push(c);
+                    width += 1;
+                }
+            }
+            row.runs.push((style, run));
+        }
+        printed.push(row);
+    }
+
+    let pages = printed.len().div_ceil(rows).max(1);
+    let (doc, first_page, first_layer) =
+        PdfDocument::new(title, Mm(page_width), Mm(page_height), "Text");
+    let font = doc
+        .add_builtin_font(BuiltinFont::Courier)
+        .map_err(|e| e.to_string())?;
+    let rgb = |c: syntect::highlighting::Color| {
+        Color::Rgb(Rgb::new(
+            f32::from(c.r) / 255.0,
+            f32::from(c.g) / 255.0,
+            f32::from(c.b) / 255.0,
+            None,
+        ))
+    };
+    let foreground = theme
+        .settings
+        .foreground
+        .map_or(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)), rgb);
+    let dimmed = theme
+        .settings
+        .gutter_foreground
+        .map_or(Color::Rgb(Rgb::new(0.5, 0.5, 0.5, None)), rgb);
+
+    for page in 0..pages {
+        let layer = if page == 0 {
+            doc.get_page(first_page).get_layer(first_layer)
+        } else {
+            let (page, layer) = doc.add_page(Mm(page_width), Mm(page_height), "Text");
+            doc.get_page(page).get_layer(layer)
+        };
+        if let Some(background) = theme.settings.background {
+            layer.set_fill_color(rgb(background));
+            layer.add_rect(
+                Rect::new(Mm(0.0), Mm(0.0), Mm(page_width), Mm(page_height))
+                    .with_mode(PaintMode::Fill),
+            );
+        }
+
+        layer.begin_text_section();
+        layer.set_font(&font, PDF_FONT_SIZE);
+        layer.set_fill_color(dimmed.clone());
+        let top = page_height - PDF_MARGIN - line_height;
+        layer.set_text_cursor(Mm(PDF_MARGIN), Mm(top));
+        layer.write_text(title.chars().map(printable).collect::<String>(), &font);
+        let footer = format!("Page {} of {}", page + 1, pages);
+        layer.set_text_cursor(
+            Mm(page_width - PDF_MARGIN - footer.len() as f32 * char_width),
+            Mm(PDF_MARGIN),
+        );
+        layer.write_text(footer, &font);
+        layer.end_text_section();
+
+        for (i, row) in printed.iter().skip(page * rows).take(rows).enumerate() {
+            let y = top - (i + 2) as f32 * line_height;
+            layer.begin_text_section();
+            layer.set_font(&font, PDF_FONT_SIZE);
+            layer.set_text_cursor(Mm(PDF_MARGIN), Mm(y));
+            if line_numbers {
+                layer.set_fill_color(dimmed.clone());
+                let label = row.number.map_or(String::new(), |n| n.to_string());
+                layer.write_text(format!("{:>width$}  ", label, width = number_width), &font);
+            }
+            for (style, run) in row.runs.iter().filter(|(_, run)| !run.is_empty()) {
+                layer.set_fill_color(match style.foreground.a {
+                    0 => foreground.clone(),
+                    _ => rgb(style.foreground),
+                });
+                layer.write_text(run.as_str(), &font);
+            }
+            layer.end_text_section();
+        }
+    }
+    doc.save_to_bytes().map_err(|e| e.to_string())
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,316 @@
+use eframe::egui;
+use encoding_rs::Encoding;
+use notify::Watcher;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Lines read on a background thread from a stream (stdin, a pipe) that the UI
+/// drains each frame.
+#[derive(Default)]
+pub struct StreamBuffer {
+    pub pending: Vec<String>,
+    pub finished: bool,
+}
+
+/// Push lines from `reader` into `buffer` until EOF or a read error.
+pub fn read_lines_into(reader: impl Read, buffer: &Mutex<StreamBuffer>, ctx: &egui::Context) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let text = String::from_utf8_lossy(&line);
+                let text = text.trim_end_matches(['\n', '\r']).to_string();
+                buffer.lock().unwrap().pending.push(text);
+                ctx.request_repaint();
+            }
+        }
+    }
+}
+
+pub fn spawn_stream_reader(
+    reader: impl Read + Send + 'static,
+    ctx: egui::Context,
+) -> Arc<Mutex<StreamBuffer>> {
+    let buffer = Arc::new(Mutex::new(StreamBuffer::default()));
+    let shared = buffer.clone();
+
+    thread::spawn(move || {
+        read_lines_into(reader, &shared, &ctx);
+        shared.lock().unwrap().finished = true;
+        ctx.request_repaint();
+    });
+
+    buffer
+}
+
+/// Read a named pipe forever. A writer closing the pipe is not the end of the
+/// document: reopening blocks until the next writer connects.
+pub fn spawn_fifo_reader(path: PathBuf, ctx: egui::Context) -> Arc<Mutex<StreamBuffer>> {
+    let buffer = Arc::new(Mutex::new(StreamBuffer::default()));
+    let shared = buffer.clone();
+
+    thread::spawn(move || {
+        while let Ok(file) = fs::File::open(&path) {
+            read_lines_into(file, &shared, &ctx);
+        }
+        shared.lock().unwrap().finished = true;
+        ctx.request_repaint();
+    });
+
+    buffer
+}
+
+#[cfg(unix)]
+pub fn is_fifo(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &str) -> bool {
+    false
+}
+
+/// The most recently modified regular, non-hidden file in `dir`.
+pub fn newest_file(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+            Some((metadata.modified().ok()?, entry.path()))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+/// Encodings offered when overriding detection.
+pub const ENCODINGS: [&Encoding; 14] = [
+    encoding_rs::UTF_8,
+    encoding_rs::UTF_16LE,
+    encoding_rs::UTF_16BE,
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::GBK,
+    encoding_rs::GB18030,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+];
+
+/// Guess how a file is encoded: by byte order mark, as BOM-less UTF-16, as
+/// UTF-8, or failing that by chardetng's guess. `None` means binary data.
+pub fn detect_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+    let head = &bytes[..bytes.len().min(8000)];
+    if let Some(encoding) = bomless_utf16(head) {
+        return Some(encoding);
+    }
+    if head.contains(&0) {
+        return None;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return Some(encoding_rs::UTF_8);
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(&bytes[..bytes.len().min(64 * 1024)], true);
+    Some(detector.guess(None, true))
+}
+
+/// Mostly-ASCII UTF-16 has a zero in every other byte: the odd ones for
+/// little endian, the even ones for big endian.
+pub fn bomless_utf16(head: &[u8]) -> Option<&'static Encoding> {
+    let pairs = head.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let zeros = |parity: usize| {
+        head.chunks_exact(2)
+            .filter(|pair| pair[parity] == 0)
+            .count()
+    };
+    let (even, odd) = (zeros(0), zeros(1));
+    if odd * 10 >= pairs * 4 && even * 10 < pairs {
+        Some(encoding_rs::UTF_16LE)
+    } else if even * 10 >= pairs * 4 && odd * 10 < pairs {
+        Some(encoding_rs::UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Decode a file as text, in `forced` if given, else in the detected encoding.
+/// Binary data is handed back unchanged.
+pub fn decode_file(
+    bytes: Vec<u8>,
+    forced: Option<&'static Encoding>,
+) -> Result<(String, &'static Encoding), Vec<u8>> {
+    let Some(encoding) = forced.or_else(|| detect_encoding(&bytes)) else {
+        return Err(bytes);
+    };
+    if encoding == encoding_rs::UTF_8 {
+        return Ok(match String::from_utf8(bytes) {
+            Ok(text) => (text, encoding),
+            Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), encoding),
+        });
+    }
+    let (text, used, _) = encoding.decode(&bytes);
+    Ok((text.into_owned(), used))
+}
+
+/// Encode `text` for saving. encoding_rs only decodes UTF-16, so that is done
+/// here, with a byte order mark.
+pub fn encode_text(text: &str, encoding: &'static Encoding) -> Result<Vec<u8>, String> {
+    let utf16 = |to_bytes: fn(u16) -> [u8; 2]| {
+        std::iter::once(0xfeff)
+            .chain(text.encode_utf16())
+            .flat_map(to_bytes)
+            .collect()
+    };
+    if encoding == encoding_rs::UTF_16LE {
+        return Ok(utf16(u16::to_le_bytes));
+    }
+    if encoding == encoding_rs::UTF_16BE {
+        return Ok(utf16(u16::to_be_bytes));
+    }
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(format!(
+            "The text has characters that {} cannot represent",
+            encoding.name()
+        ));
+    }
+    Ok(bytes.into_owned())
+}
+
+/// Flags changes to one file. The watch is on its directory, since many
+/// editors save by writing a new file and renaming it over the old one.
+pub struct FileWatcher {
+    /// The path as opened, to notice when the document changes.
+    pub file: String,
+    pub changed: Arc<Mutex<bool>>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(file: &str, ctx: egui::Context) -> Result<Self, String> {
+        let target = fs::canonicalize(file).map_err(|e| e.to_string())?;
+        let changed = Arc::new(Mutex::new(false));
+        let flag = changed.clone();
+        let dir = target.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if !event.kind.is_access() && event.paths.contains(&target) {
+                    *flag.lock().unwrap() = true;
+                    ctx.request_repaint();
+                }
+            })
+            .map_err(|e| e.to_string())?;
+        watcher
+            .watch(&dir, notify::RecursiveMode::NonRecursive)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            file: file.to_string(),
+            changed,
+            _watcher: watcher,
+        })
+    }
+
+    /// Whether the file changed since the last call.
+    pub fn take_change(&self) -> bool {
+        std::mem::take(&mut *self.changed.lock().unwrap())
+    }
+}
+
+pub enum FollowUpdate {
+    Unchanged,
+    Appended(Vec<String>),
+    /// The file shrank, e.g. truncated by `copytruncate` log rotation.
+    Truncated,
+}
+
+/// Reads whatever has been appended to a file since the last poll.
+pub struct FileFollower {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub partial: Vec<u8>,
+}
+
+impl FileFollower {
+    pub fn new(path: PathBuf, offset: u64) -> Self {
+        Self {
+            path,
+            offset,
+            partial: Vec::new(),
+        }
+    }
+
+    pub fn poll(&mut self) -> io::Result<FollowUpdate> {
+        let len = fs::metadata(&self.path)?.len();
+        if len < self.offset {
+            return Ok(FollowUpdate::Truncated);
+        }
+        if len == self.offset {
+            return Ok(FollowUpdate::Unchanged);
+        }
+
+        let mut file = fs::File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.take(len - self.offset).read_to_end(&mut bytes)?;
+        self.offset += bytes.len() as u64;
+        self.partial.extend(bytes);
+
+        // Hold back a trailing partial line until the writer finishes it.
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(FollowUpdate::Unchanged);
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        let lines = String::from_utf8_lossy(&complete)
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .collect();
+        Ok(FollowUpdate::Appended(lines))
+    }
+}
+
+/// Read a text file in whatever encoding it uses.
+pub fn read_text_file(path: &str) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    decode_file(bytes, None)
+        .map(|(text, _)| text)
+        .map_err(|_| format!("{} is a binary file", path))
+}
+
+/// Tab title for a document path.
+pub fn display_name(path: Option<&str>) -> String {
+    match path {
+        Some("-") => "stdin".to_string(),
+        Some(path) => Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string()),
+        None => "No file".to_string(),
+    }
+}
@@ -0,0 +1,431 @@
+#[cfg(feature = "tree-sitter")]
+pub mod tree_sitter;
+
+use crate::config::{config_dir, data_dir};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{FontStyle, Style, Theme, ThemeSet};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+pub const DEFAULT_LIGHT_THEME: &str = "base16-ocean.light";
+
+pub fn ansi_color(index: u8) -> syntect::highlighting::Color {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 49, 49),
+        (13, 188, 121),
+        (229, 229, 16),
+        (36, 114, 200),
+        (188, 63, 188),
+        (17, 168, 205),
+        (229, 229, 229),
+        (102, 102, 102),
+        (241, 76, 76),
+        (35, 209, 139),
+        (245, 245, 67),
+        (59, 142, 234),
+        (214, 112, 214),
+        (41, 184, 219),
+        (255, 255, 255),
+    ];
+    let (r, g, b) = match index {
+        0..=15 => BASIC[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            let scale = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            (scale(i / 36), scale((i / 6) % 6), scale(i % 6))
+        }
+        _ => {
+            let v = 8 + (index - 232) * 10;
+            (v, v, v)
+        }
+    };
+    syntect::highlighting::Color { r, g, b, a: 255 }
+}
+
+/// Apply the SGR parameters of one `ESC [ ... m` sequence to `style`.
+pub fn apply_sgr(params: &str, style: &mut Style, default: Style) {
+    let codes: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 => *style = default,
+            1 => style.font_style.insert(FontStyle::BOLD),
+            3 => style.font_style.insert(FontStyle::ITALIC),
+            4 => style.font_style.insert(FontStyle::UNDERLINE),
+            22 => style.font_style.remove(FontStyle::BOLD),
+            23 => style.font_style.remove(FontStyle::ITALIC),
+            24 => style.font_style.remove(FontStyle::UNDERLINE),
+            c @ 30..=37 => style.foreground = ansi_color((c - 30) as u8),
+            c @ 90..=97 => style.foreground = ansi_color((c - 90 + 8) as u8),
+            39 => style.foreground = default.foreground,
+            38 if codes.get(i + 1) == Some(&5) => {
+                if let Some(&n) = codes.get(i + 2) {
+                    style.foreground = ansi_color(n.min(255) as u8);
+                }
+                i += 2;
+            }
+            38 if codes.get(i + 1) == Some(&2) => {
+                if let (Some(&r), Some(&g), Some(&b)) =
+                    (codes.get(i + 2), codes.get(i + 3), codes.get(i + 4))
+                {
+                    style.foreground = syntect::highlighting::Color {
+                        r: r.min(255) as u8,
+                        g: g.min(255) as u8,
+                        b: b.min(255) as u8,
+                        a: 255,
+                    };
+                }
+                i += 4;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Turn a line of terminal output into styled segments, interpreting ANSI SGR
+/// sequences and the backspace overstrike used by man/nroff (`x\bx` bold,
+/// `_\bx` underline). Returns the segments and the plain text.
+pub fn parse_terminal_line(line: &str, default: Style) -> (Vec<(Style, String)>, String) {
+    let mut styled: Vec<(Style, char)> = Vec::new();
+    let mut style = default;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.peek() != Some(&'[') {
+                    continue;
+                }
+                chars.next();
+                let mut params = String::new();
+                // CSI parameters end at the first byte in '@'..='~'.
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        if c == 'm' {
+                            apply_sgr(&params, &mut style, default);
+                        }
+                        break;
+                    }
+                    params.push(c);
+                }
+            }
+            '\x08' => {
+                let (Some((_, previous)), Some(next)) = (styled.pop(), chars.next()) else {
+                    continue;
+                };
+                let mut overstruck = style;
+                if previous == '_' && next != '_' {
+                    overstruck.font_style.insert(FontStyle::UNDERLINE);
+                } else {
+                    overstruck.font_style.insert(FontStyle::BOLD);
+                }
+                styled.push((overstruck, next));
+            }
+            '\t' => styled.extend(std::iter::repeat_n((style, ' '), 4)),
+            c if c.is_control() => {}
+            c => styled.push((style, c)),
+        }
+    }
+
+    let plain: String = styled.iter().map(|(_, c)| *c).collect();
+    let mut segments: Vec<(Style, String)> = Vec::new();
+    for (style, c) in styled {
+        match segments.last_mut() {
+            Some((last, text)) if *last == style => text.push(c),
+            _ => segments.push((style, c.to_string())),
+        }
+    }
+
+    (segments, plain)
+}
+
+/// The bundled syntect themes plus any `.tmTheme` files in the user's themes directory.
+pub fn load_theme_set() -> ThemeSet {
+    let mut themes = ThemeSet::load_defaults();
+    if let Some(dir) = config_dir().map(|dir| dir.join("themes")) {
+        if dir.is_dir() {
+            let _ = themes.add_from_folder(dir);
+        }
+    }
+    themes
+}
+
+pub fn syntax_overrides_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("syntax_overrides.json"))
+}
+
+/// Syntax names chosen by hand, keyed by canonical file path.
+pub fn load_syntax_overrides() -> BTreeMap<String, String> {
+    syntax_overrides_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_syntax_overrides(overrides: &BTreeMap<String, String>) {
+    let Some(path) = syntax_overrides_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(overrides) {
+        let _ = fs::write(path, json);
+    }
+}
+
+/// Whether a theme is meant for a dark background, judged by its background colour.
+pub fn is_dark_theme(theme: &Theme) -> bool {
+    theme.settings.background.is_none_or(|c| {
+        (u32::from(c.r) * 299 + u32::from(c.g) * 587 + u32::from(c.b) * 114) / 1000 < 128
+    })
+}
+
+/// The syntax for a file: by its name, else by its first line (a shebang or
+/// mode line), else plain text.
+pub fn detect_syntax<'a>(
+    syntax_set: &'a SyntaxSet,
+    path: Option<&str>,
+    content: &str,
+) -> &'a SyntaxReference {
+    path.and_then(|path| syntax_set.find_syntax_for_file(path).ok().flatten())
+        .or_else(|| {
+            content
+                .lines()
+                .next()
+                .and_then(|line| syntax_set.find_syntax_by_first_line(line))
+        })
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text())
+}
+
+/// Lines highlighted by a background worker, waiting to be picked up by the UI.
+#[derive(Default)]
+pub struct HighlightJob {
+    pub lines: Vec<Vec<(Style, String)>>,
+    pub done: bool,
+}
+
+/// A running highlight job and the index of the next line it will fill in.
+pub struct HighlightTask {
+    pub job: Arc<Mutex<HighlightJob>>,
+    pub next: usize,
+}
+
+impl HighlightTask {
+    /// Copy the lines highlighted so far into `lines`. Returns whether the
+    /// job has finished.
+    pub fn poll(&mut self, lines: &mut [Vec<(Style, String)>]) -> bool {
+        let (finished, done) = {
+            let mut job = self.job.lock().unwrap();
+            (std::mem::take(&mut job.lines), job.done)
+        };
+        for line in finished {
+            if let Some(slot) = lines.get_mut(self.next) {
+                *slot = line;
+            }
+            self.next += 1;
+        }
+        done
+    }
+}
+
+/// Which engine highlights a language.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HighlightBackend {
+    /// TextMate grammars, line by line.
+    Syntect,
+    /// Parsed with tree-sitter; needs the `tree-sitter` feature and a
+    /// grammar for the language, otherwise syntect is used.
+    TreeSitter,
+}
+
+/// Lines handed over by a highlighter; returns `false` once nobody wants more.
+pub type LineSink<'a> = dyn FnMut(Vec<Vec<(Style, String)>>) -> bool + 'a;
+
+/// Styles a document on a worker thread.
+pub trait Highlighter: Send {
+    /// Hand the styled segments of each line of `content`, without its line
+    /// break, to `emit` in batches, in order. Stops when `emit` returns `false`.
+    fn highlight(&mut self, content: &str, emit: &mut LineSink<'_>);
+}
+
+/// Highlighting with syntect's TextMate grammars.
+pub struct SyntectHighlighter {
+    pub syntax_set: Arc<SyntaxSet>,
+    pub syntax_name: String,
+    pub theme: Theme,
+}
+
+impl Highlighter for SyntectHighlighter {
+    fn highlight(&mut self, content: &str, emit: &mut LineSink<'_>) {
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_name(&self.syntax_name)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+
+        let mut batch = Vec::new();
+        for line in LinesWithEndings::from(content) {
+            let ranges = highlighter
+                .highlight_line(line, &self.syntax_set)
+                .unwrap_or_else(|_| vec![(Style::default(), line)]);
+            batch.push(
+                ranges
+                    .into_iter()
+                    .map(|(style, text)| (style, text.trim_end_matches(['\n', '\r']).to_string()))
+                    .collect(),
+            );
+
+            if batch.len() == 500 && !emit(std::mem::take(&mut batch)) {
+                return;
+            }
+        }
+        emit(batch);
+    }
+}
+
+/// Run `highlighter` over `content`, handing lines over in batches. Stops
+/// early once the UI has dropped its handle (the document was closed or reloaded).
+pub fn run_highlighter(
+    content: String,
+    mut highlighter: Box<dyn Highlighter>,
+    job: Arc<Mutex<HighlightJob>>,
+) {
+    highlighter.highlight(&content, &mut |mut lines| {
+        if Arc::strong_count(&job) == 1 {
+            return false;
+        }
+        job.lock().unwrap().lines.append(&mut lines);
+        true
+    });
+    job.lock().unwrap().done = true;
+}
+
+/// Bracket colours by nesting depth, readable on dark and light themes.
+const RAINBOW: [(u8, u8, u8); 6] = [
+    (230, 180, 40),
+    (200, 100, 220),
+    (60, 160, 230),
+    (80, 190, 110),
+    (230, 110, 80),
+    (60, 190, 190),
+];
+
+/// `segments` with each bracket recoloured by its nesting depth, starting
+/// at `depth` (see `bracket_depths`).
+pub fn rainbow_brackets(segments: &[(Style, String)], mut depth: usize) -> Vec<(Style, String)> {
+    let colored = |style: Style, depth: usize| {
+        let (r, g, b) = RAINBOW[depth % RAINBOW.len()];
+        Style {
+            foreground: syntect::highlighting::Color { r, g, b, a: 255 },
+            ..style
+        }
+    };
+    let mut out: Vec<(Style, String)> = Vec::new();
+    for (style, text) in segments {
+        for c in text.chars() {
+            let style = match c {
+                '(' | '[' | '{' => {
+                    depth += 1;
+                    colored(*style, depth - 1)
+                }
+                ')' | ']' | '}' => {
+                    depth = depth.saturating_sub(1);
+                    colored(*style, depth)
+                }
+                _ => *style,
+            };
+            match out.last_mut() {
+                Some((last, text)) if *last == style => text.push(c),
+                _ => out.push((style, c.to_string())),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_syntax_from_the_file_name() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let syntax = detect_syntax(&syntax_set, Some("src/lib.rs"), "");
+        assert_eq!(syntax.name, "Rust");
+        let syntax = detect_syntax(&syntax_set, Some("notes.md"), "#!/bin/sh\n");
+        assert_eq!(syntax.name, "Markdown");
+    }
+
+    #[test]
+    fn detects_syntax_from_the_first_line() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let syntax = detect_syntax(&syntax_set, Some("deploy"), "#!/bin/bash\necho hi\n");
+        assert_eq!(syntax.name, "Bourne Again Shell (bash)");
+        let syntax = detect_syntax(&syntax_set, None, "#!/usr/bin/env python3\n");
+        assert_eq!(syntax.name, "Python");
+    }
+
+    #[test]
+    fn falls_back_to_plain_text() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let syntax = detect_syntax(&syntax_set, Some("-"), "just some words\n");
+        assert_eq!(syntax.name, "Plain Text");
+        let syntax = detect_syntax(&syntax_set, None, "");
+        assert_eq!(syntax.name, "Plain Text");
+    }
+
+    #[test]
+    fn syntect_highlighter_keeps_one_entry_per_line() {
+        let mut highlighter = SyntectHighlighter {
+            syntax_set: Arc::new(SyntaxSet::load_defaults_newlines()),
+            syntax_name: "Rust".to_string(),
+            theme: ThemeSet::load_defaults().themes[DEFAULT_THEME].clone(),
+        };
+        let mut lines = Vec::new();
+        highlighter.highlight("fn main() {\r\n}\n", &mut |mut batch| {
+            lines.append(&mut batch);
+            true
+        });
+        let text: Vec<String> = lines
+            .iter()
+            .map(|line| line.iter().map(|(_, text)| text.as_str()).collect())
+            .collect();
+        assert_eq!(text, ["fn main() {", "}"]);
+    }
+
+    #[test]
+    fn rainbow_brackets_colour_by_depth() {
+        let default = Style::default();
+        let line = [(default, "f(a[0]".to_string()), (default, ")".to_string())];
+        let segments = rainbow_brackets(&line, 1);
+        let text: String = segments.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(text, "f(a[0])");
+        let colour = |c: &str| segments.iter().find(|(_, t)| t == c).unwrap().0.foreground;
+        assert_eq!(colour("("), colour(")"));
+        assert_ne!(colour("("), colour("["));
+        assert_eq!(segments[0], (default, "f".to_string()));
+    }
+
+    #[test]
+    fn parses_terminal_colours() {
+        let default = Style::default();
+        let (segments, plain) = parse_terminal_line("\x1b[1;31merror\x1b[0m: failed", default);
+        assert_eq!(plain, "error: failed");
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].1, "error");
+        assert_eq!(segments[0].0.foreground, ansi_color(1));
+        assert!(segments[0].0.font_style.contains(FontStyle::BOLD));
+        assert_eq!(segments[1], (default, ": failed".to_string()));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::highlight::{Highlighter, LineSink};
+use syntect::highlighting::{Style, Theme};
+use syntect::parsing::Scope;
+use tree_sitter_highlight::{Highlight, HighlightConfiguration, HighlightEvent};
+
+/// Capture names of the grammars' highlight queries, with the TextMate
+/// scope whose theme colour they take. Longer names such as
+/// `function.method.call` fall back to the longest listed prefix.
+const CAPTURES: [(&str, &str); 26] = [
+    ("attribute", "entity.other.attribute-name"),
+    ("comment", "comment"),
+    ("constant", "constant"),
+    ("constant.builtin", "constant.language"),
+    ("constructor", "entity.name.type"),
+    ("escape", "constant.character.escape"),
+    ("function", "entity.name.function"),
+    ("function.builtin", "support.function"),
+    ("function.macro", "entity.name.function.macro"),
+    ("function.method", "entity.name.function"),
+    ("keyword", "keyword"),
+    ("label", "entity.name.label"),
+    ("number", "constant.numeric"),
+    ("operator", "keyword.operator"),
+    ("property", "variable.other.member"),
+    ("punctuation", "punctuation"),
+    ("punctuation.bracket", "punctuation.section"),
+    ("punctuation.delimiter", "punctuation.separator"),
+    ("string", "string"),
+    ("string.special", "string.regexp"),
+    ("tag", "entity.name.tag"),
+    ("type", "entity.name.type"),
+    ("type.builtin", "storage.type"),
+    ("variable", "variable"),
+    ("variable.builtin", "variable.language"),
+    ("variable.parameter", "variable.parameter"),
+];
+
+/// Whether tree-sitter has a grammar for the syntect syntax `syntax_name`.
+pub fn supports(syntax_name: &str) -> bool {
+    matches!(
+        syntax_name,
+        "Rust" | "Python" | "JavaScript" | "JavaScript (Babel)"
+    )
+}
+
+fn configuration(syntax_name: &str) -> Option<HighlightConfiguration> {
+    let config = match syntax_name {
+        "Rust" => HighlightConfiguration::new(
+            tree_sitter_rust::language(),
+            tree_sitter_rust::HIGHLIGHT_QUERY,
+            "",
+            "",
+        ),
+        "Python" => HighlightConfiguration::new(
+            tree_sitter_python::language(),
+            tree_sitter_python::HIGHLIGHT_QUERY,
+            "",
+            "",
+        ),
+        "JavaScript" | "JavaScript (Babel)" => HighlightConfiguration::new(
+            tree_sitter_javascript::language(),
+            tree_sitter_javascript::HIGHLIGHT_QUERY,
+            "",
+            tree_sitter_javascript::LOCALS_QUERY,
+        ),
+        _ => return None,
+    };
+    config.ok()
+}
+
+/// Highlighting from a tree-sitter parse of the whole document, coloured
+/// with the scopes of the current syntect theme.
+pub struct TreeSitterHighlighter {
+    config: HighlightConfiguration,
+    /// Style of each entry of `CAPTURES`.
+    styles: Vec<Style>,
+    default: Style,
+}
+
+impl TreeSitterHighlighter {
+    /// A highlighter for the syntect syntax `syntax_name`, if tree-sitter has
+    /// a grammar for it.
+    pub fn new(syntax_name: &str, theme: &Theme) -> Option<Self> {
+        let mut config = configuration(syntax_name)?;
+        let names: Vec<&str> = CAPTURES.iter().map(|(name, _)| *name).collect();
+        config.configure(&names);
+
+        let themed = syntect::highlighting::Highlighter::new(theme);
+        let styles = CAPTURES
+            .iter()
+            .map(|(_, scope)| match Scope::new(scope) {
+                Ok(scope) => themed.style_for_stack(&[scope]),
+                Err(_) => themed.get_default(),
+            })
+            .collect();
+        Some(Self {
+            config,
+            styles,
+            default: themed.get_default(),
+        })
+    }
+}
+
+impl Highlighter for TreeSitterHighlighter {
+    fn highlight(&mut self, content: &str, emit: &mut LineSink<'_>) {
+        let mut parser = tree_sitter_highlight::Highlighter::new();
+        let Ok(events) = parser.highlight(&self.config, content.as_bytes(), None, |_| None) else {
+            return;
+        };
+
+        let mut styles: Vec<Style> = Vec::new();
+        let mut line: Vec<(Style, String)> = Vec::new();
+        let mut batch = Vec::new();
+        for event in events {
+            match event {
+                Ok(HighlightEvent::HighlightStart(Highlight(index))) => {
+                    styles.push(self.styles[index]);
+                }
+                Ok(HighlightEvent::HighlightEnd) => {
+                    styles.pop();
+                }
+                Ok(HighlightEvent::Source { start, end }) => {
+                    let style = styles.last().copied().unwrap_or(self.default);
+                    for (i, piece) in content[start..end].split('\n').enumerate() {
+                        if i > 0 {
+                            if let Some((_, text)) = line.last_mut() {
+                                if text.ends_with('\r') {
+                                    text.pop();
+                                }
+                            }
+                            batch.push(std::mem::take(&mut line));
+                            if batch.len() == 500 && !emit(std::mem::take(&mut batch)) {
+                                return;
+                            }
+                        }
+                        match line.last_mut() {
+                            _ if piece.is_empty() => {}
+                            Some((last, text)) if *last == style => text.push_str(piece),
+                            _ => line.push((style, piece.to_string())),
+                        }
+                    }
+                }
+                // Parsing failed part way; the rest of the lines stay plain.
+                Err(_) => break,
+            }
+        }
+        // Like `LinesWithEndings`, no empty line after a final line break.
+        if !line.is_empty() {
+            batch.push(line);
+        }
+        emit(batch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntect::highlighting::ThemeSet;
+
+    #[test]
+    fn lines_match_the_document() {
+        let theme = &ThemeSet::load_defaults().themes[crate::highlight::DEFAULT_THEME];
+        let mut highlighter = TreeSitterHighlighter::new("Rust", theme).unwrap();
+        let mut lines = Vec::new();
+        highlighter.highlight("fn main() {\r\n    // hi\n\n}\n", &mut |mut batch| {
+            lines.append(&mut batch);
+            true
+        });
+        let text: Vec<String> = lines
+            .iter()
+            .map(|line| line.iter().map(|(_, text)| text.as_str()).collect())
+            .collect();
+        assert_eq!(text, ["fn main() {", "    // hi", "", "}"]);
+        // The keyword and the comment are coloured differently.
+        assert_ne!(lines[0][0].0, lines[1].last().unwrap().0);
+        assert!(!supports("Plain Text"));
+    }
+}
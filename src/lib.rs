@@ -0,0 +1,13 @@
+//! The tty_doc document viewer. The viewer's parsers and the AI client are
+//! usable on their own; `app` ties them together into the egui application.
+
+pub mod ai;
+pub mod app;
+pub mod cli;
+mod commands;
+pub mod config;
+pub mod export;
+pub mod files;
+pub mod highlight;
+mod session;
+pub mod viewer;
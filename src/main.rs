@@ -1,33 +1,35 @@
+use clap::Parser;
 use eframe::egui;
+use std::io::{self, IsTerminal};
+use tty_doc::app::MyApp;
+use tty_doc::cli::{self, Cli};
 
 fn main() -> Result<(), eframe::Error> {
+    let mut cli = Cli::parse();
+    if let Some(mode) = &cli.mode {
+        if let Err(e) = cli::run(&cli, mode) {
+            eprintln!("tty_doc: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    // The left file of a diff is also opened as the document.
+    if let Some(left) = cli.diff.as_ref().and_then(|files| files.first()) {
+        cli.files.insert(0, left.clone());
+    }
+    // Reading from a pipe, as when used as $PAGER or $MANPAGER.
+    if cli.files.is_empty() && !io::stdin().is_terminal() {
+        cli.files.push("-".to_string());
+    }
+
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([320.0, 240.0]),
+        viewport: egui::ViewportBuilder::default().with_inner_size([1200.0, 800.0]),
         ..Default::default()
     };
-    
+
     eframe::run_native(
-        "My egui App",
+        "tty_doc",
         options,
-        Box::new(|_cc| Box::new(MyApp::default())),
+        Box::new(move |cc| Box::new(MyApp::new(cc, cli))),
     )
 }
-
-struct MyApp {
-    
-}
-
-impl Default for MyApp {
-    fn default() -> Self {
-        Self {}
-    }
-}
-
-impl eframe::App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |_ui| {
-          
-        });
-    }
-}
\ No newline at end of file
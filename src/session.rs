@@ -0,0 +1,263 @@
+use crate::config::{data_dir, stable_hash};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MAX_RECENT_FILES: usize = 15;
+
+pub fn recent_files_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("recent.json"))
+}
+
+/// Canonical paths of recently opened files, newest first.
+pub fn load_recent_files() -> Vec<String> {
+    recent_files_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_recent_files(files: &[String]) {
+    let Some(path) = recent_files_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(files) {
+        let _ = fs::write(path, json);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionTab {
+    pub path: String,
+    pub scroll_offset: f32,
+}
+
+/// The open tabs and panels, written as they change so the next start can
+/// offer to bring them back.
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Session {
+    pub tabs: Vec<SessionTab>,
+    pub active: usize,
+    pub folder: Option<PathBuf>,
+    pub show_bookmarks: bool,
+}
+
+pub fn session_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("session.json"))
+}
+
+pub fn load_session() -> Option<Session> {
+    session_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str::<Session>(&json).ok())
+        .filter(|session| !session.tabs.is_empty())
+}
+
+pub fn save_session(session: &Session) {
+    let Some(path) = session_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(session) {
+        let _ = fs::write(path, json);
+    }
+}
+
+/// Unsaved edits written by autosave. The files are removed on saving and
+/// on a clean exit, so any found at startup were left by a crash.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Recovery {
+    /// Canonical path of the document.
+    pub path: String,
+    pub content: String,
+    /// Seconds since the Unix epoch.
+    pub saved_at: i64,
+}
+
+/// `recovery/<hash>.json` in the data directory.
+fn recovery_path(key: &str) -> Option<PathBuf> {
+    data_dir().map(|dir| {
+        dir.join("recovery")
+            .join(format!("{:016x}.json", stable_hash(key)))
+    })
+}
+
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+pub fn save_recovery(key: &str, content: &str) {
+    let Some(path) = recovery_path(key) else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let recovery = Recovery {
+        path: key.to_string(),
+        content: content.to_string(),
+        saved_at: unix_seconds(SystemTime::now()),
+    };
+    if let Ok(json) = serde_json::to_string(&recovery) {
+        let _ = fs::write(path, json);
+    }
+}
+
+pub fn remove_recovery(key: &str) {
+    if let Some(path) = recovery_path(key) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Key of the recovery file to remove when a document's edits are saved,
+/// discarded, or dropped by closing its tab or quitting. Only dirty
+/// documents read from a file are autosaved; streamed ones have none.
+pub fn stale_recovery(path: Option<&str>, dirty: bool, streamed: bool) -> Option<String> {
+    let path = path.filter(|_| dirty && !streamed)?;
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Recovered edits that are newer than their file on disk and differ from
+/// it, newest first. Others are out of date and removed.
+pub fn load_recoveries() -> Vec<Recovery> {
+    let Some(dir) = data_dir().map(|dir| dir.join("recovery")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut recoveries = Vec::new();
+    for entry in entries.flatten() {
+        let Some(recovery) = fs::read_to_string(entry.path())
+            .ok()
+            .and_then(|json| serde_json::from_str::<Recovery>(&json).ok())
+        else {
+            continue;
+        };
+        let modified = fs::metadata(&recovery.path)
+            .and_then(|metadata| metadata.modified())
+            .map_or(0, unix_seconds);
+        let on_disk = fs::read_to_string(&recovery.path).ok();
+        if recovery.saved_at >= modified && on_disk.as_deref() != Some(&recovery.content) {
+            recoveries.push(recovery);
+        } else {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    recoveries.sort_by_key(|recovery| std::cmp::Reverse(recovery.saved_at));
+    recoveries
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct Bookmark {
+    /// Canonical path of the document.
+    pub path: String,
+    /// 0-based line.
+    pub line: usize,
+    pub label: String,
+}
+
+pub fn bookmarks_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("bookmarks.json"))
+}
+
+pub fn load_bookmarks() -> Vec<Bookmark> {
+    bookmarks_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_bookmarks(bookmarks: &[Bookmark]) {
+    let Some(path) = bookmarks_path() else { return };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(bookmarks) {
+        let _ = fs::write(path, json);
+    }
+}
+
+/// Write `bookmarks` to `target` for sharing. Paths under the target's
+/// directory are stored relative to it so they resolve on another machine
+/// that has the same checkout.
+pub fn export_bookmarks(bookmarks: &[Bookmark], target: &Path) -> Result<(), String> {
+    let base = target.parent().and_then(|dir| fs::canonicalize(dir).ok());
+    let shared: Vec<Bookmark> = bookmarks
+        .iter()
+        .map(|bookmark| {
+            let path = base
+                .as_deref()
+                .and_then(|base| Path::new(&bookmark.path).strip_prefix(base).ok())
+                .map_or_else(
+                    || bookmark.path.clone(),
+                    |p| p.to_string_lossy().into_owned(),
+                );
+            Bookmark {
+                path,
+                ..bookmark.clone()
+            }
+        })
+        .collect();
+
+    let json = serde_json::to_string_pretty(&shared).map_err(|e| e.to_string())?;
+    fs::write(target, json).map_err(|e| format!("Could not write {}: {}", target.display(), e))
+}
+
+/// Read bookmarks exported by [`export_bookmarks`], resolving relative paths
+/// against the file's directory.
+pub fn import_bookmarks(source: &Path) -> Result<Vec<Bookmark>, String> {
+    let json = fs::read_to_string(source)
+        .map_err(|e| format!("Could not read {}: {}", source.display(), e))?;
+    let bookmarks: Vec<Bookmark> =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid bookmark file: {}", e))?;
+
+    let base = source.parent().unwrap_or(Path::new("."));
+    Ok(bookmarks
+        .into_iter()
+        .map(|bookmark| {
+            let path = base.join(&bookmark.path);
+            let path = fs::canonicalize(&path).unwrap_or(path);
+            Bookmark {
+                path: path.to_string_lossy().into_owned(),
+                ..bookmark
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recoveries_are_left_only_by_dirty_files() {
+        let file = std::env::temp_dir().join("tty_doc_stale_recovery.txt");
+        fs::write(&file, "edited").unwrap();
+        let path = file.to_string_lossy().into_owned();
+        let key = fs::canonicalize(&file).unwrap();
+
+        assert_eq!(
+            stale_recovery(Some(&path), true, false),
+            Some(key.to_string_lossy().into_owned())
+        );
+        assert_eq!(stale_recovery(Some(&path), false, false), None);
+        assert_eq!(stale_recovery(Some(&path), true, true), None);
+        assert_eq!(stale_recovery(None, true, false), None);
+        // A file removed since opening keeps the path it was opened with.
+        assert_eq!(
+            stale_recovery(Some("/no/such/file.txt"), true, false).as_deref(),
+            Some("/no/such/file.txt")
+        );
+        fs::remove_file(file).unwrap();
+    }
+}
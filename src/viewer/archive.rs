@@ -0,0 +1,224 @@
+use crate::viewer::gguf::format_size;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+
+/// Entries larger than this are not opened, since they are read into memory.
+const MAX_ENTRY_SIZE: u64 = 64 << 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// The kind of archive `path` names, by its extension.
+    pub fn of(path: &str) -> Option<ArchiveKind> {
+        let path = path.to_lowercase();
+        if path.ends_with(".zip") || path.ends_with(".jar") || path.ends_with(".whl") {
+            Some(ArchiveKind::Zip)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file in an archive; directories are not listed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ArchiveEntry {
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+}
+
+/// The entries of a zip or tar archive, listed in the archive sidebar. The
+/// archive stays on disk; entries are decompressed when opened.
+#[derive(Clone)]
+pub struct Archive {
+    pub path: String,
+    pub kind: ArchiveKind,
+    pub entries: Vec<ArchiveEntry>,
+    /// Text typed into the sidebar's filter field.
+    pub filter: String,
+}
+
+impl Archive {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let kind = ArchiveKind::of(path).ok_or("Not a zip or tar archive")?;
+        let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let entries = match kind {
+            ArchiveKind::Zip => zip_entries(file),
+            ArchiveKind::Tar => tar_entries(file),
+            ArchiveKind::TarGz => tar_entries(GzDecoder::new(file)),
+        }?;
+        Ok(Self {
+            path: path.to_string(),
+            kind,
+            entries,
+            filter: String::new(),
+        })
+    }
+
+    /// The bytes of the entry `name`, decompressed in memory.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, String> {
+        let file = BufReader::new(File::open(&self.path).map_err(|e| e.to_string())?);
+        match self.kind {
+            ArchiveKind::Zip => read_zip_entry(file, name),
+            ArchiveKind::Tar => read_tar_entry(file, name),
+            ArchiveKind::TarGz => read_tar_entry(GzDecoder::new(file), name),
+        }
+    }
+
+    /// The path an entry's tab is known by: the entry's name under the
+    /// archive's path.
+    pub fn entry_path(&self, name: &str) -> String {
+        format!("{}/{}", self.path, name)
+    }
+
+    /// Entries whose name contains the filter text, ignoring case.
+    pub fn matching_entries(&self) -> Vec<&ArchiveEntry> {
+        let filter = self.filter.trim().to_lowercase();
+        self.entries
+            .iter()
+            .filter(|entry| entry.name.to_lowercase().contains(&filter))
+            .collect()
+    }
+
+    /// Plain-text listing used as the document content for AI questions.
+    pub fn to_text(&self) -> String {
+        let total: u64 = self.entries.iter().map(|entry| entry.size).sum();
+        let mut text = format!(
+            "{} files, {} uncompressed\n\n",
+            self.entries.len(),
+            format_size(total)
+        );
+        for entry in &self.entries {
+            text.push_str(&format!(
+                "{:>10}  {}\n",
+                format_size(entry.size),
+                entry.name
+            ));
+        }
+        text
+    }
+}
+
+fn zip_entries(reader: impl Read + Seek) -> Result<Vec<ArchiveEntry>, String> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i).map_err(|e| e.to_string())?;
+        if !file.is_dir() {
+            entries.push(ArchiveEntry {
+                name: file.name().to_string(),
+                size: file.size(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+fn read_zip_entry(reader: impl Read + Seek, name: &str) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let file = archive.by_name(name).map_err(|e| e.to_string())?;
+    read_limited(file)
+}
+
+fn tar_entries(reader: impl Read) -> Result<Vec<ArchiveEntry>, String> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.header().entry_type().is_file() {
+            entries.push(ArchiveEntry {
+                name: entry
+                    .path()
+                    .map_err(|e| e.to_string())?
+                    .display()
+                    .to_string(),
+                size: entry.size(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Tar has no index, so this reads through the archive up to the entry.
+fn read_tar_entry(reader: impl Read, name: &str) -> Result<Vec<u8>, String> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let found = entry
+            .path()
+            .is_ok_and(|path| path.display().to_string() == name);
+        if found {
+            return read_limited(entry);
+        }
+    }
+    Err(format!("{} is not in the archive", name))
+}
+
+fn read_limited(entry: impl Read) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    entry
+        .take(MAX_ENTRY_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    if bytes.len() as u64 > MAX_ENTRY_SIZE {
+        return Err(format!(
+            "Entries over {} are not opened",
+            format_size(MAX_ENTRY_SIZE)
+        ));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Cursor;
+
+    #[test]
+    fn tarball_entries_are_listed_and_read() {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Default::default()));
+        for (name, content) in [
+            ("pkg/README.md", "# Hi\n"),
+            ("pkg/src/lib.rs", "fn x() {}\n"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        let tarball = builder.into_inner().unwrap().finish().unwrap();
+
+        let entries = tar_entries(GzDecoder::new(Cursor::new(&tarball))).unwrap();
+        assert_eq!(
+            entries,
+            [
+                ArchiveEntry {
+                    name: "pkg/README.md".to_string(),
+                    size: 5
+                },
+                ArchiveEntry {
+                    name: "pkg/src/lib.rs".to_string(),
+                    size: 10
+                },
+            ]
+        );
+        let lib = read_tar_entry(GzDecoder::new(Cursor::new(&tarball)), "pkg/src/lib.rs");
+        assert_eq!(lib.unwrap(), b"fn x() {}\n");
+        assert!(read_tar_entry(GzDecoder::new(Cursor::new(&tarball)), "gone").is_err());
+        assert_eq!(ArchiveKind::of("release-1.0.TGZ"), Some(ArchiveKind::TarGz));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::highlight::HighlightTask;
+use eframe::egui;
+use syntect::highlighting::Style;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DiffKind {
+    Equal,
+    Removed,
+    Added,
+    /// A removed line shown next to the added line that replaced it.
+    Changed,
+}
+
+/// One row of a diff, with the 0-based lines it shows on each side.
+#[derive(Clone, Copy)]
+pub struct DiffRow {
+    pub kind: DiffKind,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+}
+
+/// Side-by-side rows: replaced lines are paired up as changes, with any
+/// extra lines on either side as plain removals or additions.
+pub fn diff_rows(old: &str, new: &str) -> Vec<DiffRow> {
+    use similar::DiffOp;
+    let row = |kind, left, right| DiffRow { kind, left, right };
+    let mut rows = Vec::new();
+    for op in similar::TextDiff::from_lines(old, new).ops() {
+        match *op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => rows.extend(
+                (0..len).map(|i| row(DiffKind::Equal, Some(old_index + i), Some(new_index + i))),
+            ),
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => rows.extend(
+                (old_index..old_index + old_len).map(|i| row(DiffKind::Removed, Some(i), None)),
+            ),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => rows.extend(
+                (new_index..new_index + new_len).map(|i| row(DiffKind::Added, None, Some(i))),
+            ),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                for i in 0..old_len.max(new_len) {
+                    let left = (i < old_len).then_some(old_index + i);
+                    let right = (i < new_len).then_some(new_index + i);
+                    let kind = match (left, right) {
+                        (Some(_), Some(_)) => DiffKind::Changed,
+                        (Some(_), None) => DiffKind::Removed,
+                        _ => DiffKind::Added,
+                    };
+                    rows.push(row(kind, left, right));
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// Unified rows: each changed pair becomes a removal followed by an addition,
+/// with all removals of a run before its additions.
+pub fn unified_rows(rows: &[DiffRow]) -> Vec<DiffRow> {
+    let mut unified = Vec::with_capacity(rows.len());
+    let mut added = Vec::new();
+    for row in rows {
+        match row.kind {
+            DiffKind::Equal => {
+                unified.append(&mut added);
+                unified.push(*row);
+            }
+            DiffKind::Removed | DiffKind::Changed => {
+                unified.push(DiffRow {
+                    kind: DiffKind::Removed,
+                    right: None,
+                    ..*row
+                });
+                if row.right.is_some() {
+                    added.push(DiffRow {
+                        kind: DiffKind::Added,
+                        left: None,
+                        ..*row
+                    });
+                }
+            }
+            DiffKind::Added => added.push(*row),
+        }
+    }
+    unified.append(&mut added);
+    unified
+}
+
+/// Indices of the rows that start a run of changes.
+pub fn change_starts(rows: &[DiffRow]) -> Vec<usize> {
+    (0..rows.len())
+        .filter(|&i| {
+            rows[i].kind != DiffKind::Equal && (i == 0 || rows[i - 1].kind == DiffKind::Equal)
+        })
+        .collect()
+}
+
+/// One file of a diff with its highlighting.
+pub struct DiffSide {
+    pub path: String,
+    /// The commit `text` was read from, when it is not the file on disk.
+    pub revision: Option<String>,
+    pub text: String,
+    pub lines: Vec<Vec<(Style, String)>>,
+    pub highlight_task: Option<HighlightTask>,
+}
+
+impl DiffSide {
+    /// The path, plus the revision when there is one.
+    pub fn label(&self) -> String {
+        match &self.revision {
+            Some(revision) => format!("{} @ {}", self.path, revision),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// Two files compared line by line.
+pub struct DiffView {
+    pub left: DiffSide,
+    pub right: DiffSide,
+    pub rows: Vec<DiffRow>,
+    pub unified: bool,
+    /// Index into the change starts of the current view.
+    pub current_change: Option<usize>,
+    pub scroll_to_row: Option<usize>,
+}
+
+impl DiffView {
+    pub fn rows(&self) -> Vec<DiffRow> {
+        if self.unified {
+            unified_rows(&self.rows)
+        } else {
+            self.rows.clone()
+        }
+    }
+
+    /// The diff in unified format, with three lines of context.
+    pub fn unified_diff(&self) -> String {
+        similar::TextDiff::from_lines(&self.left.text, &self.right.text)
+            .unified_diff()
+            .context_radius(3)
+            .header(&self.left.label(), &self.right.label())
+            .to_string()
+    }
+
+    pub fn added_and_removed(&self) -> (usize, usize) {
+        self.rows
+            .iter()
+            .fold((0, 0), |(added, removed), row| match row.kind {
+                DiffKind::Equal => (added, removed),
+                DiffKind::Removed => (added, removed + 1),
+                DiffKind::Added => (added + 1, removed),
+                DiffKind::Changed => (added + 1, removed + 1),
+            })
+    }
+
+    /// Move to the next (`step` 1) or previous (`step` -1) change, wrapping around.
+    pub fn step_change(&mut self, step: isize) {
+        let starts = change_starts(&self.rows());
+        if starts.is_empty() {
+            return;
+        }
+        let count = starts.len() as isize;
+        let next = match self.current_change {
+            Some(current) => (current as isize + step).rem_euclid(count),
+            None if step > 0 => 0,
+            None => count - 1,
+        } as usize;
+        self.current_change = Some(next);
+        self.scroll_to_row = Some(starts[next]);
+    }
+}
+
+pub fn diff_fill(kind: DiffKind, dark_mode: bool) -> Option<egui::Color32> {
+    let alpha = if dark_mode { 50 } else { 60 };
+    match kind {
+        DiffKind::Equal => None,
+        DiffKind::Removed => Some(egui::Color32::from_rgba_unmultiplied(230, 60, 60, alpha)),
+        DiffKind::Added => Some(egui::Color32::from_rgba_unmultiplied(60, 200, 80, alpha)),
+        DiffKind::Changed => Some(egui::Color32::from_rgba_unmultiplied(230, 170, 40, alpha)),
+    }
+}
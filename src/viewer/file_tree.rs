@@ -0,0 +1,139 @@
+use eframe::egui;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct TreeEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub is_dir: bool,
+}
+
+/// The folder shown in the file tree sidebar. Folders are listed when first
+/// expanded; `.gitignore`d and hidden entries are left out unless asked for.
+pub struct FileTree {
+    pub root: PathBuf,
+    pub listings: HashMap<PathBuf, Vec<TreeEntry>>,
+    pub filter: String,
+    /// Files under `root` matching `filter`, and the filter they were found for.
+    pub matches: Option<(String, Vec<PathBuf>)>,
+    pub show_ignored: bool,
+}
+
+impl FileTree {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            listings: HashMap::new(),
+            filter: String::new(),
+            matches: None,
+            show_ignored: false,
+        }
+    }
+
+    pub fn walker(&self, dir: &Path) -> ignore::WalkBuilder {
+        let mut walker = ignore::WalkBuilder::new(dir);
+        walker
+            .standard_filters(!self.show_ignored)
+            .require_git(false);
+        walker
+    }
+
+    /// Folders first, then files, each sorted by name.
+    pub fn listing(&mut self, dir: &Path) -> Vec<TreeEntry> {
+        if let Some(entries) = self.listings.get(dir) {
+            return entries.clone();
+        }
+        let mut entries: Vec<TreeEntry> = self
+            .walker(dir)
+            .max_depth(Some(1))
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.depth() == 1)
+            .map(|entry| TreeEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                is_dir: entry.file_type().is_some_and(|t| t.is_dir()),
+                path: entry.into_path(),
+            })
+            .collect();
+        entries.sort_by_key(|entry| (!entry.is_dir, entry.name.to_lowercase()));
+        self.listings.insert(dir.to_path_buf(), entries.clone());
+        entries
+    }
+
+    /// Files anywhere under the root whose relative path contains the filter.
+    pub fn matching_files(&mut self) -> Vec<PathBuf> {
+        const MAX_MATCHES: usize = 500;
+        if let Some((filter, matches)) = &self.matches {
+            if *filter == self.filter {
+                return matches.clone();
+            }
+        }
+        let needle = self.filter.to_lowercase();
+        let matches: Vec<PathBuf> = self
+            .walker(&self.root)
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                path.strip_prefix(&self.root)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_lowercase()
+                    .contains(&needle)
+            })
+            .take(MAX_MATCHES)
+            .collect();
+        self.matches = Some((self.filter.clone(), matches.clone()));
+        matches
+    }
+
+    pub fn refresh(&mut self) {
+        self.listings.clear();
+        self.matches = None;
+    }
+
+    /// One folder level; recurses into expanded subfolders. A clicked file is
+    /// stored in `clicked`, with whether it should open in a new tab.
+    pub fn show_dir(
+        &mut self,
+        ui: &mut egui::Ui,
+        dir: &Path,
+        current: Option<&Path>,
+        clicked: &mut Option<(PathBuf, bool)>,
+    ) {
+        for entry in self.listing(dir) {
+            if entry.is_dir {
+                egui::CollapsingHeader::new(format!("📁 {}", entry.name))
+                    .id_source(&entry.path)
+                    .show(ui, |ui| self.show_dir(ui, &entry.path, current, clicked));
+            } else {
+                show_tree_file(ui, &entry.path, &entry.name, current, clicked);
+            }
+        }
+    }
+}
+
+pub fn show_tree_file(
+    ui: &mut egui::Ui,
+    path: &Path,
+    label: &str,
+    current: Option<&Path>,
+    clicked: &mut Option<(PathBuf, bool)>,
+) {
+    let response = ui
+        .selectable_label(current == Some(path), label)
+        .on_hover_text("Ctrl+click or middle-click to open in a new tab");
+    let new_tab =
+        response.middle_clicked() || (response.clicked() && ui.input(|i| i.modifiers.command));
+    if new_tab || response.clicked() {
+        *clicked = Some((path.to_path_buf(), new_tab));
+    }
+    response.context_menu(|ui| {
+        if ui.button("Open in new tab").clicked() {
+            *clicked = Some((path.to_path_buf(), true));
+            ui.close_menu();
+        }
+    });
+}
@@ -0,0 +1,117 @@
+use crate::config::config_dir;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Layout options for reading prose.
+pub struct Typography {
+    pub justify: bool,
+    /// Multiple of the font size.
+    pub line_height: f32,
+    pub paragraph_spacing: f32,
+    pub serif: bool,
+}
+
+impl Default for Typography {
+    fn default() -> Self {
+        Self {
+            justify: false,
+            line_height: 1.5,
+            paragraph_spacing: 10.0,
+            serif: false,
+        }
+    }
+}
+
+pub const SERIF_FONT_CANDIDATES: [&str; 8] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSerif.ttf",
+    "/usr/share/fonts/TTF/DejaVuSerif.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSerif-Regular.ttf",
+    "/usr/share/fonts/liberation-serif/LiberationSerif-Regular.ttf",
+    "/usr/share/fonts/noto/NotoSerif-Regular.ttf",
+    "/System/Library/Fonts/Supplemental/Georgia.ttf",
+    "/Library/Fonts/Georgia.ttf",
+    "C:\\Windows\\Fonts\\georgia.ttf",
+];
+
+/// The `[fonts]` table of `config.toml`: font files to load at startup.
+/// Relative paths are resolved against the config directory.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FontConfig {
+    /// Fonts for the document text, tried in order before the built-in
+    /// monospace font, e.g. a Nerd Font.
+    pub content: Vec<String>,
+    /// Fonts for the rest of the UI, tried before the built-in proportional font.
+    pub ui: Vec<String>,
+    /// Tried last by every family, for glyphs the others lack, e.g. a CJK font.
+    pub fallback: Vec<String>,
+}
+
+/// Load the fonts from `config` and a serif family for prose, preferring
+/// `serif.ttf` in the config directory over well-known system fonts.
+/// Returns whether a serif font was found, and the fonts that failed to load.
+pub fn setup_fonts(ctx: &egui::Context, config: &FontConfig) -> (bool, Vec<String>) {
+    let mut fonts = egui::FontDefinitions::default();
+    let mut errors = Vec::new();
+    let mut load = |role: &str, paths: &[String]| -> Vec<String> {
+        let mut names = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let path = match config_dir() {
+                Some(dir) if Path::new(path).is_relative() => dir.join(path),
+                _ => PathBuf::from(path),
+            };
+            match fs::read(&path) {
+                Ok(bytes) => {
+                    let name = format!("{}-{}", role, i);
+                    fonts
+                        .font_data
+                        .insert(name.clone(), egui::FontData::from_owned(bytes));
+                    names.push(name);
+                }
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        names
+    };
+    let content = load("content", &config.content);
+    let ui = load("ui", &config.ui);
+    let fallback = load("fallback", &config.fallback);
+    for (family, first) in [
+        (egui::FontFamily::Monospace, content),
+        (egui::FontFamily::Proportional, ui),
+    ] {
+        let names = fonts.families.entry(family).or_default();
+        names.splice(0..0, first);
+        names.extend(fallback.iter().cloned());
+    }
+
+    let user_font = config_dir().map(|dir| dir.join("serif.ttf"));
+    let serif = user_font
+        .into_iter()
+        .chain(SERIF_FONT_CANDIDATES.iter().map(PathBuf::from))
+        .find_map(|path| fs::read(path).ok());
+    if let Some(bytes) = &serif {
+        fonts.font_data.insert(
+            "serif".to_string(),
+            egui::FontData::from_owned(bytes.clone()),
+        );
+
+        // Fall back to the proportional fonts for glyphs the serif font lacks.
+        let mut family = vec!["serif".to_string()];
+        family.extend(
+            fonts
+                .families
+                .get(&egui::FontFamily::Proportional)
+                .cloned()
+                .unwrap_or_default(),
+        );
+        fonts
+            .families
+            .insert(egui::FontFamily::Name("serif".into()), family);
+    }
+
+    ctx.set_fonts(fonts);
+    (serif.is_some(), errors)
+}
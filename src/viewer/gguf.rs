@@ -0,0 +1,300 @@
+use std::io::{self, Read};
+
+/// How deep arrays may nest in one another. Real files nest none; a crafted
+/// one could nest deep enough to overflow the stack.
+const MAX_ARRAY_DEPTH: usize = 4;
+
+/// Header metadata read from a GGUF model file. Only the key/value section and
+/// tensor descriptors are parsed; tensor data is never loaded.
+pub struct GgufInfo {
+    pub version: u32,
+    pub tensor_count: u64,
+    pub parameter_count: u64,
+    pub metadata: Vec<(String, String)>,
+}
+
+impl GgufInfo {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn architecture(&self) -> Option<&str> {
+        self.get("general.architecture")
+    }
+
+    pub fn context_length(&self) -> Option<&str> {
+        let arch = self.architecture()?;
+        self.get(&format!("{}.context_length", arch))
+    }
+
+    pub fn quantization(&self) -> Option<&'static str> {
+        let file_type = self.get("general.file_type")?.parse::<u32>().ok()?;
+        Some(match file_type {
+            0 => "F32",
+            1 => "F16",
+            2 => "Q4_0",
+            3 => "Q4_1",
+            7 => "Q8_0",
+            8 => "Q5_0",
+            9 => "Q5_1",
+            10 => "Q2_K",
+            11 => "Q3_K_S",
+            12 => "Q3_K_M",
+            13 => "Q3_K_L",
+            14 => "Q4_K_S",
+            15 => "Q4_K_M",
+            16 => "Q5_K_S",
+            17 => "Q5_K_M",
+            18 => "Q6_K",
+            _ => "unknown",
+        })
+    }
+
+    /// Plain-text rendering used as the document content for AI questions.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "GGUF version {}\ntensors: {}\nparameters: {}\n",
+            self.version, self.tensor_count, self.parameter_count
+        );
+        for (key, value) in &self.metadata {
+            text.push_str(&format!("{}: {}\n", key, value));
+        }
+        text
+    }
+}
+
+pub fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub fn read_gguf_string(reader: &mut impl Read) -> io::Result<String> {
+    let len = read_u64(reader)?;
+    if len > 64 * 1024 * 1024 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "string too long",
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Read one GGUF value of type `value_type` and format it for display.
+pub fn read_gguf_value(reader: &mut impl Read, value_type: u32) -> io::Result<String> {
+    read_nested_value(reader, value_type, 0)
+}
+
+/// `read_gguf_value` for a value inside `depth` arrays.
+fn read_nested_value(reader: &mut impl Read, value_type: u32, depth: usize) -> io::Result<String> {
+    let mut buf = [0u8; 8];
+    Ok(match value_type {
+        0 | 1 | 7 => {
+            reader.read_exact(&mut buf[..1])?;
+            match value_type {
+                0 => buf[0].to_string(),
+                1 => (buf[0] as i8).to_string(),
+                _ => (buf[0] != 0).to_string(),
+            }
+        }
+        2 | 3 => {
+            reader.read_exact(&mut buf[..2])?;
+            let raw = [buf[0], buf[1]];
+            if value_type == 2 {
+                u16::from_le_bytes(raw).to_string()
+            } else {
+                i16::from_le_bytes(raw).to_string()
+            }
+        }
+        4 => read_u32(reader)?.to_string(),
+        5 => (read_u32(reader)? as i32).to_string(),
+        6 => f32::from_bits(read_u32(reader)?).to_string(),
+        8 => read_gguf_string(reader)?,
+        9 => {
+            if depth == MAX_ARRAY_DEPTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "arrays nested too deep",
+                ));
+            }
+            // Arrays (e.g. the tokenizer vocabulary) can hold hundreds of thousands
+            // of entries, so only the first few are kept for display.
+            let item_type = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            let mut preview = Vec::new();
+            for i in 0..len {
+                let item = read_nested_value(reader, item_type, depth + 1)?;
+                if i < 8 {
+                    preview.push(item);
+                }
+            }
+            if len > 8 {
+                format!("[{}, … ({} items)]", preview.join(", "), len)
+            } else {
+                format!("[{}]", preview.join(", "))
+            }
+        }
+        10 => read_u64(reader)?.to_string(),
+        11 => (read_u64(reader)? as i64).to_string(),
+        12 => f64::from_bits(read_u64(reader)?).to_string(),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown value type {}", other),
+            ))
+        }
+    })
+}
+
+pub fn parse_gguf(mut reader: impl Read) -> io::Result<GgufInfo> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"GGUF" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a GGUF file",
+        ));
+    }
+
+    let version = read_u32(&mut reader)?;
+    // Version 1 used 32-bit counts and lengths; it predates every model still in circulation.
+    if version < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported GGUF version {}", version),
+        ));
+    }
+
+    let tensor_count = read_u64(&mut reader)?;
+    let kv_count = read_u64(&mut reader)?;
+
+    let mut metadata = Vec::new();
+    for _ in 0..kv_count {
+        let key = read_gguf_string(&mut reader)?;
+        let value_type = read_u32(&mut reader)?;
+        let value = read_gguf_value(&mut reader, value_type)?;
+        metadata.push((key, value));
+    }
+
+    let mut parameter_count = 0u64;
+    for _ in 0..tensor_count {
+        let _name = read_gguf_string(&mut reader)?;
+        let n_dims = read_u32(&mut reader)?;
+        let mut elements = 1u64;
+        for _ in 0..n_dims {
+            elements = elements.saturating_mul(read_u64(&mut reader)?);
+        }
+        let _tensor_type = read_u32(&mut reader)?;
+        let _offset = read_u64(&mut reader)?;
+        parameter_count = parameter_count.saturating_add(elements);
+    }
+
+    Ok(GgufInfo {
+        version,
+        tensor_count,
+        parameter_count,
+        metadata,
+    })
+}
+
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.1} GB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MB", b as f64 / (1u64 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1} KB", b as f64 / (1u64 << 10) as f64),
+        b => format!("{} B", b),
+    }
+}
+
+pub fn format_parameter_count(count: u64) -> String {
+    match count {
+        c if c >= 1_000_000_000 => format!("{:.1}B", c as f64 / 1e9),
+        c if c >= 1_000_000 => format!("{:.1}M", c as f64 / 1e6),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(bytes: &mut Vec<u8>, text: &str) {
+        bytes.extend((text.len() as u64).to_le_bytes());
+        bytes.extend(text.as_bytes());
+    }
+
+    /// A version 3 header with two metadata values and one 4×8 tensor.
+    fn header() -> Vec<u8> {
+        let mut bytes = b"GGUF".to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        string(&mut bytes, "general.architecture");
+        bytes.extend(8u32.to_le_bytes());
+        string(&mut bytes, "llama");
+        string(&mut bytes, "llama.context_length");
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend(4096u32.to_le_bytes());
+        string(&mut bytes, "token_embd.weight");
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(4u64.to_le_bytes());
+        bytes.extend(8u64.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn headers_are_parsed_without_tensor_data() {
+        let info = parse_gguf(header().as_slice()).unwrap();
+        assert_eq!(info.version, 3);
+        assert_eq!(info.tensor_count, 1);
+        assert_eq!(info.parameter_count, 32);
+        assert_eq!(info.architecture(), Some("llama"));
+        assert_eq!(info.context_length(), Some("4096"));
+    }
+
+    #[test]
+    fn damaged_files_are_rejected() {
+        let mut wrong_magic = header();
+        wrong_magic[..4].copy_from_slice(b"GGML");
+        let err = parse_gguf(wrong_magic.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let header = header();
+        let err = parse_gguf(&header[..header.len() - 4]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn arrays_nested_too_deep_are_rejected() {
+        let mut bytes = Vec::new();
+        for _ in 0..1000 {
+            bytes.extend(9u32.to_le_bytes());
+            bytes.extend(1u64.to_le_bytes());
+        }
+        let err = read_gguf_value(&mut bytes.as_slice(), 9).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut nested = Vec::new();
+        nested.extend(9u32.to_le_bytes());
+        nested.extend(1u64.to_le_bytes());
+        nested.extend(4u32.to_le_bytes());
+        nested.extend(2u64.to_le_bytes());
+        nested.extend(7u32.to_le_bytes());
+        nested.extend(9u32.to_le_bytes());
+        let value = read_gguf_value(&mut nested.as_slice(), 9).unwrap();
+        assert_eq!(value, "[[7, 9]]");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::viewer::diff::{diff_rows, DiffKind};
+use eframe::egui;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How a line differs from the committed version of the file.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines were deleted just above this one.
+    DeletedAbove,
+}
+
+/// Who last changed a line, for the blame column.
+pub struct BlameLine {
+    pub commit: String,
+    pub author: String,
+    pub date: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct BlameState {
+    pub loading: bool,
+    /// Per line; `None` for lines that are not committed yet.
+    pub lines: Option<Result<Vec<Option<Arc<BlameLine>>>, String>>,
+}
+
+/// The open file's place in a git repository.
+pub struct GitFile {
+    pub repo_root: PathBuf,
+    /// Path relative to the repository root.
+    pub relative: PathBuf,
+    pub branch: Option<String>,
+    /// The file's content at HEAD; `None` when it is not committed yet.
+    pub head: Option<String>,
+    pub changes: Vec<Option<LineChange>>,
+    pub blame: Arc<Mutex<BlameState>>,
+}
+
+impl GitFile {
+    /// Find the repository containing `path`, if any.
+    pub fn open(path: &str, content: &str) -> Option<Self> {
+        let path = fs::canonicalize(path).ok()?;
+        let repo = git2::Repository::discover(path.parent()?).ok()?;
+        let repo_root = fs::canonicalize(repo.workdir()?).ok()?;
+        let relative = path.strip_prefix(&repo_root).ok()?.to_path_buf();
+        let head = repo.head().ok();
+        let branch = head
+            .as_ref()
+            .and_then(|head| head.shorthand())
+            .map(str::to_string);
+        let committed = head
+            .and_then(|head| head.peel_to_tree().ok())
+            .and_then(|tree| tree.get_path(&relative).ok())
+            .and_then(|entry| entry.to_object(&repo).ok())
+            .and_then(|object| object.peel_to_blob().ok())
+            .map(|blob| String::from_utf8_lossy(blob.content()).into_owned());
+        let mut file = Self {
+            repo_root,
+            relative,
+            branch,
+            head: committed,
+            changes: Vec::new(),
+            blame: Arc::default(),
+        };
+        file.update(content);
+        Some(file)
+    }
+
+    /// Compare `content` with HEAD again, after it changed. Blame is
+    /// recomputed when next shown.
+    pub fn update(&mut self, content: &str) {
+        self.changes = line_changes(self.head.as_deref(), content);
+        self.blame = Arc::default();
+    }
+
+    /// Blame `content` in the background, unless that is already done or running.
+    pub fn start_blame(&self, content: &str, ctx: &egui::Context) {
+        {
+            let mut state = self.blame.lock().unwrap();
+            if state.loading || state.lines.is_some() {
+                return;
+            }
+            state.loading = true;
+        }
+        let state = self.blame.clone();
+        let repo_root = self.repo_root.clone();
+        let relative = self.relative.clone();
+        let content = content.to_string();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let lines = blame_lines(&repo_root, &relative, &content);
+            let mut state = state.lock().unwrap();
+            state.loading = false;
+            state.lines = Some(lines);
+            ctx.request_repaint();
+        });
+    }
+}
+
+/// A commit that changed the open file.
+pub struct GitCommit {
+    pub id: git2::Oid,
+    pub short: String,
+    pub author: String,
+    pub date: String,
+    pub summary: String,
+}
+
+/// How many commits to list in the history menu.
+pub const MAX_HISTORY: usize = 50;
+
+impl GitFile {
+    /// Recent commits reachable from HEAD that changed this file, newest first.
+    pub fn history(&self) -> Result<Vec<GitCommit>, String> {
+        let repo = git2::Repository::open(&self.repo_root).map_err(|e| e.message().to_string())?;
+        let mut walk = repo.revwalk().map_err(|e| e.message().to_string())?;
+        walk.set_sorting(git2::Sort::TIME)
+            .and_then(|_| walk.push_head())
+            .map_err(|e| e.message().to_string())?;
+        let blob_at = |commit: &git2::Commit| {
+            commit
+                .tree()
+                .ok()
+                .and_then(|tree| tree.get_path(&self.relative).ok())
+                .map(|entry| entry.id())
+        };
+
+        let mut commits = Vec::new();
+        for oid in walk.flatten() {
+            let Ok(commit) = repo.find_commit(oid) else {
+                continue;
+            };
+            let blob = blob_at(&commit);
+            if blob.is_none() {
+                continue;
+            }
+            let parent = commit.parent(0).ok();
+            if parent
+                .as_ref()
+                .is_some_and(|parent| blob_at(parent) == blob)
+            {
+                continue;
+            }
+            commits.push(GitCommit {
+                id: oid,
+                short: oid.to_string()[..8].to_string(),
+                author: commit.author().name().unwrap_or("?").to_string(),
+                date: format_date(commit.time().seconds()),
+                summary: commit.summary().unwrap_or("").to_string(),
+            });
+            if commits.len() == MAX_HISTORY {
+                break;
+            }
+        }
+        Ok(commits)
+    }
+
+    /// The file's content as of commit `id`.
+    pub fn content_at(&self, id: git2::Oid) -> Result<String, String> {
+        let repo = git2::Repository::open(&self.repo_root).map_err(|e| e.message().to_string())?;
+        let blob = repo
+            .find_commit(id)
+            .and_then(|commit| commit.tree())
+            .and_then(|tree| tree.get_path(&self.relative))
+            .and_then(|entry| entry.to_object(&repo))
+            .and_then(|object| object.peel_to_blob())
+            .map_err(|e| e.message().to_string())?;
+        Ok(String::from_utf8_lossy(blob.content()).into_owned())
+    }
+}
+
+/// Markers for each line of `current`, compared with the committed text.
+pub fn line_changes(head: Option<&str>, current: &str) -> Vec<Option<LineChange>> {
+    let count = current.lines().count();
+    let Some(head) = head else {
+        return vec![Some(LineChange::Added); count];
+    };
+    let mut changes = vec![None; count];
+    let mut deleted = false;
+    for row in diff_rows(head, current) {
+        match (row.kind, row.right) {
+            (DiffKind::Removed, _) => deleted = true,
+            (kind, Some(line)) => {
+                changes[line] = match kind {
+                    DiffKind::Added => Some(LineChange::Added),
+                    DiffKind::Changed => Some(LineChange::Modified),
+                    _ if deleted => Some(LineChange::DeletedAbove),
+                    _ => None,
+                };
+                deleted = false;
+            }
+            _ => {}
+        }
+    }
+    // Lines deleted at the end of the file are marked on the last line.
+    if deleted {
+        if let Some(last) = changes.last_mut().filter(|change| change.is_none()) {
+            *last = Some(LineChange::DeletedAbove);
+        }
+    }
+    changes
+}
+
+/// Blame the working copy's `content` against the history of `relative`.
+pub fn blame_lines(
+    repo_root: &Path,
+    relative: &Path,
+    content: &str,
+) -> Result<Vec<Option<Arc<BlameLine>>>, String> {
+    let repo = git2::Repository::open(repo_root).map_err(|e| e.message().to_string())?;
+    let committed = repo
+        .blame_file(relative, None)
+        .map_err(|e| e.message().to_string())?;
+    let blame = committed
+        .blame_buffer(content.as_bytes())
+        .map_err(|e| e.message().to_string())?;
+
+    let mut commits: HashMap<git2::Oid, Arc<BlameLine>> = HashMap::new();
+    let mut lines = vec![None; content.lines().count()];
+    for hunk in blame.iter() {
+        let oid = hunk.final_commit_id();
+        if oid.is_zero() {
+            continue;
+        }
+        let line = match commits.get(&oid) {
+            Some(line) => line.clone(),
+            None => {
+                let Ok(commit) = repo.find_commit(oid) else {
+                    continue;
+                };
+                let line = Arc::new(BlameLine {
+                    commit: oid.to_string()[..8].to_string(),
+                    author: commit.author().name().unwrap_or("?").to_string(),
+                    date: format_date(commit.time().seconds()),
+                    message: commit.message().unwrap_or("").trim().to_string(),
+                });
+                commits.insert(oid, line.clone());
+                line
+            }
+        };
+        let start = hunk.final_start_line().saturating_sub(1);
+        for slot in lines.iter_mut().skip(start).take(hunk.lines_in_hunk()) {
+            *slot = Some(line.clone());
+        }
+    }
+    Ok(lines)
+}
+
+/// `YYYY-MM-DD` (UTC) for seconds since the Unix epoch.
+pub fn format_date(seconds: i64) -> String {
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let days = seconds.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,193 @@
+use eframe::egui;
+use image::ImageFormat;
+use resvg::{tiny_skia, usvg};
+use std::io::Cursor;
+use std::path::Path;
+
+/// Extensions shown as images rather than as a hex dump.
+pub const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "bmp", "webp", "svg"];
+
+/// Longest side SVGs are rasterised to, so zooming in stays sharp.
+const SVG_RENDER_SIZE: f32 = 2048.0;
+
+pub fn is_image_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// A decoded image, shown zoomable in place of the hex view.
+pub struct ImagePreview {
+    /// Size in image pixels; for SVGs, the size the drawing declares.
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+    /// PNG or JPEG bytes, the formats vision models take.
+    pub encoded: Vec<u8>,
+    /// Pixels waiting to be uploaded on the first frame.
+    pixels: Option<egui::ColorImage>,
+    texture: Option<egui::TextureHandle>,
+    /// Screen points per image pixel; `None` fits the image to the view.
+    zoom: Option<f32>,
+    /// The zoom of the last frame, fitted or not.
+    pub shown_zoom: f32,
+    /// Offset of the image centre from the centre of the view.
+    pan: egui::Vec2,
+    pub show_hex: bool,
+}
+
+impl ImagePreview {
+    pub fn decode(path: &str, bytes: &[u8]) -> Result<Self, String> {
+        let is_svg = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+        if is_svg {
+            return Self::rasterize_svg(bytes);
+        }
+
+        let format = image::guess_format(bytes).map_err(|e| e.to_string())?;
+        let image = image::load_from_memory_with_format(bytes, format)
+            .map_err(|e| e.to_string())?
+            .to_rgba8();
+        let encoded = match format {
+            ImageFormat::Png | ImageFormat::Jpeg => bytes.to_vec(),
+            _ => {
+                let mut png = Vec::new();
+                image
+                    .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                    .map_err(|e| e.to_string())?;
+                png
+            }
+        };
+        let size = [image.width() as usize, image.height() as usize];
+        Ok(Self::new(
+            image.width(),
+            image.height(),
+            format!("{:?}", format).to_uppercase(),
+            encoded,
+            egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw()),
+        ))
+    }
+
+    fn rasterize_svg(bytes: &[u8]) -> Result<Self, String> {
+        let mut options = usvg::Options::default();
+        options.fontdb_mut().load_system_fonts();
+        let tree = usvg::Tree::from_data(bytes, &options).map_err(|e| e.to_string())?;
+        let size = tree.size();
+        let scale = (SVG_RENDER_SIZE / size.width().max(size.height())).clamp(1.0, 16.0);
+        let (width, height) = (
+            (size.width() * scale).ceil() as u32,
+            (size.height() * scale).ceil() as u32,
+        );
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("The drawing has no size")?;
+        resvg::render(
+            &tree,
+            tiny_skia::Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+        let encoded = pixmap.encode_png().map_err(|e| e.to_string())?;
+        let pixels = egui::ColorImage::from_rgba_premultiplied(
+            [width as usize, height as usize],
+            pixmap.data(),
+        );
+        Ok(Self::new(
+            size.width().round() as u32,
+            size.height().round() as u32,
+            "SVG".to_string(),
+            encoded,
+            pixels,
+        ))
+    }
+
+    fn new(
+        width: u32,
+        height: u32,
+        format: String,
+        encoded: Vec<u8>,
+        pixels: egui::ColorImage,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            encoded,
+            pixels: Some(pixels),
+            texture: None,
+            zoom: None,
+            shown_zoom: 1.0,
+            pan: egui::Vec2::ZERO,
+            show_hex: false,
+        }
+    }
+
+    /// What the AI features read about the image in place of its text.
+    pub fn summary(&self, name: &str) -> String {
+        format!(
+            "Image: {}\nFormat: {}\nSize: {} × {} pixels\n",
+            name, self.format, self.width, self.height
+        )
+    }
+
+    /// The image, fitted to the view until scrolled to zoom around the
+    /// pointer; dragging pans.
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        if let Some(pixels) = self.pixels.take() {
+            self.texture = Some(ui.ctx().load_texture(
+                "image_preview",
+                pixels,
+                egui::TextureOptions::LINEAR,
+            ));
+        }
+        let Some(texture) = self.texture.clone() else {
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            if ui.selectable_label(self.zoom.is_none(), "Fit").clicked() {
+                self.zoom = None;
+                self.pan = egui::Vec2::ZERO;
+            }
+            if ui
+                .selectable_label(self.zoom == Some(1.0), "100%")
+                .clicked()
+            {
+                self.zoom = Some(1.0);
+                self.pan = egui::Vec2::ZERO;
+            }
+            ui.weak("Scroll to zoom, drag to pan");
+        });
+
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+        let image_size = egui::vec2(self.width.max(1) as f32, self.height.max(1) as f32);
+        // Fitting shrinks large images but leaves small ones at their size.
+        let fit = (rect.width() / image_size.x)
+            .min(rect.height() / image_size.y)
+            .min(1.0);
+        let zoom = self.zoom.unwrap_or(fit);
+        let scroll = ui.input(|i| i.scroll_delta.y);
+        if let Some(pointer) = response.hover_pos().filter(|_| scroll != 0.0) {
+            let new_zoom = (zoom * (scroll / 200.0).exp()).clamp(0.02, 32.0);
+            // Keep the pixel under the pointer where it is.
+            let from_center = pointer - rect.center() - self.pan;
+            self.pan -= from_center * (new_zoom / zoom - 1.0);
+            self.zoom = Some(new_zoom);
+        }
+        if response.dragged() {
+            self.pan += response.drag_delta();
+            self.zoom = Some(self.zoom.unwrap_or(fit));
+        }
+        self.shown_zoom = self.zoom.unwrap_or(fit);
+
+        let image_rect =
+            egui::Rect::from_center_size(rect.center() + self.pan, image_size * self.shown_zoom);
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(image_rect, 0.0, ui.visuals().extreme_bg_color);
+        painter.image(
+            texture.id(),
+            image_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE,
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use eframe::egui;
+use std::collections::HashSet;
+
+/// Escape one JSON pointer segment (RFC 6901).
+pub fn pointer_segment(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+pub fn unescape_pointer_segment(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+/// The tree view of a JSON document.
+pub struct JsonTree {
+    /// Length of the text the tree was parsed from, to notice appended lines.
+    pub source_len: usize,
+    pub root: Result<serde_json::Value, String>,
+    /// JSON pointer of the selected node; `""` is the root.
+    pub selected: Option<String>,
+    pub search: String,
+    /// Pointers of nodes matching `search` and of their ancestors, and the
+    /// search they were found for.
+    pub matches: Option<(String, HashSet<String>)>,
+}
+
+impl JsonTree {
+    /// Children of a large array or object beyond this are not listed.
+    pub const MAX_CHILDREN: usize = 1000;
+
+    pub fn parse(text: &str) -> Self {
+        Self {
+            source_len: text.len(),
+            root: serde_json::from_str(text).map_err(|e| e.to_string()),
+            selected: None,
+            search: String::new(),
+            matches: None,
+        }
+    }
+
+    /// Parse changed text, keeping the selection and search.
+    pub fn reparse(&mut self, text: &str) {
+        self.source_len = text.len();
+        self.root = serde_json::from_str(text).map_err(|e| e.to_string());
+        self.matches = None;
+    }
+
+    /// Nodes whose key or scalar value contains the search, case-insensitively,
+    /// plus their ancestors so the tree can be opened down to them.
+    pub fn search_matches(&mut self) -> Option<&HashSet<String>> {
+        fn visit(
+            value: &serde_json::Value,
+            key: &str,
+            pointer: &str,
+            needle: &str,
+            found: &mut HashSet<String>,
+        ) -> bool {
+            let mut matched = key.to_lowercase().contains(needle);
+            match value {
+                serde_json::Value::Object(map) => {
+                    for (child_key, child) in map {
+                        let child_pointer = format!("{}/{}", pointer, pointer_segment(child_key));
+                        matched |= visit(child, child_key, &child_pointer, needle, found);
+                    }
+                }
+                serde_json::Value::Array(items) => {
+                    for (i, child) in items.iter().enumerate() {
+                        let child_pointer = format!("{}/{}", pointer, i);
+                        matched |= visit(child, "", &child_pointer, needle, found);
+                    }
+                }
+                scalar => matched |= scalar.to_string().to_lowercase().contains(needle),
+            }
+            if matched {
+                found.insert(pointer.to_string());
+            }
+            matched
+        }
+
+        if self.search.is_empty() {
+            return None;
+        }
+        let stale = self
+            .matches
+            .as_ref()
+            .is_none_or(|(search, _)| *search != self.search);
+        if stale {
+            let mut found = HashSet::new();
+            if let Ok(root) = &self.root {
+                visit(root, "", "", &self.search.to_lowercase(), &mut found);
+            }
+            self.matches = Some((self.search.clone(), found));
+        }
+        self.matches.as_ref().map(|(_, found)| found)
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, font_size: f32) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.search)
+                    .hint_text("Search keys and values…")
+                    .desired_width(220.0),
+            );
+            if let Some(found) = self.search_matches() {
+                ui.weak(format!("{} matching nodes", found.len()));
+            }
+        });
+        self.show_breadcrumb(ui);
+        ui.separator();
+
+        let matches = self
+            .matches
+            .as_ref()
+            .filter(|_| !self.search.is_empty())
+            .map(|(_, found)| found);
+        let root = match &self.root {
+            Ok(root) => root,
+            Err(error) => {
+                ui.colored_label(egui::Color32::LIGHT_RED, format!("Invalid JSON: {}", error));
+                return;
+            }
+        };
+        egui::ScrollArea::both()
+            .id_source("json_tree")
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.style_mut().override_font_id = Some(egui::FontId::monospace(font_size));
+                show_json_node(ui, "root", root, "", matches, &mut self.selected);
+            });
+    }
+
+    /// `root › key › 0` for the selected node; each part selects that ancestor.
+    pub fn show_breadcrumb(&mut self, ui: &mut egui::Ui) {
+        let Some(selected) = self.selected.clone() else {
+            ui.weak("Click a node to select it; right-click to copy its JSON pointer.");
+            return;
+        };
+        ui.horizontal_wrapped(|ui| {
+            ui.spacing_mut().item_spacing.x = 2.0;
+            if ui.link("root").clicked() {
+                self.selected = Some(String::new());
+            }
+            let mut pointer = String::new();
+            for segment in selected.split('/').skip(1) {
+                pointer.push('/');
+                pointer.push_str(segment);
+                ui.weak("›");
+                if ui.link(unescape_pointer_segment(segment)).clicked() {
+                    self.selected = Some(pointer.clone());
+                }
+            }
+            if ui
+                .small_button("📋")
+                .on_hover_text("Copy JSON pointer")
+                .clicked()
+            {
+                ui.output_mut(|o| o.copied_text = selected.clone());
+            }
+        });
+    }
+}
+
+pub fn json_value_color(value: &serde_json::Value, dark_mode: bool) -> egui::Color32 {
+    use serde_json::Value;
+    match (value, dark_mode) {
+        (Value::String(_), true) => egui::Color32::from_rgb(152, 195, 121),
+        (Value::String(_), false) => egui::Color32::from_rgb(40, 120, 40),
+        (Value::Number(_), true) => egui::Color32::from_rgb(209, 154, 102),
+        (Value::Number(_), false) => egui::Color32::from_rgb(170, 90, 0),
+        (Value::Bool(_), true) => egui::Color32::from_rgb(97, 175, 239),
+        (Value::Bool(_), false) => egui::Color32::from_rgb(0, 90, 180),
+        _ => egui::Color32::GRAY,
+    }
+}
+
+/// One node of the JSON tree; objects and arrays recurse into their children.
+/// Only nodes in `matches` are shown while searching.
+pub fn show_json_node(
+    ui: &mut egui::Ui,
+    key: &str,
+    value: &serde_json::Value,
+    pointer: &str,
+    matches: Option<&HashSet<String>>,
+    selected: &mut Option<String>,
+) {
+    use serde_json::Value;
+    if matches.is_some_and(|found| !found.contains(pointer)) {
+        return;
+    }
+    let is_selected = selected.as_deref() == Some(pointer);
+    let context_menu = |ui: &mut egui::Ui| {
+        if ui.button("Copy JSON pointer").clicked() {
+            ui.output_mut(|o| o.copied_text = pointer.to_string());
+            ui.close_menu();
+        }
+        if ui.button("Copy value").clicked() {
+            let text = match value {
+                Value::String(s) => s.clone(),
+                other => serde_json::to_string_pretty(other).unwrap_or_default(),
+            };
+            ui.output_mut(|o| o.copied_text = text);
+            ui.close_menu();
+        }
+    };
+
+    let children: Vec<(String, String, &Value)> = match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(k, v)| (k.clone(), format!("{}/{}", pointer, pointer_segment(k)), v))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), format!("{}/{}", pointer, i), v))
+            .collect(),
+        scalar => {
+            let color = json_value_color(scalar, ui.visuals().dark_mode);
+            let mut job = egui::text::LayoutJob::default();
+            let font = egui::TextStyle::Monospace.resolve(ui.style());
+            let text_color = ui.visuals().text_color();
+            job.append(
+                &format!("{}: ", key),
+                0.0,
+                egui::TextFormat::simple(font.clone(), text_color),
+            );
+            job.append(
+                &scalar.to_string(),
+                0.0,
+                egui::TextFormat::simple(font, color),
+            );
+            let response = ui.selectable_label(is_selected, job);
+            if response.clicked() {
+                *selected = Some(pointer.to_string());
+            }
+            response.context_menu(context_menu);
+            return;
+        }
+    };
+
+    let summary = match value {
+        Value::Object(_) => format!("{} {{{}}}", key, children.len()),
+        _ => format!("{} [{}]", key, children.len()),
+    };
+    let label = if is_selected {
+        egui::RichText::new(summary).strong().underline()
+    } else {
+        egui::RichText::new(summary)
+    };
+    let header = egui::CollapsingHeader::new(label)
+        .id_source(("json_node", pointer))
+        .default_open(pointer.is_empty())
+        .open(matches.map(|_| true));
+    let response = header.show(ui, |ui| {
+        for (child_key, child_pointer, child) in children.iter().take(JsonTree::MAX_CHILDREN) {
+            show_json_node(ui, child_key, child, child_pointer, matches, selected);
+        }
+        if children.len() > JsonTree::MAX_CHILDREN {
+            ui.weak(format!(
+                "… {} more",
+                children.len() - JsonTree::MAX_CHILDREN
+            ));
+        }
+    });
+    if response.header_response.clicked() {
+        *selected = Some(pointer.to_string());
+    }
+    response.header_response.context_menu(context_menu);
+}
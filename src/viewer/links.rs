@@ -0,0 +1,158 @@
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LinkTarget {
+    Url(String),
+    /// An existing file, and the 0-based line of a `path:line` reference.
+    File {
+        path: PathBuf,
+        line: Option<usize>,
+    },
+}
+
+/// A URL or file reference in a line of the document.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Link {
+    /// Character columns covered, as drawn in the code view.
+    pub columns: Range<usize>,
+    pub target: LinkTarget,
+}
+
+/// Links of each line that has any: http(s) URLs, and paths such as
+/// `src/main.rs:42` for which `resolve` finds an existing file.
+pub fn find_links(
+    text: &str,
+    mut resolve: impl FnMut(&str) -> Option<PathBuf>,
+) -> BTreeMap<usize, Vec<Link>> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    static PATH: OnceLock<Regex> = OnceLock::new();
+    let url = URL.get_or_init(|| Regex::new(r#"https?://[^\s<>"'`]+"#).unwrap());
+    let path = PATH.get_or_init(|| Regex::new(r"[\w.~/@+\-]+(?::(\d+))?(?::\d+)?").unwrap());
+    // Log files name the same few files over and over.
+    let mut resolved: HashMap<&str, Option<PathBuf>> = HashMap::new();
+
+    let mut links = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let column = |offset: usize| line[..offset].chars().count();
+        let mut found: Vec<(Range<usize>, LinkTarget)> = Vec::new();
+        for m in url.find_iter(line) {
+            let target = trim_url(m.as_str());
+            found.push((
+                m.start()..m.start() + target.len(),
+                LinkTarget::Url(target.to_string()),
+            ));
+        }
+        for captures in path.captures_iter(line) {
+            let whole = captures.get(0).unwrap();
+            if found
+                .iter()
+                .any(|(range, _)| range.contains(&whole.start()))
+            {
+                continue;
+            }
+            let name = whole.as_str().split(':').next().unwrap_or_default();
+            let name = name.trim_end_matches('.');
+            let is_path_like = name.contains('/')
+                || name.rsplit_once('.').is_some_and(|(stem, ext)| {
+                    !stem.is_empty() && ext.starts_with(char::is_alphabetic)
+                });
+            if !is_path_like {
+                continue;
+            }
+            let Some(file) = resolved
+                .entry(name)
+                .or_insert_with(|| resolve(name))
+                .clone()
+            else {
+                continue;
+            };
+            let line_number = captures.get(1).filter(|_| name.len() < whole.len());
+            let end = match line_number {
+                Some(_) => whole.end(),
+                None => whole.start() + name.len(),
+            };
+            let line_number = line_number.and_then(|m| m.as_str().parse::<usize>().ok());
+            found.push((
+                whole.start()..end,
+                LinkTarget::File {
+                    path: file,
+                    line: line_number.map(|n| n.saturating_sub(1)),
+                },
+            ));
+        }
+        if !found.is_empty() {
+            found.sort_by_key(|(range, _)| range.start);
+            let line_links = found
+                .into_iter()
+                .map(|(range, target)| Link {
+                    columns: column(range.start)..column(range.end),
+                    target,
+                })
+                .collect();
+            links.insert(index, line_links);
+        }
+    }
+    links
+}
+
+/// A URL without the punctuation of the sentence around it, keeping
+/// balanced parentheses as in Wikipedia links.
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(rest) if trimmed.matches('(').count() < trimmed.matches(')').count() => rest,
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_urls_and_existing_files() {
+        let text = "See https://example.com/a_(b). or (https://x.io/).\n\
+                    error at src/main.rs:42:7, not in src/gone.rs or e.g. 1.5\n";
+        let links = find_links(text, |name| {
+            (name == "src/main.rs").then(|| PathBuf::from("/repo/src/main.rs"))
+        });
+        let targets: Vec<(usize, Range<usize>, LinkTarget)> = links
+            .iter()
+            .flat_map(|(&line, links)| {
+                links
+                    .iter()
+                    .map(move |link| (line, link.columns.clone(), link.target.clone()))
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (
+                    0,
+                    4..29,
+                    LinkTarget::Url("https://example.com/a_(b)".to_string())
+                ),
+                (0, 35..48, LinkTarget::Url("https://x.io/".to_string())),
+                (
+                    1,
+                    9..25,
+                    LinkTarget::File {
+                        path: PathBuf::from("/repo/src/main.rs"),
+                        line: Some(41)
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use eframe::egui;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 5] = [
+        LogLevel::Trace,
+        LogLevel::Debug,
+        LogLevel::Info,
+        LogLevel::Warn,
+        LogLevel::Error,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+
+    pub fn color(self) -> egui::Color32 {
+        match self {
+            LogLevel::Trace | LogLevel::Debug => egui::Color32::GRAY,
+            LogLevel::Info => egui::Color32::from_rgb(90, 160, 240),
+            LogLevel::Warn => egui::Color32::from_rgb(230, 170, 30),
+            LogLevel::Error => egui::Color32::from_rgb(230, 80, 80),
+        }
+    }
+
+    /// Background of lines at this level in log mode.
+    pub fn fill(self) -> Option<egui::Color32> {
+        let (r, g, b, a) = match self {
+            LogLevel::Trace => return None,
+            LogLevel::Debug => (128, 128, 128, 16),
+            LogLevel::Info => (60, 140, 230, 16),
+            LogLevel::Warn => (230, 170, 30, 35),
+            LogLevel::Error => (220, 60, 60, 40),
+        };
+        Some(egui::Color32::from_rgba_unmultiplied(r, g, b, a))
+    }
+}
+
+/// The level named on a log line, taken from the first level-like word.
+pub fn log_level(line: &str) -> Option<LogLevel> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r"(?i)\b(trace|debug|info|warn(?:ing)?|err(?:or)?|fatal|crit(?:ical)?)\b")
+            .unwrap()
+    });
+
+    let word = pattern.captures(line)?.get(1)?.as_str().to_lowercase();
+    Some(match word.as_str() {
+        "trace" => LogLevel::Trace,
+        "debug" => LogLevel::Debug,
+        "info" => LogLevel::Info,
+        "warn" | "warning" => LogLevel::Warn,
+        _ => LogLevel::Error,
+    })
+}
+
+/// When a log line was written. Lines with only a time of day (syslog,
+/// `[12:00:01]`) have no `day`, and compare by time alone.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LogTime {
+    /// A number that sorts like the date, not a count of days.
+    pub day: Option<u32>,
+    pub second: u32,
+}
+
+impl LogTime {
+    fn new(date: Option<(u32, u32, u32)>, hour: u32, minute: u32, second: u32) -> Self {
+        LogTime {
+            day: date.map(|(year, month, day)| (year * 13 + month) * 32 + day),
+            second: hour * 3600 + minute * 60 + second,
+        }
+    }
+
+    /// Ordering by date and time, or by time of day when either lacks a date.
+    fn cmp_with(self, other: LogTime) -> std::cmp::Ordering {
+        match (self.day, other.day) {
+            (Some(a), Some(b)) => (a, self.second).cmp(&(b, other.second)),
+            _ => self.second.cmp(&other.second),
+        }
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The timestamp near the start of a log line: ISO 8601 (`2024-05-01
+/// 12:00:01`, `2024-05-01T12:00:01Z`), Apache's `01/May/2024:12:00:01`, or a
+/// bare `12:00:01` as in syslog.
+pub fn log_time(line: &str) -> Option<LogTime> {
+    static ISO: OnceLock<Regex> = OnceLock::new();
+    static APACHE: OnceLock<Regex> = OnceLock::new();
+    static TIME: OnceLock<Regex> = OnceLock::new();
+    let iso = ISO.get_or_init(|| {
+        Regex::new(r"(\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2})(?::(\d{2}))?").unwrap()
+    });
+    let apache = APACHE.get_or_init(|| {
+        Regex::new(r"(\d{2})/([A-Z][a-z]{2})/(\d{4}):(\d{2}):(\d{2}):(\d{2})").unwrap()
+    });
+    let time = TIME.get_or_init(|| Regex::new(r"\b(\d{2}):(\d{2}):(\d{2})\b").unwrap());
+
+    // Timestamps in the message itself don't date the line.
+    let end = (0..=line.len().min(64))
+        .rev()
+        .find(|&i| line.is_char_boundary(i))
+        .unwrap_or(0);
+    let head = &line[..end];
+    let number = |captures: &regex::Captures, i: usize| -> u32 {
+        captures
+            .get(i)
+            .map_or(0, |m| m.as_str().parse().unwrap_or(0))
+    };
+    if let Some(c) = iso.captures(head) {
+        let date = (number(&c, 1), number(&c, 2), number(&c, 3));
+        return Some(LogTime::new(
+            Some(date),
+            number(&c, 4),
+            number(&c, 5),
+            number(&c, 6),
+        ));
+    }
+    if let Some(c) = apache.captures(head) {
+        let month = MONTHS.iter().position(|m| *m == &c[2])? as u32 + 1;
+        let date = (number(&c, 3), month, number(&c, 1));
+        return Some(LogTime::new(
+            Some(date),
+            number(&c, 4),
+            number(&c, 5),
+            number(&c, 6),
+        ));
+    }
+    let c = time.captures(head)?;
+    Some(LogTime::new(
+        None,
+        number(&c, 1),
+        number(&c, 2),
+        number(&c, 3),
+    ))
+}
+
+/// A bound typed into the time filter: `12:30`, `12:30:15`, `2024-05-01` or
+/// `2024-05-01 12:30`. Missing parts count as the start of the period, or
+/// its end when `end` is set, so `to 12:30` keeps 12:30:59.
+pub fn parse_time_bound(input: &str, end: bool) -> Option<LogTime> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r"^(?:(\d{4})-(\d{1,2})-(\d{1,2}))?[T ]?(?:(\d{1,2}):(\d{2})(?::(\d{2}))?)?$")
+            .unwrap()
+    });
+    let c = pattern.captures(input.trim())?;
+    let number = |i: usize| c.get(i).map(|m| m.as_str().parse::<u32>().unwrap_or(0));
+    let date = match (number(1), number(2), number(3)) {
+        (Some(year), Some(month), Some(day)) => Some((year, month, day)),
+        _ => None,
+    };
+    let (hour, minute) = match (number(4), number(5)) {
+        (Some(hour), Some(minute)) => (hour, minute),
+        _ if date.is_none() => return None,
+        _ if end => (23, 59),
+        _ => (0, 0),
+    };
+    let second = number(6).unwrap_or(if end { 59 } else { 0 });
+    Some(LogTime::new(date, hour, minute, second))
+}
+
+/// Level and timestamp of each line. Lines without them (stack traces,
+/// wrapped messages) belong to the entry above.
+#[derive(Default)]
+pub struct LogLines {
+    pub levels: Vec<Option<LogLevel>>,
+    pub times: Vec<Option<LogTime>>,
+}
+
+impl LogLines {
+    pub fn new(text: &str) -> Self {
+        let mut lines = LogLines::default();
+        for line in text.lines() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    pub fn push(&mut self, line: &str) {
+        let level = log_level(line).or(self.levels.last().copied().flatten());
+        let time = log_time(line).or(self.times.last().copied().flatten());
+        self.levels.push(level);
+        self.times.push(time);
+    }
+
+    pub fn clear(&mut self) {
+        self.levels.clear();
+        self.times.clear();
+    }
+
+    pub fn has_levels(&self) -> bool {
+        self.levels.iter().any(Option::is_some)
+    }
+
+    /// How many lines have each level, in `LogLevel::ALL` order.
+    pub fn level_counts(&self) -> [usize; 5] {
+        let mut counts = [0; 5];
+        for level in self.levels.iter().flatten() {
+            counts[*level as usize] += 1;
+        }
+        counts
+    }
+}
+
+/// Which lines log mode shows.
+#[derive(Default)]
+pub struct LogFilter {
+    pub hidden_levels: BTreeSet<LogLevel>,
+    /// The time range as typed, and as parsed.
+    pub from: String,
+    pub to: String,
+    pub range: (Option<LogTime>, Option<LogTime>),
+}
+
+impl LogFilter {
+    pub fn is_active(&self) -> bool {
+        !self.hidden_levels.is_empty() || self.range != (None, None)
+    }
+
+    /// Parse the typed range again after an edit.
+    pub fn update_range(&mut self) {
+        self.range = (
+            parse_time_bound(&self.from, false),
+            parse_time_bound(&self.to, true),
+        );
+    }
+
+    /// Whether line `index` of `lines` is filtered out. Lines before the
+    /// first level or timestamp are always shown.
+    pub fn hides(&self, lines: &LogLines, index: usize) -> bool {
+        let level = lines.levels.get(index).copied().flatten();
+        if level.is_some_and(|level| self.hidden_levels.contains(&level)) {
+            return true;
+        }
+        let Some(time) = lines.times.get(index).copied().flatten() else {
+            return false;
+        };
+        let (from, to) = self.range;
+        from.is_some_and(|from| time.cmp_with(from).is_lt())
+            || to.is_some_and(|to| time.cmp_with(to).is_gt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_common_timestamps() {
+        let iso = log_time("2024-05-01T12:00:01Z INFO started").unwrap();
+        let apache = log_time("1.2.3.4 - - [01/May/2024:12:00:01 +0000] \"GET /\"").unwrap();
+        let syslog = log_time("May  1 12:00:01 host sshd[42]: accepted").unwrap();
+        assert_eq!(iso, apache);
+        assert_eq!((syslog.day, syslog.second), (None, iso.second));
+        let late = format!("{} at 12:00:01", "a long message ".repeat(5));
+        assert_eq!(log_time(&late), None);
+    }
+
+    #[test]
+    fn filters_by_level_and_time() {
+        let text = "10:00:00 INFO a\n10:05:00 ERROR b\n  at main\n10:10:00 DEBUG c\n";
+        let lines = LogLines::new(text);
+        let mut filter = LogFilter::default();
+        filter.hidden_levels.insert(LogLevel::Debug);
+        filter.from = "10:01".to_string();
+        filter.to = "10:05".to_string();
+        filter.update_range();
+        let shown: Vec<usize> = (0..4).filter(|&i| !filter.hides(&lines, i)).collect();
+        assert_eq!(shown, [1, 2]);
+        assert_eq!(lines.level_counts(), [0, 1, 1, 0, 2]);
+        assert_eq!(parse_time_bound("yesterday", false), None);
+    }
+}
@@ -0,0 +1,339 @@
+use eframe::egui;
+
+/// A block of Markdown, from a model's answer or a `.md` document.
+pub enum MarkdownBlock {
+    Heading {
+        level: usize,
+        text: String,
+    },
+    Paragraph(String),
+    ListItem {
+        marker: String,
+        indent: usize,
+        text: String,
+    },
+    Quote(String),
+    Code {
+        language: String,
+        code: String,
+    },
+    Table {
+        header: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+    /// An image on a line of its own.
+    Image {
+        alt: String,
+        src: String,
+    },
+    Rule,
+}
+
+/// Split Markdown into blocks, each with its first (0-based) source line.
+/// Only the commonly used subset is recognised; an unclosed code fence (as
+/// while an answer is streaming) runs to the end of the text.
+pub fn parse_markdown(text: &str) -> Vec<(usize, MarkdownBlock)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut blocks = Vec::new();
+    let mut paragraph: Option<(usize, String)> = None;
+    let mut code: Option<(usize, String, String)> = None;
+
+    fn flush(paragraph: &mut Option<(usize, String)>, blocks: &mut Vec<(usize, MarkdownBlock)>) {
+        if let Some((line, text)) = paragraph.take() {
+            blocks.push((line, MarkdownBlock::Paragraph(text)));
+        }
+    }
+
+    let mut i = 0;
+    while i < lines.len() {
+        let (number, line) = (i, lines[i]);
+        i += 1;
+        let trimmed = line.trim_start();
+        if let Some((start, language, body)) = &mut code {
+            if trimmed.starts_with("```") {
+                blocks.push((
+                    *start,
+                    MarkdownBlock::Code {
+                        language: std::mem::take(language),
+                        code: std::mem::take(body),
+                    },
+                ));
+                code = None;
+            } else {
+                body.push_str(line);
+                body.push('\n');
+            }
+            continue;
+        }
+
+        if let Some(language) = trimmed.strip_prefix("```") {
+            flush(&mut paragraph, &mut blocks);
+            code = Some((number, language.trim().to_string(), String::new()));
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush(&mut paragraph, &mut blocks);
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push((
+                number,
+                MarkdownBlock::Heading {
+                    level: hashes,
+                    text: trimmed[hashes..].trim().to_string(),
+                },
+            ));
+            continue;
+        }
+        if trimmed.len() >= 3
+            && ["-", "*", "_"].iter().any(|c| {
+                trimmed
+                    .replace(' ', "")
+                    .chars()
+                    .all(|x| x.to_string() == *c)
+            })
+        {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push((number, MarkdownBlock::Rule));
+            continue;
+        }
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push((number, MarkdownBlock::Quote(quote.trim().to_string())));
+            continue;
+        }
+        if trimmed.starts_with('|') && lines.get(i).is_some_and(|next| is_table_separator(next)) {
+            flush(&mut paragraph, &mut blocks);
+            let header = table_cells(trimmed);
+            let mut rows = Vec::new();
+            i += 1;
+            while let Some(row) = lines
+                .get(i)
+                .map(|row| row.trim())
+                .filter(|row| row.starts_with('|'))
+            {
+                rows.push(table_cells(row));
+                i += 1;
+            }
+            blocks.push((number, MarkdownBlock::Table { header, rows }));
+            continue;
+        }
+        if trimmed.starts_with("![") {
+            if let Some((alt, src, "")) = parse_link(trimmed.trim_end()) {
+                flush(&mut paragraph, &mut blocks);
+                blocks.push((
+                    number,
+                    MarkdownBlock::Image {
+                        alt: alt.to_string(),
+                        src: src.to_string(),
+                    },
+                ));
+                continue;
+            }
+        }
+
+        let indent = (line.len() - trimmed.len()) / 2;
+        let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+        let item = if let Some(text) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet))
+        {
+            Some(("•".to_string(), text))
+        } else if digits > 0
+            && (trimmed[digits..].starts_with(". ") || trimmed[digits..].starts_with(") "))
+        {
+            Some((format!("{}.", &trimmed[..digits]), &trimmed[digits + 2..]))
+        } else {
+            None
+        };
+        if let Some((marker, text)) = item {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push((
+                number,
+                MarkdownBlock::ListItem {
+                    marker,
+                    indent,
+                    text: text.trim().to_string(),
+                },
+            ));
+            continue;
+        }
+
+        // A wrapped list item continues on an indented line.
+        if let (true, Some((_, MarkdownBlock::ListItem { text, .. }))) =
+            (paragraph.is_none() && indent > 0, blocks.last_mut())
+        {
+            text.push(' ');
+            text.push_str(trimmed);
+            continue;
+        }
+        match &mut paragraph {
+            Some((_, text)) => {
+                text.push(' ');
+                text.push_str(trimmed);
+            }
+            None => paragraph = Some((number, trimmed.to_string())),
+        }
+    }
+
+    flush(&mut paragraph, &mut blocks);
+    if let Some((start, language, code)) = code {
+        blocks.push((start, MarkdownBlock::Code { language, code }));
+    }
+    blocks
+}
+
+pub fn is_table_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.contains('|')
+        && line.contains('-')
+        && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+pub fn table_cells(row: &str) -> Vec<String> {
+    row.trim()
+        .trim_start_matches('|')
+        .trim_end_matches('|')
+        .split('|')
+        .map(|cell| cell.trim().to_string())
+        .collect()
+}
+
+/// `[label](target)` or `![alt](src)` at the start of `text`: the label, the
+/// target and the text after it.
+pub fn parse_link(text: &str) -> Option<(&str, &str, &str)> {
+    let rest = text.strip_prefix('!').unwrap_or(text).strip_prefix('[')?;
+    let (label, rest) = rest.split_once("](")?;
+    let (target, after) = rest.split_once(')')?;
+    Some((label, target, after))
+}
+
+/// A run of Markdown text with one formatting.
+#[derive(Default)]
+pub struct InlineSpan {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+    pub link: Option<String>,
+}
+
+/// Split a line of Markdown into `code`, **bold**, *italic* and [linked] runs.
+/// Inline images become links to the image.
+pub fn inline_spans(text: &str) -> Vec<InlineSpan> {
+    let mut spans = Vec::new();
+    let (mut bold, mut italic) = (false, false);
+    let mut plain = String::new();
+    let flush = |plain: &mut String, spans: &mut Vec<InlineSpan>, bold, italic| {
+        if !plain.is_empty() {
+            spans.push(InlineSpan {
+                text: std::mem::take(plain),
+                bold,
+                italic,
+                ..InlineSpan::default()
+            });
+        }
+    };
+
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some((code, after)) = rest
+            .strip_prefix('`')
+            .and_then(|after| after.split_once('`'))
+        {
+            flush(&mut plain, &mut spans, bold, italic);
+            spans.push(InlineSpan {
+                text: code.to_string(),
+                code: true,
+                ..InlineSpan::default()
+            });
+            rest = after;
+            continue;
+        }
+        if let Some((label, target, after)) = parse_link(rest) {
+            flush(&mut plain, &mut spans, bold, italic);
+            let label = if rest.starts_with('!') {
+                format!("🖼 {}", label)
+            } else {
+                label.to_string()
+            };
+            spans.push(InlineSpan {
+                text: label,
+                bold,
+                italic,
+                link: Some(target.to_string()),
+                ..InlineSpan::default()
+            });
+            rest = after;
+            continue;
+        }
+        // Markers only count when they are closed later (or close an open span).
+        if let Some(after) = rest.strip_prefix("**").filter(|a| bold || a.contains("**")) {
+            flush(&mut plain, &mut spans, bold, italic);
+            bold = !bold;
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('*').filter(|a| italic || a.contains('*')) {
+            flush(&mut plain, &mut spans, bold, italic);
+            italic = !italic;
+            rest = after;
+            continue;
+        }
+        plain.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    flush(&mut plain, &mut spans, bold, italic);
+    spans
+}
+
+pub fn is_web_link(target: &str) -> bool {
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| target.starts_with(scheme))
+}
+
+/// Lay out a line of Markdown as wrapping labels. Web links open in the
+/// browser; a clicked link to anything else is returned for the caller.
+pub fn show_inline(
+    ui: &mut egui::Ui,
+    text: &str,
+    size: f32,
+    color: Option<egui::Color32>,
+) -> Option<String> {
+    let mut clicked = None;
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        for span in inline_spans(text) {
+            let mut rich = egui::RichText::new(span.text).size(size);
+            if span.code {
+                rich = rich.code();
+            }
+            if span.italic {
+                rich = rich.italics();
+            }
+            if span.bold {
+                rich = rich.strong();
+            } else if let Some(color) = color {
+                rich = rich.color(color);
+            }
+            match span.link {
+                Some(target) if is_web_link(&target) => {
+                    ui.hyperlink_to(rich, target);
+                }
+                Some(target) => {
+                    if ui.link(rich).on_hover_text(&target).clicked() {
+                        clicked = Some(target);
+                    }
+                }
+                None => {
+                    ui.label(rich);
+                }
+            }
+        }
+    });
+    clicked
+}
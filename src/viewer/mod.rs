@@ -0,0 +1,197 @@
+pub mod archive;
+pub mod diff;
+pub mod file_tree;
+pub mod fonts;
+pub mod gguf;
+pub mod git;
+pub mod image_preview;
+pub mod json;
+pub mod links;
+pub mod log;
+pub mod markdown;
+pub mod outline;
+pub mod text;
+pub mod text_stats;
+
+use crate::ai::{Annotation, AnnotationKind};
+use crate::viewer::links::LinkTarget;
+use eframe::egui;
+use syntect::highlighting::Style;
+
+/// One hex dump row: `chunk` as hex padded to `width` bytes, with an extra
+/// space every eight, and its printable ASCII with dots for everything else.
+pub fn hex_row(chunk: &[u8], width: usize) -> (String, String) {
+    let mut hex = String::with_capacity(width * 3 + width / 8);
+    for i in 0..width {
+        match chunk.get(i) {
+            Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+            None => hex.push_str("   "),
+        }
+        if i % 8 == 7 && i + 1 < width {
+            hex.push(' ');
+        }
+    }
+    let ascii = chunk
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    (hex, ascii)
+}
+
+pub fn hex_dump_preview(bytes: &[u8]) -> String {
+    const PREVIEW_BYTES: usize = 4096;
+    let mut text = format!(
+        "Binary file, {} bytes. Hex dump of the start:\n",
+        bytes.len()
+    );
+    for (i, chunk) in bytes[..bytes.len().min(PREVIEW_BYTES)]
+        .chunks(16)
+        .enumerate()
+    {
+        let (hex, ascii) = hex_row(chunk, 16);
+        text.push_str(&format!("{:08x}  {} |{}|\n", i * 16, hex, ascii));
+    }
+    if bytes.len() > PREVIEW_BYTES {
+        text.push_str(&format!("… {} more bytes\n", bytes.len() - PREVIEW_BYTES));
+    }
+    text
+}
+
+/// How a document with a rendered form (Markdown, JSON) is shown.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DocumentView {
+    Source,
+    /// The Markdown preview or the JSON tree.
+    Rendered,
+    /// Source and rendered form side by side.
+    Split,
+}
+
+/// The minimap of the active document, drawn as one pixel per character.
+pub struct Minimap {
+    /// Path, text length, highlighted lines, whether highlighting is still
+    /// running, and theme: the minimap is redrawn when any of these change.
+    pub key: (Option<String>, usize, usize, bool, String),
+    pub texture: egui::TextureHandle,
+}
+
+impl Minimap {
+    pub const WIDTH: f32 = 90.0;
+    /// Columns of each line that are drawn.
+    pub const COLUMNS: usize = 120;
+    /// Longer documents are sampled down to this many lines.
+    pub const MAX_LINES: usize = 4096;
+
+    pub fn render(
+        ctx: &egui::Context,
+        key: (Option<String>, usize, usize, bool, String),
+        lines: &[Vec<(Style, String)>],
+    ) -> Self {
+        let height = lines.len().clamp(1, Self::MAX_LINES);
+        let mut image = egui::ColorImage::new([Self::COLUMNS, height], egui::Color32::TRANSPARENT);
+        for row in 0..height {
+            let Some(line) = lines.get(row * lines.len() / height) else {
+                continue;
+            };
+            let mut column = 0;
+            for (style, text) in line {
+                let color = egui::Color32::from_rgba_unmultiplied(
+                    style.foreground.r,
+                    style.foreground.g,
+                    style.foreground.b,
+                    200,
+                );
+                for c in text.chars() {
+                    if column >= Self::COLUMNS {
+                        break;
+                    }
+                    if !c.is_whitespace() {
+                        image[(column, row)] = color;
+                    }
+                    column += if c == '\t' { 4 } else { 1 };
+                }
+            }
+        }
+        Self {
+            key,
+            texture: ctx.load_texture("minimap", image, egui::TextureOptions::LINEAR),
+        }
+    }
+}
+
+/// Per-frame results collected while rendering the document view.
+#[derive(Default)]
+pub struct ViewFrame {
+    /// First and last line intersecting the viewport.
+    pub visible: Option<(usize, usize)>,
+    /// Line at the vertical center of the viewport.
+    pub center_line: Option<usize>,
+    pub new_annotation: Option<Annotation>,
+    /// Line and character column that was clicked.
+    pub clicked: Option<(usize, usize)>,
+    /// Link that was Ctrl+clicked.
+    pub open_link: Option<LinkTarget>,
+    /// Line number clicked in the gutter, and whether Shift was held.
+    pub gutter_clicked: Option<(usize, bool)>,
+    /// Line whose bookmark column was clicked.
+    pub bookmark_toggled: Option<usize>,
+    /// First line of a fold region whose marker was clicked.
+    pub fold_toggled: Option<usize>,
+    /// Fold (`true`) or unfold (`false`) every region.
+    pub fold_all: Option<bool>,
+    /// Line and column where a drag selection started.
+    pub drag_started: Option<(usize, usize)>,
+    /// Text area of each code row laid out this frame.
+    pub rows: Vec<(usize, egui::Rect)>,
+    pub copy_selection: Option<CopyFormat>,
+    pub explain_selection: bool,
+    pub ask_about_selection: bool,
+    pub edit_selection: bool,
+    pub document_selection: bool,
+    pub suggest_tests: bool,
+}
+
+/// How the selection goes onto the clipboard.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CopyFormat {
+    Plain,
+    /// 24-bit ANSI colour escapes, for pasting into a terminal.
+    Ansi,
+    /// Rich text, with the plain text as a fallback.
+    Html,
+}
+
+/// Context-menu entries for annotating `first_line..=last_line`.
+pub fn annotation_menu(
+    ui: &mut egui::Ui,
+    first_line: usize,
+    last_line: usize,
+    frame: &mut ViewFrame,
+) {
+    let mut add = |kind| {
+        frame.new_annotation = Some(Annotation {
+            kind,
+            first_line,
+            last_line,
+            note: String::new(),
+        });
+    };
+    if ui.button("🖍 Highlight").clicked() {
+        add(AnnotationKind::Highlight);
+        ui.close_menu();
+    }
+    if ui.button("📝 Add note").clicked() {
+        add(AnnotationKind::Note);
+        ui.close_menu();
+    }
+    if ui.button("🔖 Bookmark").clicked() {
+        add(AnnotationKind::Bookmark);
+        ui.close_menu();
+    }
+}
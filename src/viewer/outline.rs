@@ -0,0 +1,277 @@
+use crate::viewer::text::indent_width;
+use regex::Regex;
+use std::path::Path;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolKind {
+    Heading,
+    Function,
+    Type,
+    Module,
+    Key,
+}
+
+impl SymbolKind {
+    pub fn icon(self) -> &'static str {
+        match self {
+            SymbolKind::Heading => "§",
+            SymbolKind::Function => "ƒ",
+            SymbolKind::Type => "◆",
+            SymbolKind::Module => "▣",
+            SymbolKind::Key => "•",
+        }
+    }
+}
+
+/// An entry of the outline panel.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    /// Nesting level: heading level, or how many enclosing symbols.
+    pub depth: usize,
+    pub line: usize,
+}
+
+/// The symbols of a document, chosen by the extension of `path`: headings
+/// of Markdown, definitions of source code, top-level keys of JSON, YAML
+/// and TOML. Empty for other files.
+pub fn outline(text: &str, path: Option<&str>) -> Vec<Symbol> {
+    let extension = path
+        .and_then(|path| Path::new(path).extension())
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "md" | "markdown" => markdown_headings(text),
+        "json" => json_keys(text),
+        "yaml" | "yml" => yaml_keys(text),
+        "toml" => toml_tables(text),
+        _ => match definition_pattern(&extension) {
+            Some(pattern) => definitions(text, pattern),
+            None => Vec::new(),
+        },
+    }
+}
+
+fn markdown_headings(text: &str) -> Vec<Symbol> {
+    let mut in_fence = false;
+    let mut symbols = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let trimmed = content.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let level = trimmed.chars().take_while(|&c| c == '#').count();
+        let name = trimmed[level..].trim().trim_end_matches('#').trim();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') && !name.is_empty() {
+            symbols.push(Symbol {
+                kind: SymbolKind::Heading,
+                name: name.to_string(),
+                depth: level - 1,
+                line,
+            });
+        }
+    }
+    symbols
+}
+
+/// Keys of the top-level object, found by tracking nesting outside strings.
+fn json_keys(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let (mut depth, mut line) = (0usize, 0);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth = depth.saturating_sub(1),
+            '"' => {
+                let mut key = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            key.push(c);
+                            key.extend(chars.next());
+                        }
+                        '"' => break,
+                        _ => key.push(c),
+                    }
+                }
+                while chars
+                    .peek()
+                    .is_some_and(|c| c.is_whitespace() && *c != '\n')
+                {
+                    chars.next();
+                }
+                if depth == 1 && chars.peek() == Some(&':') {
+                    symbols.push(Symbol {
+                        kind: SymbolKind::Key,
+                        name: key,
+                        depth: 0,
+                        line,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
+
+fn yaml_keys(text: &str) -> Vec<Symbol> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern =
+        PATTERN.get_or_init(|| Regex::new(r#"^(["']?[\w.\- ]+["']?)\s*:(\s|$)"#).unwrap());
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, content)| {
+            let name = pattern.captures(content)?.get(1)?.as_str();
+            Some(Symbol {
+                kind: SymbolKind::Key,
+                name: name.trim_matches(['"', '\'']).to_string(),
+                depth: 0,
+                line,
+            })
+        })
+        .collect()
+}
+
+fn toml_tables(text: &str) -> Vec<Symbol> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, content)| {
+            let name = content.trim().strip_prefix('[')?.split(']').next()?.trim();
+            Some(Symbol {
+                kind: SymbolKind::Module,
+                name: name.trim_matches(['[', ']']).to_string(),
+                depth: 0,
+                line,
+            })
+        })
+        .collect()
+}
+
+/// A pattern matching a definition line of the language with `extension`,
+/// capturing the kind of definition and its name.
+fn definition_pattern(extension: &str) -> Option<&'static Regex> {
+    static RUST: OnceLock<Regex> = OnceLock::new();
+    static PYTHON: OnceLock<Regex> = OnceLock::new();
+    static SCRIPT: OnceLock<Regex> = OnceLock::new();
+    static GO: OnceLock<Regex> = OnceLock::new();
+    static JAVA: OnceLock<Regex> = OnceLock::new();
+    static C: OnceLock<Regex> = OnceLock::new();
+    static RUBY: OnceLock<Regex> = OnceLock::new();
+    static SHELL: OnceLock<Regex> = OnceLock::new();
+    let (cell, pattern) = match extension {
+        "rs" => (
+            &RUST,
+            r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|extern\s+\S+)\s+)*(fn|struct|enum|union|trait|mod|type|impl|macro_rules!)(?:\s*<[^{]*?>)?\s+([^\s({;<:=]+(?:\s+for\s+[^\s{<]+)?)",
+        ),
+        "py" | "pyi" => (&PYTHON, r"^\s*(?:async\s+)?(def|class)\s+(\w+)"),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => (
+            &SCRIPT,
+            r"^\s*(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(function\*?|class|interface|type|enum|namespace)\s+(\w+)",
+        ),
+        "go" => (&GO, r"^(func|type)\s+(?:\([^)]*\)\s*)?(\w+)"),
+        "java" | "kt" | "kts" | "scala" | "cs" => (
+            &JAVA,
+            r"^\s*(?:(?:public|private|protected|internal|static|final|abstract|sealed|open|data|partial)\s+)*(class|interface|enum|record|object|struct|fun)\s+(\w+)",
+        ),
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" => (
+            &C,
+            r"^(?:(struct|class|enum|union|namespace)\s+(\w+)\s*(?:\{|:|$)|[A-Za-z_][\w\s\*&:<>,]*?[\s\*&]\**(\w+)\s*\([^;]*$)",
+        ),
+        "rb" => (&RUBY, r"^\s*(def|class|module)\s+([\w.:?!]+)"),
+        "sh" | "bash" | "zsh" => (&SHELL, r"^\s*(function\s+)?(\w+)\s*\(\)"),
+        _ => return None,
+    };
+    Some(cell.get_or_init(|| Regex::new(pattern).unwrap()))
+}
+
+/// Definitions matched by `pattern`, nested by indentation.
+fn definitions(text: &str, pattern: &Regex) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    // Indentation of the enclosing definitions.
+    let mut enclosing: Vec<usize> = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let Some(captures) = pattern.captures(content) else {
+            continue;
+        };
+        // C functions match without a keyword; shell functions may lack one.
+        let keyword = captures.get(1).map_or("fn", |m| m.as_str());
+        let Some(name) = captures.iter().skip(2).flatten().next() else {
+            continue;
+        };
+        let indent = indent_width(content);
+        while enclosing.last().is_some_and(|&outer| outer >= indent) {
+            enclosing.pop();
+        }
+        let kind = match keyword.trim() {
+            "struct" | "enum" | "union" | "trait" | "type" | "class" | "interface" | "record"
+            | "object" | "impl" => SymbolKind::Type,
+            "mod" | "module" | "namespace" => SymbolKind::Module,
+            _ => SymbolKind::Function,
+        };
+        symbols.push(Symbol {
+            kind,
+            name: name.as_str().trim().to_string(),
+            depth: enclosing.len(),
+            line,
+        });
+        enclosing.push(indent);
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(symbols: &[Symbol]) -> Vec<(usize, &str, usize)> {
+        symbols
+            .iter()
+            .map(|s| (s.line, s.name.as_str(), s.depth))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_headings_skip_code_blocks() {
+        let text = "# Title\n\n## Usage ##\n```sh\n# not a heading\n```\n#hashtag\n### Flags\n";
+        let symbols = outline(text, Some("README.md"));
+        assert_eq!(
+            names(&symbols),
+            [(0, "Title", 0), (2, "Usage", 1), (7, "Flags", 2)]
+        );
+    }
+
+    #[test]
+    fn rust_definitions_nest_by_indentation() {
+        let text = "pub struct App {\n    x: u8,\n}\n\nimpl<T> Show for App {\n    pub fn show(&self) {}\n}\nfn main() {}\n";
+        let symbols = outline(text, Some("src/main.rs"));
+        assert_eq!(
+            names(&symbols),
+            [
+                (0, "App", 0),
+                (4, "Show for App", 0),
+                (5, "show", 1),
+                (7, "main", 0)
+            ]
+        );
+        assert_eq!(symbols[1].kind, SymbolKind::Type);
+    }
+
+    #[test]
+    fn json_lists_only_top_level_keys() {
+        let text = "{\n  \"name\": \"x\",\n  \"deps\": {\"a\": 1},\n  \"list\": [\"b\", \"c\"]\n}";
+        let symbols = outline(text, Some("package.json"));
+        assert_eq!(
+            names(&symbols),
+            [(1, "name", 0), (2, "deps", 0), (3, "list", 0)]
+        );
+    }
+}
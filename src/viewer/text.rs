@@ -0,0 +1,717 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// A paragraph of the document as an inclusive, 0-based line range.
+pub struct Paragraph {
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+/// Split text into blank-line-separated paragraphs (0-based, inclusive line ranges).
+pub fn split_paragraphs(text: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut start = None;
+    let mut last = 0;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            if let Some(first_line) = start.take() {
+                paragraphs.push(Paragraph {
+                    first_line,
+                    last_line: i - 1,
+                });
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+        last = i;
+    }
+    if let Some(first_line) = start {
+        paragraphs.push(Paragraph {
+            first_line,
+            last_line: last,
+        });
+    }
+    paragraphs
+}
+
+pub fn paragraph_text(text: &str, paragraph: &Paragraph) -> String {
+    text.lines()
+        .skip(paragraph.first_line)
+        .take(paragraph.last_line - paragraph.first_line + 1)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Lines that keep their own layout when reflowing prose.
+pub fn is_structural_line(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    line.starts_with("    ")
+        || line.starts_with('\t')
+        || trimmed.starts_with('#')
+        || trimmed.starts_with('|')
+        || trimmed.starts_with('>')
+}
+
+pub fn is_list_item(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("- ")
+        || trimmed.starts_with("* ")
+        || trimmed.starts_with("+ ")
+        || trimmed
+            .split_once(". ")
+            .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// A foldable block of lines: `first` stays visible when it is folded, and
+/// `first + 1..=last` are hidden.
+#[derive(Clone, Copy)]
+pub struct FoldRegion {
+    pub first: usize,
+    pub last: usize,
+}
+
+/// Width of a line's indentation, counting a tab as four columns.
+pub fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Foldable regions, sorted by first line. Brackets opened on one line and
+/// closed on a later one give a region ending before the closing line, so the
+/// closer stays visible. Lines followed by more-indented lines give a region
+/// too, which covers languages such as Python and YAML. Brackets in string
+/// literals and `//` comments are skipped.
+pub fn fold_regions(text: &str) -> Vec<FoldRegion> {
+    let mut regions: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut add = |first: usize, last: usize| {
+        if last > first {
+            let end = regions.entry(first).or_insert(last);
+            *end = (*end).max(last);
+        }
+    };
+
+    let mut open: Vec<usize> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => in_string = true,
+                '/' if chars.peek() == Some(&'/') => break,
+                '{' | '[' | '(' => open.push(number),
+                '}' | ']' | ')' => {
+                    if let Some(first) = open.pop() {
+                        add(first, number.saturating_sub(1));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Lines still waiting for a more-indented line to end their block, with
+    // their indentation.
+    let mut pending: Vec<(usize, usize)> = Vec::new();
+    let mut last_text_line = 0;
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indent = indent_width(line);
+        while let Some(&(first, first_indent)) = pending.last() {
+            if first_indent < indent {
+                break;
+            }
+            pending.pop();
+            add(first, last_text_line);
+        }
+        pending.push((number, indent));
+        last_text_line = number;
+    }
+    for (first, _) in pending {
+        add(first, last_text_line);
+    }
+
+    regions
+        .into_iter()
+        .map(|(first, last)| FoldRegion { first, last })
+        .collect()
+}
+
+/// A unit of prose layout: either a paragraph to be wrapped by the viewer, or a
+/// line (heading, table row, code) that keeps its original layout.
+pub struct ProseBlock {
+    /// 0-based source line the block starts on.
+    pub source_line: usize,
+    pub last_line: usize,
+    pub text: String,
+    pub paragraph: bool,
+}
+
+/// Split prose into paragraphs (joined into a single line each) and verbatim lines.
+pub fn prose_blocks(text: &str) -> Vec<ProseBlock> {
+    let mut blocks = Vec::new();
+    let mut paragraph = String::new();
+    let mut paragraph_start = 0;
+    let mut paragraph_end = 0;
+    let mut in_fence = false;
+
+    let flush = |paragraph: &mut String, start: usize, end: usize, blocks: &mut Vec<ProseBlock>| {
+        if !paragraph.is_empty() {
+            blocks.push(ProseBlock {
+                source_line: start,
+                last_line: end,
+                text: std::mem::take(paragraph),
+                paragraph: true,
+            });
+        }
+    };
+
+    for (i, line) in text.lines().enumerate() {
+        let fence = line.trim_start().starts_with("```");
+        if fence || in_fence || line.trim().is_empty() || is_structural_line(line) {
+            flush(&mut paragraph, paragraph_start, paragraph_end, &mut blocks);
+            if fence {
+                in_fence = !in_fence;
+            }
+            // Blank lines are represented by paragraph spacing instead.
+            if !line.trim().is_empty() {
+                blocks.push(ProseBlock {
+                    source_line: i,
+                    last_line: i,
+                    text: line.to_string(),
+                    paragraph: false,
+                });
+            }
+            continue;
+        }
+
+        if is_list_item(line) {
+            flush(&mut paragraph, paragraph_start, paragraph_end, &mut blocks);
+        }
+        if paragraph.is_empty() {
+            paragraph_start = i;
+        } else {
+            paragraph.push(' ');
+        }
+        paragraph.push_str(line.trim());
+        paragraph_end = i;
+    }
+    flush(&mut paragraph, paragraph_start, paragraph_end, &mut blocks);
+
+    blocks
+}
+
+/// Byte offset at which each line of `text` starts.
+pub fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// The case-insensitive pattern the find bar searches with: `query` as a
+/// regular expression when `regex` is set, else literally. `None` for an
+/// empty query or an invalid expression.
+pub fn search_pattern(query: &str, regex: bool) -> Option<Regex> {
+    if query.is_empty() {
+        return None;
+    }
+    let query = match regex {
+        true => query.to_string(),
+        false => regex::escape(query),
+    };
+    Regex::new(&format!("(?i){}", query)).ok()
+}
+
+/// Occurrences of `pattern` in `text`, as byte ranges. Empty matches, which
+/// could not be shown, are left out.
+pub fn find_matches(text: &str, pattern: &Regex) -> Vec<Range<usize>> {
+    pattern
+        .find_iter(text)
+        .map(|m| m.range())
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+/// `text` with the matches of `pattern` starting at `starts` replaced by
+/// `replacement`, and how many were replaced. With `expand`, `$1` or
+/// `${name}` in the replacement stand for capture groups.
+pub fn replace_matches(
+    text: &str,
+    pattern: &Regex,
+    starts: &[usize],
+    replacement: &str,
+    expand: bool,
+) -> (String, usize) {
+    let mut replaced = String::with_capacity(text.len());
+    let (mut copied, mut count) = (0, 0);
+    for &start in starts {
+        let Some(captures) = pattern.captures_at(text, start) else {
+            continue;
+        };
+        let whole = captures.get(0).unwrap();
+        if whole.start() != start || whole.is_empty() || start < copied {
+            continue;
+        }
+        replaced.push_str(&text[copied..start]);
+        match expand {
+            true => captures.expand(replacement, &mut replaced),
+            false => replaced.push_str(replacement),
+        }
+        copied = whole.end();
+        count += 1;
+    }
+    replaced.push_str(&text[copied..]);
+    (replaced, count)
+}
+
+/// The text as it was before each replacement made from the find bar,
+/// newest last. Undoing puts a snapshot back whole, so it is only right
+/// while the text is as the last replacement left it: any other change to
+/// the text must `clear` it.
+#[derive(Default)]
+pub struct ReplaceUndo {
+    snapshots: Vec<String>,
+}
+
+impl ReplaceUndo {
+    /// Replacements kept for undoing; older ones are dropped.
+    const MAX_UNDO: usize = 20;
+
+    pub fn record(&mut self, before: String) {
+        self.snapshots.push(before);
+        if self.snapshots.len() > Self::MAX_UNDO {
+            self.snapshots.remove(0);
+        }
+    }
+
+    /// The text before the last replacement, if any.
+    pub fn undo(&mut self) -> Option<String> {
+        self.snapshots.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+/// Byte offset of character column `column` on the line starting at `line_start`.
+pub fn column_offset(text: &str, line_start: usize, column: usize) -> usize {
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    line_start
+        + line
+            .char_indices()
+            .nth(column)
+            .map_or(line.len(), |(i, _)| i)
+}
+
+/// Parse a 1-based `line` or `line:column` into 0-based numbers.
+pub fn parse_goto(input: &str) -> Option<(usize, Option<usize>)> {
+    let (line, column) = match input.trim().split_once(':') {
+        Some((line, column)) => (line, Some(column.trim())),
+        None => (input.trim(), None),
+    };
+    let line = line.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let column = match column {
+        Some(column) => Some(column.parse::<usize>().ok()?.checked_sub(1)?),
+        None => None,
+    };
+    Some((line, column))
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+pub fn word_range(text: &str, range: &Range<usize>) -> Range<usize> {
+    let start = text[..range.start]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map_or(range.start, |(i, _)| i);
+    let end = range.end
+        + text[range.end..]
+            .chars()
+            .take_while(|c| is_word_char(*c))
+            .map(char::len_utf8)
+            .sum::<usize>();
+    start..end
+}
+
+/// Contents of, and including, the quoted string around `range` on its line.
+pub fn quoted_ranges(text: &str, range: &Range<usize>) -> Vec<Range<usize>> {
+    let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[range.start..]
+        .find('\n')
+        .map_or(text.len(), |i| range.start + i);
+    if range.end > line_end {
+        return Vec::new();
+    }
+
+    let line = &text.as_bytes()[line_start..line_end];
+    let mut ranges = Vec::new();
+    for quote in [b'"', b'\'', b'`'] {
+        let positions: Vec<usize> = (0..line.len())
+            .filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != b'\\'))
+            .map(|i| line_start + i)
+            .collect();
+        for pair in positions.chunks_exact(2) {
+            let (open, close) = (pair[0], pair[1]);
+            if open < range.start && range.end <= close {
+                ranges.push(open + 1..close);
+                ranges.push(open..close + 1);
+            }
+        }
+    }
+    ranges
+}
+
+/// The innermost bracket pair enclosing `range`, as (opening, closing) byte offsets.
+pub fn enclosing_brackets(text: &str, range: &Range<usize>) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut search_from = range.start;
+
+    loop {
+        let mut depth = 0;
+        let open = (0..search_from).rev().find(|&i| match bytes[i] {
+            b')' | b']' | b'}' => {
+                depth += 1;
+                false
+            }
+            b'(' | b'[' | b'{' if depth == 0 => true,
+            b'(' | b'[' | b'{' => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        })?;
+
+        let mut depth = 0;
+        let close = (open + 1..bytes.len()).find(|&i| match bytes[i] {
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                false
+            }
+            b')' | b']' | b'}' if depth == 0 => true,
+            b')' | b']' | b'}' => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        })?;
+
+        if close >= range.end {
+            return Some((open, close));
+        }
+        search_from = open;
+    }
+}
+
+/// Offset of the bracket matching the one at `offset`, if there is one.
+pub fn matching_bracket(text: &str, offset: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    match *bytes.get(offset)? {
+        b'(' | b'[' | b'{' => (offset + 1..bytes.len()).find(|&i| match bytes[i] {
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                false
+            }
+            b')' | b']' | b'}' if depth == 0 => true,
+            b')' | b']' | b'}' => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        }),
+        b')' | b']' | b'}' => (0..offset).rev().find(|&i| match bytes[i] {
+            b')' | b']' | b'}' => {
+                depth += 1;
+                false
+            }
+            b'(' | b'[' | b'{' if depth == 0 => true,
+            b'(' | b'[' | b'{' => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        }),
+        _ => None,
+    }
+}
+
+/// The bracket next to `range` and its match, as (bracket, match). The
+/// bracket at the start wins over the one just before it, then the ones
+/// around the end.
+pub fn bracket_pair_at(text: &str, range: &Range<usize>) -> Option<(usize, usize)> {
+    [
+        Some(range.start),
+        range.start.checked_sub(1),
+        range.end.checked_sub(1).filter(|&end| end > range.start),
+        Some(range.end).filter(|&end| end > range.start),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|at| Some((at, matching_bracket(text, at)?)))
+}
+
+/// Bracket nesting depth at the start of each line.
+pub fn bracket_depths(text: &str) -> Vec<usize> {
+    let mut depth = 0usize;
+    text.lines()
+        .map(|line| {
+            let start = depth;
+            for byte in line.bytes() {
+                match byte {
+                    b'(' | b'[' | b'{' => depth += 1,
+                    b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+            start
+        })
+        .collect()
+}
+
+/// Whole lines covered by `range`, without the final newline.
+pub fn full_lines(text: &str, range: &Range<usize>) -> Range<usize> {
+    let last = if range.end > range.start {
+        range.end - 1
+    } else {
+        range.end
+    };
+    let start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[last..].find('\n').map_or(text.len(), |i| last + i);
+    start..end.max(range.end)
+}
+
+pub fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// The indented block containing `range` together with its header line and,
+/// for brace or `end` languages, its closing line: usually the enclosing
+/// function or type.
+pub fn indent_block(text: &str, range: &Range<usize>) -> Option<Range<usize>> {
+    let starts = line_starts(text);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let line_of = |offset: usize| starts.partition_point(|&s| s <= offset) - 1;
+    let first = line_of(range.start);
+    let last = line_of(range.end.saturating_sub(1).max(range.start));
+
+    let indent = (first..=last)
+        .filter(|&i| !lines[i].trim().is_empty())
+        .map(|i| indentation(lines[i]))
+        .min()?;
+    let header = (0..first)
+        .rev()
+        .find(|&i| !lines[i].trim().is_empty() && indentation(lines[i]) < indent)?;
+    let header_indent = indentation(lines[header]);
+
+    let mut end = last;
+    for (i, line) in lines.iter().enumerate().skip(last + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indentation(line) > header_indent {
+            end = i;
+            continue;
+        }
+        let closing = line.trim_start();
+        if closing.starts_with(['}', ')', ']']) || closing.starts_with("end") {
+            end = i;
+        }
+        break;
+    }
+
+    Some(starts[header]..starts[end] + lines[end].len())
+}
+
+/// The smallest syntactic unit strictly containing `range`: word, string,
+/// bracket contents, brackets, lines, indented block, then the whole text.
+pub fn expand_selection(text: &str, range: &Range<usize>) -> Option<Range<usize>> {
+    let mut candidates = vec![word_range(text, range), full_lines(text, range)];
+    candidates.extend(quoted_ranges(text, range));
+    if let Some((open, close)) = enclosing_brackets(text, range) {
+        candidates.push(open + 1..close);
+        candidates.push(open..close + 1);
+    }
+    candidates.extend(indent_block(text, range));
+    candidates.push(0..text.len());
+
+    candidates
+        .into_iter()
+        .filter(|c| c.start <= range.start && c.end >= range.end && c.len() > range.len())
+        .min_by_key(|c| c.len())
+}
+
+/// A whole-document conversion offered while editing.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Conversion {
+    Lf,
+    Crlf,
+    TabsToSpaces,
+    SpacesToTabs,
+}
+
+impl Conversion {
+    pub const ALL: [Conversion; 4] = [
+        Conversion::Lf,
+        Conversion::Crlf,
+        Conversion::TabsToSpaces,
+        Conversion::SpacesToTabs,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Conversion::Lf => "Line endings to LF (Unix)",
+            Conversion::Crlf => "Line endings to CRLF (Windows)",
+            Conversion::TabsToSpaces => "Indent with spaces",
+            Conversion::SpacesToTabs => "Indent with tabs",
+        }
+    }
+
+    pub fn is_indentation(self) -> bool {
+        matches!(self, Conversion::TabsToSpaces | Conversion::SpacesToTabs)
+    }
+}
+
+/// How many lines end in LF and in CRLF.
+pub fn line_ending_counts(text: &str) -> (usize, usize) {
+    let lines = text.matches('\n').count();
+    let crlf = text.matches("\r\n").count();
+    (lines - crlf, crlf)
+}
+
+/// `text` after `conversion`, and how many lines it changed. The tab
+/// conversions only touch indentation, with tab stops every `tab_width`
+/// columns; tabs further along a line are left alone.
+pub fn convert(text: &str, conversion: Conversion, tab_width: usize) -> (String, usize) {
+    let tab_width = tab_width.max(1);
+    let mut converted = String::with_capacity(text.len());
+    let mut changed = 0;
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_end_matches(['\n', '\r']);
+        let mut ending = &line[body.len()..];
+        let mut indent = String::new();
+        let mut rest = body;
+        match conversion {
+            Conversion::Lf if !ending.is_empty() => ending = "\n",
+            Conversion::Crlf if !ending.is_empty() => ending = "\r\n",
+            Conversion::TabsToSpaces | Conversion::SpacesToTabs => {
+                rest = body.trim_start_matches([' ', '\t']);
+                let width = body[..body.len() - rest.len()]
+                    .chars()
+                    .fold(0, |width, c| match c {
+                        '\t' => (width / tab_width + 1) * tab_width,
+                        _ => width + 1,
+                    });
+                indent = match conversion {
+                    Conversion::TabsToSpaces => " ".repeat(width),
+                    _ => "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width),
+                };
+            }
+            _ => {}
+        }
+        let start = converted.len();
+        converted.push_str(&indent);
+        converted.push_str(rest);
+        converted.push_str(ending);
+        if converted[start..] != *line {
+            changed += 1;
+        }
+    }
+    (converted, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_matches_with_capture_groups() {
+        let text = "let a = f(x);\nlet b = F(y);\n";
+        let pattern = search_pattern(r"f\((\w)\)", true).unwrap();
+        let matches = find_matches(text, &pattern);
+        assert_eq!(matches, [8..12, 22..26]);
+        let starts: Vec<usize> = matches.iter().map(|m| m.start).collect();
+        let (all, count) = replace_matches(text, &pattern, &starts, "g($1, 0)", true);
+        assert_eq!(count, 2);
+        assert_eq!(all, "let a = g(x, 0);\nlet b = g(y, 0);\n");
+        let (one, _) = replace_matches(text, &pattern, &starts[1..], "$0", false);
+        assert_eq!(one, "let a = f(x);\nlet b = $0;\n");
+        assert!(search_pattern("f(", true).is_none());
+        let literal = search_pattern("(", false).unwrap();
+        assert_eq!(find_matches("a(b(", &literal), [1..2, 3..4]);
+    }
+
+    #[test]
+    fn replace_all_is_undone_as_a_whole() {
+        let original = "foo bar foo\nfoo\n";
+        let pattern = search_pattern("foo", false).unwrap();
+        let starts: Vec<usize> = find_matches(original, &pattern)
+            .into_iter()
+            .map(|m| m.start)
+            .collect();
+        let mut undo = ReplaceUndo::default();
+        let (replaced, count) = replace_matches(original, &pattern, &starts, "baz", false);
+        assert_eq!((replaced.as_str(), count), ("baz bar baz\nbaz\n", 3));
+        undo.record(original.to_string());
+
+        assert_eq!(undo.undo().as_deref(), Some(original));
+        assert!(undo.is_empty());
+    }
+
+    #[test]
+    fn replacements_are_undone_latest_first_up_to_the_cap() {
+        let mut undo = ReplaceUndo::default();
+        for i in 0..ReplaceUndo::MAX_UNDO + 2 {
+            undo.record(format!("text {}", i));
+        }
+        let undone: Vec<String> = std::iter::from_fn(|| undo.undo()).collect();
+        assert_eq!(undone.len(), ReplaceUndo::MAX_UNDO);
+        assert_eq!(undone[0], format!("text {}", ReplaceUndo::MAX_UNDO + 1));
+        // The two oldest were dropped.
+        assert_eq!(undone.last().map(String::as_str), Some("text 2"));
+    }
+
+    #[test]
+    fn converts_line_endings_and_indentation() {
+        let text = "a\r\n\tb\tc\n      d";
+        assert_eq!(line_ending_counts(text), (1, 1));
+        assert_eq!(
+            convert(text, Conversion::Lf, 4),
+            ("a\n\tb\tc\n      d".into(), 1)
+        );
+        assert_eq!(
+            convert(text, Conversion::Crlf, 4),
+            ("a\r\n\tb\tc\r\n      d".into(), 1)
+        );
+        assert_eq!(
+            convert(text, Conversion::TabsToSpaces, 4),
+            ("a\r\n    b\tc\n      d".into(), 1)
+        );
+        assert_eq!(
+            convert(text, Conversion::SpacesToTabs, 4),
+            ("a\r\n\tb\tc\n\t  d".into(), 1)
+        );
+    }
+}
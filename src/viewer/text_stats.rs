@@ -0,0 +1,100 @@
+use crate::ai::prompt::estimate_tokens;
+use std::collections::HashSet;
+
+/// Words read per minute for the reading time estimate.
+const READING_SPEED: usize = 230;
+
+/// Text larger than this is counted on a worker thread.
+pub const BACKGROUND_STATS_BYTES: usize = 256 * 1024;
+
+/// Figures shown in the document statistics window.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextStats {
+    pub lines: usize,
+    pub blank_lines: usize,
+    pub words: usize,
+    /// Distinct words, ignoring case.
+    pub unique_words: usize,
+    pub chars: usize,
+    pub bytes: usize,
+    /// 0-based line with the most characters, and its length.
+    pub longest_line: (usize, usize),
+    /// Characters per non-blank line.
+    pub average_line_length: f32,
+    pub reading_minutes: f32,
+    pub tokens: usize,
+}
+
+/// Statistics of `text`, with tokens estimated as for the budget that cuts
+/// documents to fit a model's context.
+pub fn text_stats(text: &str) -> TextStats {
+    let mut lines = 0;
+    let mut blank_lines = 0;
+    let mut longest_line = (0, 0);
+    let mut filled_chars = 0;
+    for (index, line) in text.lines().enumerate() {
+        lines += 1;
+        let length = line.chars().count();
+        if line.trim().is_empty() {
+            blank_lines += 1;
+        } else {
+            filled_chars += length;
+        }
+        if length > longest_line.1 {
+            longest_line = (index, length);
+        }
+    }
+
+    let mut words = 0;
+    let mut unique = HashSet::new();
+    for word in text
+        .split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '_'))
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+    {
+        words += 1;
+        unique.insert(word.to_lowercase());
+    }
+
+    TextStats {
+        lines,
+        blank_lines,
+        words,
+        unique_words: unique.len(),
+        chars: text.chars().count(),
+        bytes: text.len(),
+        longest_line,
+        average_line_length: filled_chars as f32 / (lines - blank_lines).max(1) as f32,
+        reading_minutes: words as f32 / READING_SPEED as f32,
+        tokens: estimate_tokens(text),
+    }
+}
+
+/// A reading time such as "4 min", or "< 1 min".
+pub fn format_minutes(minutes: f32) -> String {
+    if minutes < 1.0 {
+        "< 1 min".to_string()
+    } else if minutes < 60.0 {
+        format!("{:.0} min", minutes)
+    } else {
+        format!("{} h {:.0} min", (minutes / 60.0) as u32, minutes % 60.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_words_lines_and_tokens() {
+        let stats = text_stats("The cat's hat.\n\n  the CAT's sat_on 'it'\n");
+        assert_eq!(stats.lines, 3);
+        assert_eq!(stats.blank_lines, 1);
+        assert_eq!(stats.words, 7);
+        // "the" and "cat's" repeat, in other cases.
+        assert_eq!(stats.unique_words, 5);
+        assert_eq!(stats.longest_line, (2, 23));
+        assert_eq!(stats.average_line_length, 18.5);
+        assert_eq!(format_minutes(75.0), "1 h 15 min");
+    }
+}